@@ -0,0 +1,90 @@
+// Throughput of canonical_hand(), which runs for every hand table lookup in
+// training and when building the equity tables. canonical_hand_with_hole()
+// is the same without the cache, and the "misses" group cycles through more
+// hands than the cache holds.
+//
+//      cargo bench --bench canonical
+//
+// The crate is a binary, so card_utils is pulled in by path.
+#[macro_use(c)]
+extern crate cute;
+#[macro_use]
+extern crate lazy_static;
+extern crate itertools;
+
+#[path = "../src/card_utils.rs"]
+#[allow(dead_code)]
+mod card_utils;
+
+use card_utils::{canonical_hand, canonical_hand_with_hole, deck, Card};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rand::prelude::SliceRandom;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+const N_HANDS: usize = 1000;
+// More than the 4096 hands in each thread's cache
+const N_MISS_HANDS: usize = 10_000;
+
+fn random_hands(n_cards: usize) -> Vec<Vec<Card>> {
+    random_hands_n(n_cards, N_HANDS)
+}
+
+fn random_hands_n(n_cards: usize, n_hands: usize) -> Vec<Vec<Card>> {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut deck = deck();
+    (0..n_hands)
+        .map(|_| {
+            deck.shuffle(&mut rng);
+            deck[..n_cards].to_vec()
+        })
+        .collect()
+}
+
+fn bench_canonical_hand(c: &mut Criterion) {
+    let mut group = c.benchmark_group("canonical_hand");
+    group.throughput(Throughput::Elements(N_HANDS as u64));
+    for n_cards in 5..=7 {
+        let hands = random_hands(n_cards);
+        group.bench_with_input(BenchmarkId::from_parameter(n_cards), &hands, |b, hands| {
+            b.iter(|| {
+                hands
+                    .iter()
+                    .map(|hand| canonical_hand(hand, true).len())
+                    .sum::<usize>()
+            })
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("canonical_hand_uncached");
+    group.throughput(Throughput::Elements(N_HANDS as u64));
+    for n_cards in 5..=7 {
+        let hands = random_hands(n_cards);
+        group.bench_with_input(BenchmarkId::from_parameter(n_cards), &hands, |b, hands| {
+            b.iter(|| {
+                hands
+                    .iter()
+                    .map(|hand| canonical_hand_with_hole(hand, 2).len())
+                    .sum::<usize>()
+            })
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("canonical_hand_misses");
+    group.throughput(Throughput::Elements(N_MISS_HANDS as u64));
+    let hands = random_hands_n(7, N_MISS_HANDS);
+    group.bench_with_input(BenchmarkId::from_parameter(7), &hands, |b, hands| {
+        b.iter(|| {
+            hands
+                .iter()
+                .map(|hand| canonical_hand(hand, true).len())
+                .sum::<usize>()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_canonical_hand);
+criterion_main!(benches);
@@ -0,0 +1,72 @@
+// Compares contains_duplicates() with the nested loop it replaced and the
+// HashSet version. The lists have no duplicates, so every implementation has
+// to look at all of them.
+//
+//      cargo bench --bench contains_duplicates
+//
+// The crate is a binary, so card_utils is pulled in by path.
+#[macro_use(c)]
+extern crate cute;
+#[macro_use]
+extern crate lazy_static;
+extern crate itertools;
+
+#[path = "../src/card_utils.rs"]
+#[allow(dead_code)]
+mod card_utils;
+
+use card_utils::contains_duplicates;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::prelude::SliceRandom;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::collections::HashSet;
+
+const N_LISTS: usize = 100;
+
+fn nested_loop(list: &[u8]) -> bool {
+    for i in 0..list.len() {
+        for j in i + 1..list.len() {
+            if list[i] == list[j] {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+fn hash_set(list: &[u8]) -> bool {
+    list.iter().collect::<HashSet<_>>().len() != list.len()
+}
+
+fn distinct_lists(len: usize) -> Vec<Vec<u8>> {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut values: Vec<u8> = (0..=255).collect();
+    (0..N_LISTS)
+        .map(|_| {
+            values.shuffle(&mut rng);
+            values[..len].to_vec()
+        })
+        .collect()
+}
+
+fn bench_contains_duplicates(c: &mut Criterion) {
+    let implementations: [(&str, fn(&[u8]) -> bool); 3] = [
+        ("bitset", contains_duplicates),
+        ("nested_loop", nested_loop),
+        ("hash_set", hash_set),
+    ];
+    let mut group = c.benchmark_group("contains_duplicates");
+    for len in &[2, 4, 8, 16] {
+        let lists = distinct_lists(*len);
+        for (name, f) in implementations.iter() {
+            group.bench_with_input(BenchmarkId::new(*name, len), &lists, |b, lists| {
+                b.iter(|| lists.iter().filter(|list| f(list)).count())
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_contains_duplicates);
+criterion_main!(benches);
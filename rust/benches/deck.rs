@@ -0,0 +1,52 @@
+// Compares deck(), which copies a constant deck, with the push loop it
+// replaced, and with going through deck_iter() without making a Vec at all.
+//
+//      cargo bench --bench deck
+//
+// The crate is a binary, so card_utils is pulled in by path.
+#[macro_use(c)]
+extern crate cute;
+#[macro_use]
+extern crate lazy_static;
+extern crate itertools;
+
+#[path = "../src/card_utils.rs"]
+#[allow(dead_code)]
+mod card_utils;
+
+use card_utils::{deck, deck_iter, shuffled_deck, Card};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+fn push_deck() -> Vec<Card> {
+    let mut deck = Vec::new();
+    for rank in 2..15 {
+        for suit in 0..4 {
+            deck.push(Card {
+                rank: rank,
+                suit: suit,
+            });
+        }
+    }
+    deck
+}
+
+fn bench_deck(c: &mut Criterion) {
+    let mut group = c.benchmark_group("deck");
+    group.bench_function("push", |b| b.iter(|| black_box(push_deck())));
+    group.bench_function("copy", |b| b.iter(|| black_box(deck())));
+    // The deck is a constant, so without black_box the sum gets worked out at
+    // compile time
+    group.bench_function("iter", |b| {
+        b.iter(|| deck_iter().map(|c| black_box(c).rank as u32).sum::<u32>())
+    });
+    let mut rng = StdRng::seed_from_u64(0);
+    group.bench_function("shuffled", |b| {
+        b.iter(|| black_box(shuffled_deck(&mut rng)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_deck);
+criterion_main!(benches);
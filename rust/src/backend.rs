@@ -1,11 +1,19 @@
-use crate::bot::bot_action;
-use crate::card_utils::{strvec2cards, Card, LightHandTable};
-use crate::trainer_utils::{Action, ActionHistory, ActionType};
+use crate::bot::{bot_action, init_blueprint};
+use crate::card_utils::{strvec2cards, try_strvec2cards, Card, LightHandTable};
+use crate::config::ServerConfig;
+use crate::trainer_utils::{
+    Action, ActionHistory, ActionType, FLOP, FOLD, OPPONENT, PREFLOP, RIVER, TURN,
+};
+use actix::{Actor, ActorContext, StreamHandler};
 use actix_cors::Cors;
-use actix_web::{http, web, App, HttpRequest, HttpServer, Responder};
+use actix_web::{http, web, App, Error, HttpRequest, HttpResponse, HttpServer, Responder};
+use actix_web_actors::ws;
 use std::collections::HashMap;
+use std::time::Duration;
 
-const SERVER: &str = "127.0.0.1:8000";
+pub mod api;
+pub mod metrics;
+pub mod session;
 
 lazy_static! {
     static ref HAND_STRENGTHS: LightHandTable = LightHandTable::new();
@@ -90,16 +98,301 @@ fn parse_cards(cards: &str) -> Vec<Card> {
     cards
 }
 
+// Websocket game sessions. Instead of sending the whole history with every
+// request like /bot, the client opens a websocket at /ws and the server keeps
+// track of the hand. The client sends messages like
+//
+//      {"type": "deal", "hand": ["Ah", "Kd"]}          (the bot's hole cards)
+//      {"type": "board", "cards": ["2c", "7d", "Th"]}  (new board cards)
+//      {"type": "action", "action": "bet", "amount": 50}
+//
+// and the server answers every message with an optional bot action like
+// {"type": "bot_action", "action": "raise", "amount": 120}, followed by a
+// "state" message saying what it's waiting for next. The bot always plays the
+// big blind, so the human acts first preflop and the bot acts first after that.
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    Deal {
+        hand: Vec<String>,
+    },
+    Board {
+        cards: Vec<String>,
+    },
+    Action {
+        action: String,
+        #[serde(default)]
+        amount: i32,
+    },
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    BotAction {
+        action: String,
+        amount: i32,
+    },
+    State {
+        street: String,
+        pot: i32,
+        stacks: [i32; 2],
+        waiting_for: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+// How the bot picks its actions, given its hole cards, the board and the
+// history so far
+pub type BotStrategy = fn(&[Card], &[Card], &ActionHistory) -> Action;
+
+#[derive(Clone)]
+struct GameState {
+    hand: Vec<Card>,
+    board: Vec<Card>,
+    history: ActionHistory,
+    strategy: BotStrategy,
+}
+
+impl GameState {
+    fn new() -> GameState {
+        GameState::with_strategy(bot_action)
+    }
+
+    fn with_strategy(strategy: BotStrategy) -> GameState {
+        GameState {
+            hand: Vec::new(),
+            board: Vec::new(),
+            history: ActionHistory::new(),
+            strategy: strategy,
+        }
+    }
+
+    fn handle(&mut self, message: ClientMessage) -> Result<Vec<ServerMessage>, String> {
+        match message {
+            ClientMessage::Deal { hand } => {
+                let hand = parse_card_list(&hand)?;
+                if hand.len() != 2 {
+                    return Err(String::from("The bot needs exactly 2 hole cards"));
+                }
+                *self = GameState::with_strategy(self.strategy);
+                self.hand = hand;
+            }
+            ClientMessage::Board { cards } => {
+                if self.waiting_for() != "board" {
+                    return Err(String::from("Not expecting board cards"));
+                }
+                let cards = parse_card_list(&cards)?;
+                if self.board.len() + cards.len() != board_size(self.history.street) {
+                    return Err(String::from("Wrong number of board cards"));
+                }
+                self.board.extend(cards);
+            }
+            ClientMessage::Action { action, amount } => {
+                if self.waiting_for() != "action" {
+                    return Err(String::from("Not expecting an action"));
+                }
+                let action = self.parse_action(&action, amount)?;
+                self.history.add(&action);
+            }
+        }
+        let mut replies = Vec::new();
+        if self.waiting_for() == "bot" {
+            let action = (self.strategy)(&self.hand, &self.board, &self.history);
+            replies.push(ServerMessage::BotAction {
+                action: action_name(&action, &self.history),
+                amount: action.amount,
+            });
+            self.history.add(&action);
+        }
+        replies.push(self.state());
+        Ok(replies)
+    }
+
+    // Returns who or what the hand is waiting on: the bot, the human's
+    // "action", the "board" cards for the next street, or "nothing" once the
+    // hand is over.
+    fn waiting_for(&self) -> &'static str {
+        if self.hand.is_empty() || self.history.hand_over() {
+            "nothing"
+        } else if self.board.len() < board_size(self.history.street) {
+            "board"
+        } else if self.history.player == OPPONENT {
+            "bot"
+        } else {
+            "action"
+        }
+    }
+
+    fn state(&self) -> ServerMessage {
+        let street = match self.history.street {
+            PREFLOP => "preflop",
+            FLOP => "flop",
+            TURN => "turn",
+            RIVER => "river",
+            _ => "showdown",
+        };
+        ServerMessage::State {
+            street: String::from(street),
+            pot: self.history.pot(),
+            stacks: self.history.stack_sizes(),
+            waiting_for: String::from(self.waiting_for()),
+        }
+    }
+
+    fn parse_action(&self, action: &str, amount: i32) -> Result<Action, String> {
+        match action {
+            "fold" => Ok(FOLD),
+            "check" | "call" => Ok(Action {
+                action: ActionType::Call,
+                amount: self.history.to_call(),
+            }),
+            "bet" | "raise" => {
+                if self.history.is_bet_legal(amount) {
+                    Ok(Action {
+                        action: ActionType::Bet,
+                        amount: amount,
+                    })
+                } else {
+                    Err(format!("Illegal bet size {}", amount))
+                }
+            }
+            _ => Err(format!("Unknown action '{}'", action)),
+        }
+    }
+}
+
+fn board_size(street: usize) -> usize {
+    match street {
+        PREFLOP => 0,
+        FLOP => 3,
+        TURN => 4,
+        _ => 5,
+    }
+}
+
+fn parse_card_list(cards: &[String]) -> Result<Vec<Card>, String> {
+    let cards: Vec<&str> = cards.iter().map(|c| c.as_str()).collect();
+    try_strvec2cards(&cards).map_err(|e| e.to_string())
+}
+
+// The name the Javascript code uses for an action, where history is the state
+// before the action was taken.
+fn action_name(action: &Action, history: &ActionHistory) -> String {
+    let name = match action.action {
+        ActionType::Fold => "fold",
+        ActionType::Call if action.amount == 0 => "check",
+        ActionType::Call => "call",
+        ActionType::Bet if history.to_call() > 0 => "raise",
+        ActionType::Bet => "bet",
+    };
+    String::from(name)
+}
+
+struct GameSession {
+    state: GameState,
+}
+
+impl Actor for GameSession {
+    type Context = ws::WebsocketContext<Self>;
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for GameSession {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        match msg {
+            Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
+            Ok(ws::Message::Text(text)) => {
+                let replies = match serde_json::from_str::<ClientMessage>(&text) {
+                    Ok(message) => self.state.handle(message),
+                    Err(e) => Err(e.to_string()),
+                };
+                let replies =
+                    replies.unwrap_or_else(|message| vec![ServerMessage::Error { message }]);
+                for reply in replies {
+                    ctx.text(serde_json::to_string(&reply).unwrap());
+                }
+            }
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            _ => (),
+        }
+    }
+}
+
+pub async fn game_session(req: HttpRequest, stream: web::Payload) -> Result<HttpResponse, Error> {
+    game_session_with(&req, stream, bot_action)
+}
+
+// Like game_session(), but the bot plays the given strategy instead of the
+// blueprint
+pub fn game_session_with(
+    req: &HttpRequest,
+    stream: web::Payload,
+    strategy: BotStrategy,
+) -> Result<HttpResponse, Error> {
+    let session = GameSession {
+        state: GameState::with_strategy(strategy),
+    };
+    ws::start(session, req, stream)
+}
+
 #[actix_rt::main]
-pub async fn main() -> std::io::Result<()> {
-    println!("[INFO] Launching server at {}", SERVER);
-    HttpServer::new(|| {
+pub async fn main(config: ServerConfig) -> std::io::Result<()> {
+    // Load the bot's blueprint and the 5-card table behind the hand endpoints
+    // now rather than on the first request that needs them. The much bigger
+    // 7-card and equity tables are left until something uses them.
+    rayon::join(|| lazy_static::initialize(&HAND_STRENGTHS), init_blueprint);
+    let address = config.address();
+    println!("[INFO] Launching server at {}", address);
+    let timeout = Duration::from_secs(config.session_timeout_secs);
+    let sessions = web::Data::new(session::SessionStore::new(timeout));
+    session::expire_sessions(sessions.clone());
+    let metrics = web::Data::new(metrics::MetricsRegistry::new());
+    HttpServer::new(move || {
         App::new()
+            .app_data(sessions.clone())
+            .app_data(metrics.clone())
             .wrap(Cors::new().allowed_origin("http://localhost:3000").finish())
-            .route("/compare", web::get().to(compare_hands))
-            .route("/bot", web::get().to(get_cpu_action))
+            .service(metrics::resource("/metrics", &metrics).route(web::get().to(metrics::metrics)))
+            .service(metrics::resource("/compare", &metrics).route(web::get().to(compare_hands)))
+            .service(metrics::resource("/bot", &metrics).route(web::get().to(get_cpu_action)))
+            .service(metrics::resource("/ws", &metrics).route(web::get().to(game_session)))
+            .service(
+                metrics::resource("/hand_strength", &metrics)
+                    .route(web::get().to(api::hand_strength))
+                    .route(web::post().to(api::hand_strength_json)),
+            )
+            .service(
+                metrics::resource("/compare_hands", &metrics)
+                    .route(web::get().to(api::compare_hands)),
+            )
+            .service(
+                metrics::resource("/session", &metrics)
+                    .route(web::post().to(session::create_session)),
+            )
+            .service(
+                metrics::resource("/session/{id}/deal", &metrics)
+                    .route(web::post().to(session::deal)),
+            )
+            .service(
+                metrics::resource("/session/{id}/action", &metrics)
+                    .route(web::post().to(session::action)),
+            )
+            .service(
+                metrics::resource("/session/{id}/state", &metrics)
+                    .route(web::get().to(session::state)),
+            )
+            .service(
+                metrics::resource("/session/{id}", &metrics)
+                    .route(web::delete().to(session::end_session)),
+            )
     })
-    .bind(SERVER)?
+    .bind(address)?
     .run()
     .await
 }
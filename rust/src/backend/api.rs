@@ -0,0 +1,133 @@
+// Simple JSON endpoints for hand evaluation that don't need a game going.
+//
+//      GET  /hand_strength?cards=AhKdQcJcTh
+//      POST /hand_strength with {"cards": "AhKdQcJcTh"}
+//      GET  /compare_hands?hand1=AhKdQcJcTh&hand2=2h2d2c2s3d
+//
+// Bad card strings get a 400 and problems on our end (like the hand table
+// failing to load) get a 500, both with a body like {"error": "..."}.
+use super::HAND_STRENGTHS;
+use crate::card_utils::{cards2str, category_from_strength, str2cards, Card, CardParseError};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use std::fmt;
+use std::panic;
+
+#[derive(Debug, serde::Deserialize)]
+pub struct HandQuery {
+    cards: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct CompareQuery {
+    hand1: String,
+    hand2: String,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct HandStrength {
+    strength: i32,
+    category: String,
+    hand: String,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Comparison {
+    // "hand1", "hand2" or "tie"
+    winner: String,
+    hand1: HandStrength,
+    hand2: HandStrength,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ApiError {
+    BadCards(CardParseError),
+    WrongNumberOfCards(usize),
+    DuplicateCard(String),
+    Internal(String),
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiError::BadCards(e) => write!(f, "bad card string: {}", e),
+            ApiError::WrongNumberOfCards(n) => {
+                write!(f, "hands need between 5 and 7 cards, got {}", n)
+            }
+            ApiError::DuplicateCard(card) => write!(f, "{} appears more than once", card),
+            ApiError::Internal(message) => write!(f, "internal error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl From<CardParseError> for ApiError {
+    fn from(error: CardParseError) -> ApiError {
+        ApiError::BadCards(error)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(serde_json::json!({"error": self.to_string()}))
+    }
+}
+
+pub async fn hand_strength(query: web::Query<HandQuery>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(evaluate(&query.cards)?))
+}
+
+pub async fn hand_strength_json(body: web::Json<HandQuery>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(evaluate(&body.cards)?))
+}
+
+pub async fn compare_hands(query: web::Query<CompareQuery>) -> Result<HttpResponse, ApiError> {
+    let hand1 = evaluate(&query.hand1)?;
+    let hand2 = evaluate(&query.hand2)?;
+    let winner = if hand1.strength > hand2.strength {
+        "hand1"
+    } else if hand2.strength > hand1.strength {
+        "hand2"
+    } else {
+        "tie"
+    };
+    Ok(HttpResponse::Ok().json(Comparison {
+        winner: String::from(winner),
+        hand1: hand1,
+        hand2: hand2,
+    }))
+}
+
+fn evaluate(cards: &str) -> Result<HandStrength, ApiError> {
+    let cards = parse_hand(cards)?;
+    // The hand table panics if it can't be loaded, which is our fault rather
+    // than the client's
+    let strength = panic::catch_unwind(|| HAND_STRENGTHS.hand_strength(&cards))
+        .map_err(|_| ApiError::Internal(String::from("hand table unavailable")))?;
+    Ok(HandStrength {
+        strength: strength,
+        category: format!("{:?}", category_from_strength(strength)),
+        hand: cards2str(&cards),
+    })
+}
+
+fn parse_hand(cards: &str) -> Result<Vec<Card>, ApiError> {
+    let cards = str2cards(cards)?;
+    if cards.len() < 5 || cards.len() > 7 {
+        return Err(ApiError::WrongNumberOfCards(cards.len()));
+    }
+    for (i, card) in cards.iter().enumerate() {
+        if cards[..i].contains(card) {
+            return Err(ApiError::DuplicateCard(card.to_string()));
+        }
+    }
+    Ok(cards)
+}
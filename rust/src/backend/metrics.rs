@@ -0,0 +1,207 @@
+// Prometheus metrics for the server, scraped from GET /metrics. Requests are
+// counted and timed by the RecordMetrics middleware on each route registered
+// with resource(), so the handlers don't need to know about any of this. The
+// rest are read from counters kept elsewhere whenever /metrics is scraped.
+use super::session::SessionStore;
+use crate::bot::blueprint_fallback_count;
+use crate::card_utils::equity_table_lookup_count;
+use actix_service::ServiceFactory;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{web, Error, HttpResponse, Resource};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+pub struct MetricsRegistry {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    blueprint_fallbacks: IntCounter,
+    active_sessions: IntGauge,
+    equity_table_lookups: IntCounter,
+}
+
+impl MetricsRegistry {
+    pub fn new() -> MetricsRegistry {
+        let requests = IntCounterVec::new(
+            Opts::new("poker_requests_total", "HTTP requests handled"),
+            &["endpoint", "status"],
+        )
+        .unwrap();
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "poker_request_duration_seconds",
+                "Time taken to handle HTTP requests",
+            ),
+            &["endpoint"],
+        )
+        .unwrap();
+        let blueprint_fallbacks = IntCounter::new(
+            "poker_blueprint_fallbacks_total",
+            "Bot decisions made without the infoset in the blueprint",
+        )
+        .unwrap();
+        let active_sessions =
+            IntGauge::new("poker_active_sessions", "Running HTTP game sessions").unwrap();
+        let equity_table_lookups = IntCounter::new(
+            "poker_equity_table_lookups_total",
+            "River equities looked up in the equity table",
+        )
+        .unwrap();
+
+        let registry = Registry::new();
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry
+            .register(Box::new(request_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(blueprint_fallbacks.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(active_sessions.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(equity_table_lookups.clone()))
+            .unwrap();
+        MetricsRegistry {
+            registry: registry,
+            requests: requests,
+            request_duration: request_duration,
+            blueprint_fallbacks: blueprint_fallbacks,
+            active_sessions: active_sessions,
+            equity_table_lookups: equity_table_lookups,
+        }
+    }
+
+    pub fn observe<B>(&self, endpoint: &str, response: &ServiceResponse<B>, seconds: f64) {
+        let status = response.status().as_u16().to_string();
+        self.requests.with_label_values(&[endpoint, &status]).inc();
+        self.request_duration
+            .with_label_values(&[endpoint])
+            .observe(seconds);
+    }
+
+    // Everything in the Prometheus text format
+    pub fn render(&self, sessions: &SessionStore) -> String {
+        sessions.remove_expired();
+        self.active_sessions.set(sessions.len() as i64);
+        // The counters only go up, so catch up with the totals kept elsewhere
+        let fallbacks = blueprint_fallback_count();
+        self.blueprint_fallbacks
+            .inc_by(fallbacks.saturating_sub(self.blueprint_fallbacks.get()));
+        let lookups = equity_table_lookup_count();
+        self.equity_table_lookups
+            .inc_by(lookups.saturating_sub(self.equity_table_lookups.get()));
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+pub async fn metrics(
+    metrics: web::Data<MetricsRegistry>,
+    sessions: web::Data<SessionStore>,
+) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics.render(&sessions))
+}
+
+// A resource for the given route whose requests are counted and timed.
+// Endpoints are labelled with the route, so each session doesn't get its own
+// series. Route parameters are written ":id" rather than "{id}", since some
+// parsers (prometheus-parse included) give up on label values with braces in
+// them.
+pub fn resource(
+    path: &str,
+    metrics: &web::Data<MetricsRegistry>,
+) -> Resource<
+    impl ServiceFactory<
+        Config = (),
+        Request = ServiceRequest,
+        Response = ServiceResponse,
+        Error = Error,
+        InitError = (),
+    >,
+> {
+    let endpoint = path.replace('{', ":").replace('}', "");
+    web::resource(path).wrap(RecordMetrics::new(metrics.clone(), &endpoint))
+}
+
+// Middleware that counts and times every request under the given endpoint
+pub struct RecordMetrics {
+    metrics: web::Data<MetricsRegistry>,
+    endpoint: String,
+}
+
+impl RecordMetrics {
+    pub fn new(metrics: web::Data<MetricsRegistry>, endpoint: &str) -> RecordMetrics {
+        RecordMetrics {
+            metrics: metrics,
+            endpoint: String::from(endpoint),
+        }
+    }
+}
+
+impl<S, B> Transform<S> for RecordMetrics
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RecordMetricsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RecordMetricsMiddleware {
+            service: service,
+            metrics: self.metrics.clone(),
+            endpoint: self.endpoint.clone(),
+        })
+    }
+}
+
+pub struct RecordMetricsMiddleware<S> {
+    service: S,
+    metrics: web::Data<MetricsRegistry>,
+    endpoint: String,
+}
+
+impl<S, B> Service for RecordMetricsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        let metrics = self.metrics.clone();
+        let endpoint = self.endpoint.clone();
+        let response = self.service.call(request);
+        Box::pin(async move {
+            let response = response.await?;
+            metrics.observe(&endpoint, &response, start.elapsed().as_secs_f64());
+            Ok(response)
+        })
+    }
+}
@@ -0,0 +1,205 @@
+// Game sessions over plain HTTP, for clients that can't keep a websocket open.
+// Each session wraps the same GameState as the websocket at /ws, and the
+// request bodies are the same messages minus the "type" field:
+//
+//      POST   /session                 start a session, returns {"id": "..."}
+//      POST   /session/{id}/deal       {"hand": ["Ah", "Kd"]} and/or {"board": ["2c", "7d", "Th"]}
+//      POST   /session/{id}/action     {"action": "bet", "amount": 50}
+//      GET    /session/{id}/state
+//      DELETE /session/{id}
+//
+// deal and action answer with the same list of messages the websocket would
+// send, ie an optional bot action followed by the state. Sessions that go
+// the server's session timeout (SESSION_TIMEOUT unless configured otherwise)
+// without a request are dropped, by a timer that checks every EXPIRY_INTERVAL.
+use super::{ClientMessage, GameState, ServerMessage};
+use actix_web::{web, HttpResponse};
+use dashmap::DashMap;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+pub const SESSION_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+pub const EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
+pub struct GameSession {
+    pub id: Uuid,
+    state: GameState,
+    last_active: Instant,
+}
+
+// All the running sessions. The server shares one store between its workers
+// through web::Data, so tests can make their own with a shorter timeout.
+pub struct SessionStore {
+    sessions: DashMap<Uuid, GameSession>,
+    timeout: Duration,
+}
+
+impl SessionStore {
+    pub fn new(timeout: Duration) -> SessionStore {
+        SessionStore {
+            sessions: DashMap::new(),
+            timeout: timeout,
+        }
+    }
+
+    pub fn create(&self) -> Uuid {
+        let id = Uuid::new_v4();
+        let session = GameSession {
+            id: id,
+            state: GameState::new(),
+            last_active: Instant::now(),
+        };
+        self.sessions.insert(id, session);
+        id
+    }
+
+    // Runs f on the session's game if the session exists and hasn't expired,
+    // and marks it as active. The session stays locked while f runs, so two
+    // requests to it can't overwrite each other's changes, and f works on a
+    // copy that's only saved if it succeeds, so a request that fails partway
+    // leaves the game as it was.
+    fn with_session<T>(
+        &self,
+        id: &str,
+        f: impl FnOnce(&mut GameState) -> Result<T, String>,
+    ) -> Option<Result<T, String>> {
+        let id = Uuid::parse_str(id).ok()?;
+        let mut session = self.sessions.get_mut(&id)?;
+        if session.last_active.elapsed() >= self.timeout {
+            return None;
+        }
+        let mut state = session.state.clone();
+        let result = f(&mut state);
+        if result.is_ok() {
+            session.state = state;
+        }
+        session.last_active = Instant::now();
+        Some(result)
+    }
+
+    pub fn remove(&self, id: &str) -> bool {
+        match Uuid::parse_str(id) {
+            Ok(id) => self.sessions.remove(&id).is_some(),
+            Err(_) => false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn remove_expired(&self) {
+        let timeout = self.timeout;
+        self.sessions
+            .retain(|_, session| session.last_active.elapsed() < timeout);
+    }
+}
+
+// Drops the expired sessions every EXPIRY_INTERVAL for as long as the server
+// runs
+pub fn expire_sessions(store: web::Data<SessionStore>) {
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(EXPIRY_INTERVAL);
+        loop {
+            interval.tick().await;
+            store.remove_expired();
+        }
+    });
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct DealRequest {
+    #[serde(default)]
+    hand: Vec<String>,
+    #[serde(default)]
+    board: Vec<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ActionRequest {
+    action: String,
+    #[serde(default)]
+    amount: i32,
+}
+
+pub async fn create_session(store: web::Data<SessionStore>) -> HttpResponse {
+    let id = store.create();
+    HttpResponse::Ok().json(serde_json::json!({ "id": id.to_string() }))
+}
+
+pub async fn deal(
+    store: web::Data<SessionStore>,
+    id: web::Path<String>,
+    body: web::Json<DealRequest>,
+) -> HttpResponse {
+    let body = body.into_inner();
+    let mut messages = Vec::new();
+    if !body.hand.is_empty() {
+        messages.push(ClientMessage::Deal { hand: body.hand });
+    }
+    if !body.board.is_empty() {
+        messages.push(ClientMessage::Board { cards: body.board });
+    }
+    if messages.is_empty() {
+        return HttpResponse::BadRequest().json(ServerMessage::Error {
+            message: String::from("Nothing to deal"),
+        });
+    }
+    handle_messages(&store, &id, messages)
+}
+
+pub async fn action(
+    store: web::Data<SessionStore>,
+    id: web::Path<String>,
+    body: web::Json<ActionRequest>,
+) -> HttpResponse {
+    let body = body.into_inner();
+    let message = ClientMessage::Action {
+        action: body.action,
+        amount: body.amount,
+    };
+    handle_messages(&store, &id, vec![message])
+}
+
+pub async fn state(store: web::Data<SessionStore>, id: web::Path<String>) -> HttpResponse {
+    match store.with_session(&id, |state| Ok(state.state())) {
+        Some(Ok(state)) => HttpResponse::Ok().json(state),
+        Some(Err(message)) => {
+            HttpResponse::BadRequest().json(ServerMessage::Error { message: message })
+        }
+        None => not_found(&id),
+    }
+}
+
+pub async fn end_session(store: web::Data<SessionStore>, id: web::Path<String>) -> HttpResponse {
+    if store.remove(&id) {
+        HttpResponse::NoContent().finish()
+    } else {
+        not_found(&id)
+    }
+}
+
+// Feeds the messages to the session's game in order, stopping at the first
+// one that's rejected. The game is only changed if they're all accepted.
+fn handle_messages(store: &SessionStore, id: &str, messages: Vec<ClientMessage>) -> HttpResponse {
+    let replies = store.with_session(id, |state| {
+        let mut replies = Vec::new();
+        for message in messages {
+            replies.extend(state.handle(message)?);
+        }
+        Ok(replies)
+    });
+    match replies {
+        Some(Ok(replies)) => HttpResponse::Ok().json(replies),
+        Some(Err(message)) => {
+            HttpResponse::BadRequest().json(ServerMessage::Error { message: message })
+        }
+        None => not_found(id),
+    }
+}
+
+fn not_found(id: &str) -> HttpResponse {
+    HttpResponse::NotFound().json(ServerMessage::Error {
+        message: format!("No session with id {}", id),
+    })
+}
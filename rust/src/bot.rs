@@ -1,25 +1,199 @@
-// Real-time bot logic. Right now this just does action translation, but this
-// is where I will add depth-limited solving.
+// Real-time bot logic. bot_action() does action translation against the
+// blueprint, and bot_action_dls() refines it with depth-limited solving.
+use crate::card_utils;
 use crate::card_utils::Card;
+use crate::hand_reading::{RangeEstimator, WeightedRange};
 use crate::trainer_utils::*;
+use rand::prelude::SliceRandom;
+use rand::thread_rng;
+use rand::Rng;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+// If depth-limited solving takes longer than this, we give up and fall back
+// to the blueprint action.
+pub const DLS_TIME_BUDGET_MS: u128 = 200;
+
+// Maximum number of bets and raises in the river subgame, after which players
+// can only call or fold. This keeps the river game tree small enough to solve
+// in real time.
+const RIVER_MAX_BETS: usize = 3;
+
+// Number of opponent hands and runouts that action_evs() averages over
+const EV_SAMPLES: usize = 500;
 
 lazy_static! {
-    static ref BLUEPRINT: HashMap<CompactInfoSet, Action> = crate::trainer::load_blueprint();
+    static ref BLUEPRINT: CompressedStrategy = crate::trainer::load_blueprint();
+}
+
+// How many times the bot has looked up an infoset that isn't in the blueprint
+static BLUEPRINT_FALLBACKS: AtomicU64 = AtomicU64::new(0);
+
+pub fn blueprint_fallback_count() -> u64 {
+    BLUEPRINT_FALLBACKS.load(Ordering::Relaxed)
+}
+
+// Loads the blueprint now rather than on the first bot action
+pub fn init_blueprint() {
+    lazy_static::initialize(&BLUEPRINT);
 }
 
 pub fn bot_action(hand: &[Card], board: &[Card], history: &ActionHistory) -> Action {
-    let translated = history.translate(&BET_ABSTRACTION.to_vec());
+    bot_action_safe(hand, board, history).0
+}
+
+// Like bot_action(), but also says whether the infoset was missing from the
+// blueprint, in which case the action is picked uniformly at random.
+pub fn bot_action_safe(hand: &[Card], board: &[Card], history: &ActionHistory) -> (Action, bool) {
+    bot_action_with_blueprint(&BLUEPRINT, hand, board, history)
+}
+
+pub fn bot_action_with_blueprint(
+    blueprint: &CompressedStrategy,
+    hand: &[Card],
+    board: &[Card],
+    history: &ActionHistory,
+) -> (Action, bool) {
     let hand = [hand, board].concat();
-    let infoset = InfoSet::from_hand(&hand, &translated).compress();
-
-    let mut action = {
-        // Action translation
-        BLUEPRINT
-            .get(&infoset)
-            .expect("Infoset not found in blueprint")
-            .clone()
-    };
+    let (strategy, fallback) = blueprint_strategy_or_uniform(blueprint, &hand, history);
+    if fallback {
+        return (sample_action_with_rng(&strategy, &mut thread_rng()), true);
+    }
+    let action = legalize(most_likely_action(&strategy), history);
+    let legal = history.legal_actions();
+    if legal.contains(&action) {
+        (action, false)
+    } else {
+        (closest_legal_action(&action, &legal), false)
+    }
+}
+
+// The blueprint's probability of taking each legal action. Blueprint actions
+// that translate to the same legal action have their probabilities added up,
+// and actions the blueprint never takes are left out.
+pub fn action_distribution(
+    hand: &[Card],
+    board: &[Card],
+    history: &ActionHistory,
+) -> HashMap<Action, f64> {
+    action_distribution_with(&BLUEPRINT, hand, board, history)
+}
+
+pub fn action_distribution_with(
+    blueprint: &CompressedStrategy,
+    hand: &[Card],
+    board: &[Card],
+    history: &ActionHistory,
+) -> HashMap<Action, f64> {
+    let hand = [hand, board].concat();
+    let (strategy, _) = blueprint_strategy_or_uniform(blueprint, &hand, history);
+    legal_distribution(&strategy, history)
+}
+
+// Plays a random action from the blueprint's distribution, instead of always
+// taking the most likely one like bot_action() does.
+pub fn sample_action(
+    hand: &[Card],
+    board: &[Card],
+    history: &ActionHistory,
+    rng: &mut impl Rng,
+) -> Action {
+    sample_action_with(&BLUEPRINT, hand, board, history, rng)
+}
+
+pub fn sample_action_with(
+    blueprint: &CompressedStrategy,
+    hand: &[Card],
+    board: &[Card],
+    history: &ActionHistory,
+    rng: &mut impl Rng,
+) -> Action {
+    sample_action_with_rng(
+        &action_distribution_with(blueprint, hand, board, history),
+        rng,
+    )
+}
+
+// Moves a strategy over the translated history's actions onto the real
+// history's legal actions.
+pub fn legal_distribution(
+    strategy: &HashMap<Action, f64>,
+    history: &ActionHistory,
+) -> HashMap<Action, f64> {
+    let legal = history.legal_actions();
+    let mut distribution = HashMap::new();
+    for (action, prob) in strategy {
+        if *prob <= 0.0 {
+            continue;
+        }
+        let mut action = legalize(action.clone(), history);
+        if !legal.contains(&action) {
+            action = closest_legal_action(&action, &legal);
+        }
+        *distribution.entry(action).or_insert(0.0) += prob;
+    }
+    normalize(&distribution)
+}
+
+// Falls back to the legal action of the same type that's closest in size, or
+// to checking/calling if there isn't one (eg folding when we could check).
+fn closest_legal_action(action: &Action, legal: &[Action]) -> Action {
+    legal
+        .iter()
+        .filter(|a| a.action == action.action)
+        .min_by_key(|a| (a.amount - action.amount).abs())
+        .or_else(|| legal.iter().find(|a| a.action == ActionType::Call))
+        .unwrap()
+        .clone()
+}
+
+// Looks up the blueprint strategy for the given cards (hole cards followed by
+// the board) after translating the history into the bet abstraction.
+fn blueprint_strategy(cards: &[Card], history: &ActionHistory) -> Option<HashMap<Action, f64>> {
+    blueprint_strategy_in(&BLUEPRINT, cards, history)
+}
+
+fn blueprint_strategy_in(
+    blueprint: &CompressedStrategy,
+    cards: &[Card],
+    history: &ActionHistory,
+) -> Option<HashMap<Action, f64>> {
+    let translated = history.translate(bet_abstraction());
+    let infoset = InfoSet::from_hand(&cards, &translated);
+    let probs = blueprint.get(&infoset.compress())?;
+    let strategy = infoset
+        .next_actions()
+        .into_iter()
+        .zip(dequantize_probs(probs))
+        .collect();
+    Some(strategy)
+}
+
+// The blueprint strategy, or a uniform one over the legal actions if the
+// infoset isn't in the blueprint (eg the abstraction changed since it was
+// trained, or training never got there). The bool is true for the fallback.
+fn blueprint_strategy_or_uniform(
+    blueprint: &CompressedStrategy,
+    cards: &[Card],
+    history: &ActionHistory,
+) -> (HashMap<Action, f64>, bool) {
+    if let Some(strategy) = blueprint_strategy_in(blueprint, cards, history) {
+        return (strategy, false);
+    }
+    BLUEPRINT_FALLBACKS.fetch_add(1, Ordering::Relaxed);
+    eprintln!(
+        "Warning: infoset not found in blueprint for {} with history {}, playing uniformly",
+        card_utils::cards2str(cards),
+        history
+    );
+    let legal = history.legal_actions();
+    let prob = 1.0 / legal.len() as f64;
+    (legal.into_iter().map(|a| (a, prob)).collect(), true)
+}
+
+fn legalize(action: Action, history: &ActionHistory) -> Action {
+    let mut action = action;
     // The translated action is based off a misunderstanding off the true bet
     // sizes, so we may have to adjust our call amount to line up with what's
     // actually in the pot as opposed to our approximation.
@@ -30,9 +204,638 @@ pub fn bot_action(hand: &[Card], board: &[Card], history: &ActionHistory) -> Act
     } else if action.action == ActionType::Bet && action.amount < history.min_bet() {
         action.amount = history.min_bet();
     }
+    if action.action == ActionType::Bet {
+        // We can't bet more than we have left
+        action.amount = action.amount.min(history.stack_sizes()[history.player]);
+    }
     action
 }
 
+// Depth-limited solving: instead of playing the blueprint action directly, we
+// re-solve the game from the current spot with a few CFR iterations. The
+// subgame only looks `depth` streets ahead of the current one, and the value
+// of reaching a leaf is estimated by playing out the rest of the hand with the
+// blueprint strategy. The opponent's hole cards are sampled uniformly from the
+// cards we can't see.
+pub fn bot_action_dls(
+    hand: &[Card],
+    board: &[Card],
+    history: &ActionHistory,
+    depth: u32,
+    iterations: u32,
+) -> Action {
+    bot_action_dls_with(
+        &BLUEPRINT,
+        hand,
+        board,
+        history,
+        depth,
+        iterations,
+        DLS_TIME_BUDGET_MS,
+    )
+}
+
+// Same as bot_action_dls(), but with any blueprint, and giving up on solving
+// after time_budget_ms instead of DLS_TIME_BUDGET_MS
+pub fn bot_action_dls_with(
+    blueprint: &CompressedStrategy,
+    hand: &[Card],
+    board: &[Card],
+    history: &ActionHistory,
+    depth: u32,
+    iterations: u32,
+    time_budget_ms: u128,
+) -> Action {
+    let start = Instant::now();
+    let mut rng = thread_rng();
+    let bot = history.player;
+    let leaf_street = history.street + depth as usize;
+    let strategy =
+        |cards: &[Card], history: &ActionHistory| blueprint_strategy_in(blueprint, cards, history);
+    let mut nodes: HashMap<InfoSet, Node> = HashMap::new();
+    for _ in 0..iterations {
+        if start.elapsed().as_millis() >= time_budget_ms {
+            return bot_action_with_blueprint(blueprint, hand, board, history).0;
+        }
+        for player in &[DEALER, OPPONENT] {
+            let deck = deal_subgame(hand, board, bot, &mut rng);
+            dls_iterate(
+                *player,
+                &deck,
+                history.clone(),
+                [1.0, 1.0],
+                leaf_street,
+                &mut nodes,
+                &strategy,
+            );
+        }
+    }
+
+    let cards = [hand, board].concat();
+    let infoset = InfoSet::from_hand(&cards, history);
+    let strategy = match nodes.get(&infoset) {
+        Some(node) => node.cumulative_strategy(),
+        None => return bot_action_with_blueprint(blueprint, hand, board, history).0,
+    };
+    // The subgame's bets are sized off the abstraction's idea of the pot
+    sample_action_from_strategy(&legal_distribution(&strategy, history))
+}
+
+// Deals a deck in the same layout the trainer uses, with the bot's hole cards
+// and the known board cards in place and everything else random.
+fn deal_subgame(hand: &[Card], board: &[Card], bot: usize, rng: &mut impl Rng) -> Vec<Card> {
+    let mut rest = card_utils::deck();
+    rest.retain(|c| !hand.contains(c) && !board.contains(c));
+    rest.shuffle(rng);
+    let opp_hole = &rest[..2];
+    let runout = &rest[2..2 + 5 - board.len()];
+    let holes = match bot {
+        DEALER => [hand, opp_hole].concat(),
+        OPPONENT => [opp_hole, hand].concat(),
+        _ => panic!("Bad player ID"),
+    };
+    [&holes[..], board, runout].concat()
+}
+
+// Same traversal as trainer::iterate(), except that it stops at leaf_street
+// and keys nodes by the full infoset since the real history may contain
+// actions outside of the bet abstraction. Leaves are valued by playing the
+// rest of the hand out with strategy.
+fn dls_iterate(
+    player: usize,
+    deck: &[Card],
+    history: ActionHistory,
+    weights: [f64; 2],
+    leaf_street: usize,
+    nodes: &mut HashMap<InfoSet, Node>,
+    strategy: &impl Fn(&[Card], &ActionHistory) -> Option<HashMap<Action, f64>>,
+) -> f64 {
+    if history.hand_over() {
+        return terminal_utility(&deck, history, player);
+    }
+    if history.street > leaf_street {
+        return rollout(&deck, history, player, strategy);
+    }
+
+    let mut history = history.clone();
+    let mut infoset = InfoSet::from_deck(&deck, &history);
+    let mut node: Node = match nodes.get(&infoset) {
+        Some(n) => n.clone(),
+        None => Node::new(&infoset),
+    };
+
+    let opponent = 1 - player;
+    if history.player == opponent {
+        history.add(&sample_action_from_node(&node));
+        if history.hand_over() {
+            return terminal_utility(&deck, history, player);
+        }
+        if history.street > leaf_street {
+            return rollout(&deck, history, player, strategy);
+        }
+        infoset = InfoSet::from_deck(&deck, &history);
+        node = match nodes.get(&infoset) {
+            Some(n) => n.clone(),
+            None => Node::new(&infoset),
+        };
+    }
+
+    let [p0, p1] = weights;
+    let current = node.current_strategy(weights[player], CFRVariant::DCFR(DCFRConfig::default()));
+    let mut utilities: HashMap<Action, f64> = HashMap::new();
+    let mut node_utility = 0.0;
+    for (action, prob) in current {
+        let mut next_history = history.clone();
+        next_history.add(&action);
+        let new_weights = match player {
+            0 => [p0 * prob, p1],
+            1 => [p0, p1 * prob],
+            _ => panic!("Bad player value"),
+        };
+        let utility = dls_iterate(
+            player,
+            &deck,
+            next_history,
+            new_weights,
+            leaf_street,
+            nodes,
+            strategy,
+        );
+        utilities.insert(action, utility);
+        node_utility += prob * utility;
+    }
+
+    for (action, utility) in &utilities {
+        node.add_regret(
+            &action,
+            weights[opponent] * (utility - node_utility),
+            CFRVariant::DCFR(DCFRConfig::default()),
+        );
+    }
+    nodes.insert(infoset, node);
+    node_utility
+}
+
+// Plays out the rest of the hand with both players taking the most likely
+// action of the given strategy. Spots the strategy doesn't cover are played by
+// checking/calling.
+fn rollout(
+    deck: &[Card],
+    history: ActionHistory,
+    player: usize,
+    strategy: &impl Fn(&[Card], &ActionHistory) -> Option<HashMap<Action, f64>>,
+) -> f64 {
+    let mut history = history;
+    while !history.hand_over() {
+        let cards = get_hand(&deck, history.player, history.street);
+        let action = match strategy(&cards, &history) {
+            Some(s) => legalize(most_likely_action(&s), &history),
+            None => call(&history),
+        };
+        history.add(&action);
+    }
+    terminal_utility(&deck, history, player)
+}
+
+fn call(history: &ActionHistory) -> Action {
+    Action {
+        action: ActionType::Call,
+        amount: history.to_call(),
+    }
+}
+
+// The expected value of each legal action in big blinds, if the bot takes it
+// and both players follow the blueprint from then on. The opponent's hole
+// cards and the rest of the board are sampled EV_SAMPLES times.
+pub fn action_evs(hand: &[Card], board: &[Card], history: &ActionHistory) -> HashMap<Action, f64> {
+    action_evs_against(hand, board, history, EV_SAMPLES, &blueprint_strategy)
+}
+
+// Same as action_evs(), but with any strategy in place of the blueprint. The
+// opponent's response to each action is weighted by their strategy, and the
+// rest of the hand is played out with rollout().
+pub fn action_evs_against(
+    hand: &[Card],
+    board: &[Card],
+    history: &ActionHistory,
+    n_samples: usize,
+    strategy: &impl Fn(&[Card], &ActionHistory) -> Option<HashMap<Action, f64>>,
+) -> HashMap<Action, f64> {
+    let mut rng = thread_rng();
+    let bot = history.player;
+    let actions = history.legal_actions();
+    let mut evs: HashMap<Action, f64> = actions.iter().map(|a| (a.clone(), 0.0)).collect();
+    for _ in 0..n_samples {
+        let deck = deal_subgame(hand, board, bot, &mut rng);
+        for action in &actions {
+            let mut next_history = history.clone();
+            next_history.add(action);
+            let value = if next_history.hand_over() || next_history.player == bot {
+                rollout(&deck, next_history, bot, strategy)
+            } else {
+                let cards = get_hand(&deck, next_history.player, next_history.street);
+                let responses = match strategy(&cards, &next_history) {
+                    Some(s) => legal_distribution(&s, &next_history),
+                    None => [(call(&next_history), 1.0)].iter().cloned().collect(),
+                };
+                let mut value = 0.0;
+                for (response, prob) in responses {
+                    let mut response_history = next_history.clone();
+                    response_history.add(&response);
+                    value += prob * rollout(&deck, response_history, bot, strategy);
+                }
+                value
+            };
+            *evs.get_mut(action).unwrap() += value;
+        }
+    }
+    for ev in evs.values_mut() {
+        *ev /= (n_samples as f64) * (BIG_BLIND as f64);
+    }
+    evs
+}
+
+// Endgame solving on the river. Once all the board cards are out, we solve the
+// rest of the hand directly with CFR instead of relying on the blueprint. Both
+// players' ranges are read off the blueprint from the betting so far, so the
+// solution is an equilibrium of the river game as each player got there, and
+// the bot plays its real hand's part of it. Falls back to the blueprint before
+// the river.
+pub fn bot_action_subgame(
+    hand: &[Card],
+    board: &[Card],
+    history: &ActionHistory,
+    time_limit_ms: u64,
+) -> Action {
+    if board.len() != 5 {
+        return bot_action(hand, board, history);
+    }
+    let start = Instant::now();
+    let estimator = RangeEstimator::from_blueprint(&BLUEPRINT);
+    let bot_range = estimator.estimate_range(history, history.player, board, &[]);
+    let opp_range = estimator.estimate_range(history, 1 - history.player, board, &[]);
+    let elapsed_ms = start.elapsed().as_millis() as u64;
+    let strategy = solve_river_subgame(
+        hand,
+        board,
+        &bot_range,
+        &opp_range,
+        history,
+        time_limit_ms.saturating_sub(elapsed_ms),
+    );
+    sample_action_from_strategy(&legal_distribution(&strategy, history))
+}
+
+// Runs CFR on the river subgame for the player to act, with each player's
+// hands weighted by their range, until the time runs out. Returns the bot's
+// average strategy at the root for the hand it actually holds.
+pub fn solve_river_subgame(
+    hand: &[Card],
+    board: &[Card],
+    bot_range: &WeightedRange,
+    opp_range: &WeightedRange,
+    history: &ActionHistory,
+    time_limit_ms: u64,
+) -> HashMap<Action, f64> {
+    let start = Instant::now();
+    let subgame = RiverSubgame::new(
+        history.player,
+        RiverRange::new(bot_range, board, Some(hand)),
+        RiverRange::new(opp_range, board, None),
+    );
+    let hand_index = subgame.ranges[BOT_SIDE].index_of(hand).unwrap();
+    let mut root = subgame.build_tree(history.clone(), RIVER_MAX_BETS);
+    let reach = [
+        subgame.ranges[BOT_SIDE].weights.clone(),
+        subgame.ranges[OPP_SIDE].weights.clone(),
+    ];
+    let mut iteration = 0;
+    loop {
+        iteration += 1;
+        for side in &[BOT_SIDE, OPP_SIDE] {
+            subgame.cfr(&mut root, *side, &reach, iteration as f64);
+        }
+        if start.elapsed().as_millis() >= time_limit_ms as u128 {
+            break;
+        }
+    }
+    match root {
+        RiverNode::Decision {
+            actions,
+            regrets,
+            strategy_sum,
+            ..
+        } => {
+            // If the bot's range says it never gets here with this hand, the
+            // average strategy is empty, but its regrets still say how to play
+            let weights = if strategy_sum[hand_index].iter().sum::<f64>() > 0.0 {
+                strategy_sum[hand_index].clone()
+            } else {
+                regret_matching(&regrets[hand_index])
+            };
+            normalize(&actions.into_iter().zip(weights).collect())
+        }
+        RiverNode::Terminal { .. } => panic!("The hand is already over"),
+    }
+}
+
+// Which of the subgame's ranges a player's hands are in
+const BOT_SIDE: usize = 0;
+const OPP_SIDE: usize = 1;
+
+// Game tree for the river subgame. Each decision node has one set of regrets
+// for every hand in the acting player's range.
+enum RiverNode {
+    Terminal {
+        history: ActionHistory,
+    },
+    Decision {
+        player: usize,
+        actions: Vec<Action>,
+        children: Vec<RiverNode>,
+        regrets: Vec<Vec<f64>>,
+        strategy_sum: Vec<Vec<f64>>,
+    },
+}
+
+// One player's hands in the river subgame, with their strengths on the board
+// and how likely the betting so far makes them
+struct RiverRange {
+    combos: Vec<[Card; 2]>,
+    strengths: Vec<i32>,
+    weights: Vec<f64>,
+    // Indices of the combos from weakest to strongest
+    order: Vec<usize>,
+}
+
+impl RiverRange {
+    // Leaves out the combos the player never holds here, except for keep,
+    // which the solver still has to find a strategy for
+    fn new(range: &WeightedRange, board: &[Card], keep: Option<&[Card]>) -> RiverRange {
+        let is_kept = |combo: &[Card; 2]| match keep {
+            Some(hand) => hand.contains(&combo[0]) && hand.contains(&combo[1]),
+            None => false,
+        };
+        let (combos, weights): (Vec<[Card; 2]>, Vec<f64>) = range
+            .combos()
+            .iter()
+            .filter(|(combo, weight)| *weight > 0.0 || is_kept(combo))
+            .cloned()
+            .unzip();
+        let strengths: Vec<i32> = combos
+            .iter()
+            .map(|combo| HAND_TABLE.hand_strength(&[&combo[..], board].concat()))
+            .collect();
+        let mut order: Vec<usize> = (0..combos.len()).collect();
+        order.sort_by_key(|i| strengths[*i]);
+        RiverRange {
+            combos: combos,
+            strengths: strengths,
+            weights: weights,
+            order: order,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.combos.len()
+    }
+
+    fn index_of(&self, hand: &[Card]) -> Option<usize> {
+        self.combos
+            .iter()
+            .position(|combo| hand.contains(&combo[0]) && hand.contains(&combo[1]))
+    }
+}
+
+fn card_index(card: &Card) -> usize {
+    (card.rank as usize - 2) * 4 + card.suit as usize
+}
+
+struct RiverSubgame {
+    bot: usize,
+    ranges: [RiverRange; 2],
+    // For each side's combos, the index of the same combo in the other side's
+    // range, if it's there
+    same_combo: [Vec<Option<usize>>; 2],
+}
+
+impl RiverSubgame {
+    fn new(bot: usize, bot_range: RiverRange, opp_range: RiverRange) -> RiverSubgame {
+        let same_combo = |from: &RiverRange, to: &RiverRange| -> Vec<Option<usize>> {
+            from.combos.iter().map(|combo| to.index_of(combo)).collect()
+        };
+        RiverSubgame {
+            bot: bot,
+            same_combo: [
+                same_combo(&bot_range, &opp_range),
+                same_combo(&opp_range, &bot_range),
+            ],
+            ranges: [bot_range, opp_range],
+        }
+    }
+
+    fn side(&self, player: usize) -> usize {
+        if player == self.bot {
+            BOT_SIDE
+        } else {
+            OPP_SIDE
+        }
+    }
+
+    fn build_tree(&self, history: ActionHistory, bets_left: usize) -> RiverNode {
+        if history.hand_over() {
+            return RiverNode::Terminal { history: history };
+        }
+        let mut actions = history.next_actions(bet_abstraction());
+        if bets_left == 0 {
+            actions.retain(|a| a.action != ActionType::Bet);
+        }
+        let children = actions
+            .iter()
+            .map(|action| {
+                let mut next_history = history.clone();
+                next_history.add(action);
+                let next_bets_left = match action.action {
+                    ActionType::Bet => bets_left - 1,
+                    _ => bets_left,
+                };
+                self.build_tree(next_history, next_bets_left)
+            })
+            .collect();
+        let n_infosets = self.ranges[self.side(history.player)].len();
+        RiverNode::Decision {
+            player: history.player,
+            regrets: vec![vec![0.0; actions.len()]; n_infosets],
+            strategy_sum: vec![vec![0.0; actions.len()]; n_infosets],
+            actions: actions,
+            children: children,
+        }
+    }
+
+    // The payoff of a terminal history to each of side's hands, summed over
+    // the other player's hands that don't share a card with it and weighted by
+    // how likely the other player is to get here with them. Both ranges are
+    // swept in order of strength, and the other player's hands that share a
+    // card are taken back out with per-card sums, so this is linear in the
+    // size of the ranges.
+    fn terminal_values(
+        &self,
+        history: &ActionHistory,
+        side: usize,
+        other_reach: &[f64],
+    ) -> Vec<f64> {
+        let mine = &self.ranges[side];
+        let other = &self.ranges[1 - side];
+        let blocked = |sums: &[f64; 52], combo: &[Card; 2]| {
+            sums[card_index(&combo[0])] + sums[card_index(&combo[1])]
+        };
+
+        if history.last_action().unwrap().action == ActionType::Fold {
+            // Folding doesn't depend on the cards, so the deck isn't needed
+            let player = if side == BOT_SIDE {
+                self.bot
+            } else {
+                1 - self.bot
+            };
+            let util = terminal_utility(&[], history.clone(), player);
+            let mut total = 0.0;
+            let mut sums = [0.0; 52];
+            for (combo, reach) in other.combos.iter().zip(other_reach) {
+                total += reach;
+                sums[card_index(&combo[0])] += reach;
+                sums[card_index(&combo[1])] += reach;
+            }
+            return mine
+                .combos
+                .iter()
+                .enumerate()
+                .map(|(i, combo)| {
+                    // The same combo was taken out once for each of its cards
+                    let same = self.same_combo[side][i].map_or(0.0, |j| other_reach[j]);
+                    util * (total - blocked(&sums, combo) + same)
+                })
+                .collect();
+        }
+
+        // A hand and the same combo in the other range always tie, so they
+        // never land in each other's sums
+        let half_pot = (history.pot() / 2) as f64;
+        let mut values = vec![0.0; mine.len()];
+        let sweep = |mine_order: &mut dyn Iterator<Item = &usize>,
+                     other_order: &mut dyn Iterator<Item = &usize>,
+                     beats: &dyn Fn(i32, i32) -> bool,
+                     payoff: f64,
+                     values: &mut [f64]| {
+            let mut other_order = other_order.peekable();
+            let mut total = 0.0;
+            let mut sums = [0.0; 52];
+            for &i in mine_order {
+                while let Some(&&j) = other_order.peek() {
+                    if !beats(mine.strengths[i], other.strengths[j]) {
+                        break;
+                    }
+                    total += other_reach[j];
+                    sums[card_index(&other.combos[j][0])] += other_reach[j];
+                    sums[card_index(&other.combos[j][1])] += other_reach[j];
+                    other_order.next();
+                }
+                values[i] += payoff * (total - blocked(&sums, &mine.combos[i]));
+            }
+        };
+        // The hands each hand beats, then the hands it loses to
+        sweep(
+            &mut mine.order.iter(),
+            &mut other.order.iter(),
+            &|a, b| a > b,
+            half_pot,
+            &mut values,
+        );
+        sweep(
+            &mut mine.order.iter().rev(),
+            &mut other.order.iter().rev(),
+            &|a, b| a < b,
+            -half_pot,
+            &mut values,
+        );
+        values
+    }
+
+    // One iteration of CFR over the whole subgame, updating side's regrets.
+    // reach has each player's probability of getting here with each of their
+    // hands. Regrets and the average strategy are discounted like DCFR, so the
+    // uniform strategy of the first few iterations is soon forgotten. Returns
+    // the counterfactual value of each of side's hands.
+    fn cfr(
+        &self,
+        node: &mut RiverNode,
+        side: usize,
+        reach: &[Vec<f64>; 2],
+        iteration: f64,
+    ) -> Vec<f64> {
+        let (player, children, regrets, strategy_sum) = match node {
+            RiverNode::Terminal { history } => {
+                return self.terminal_values(history, side, &reach[1 - side])
+            }
+            RiverNode::Decision {
+                player,
+                children,
+                regrets,
+                strategy_sum,
+                ..
+            } => (*player, children, regrets, strategy_sum),
+        };
+        let acting = self.side(player);
+        let strategies: Vec<Vec<f64>> = regrets.iter().map(|r| regret_matching(r)).collect();
+        let mut values = vec![0.0; self.ranges[side].len()];
+
+        if acting == side {
+            let dcfr = DCFRConfig::default();
+            let t = iteration;
+            let mut action_values = Vec::new();
+            for (a, child) in children.iter_mut().enumerate() {
+                let child_values = self.cfr(child, side, reach, iteration);
+                for (i, value) in values.iter_mut().enumerate() {
+                    *value += strategies[i][a] * child_values[i];
+                }
+                action_values.push(child_values);
+            }
+            for (i, value) in values.iter().enumerate() {
+                for a in 0..children.len() {
+                    let regret = regrets[i][a] + action_values[a][i] - value;
+                    let exponent = if regret >= 0.0 { dcfr.alpha } else { dcfr.beta };
+                    regrets[i][a] = regret * t.powf(exponent) / (t.powf(exponent) + 1.0);
+                    strategy_sum[i][a] = (strategy_sum[i][a] + reach[side][i] * strategies[i][a])
+                        * (t / (t + 1.0)).powf(dcfr.gamma);
+                }
+            }
+        } else {
+            for (a, child) in children.iter_mut().enumerate() {
+                let mut child_reach = reach.clone();
+                child_reach[acting] = reach[acting]
+                    .iter()
+                    .zip(&strategies)
+                    .map(|(r, s)| r * s[a])
+                    .collect();
+                let child_values = self.cfr(child, side, &child_reach, iteration);
+                for (value, child_value) in values.iter_mut().zip(&child_values) {
+                    *value += child_value;
+                }
+            }
+        }
+        values
+    }
+}
+
+fn regret_matching(regrets: &[f64]) -> Vec<f64> {
+    let positive: Vec<f64> = regrets.iter().map(|r| r.max(0.0)).collect();
+    let sum: f64 = positive.iter().sum();
+    if sum > 0.0 {
+        positive.iter().map(|r| r / sum).collect()
+    } else {
+        vec![1.0 / regrets.len() as f64; regrets.len()]
+    }
+}
+
 // After an opponent makes an off-tree action, we need to solve a new subgame
 // starting from that action.
 // Solves a subgame starting with their move, but including their action in the
@@ -6,26 +6,36 @@
 
 use crate::card_utils;
 use crate::card_utils::{Card, HandData};
+use crate::config::config;
+use crate::itertools::Itertools;
+use rand::prelude::SliceRandom;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use rayon::iter::IntoParallelRefIterator;
 use rayon::iter::ParallelIterator;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::fmt;
 use std::fs;
 use std::fs::{File, OpenOptions};
 use std::io::ErrorKind;
-use std::io::{BufRead, BufReader, Read, Write};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, Write};
 
-const FLOP_PATH: &str = "products/flop_abstraction.txt";
-const TURN_PATH: &str = "products/turn_abstraction.txt";
-const RIVER_PATH: &str = "products/river_abstraction.txt";
-const RIVER_SORTED_DIR: &str = "products/river_sorted_ehs2";
+// Number of hands between checkpoints of the turn equity distributions
+const TURN_EQUITY_CHECKPOINT: usize = 500_000;
 
-const N_FLOP_CANONICAL: i32 = 1_342_562;
+pub const N_FLOP_CANONICAL: i32 = 1_342_562;
 const N_TURN_CANONICAL: i32 = 14_403_610;
-const N_RIVER_CANONICAL: i32 = 125_756_657;
+pub const N_RIVER_CANONICAL: i32 = 125_756_657;
 
-const FLOP_BUCKETS: i32 = 1000;
-const TURN_BUCKETS: i32 = 1000;
-const RIVER_BUCKETS: i32 = 1000;
+// Number of equity bins in each half of a potential-aware distribution
+pub const EQUITY_BINS: usize = 50;
+
+// One preflop bucket per entry in the 13x13 starting hand matrix
+pub const PREFLOP_BUCKETS: i32 = 169;
+pub const FLOP_BUCKETS: i32 = 1000;
+pub const TURN_BUCKETS: i32 = 1000;
+pub const RIVER_BUCKETS: i32 = 1000;
 
 pub struct Abstraction {
     flop: HandData,
@@ -33,12 +43,100 @@ pub struct Abstraction {
     river: HandData,
 }
 
+// Something wrong with one of the abstraction tables, as found by
+// Abstraction::validate(). Hands are written out as card strings.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AbstractionError {
+    WrongNumberOfCards {
+        street: &'static str,
+        hand: String,
+    },
+    BinOutOfRange {
+        street: &'static str,
+        hand: String,
+        bin: i32,
+    },
+    // A hand that isn't in canonical form would never be looked up, and it's
+    // isomorphic to some other hand that should be in the table instead
+    NotCanonical {
+        street: &'static str,
+        hand: String,
+    },
+    WrongCount {
+        street: &'static str,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for AbstractionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AbstractionError::WrongNumberOfCards { street, hand } => {
+                write!(f, "{} hand {} has the wrong number of cards", street, hand)
+            }
+            AbstractionError::BinOutOfRange { street, hand, bin } => {
+                write!(
+                    f,
+                    "{} hand {} is in bin {}, out of range",
+                    street, hand, bin
+                )
+            }
+            AbstractionError::NotCanonical { street, hand } => {
+                write!(f, "{} hand {} isn't canonical", street, hand)
+            }
+            AbstractionError::WrongCount {
+                street,
+                expected,
+                found,
+            } => write!(f, "expected {} {} hands, found {}", expected, street, found),
+        }
+    }
+}
+
+impl std::error::Error for AbstractionError {}
+
 impl Abstraction {
     pub fn new() -> Abstraction {
-        Abstraction {
-            flop: load_abstraction(FLOP_PATH, 5, FLOP_BUCKETS),
-            turn: load_abstraction(TURN_PATH, 6, TURN_BUCKETS),
-            river: load_abstraction(RIVER_PATH, 7, RIVER_BUCKETS),
+        let paths = &config().paths;
+        let abstraction = Abstraction {
+            flop: load_abstraction(&paths.flop_abstraction, 5, FLOP_BUCKETS),
+            turn: load_abstraction(&paths.turn_abstraction, 6, TURN_BUCKETS),
+            river: load_abstraction(&paths.river_abstraction, 7, RIVER_BUCKETS),
+        };
+        #[cfg(debug_assertions)]
+        {
+            if let Err(e) = abstraction.validate() {
+                panic!("The card abstraction is invalid: {}", e);
+            }
+        }
+        abstraction
+    }
+
+    // Checks that each table has exactly one entry for every canonical hand of
+    // its street, each in a valid bin
+    pub fn validate(&self) -> Result<(), AbstractionError> {
+        let flop_count = N_FLOP_CANONICAL as usize;
+        let turn_count = N_TURN_CANONICAL as usize;
+        let river_count = N_RIVER_CANONICAL as usize;
+        validate_table("flop", &self.flop, 5, FLOP_BUCKETS, flop_count)?;
+        validate_table("turn", &self.turn, 6, TURN_BUCKETS, turn_count)?;
+        validate_table("river", &self.river, 7, RIVER_BUCKETS, river_count)
+    }
+
+    // How evenly the flop, turn and river hands are spread over their bins
+    pub fn stats(&self) -> [AbstractionStats; 3] {
+        [
+            AbstractionStats::from_table(&self.flop, FLOP_BUCKETS),
+            AbstractionStats::from_table(&self.turn, TURN_BUCKETS),
+            AbstractionStats::from_table(&self.river, RIVER_BUCKETS),
+        ]
+    }
+
+    pub fn print_histogram(&self) {
+        for (street, stats) in ["Flop", "Turn", "River"].iter().zip(self.stats().iter()) {
+            println!("{}: {}", street, stats);
+            println!("{}", stats.histogram(HISTOGRAM_ROWS, HISTOGRAM_WIDTH));
         }
     }
 
@@ -51,36 +149,336 @@ impl Abstraction {
     }
 
     fn preflop_bin(cards: &[Card]) -> i32 {
-        let mut cards = cards.to_vec();
-        cards.sort_by_key(|c| c.rank);
-        let rank1 = cards[0].rank;
-        let rank2 = cards[1].rank;
-        let mut bin = 2 * (rank1 * 100 + rank2);
-        if cards[0].suit == cards[1].suit {
-            bin += 1;
-        }
-        return bin as i32;
+        preflop_bin_index(cards) as i32
     }
 
     // Lookup methods: Translate the card to its canonical version and return
     // the ID stored in the corresponding abstraction lookup table
 
     fn postflop_bin(&self, cards: &[Card]) -> i32 {
-        let canonical = card_utils::canonical_hand(cards, true);
-        let hand = card_utils::cards2hand(&canonical);
-        match cards.len() {
-            5 => self.flop.get(&hand).clone(),
-            6 => self.turn.get(&hand).clone(),
-            7 => self.river.get(&hand).clone(),
+        let bin = match cards.len() {
+            5 => self.flop_bin(cards),
+            6 => self.turn_bin(cards),
+            7 => self.river_bin(cards),
             _ => panic!("Bad number of cards"),
+        };
+        match bin {
+            Some(b) => b,
+            None => panic!(
+                "Hand {} not found in the abstraction",
+                card_utils::cards2str(cards)
+            ),
+        }
+    }
+
+    pub fn flop_bin(&self, cards: &[Card]) -> Option<i32> {
+        Abstraction::lookup(&self.flop, cards)
+    }
+
+    pub fn turn_bin(&self, cards: &[Card]) -> Option<i32> {
+        Abstraction::lookup(&self.turn, cards)
+    }
+
+    pub fn river_bin(&self, cards: &[Card]) -> Option<i32> {
+        Abstraction::lookup(&self.river, cards)
+    }
+
+    fn lookup(table: &HandData, cards: &[Card]) -> Option<i32> {
+        let canonical = card_utils::canonical_hand_cached(cards, true);
+        table.try_get(&card_utils::cards2hand(&canonical))
+    }
+}
+
+// The checks behind Abstraction::validate() for a single street
+pub fn validate_table(
+    street: &'static str,
+    table: &HandData,
+    n_cards: usize,
+    n_buckets: i32,
+    n_canonical: usize,
+) -> Result<(), AbstractionError> {
+    for (&hand, &bin) in table.iter() {
+        let cards = card_utils::hand2cards(hand);
+        if cards.len() != n_cards {
+            return Err(AbstractionError::WrongNumberOfCards {
+                street: street,
+                hand: card_utils::cards2str(&cards),
+            });
+        }
+        if bin < 0 || bin >= n_buckets {
+            return Err(AbstractionError::BinOutOfRange {
+                street: street,
+                hand: card_utils::cards2str(&cards),
+                bin: bin,
+            });
+        }
+        // The hands are keyed by their u64 representation, so two entries can
+        // only clash if they're different versions of the same canonical hand
+        if !card_utils::is_canonical(&cards, true) {
+            return Err(AbstractionError::NotCanonical {
+                street: street,
+                hand: card_utils::cards2str(&cards),
+            });
+        }
+    }
+    if table.len() != n_canonical {
+        return Err(AbstractionError::WrongCount {
+            street: street,
+            expected: n_canonical,
+            found: table.len(),
+        });
+    }
+    Ok(())
+}
+
+// Size of the histograms from Abstraction::print_histogram()
+const HISTOGRAM_ROWS: usize = 20;
+const HISTOGRAM_WIDTH: usize = 50;
+
+// How many hands ended up in each bin of one street's abstraction. Bins with
+// lots more hands than the rest lump together hands that play differently.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AbstractionStats {
+    pub total_hands: usize,
+    pub num_bins: usize,
+    pub hands_per_bin: Vec<usize>,
+    // 0 when every bin has the same number of hands, and (n - 1) / n for n
+    // bins when they're all in one
+    pub gini_coefficient: f64,
+    pub min_bin_size: usize,
+    pub max_bin_size: usize,
+}
+
+impl AbstractionStats {
+    // Bins outside 0..n_bins aren't counted, validate_table() catches those
+    pub fn from_table(table: &HandData, n_bins: i32) -> AbstractionStats {
+        let mut hands_per_bin = vec![0; n_bins.max(0) as usize];
+        for (_, &bin) in table.iter() {
+            if bin >= 0 && bin < n_bins {
+                hands_per_bin[bin as usize] += 1;
+            }
+        }
+        AbstractionStats::from_bin_sizes(hands_per_bin)
+    }
+
+    pub fn from_bin_sizes(hands_per_bin: Vec<usize>) -> AbstractionStats {
+        let total: usize = hands_per_bin.iter().sum();
+        let n = hands_per_bin.len();
+        // Mean absolute difference over all pairs of bins, from the sizes in
+        // increasing order
+        let mut sorted = hands_per_bin.clone();
+        sorted.sort_unstable();
+        let gini = if total == 0 {
+            0.0
+        } else {
+            let weighted: f64 = sorted
+                .iter()
+                .enumerate()
+                .map(|(i, &size)| (2 * i + 1) as f64 * size as f64)
+                .sum();
+            weighted / (n as f64 * total as f64) - 1.0
+        };
+        AbstractionStats {
+            total_hands: total,
+            num_bins: n,
+            min_bin_size: sorted.first().cloned().unwrap_or(0),
+            max_bin_size: sorted.last().cloned().unwrap_or(0),
+            hands_per_bin: hands_per_bin,
+            gini_coefficient: gini,
+        }
+    }
+
+    // ASCII bar chart of the bin sizes, with consecutive bins added up so it
+    // fits in the given number of rows
+    pub fn histogram(&self, rows: usize, width: usize) -> String {
+        let rows = rows.max(1);
+        let per_row = self.num_bins.div_ceil(rows).max(1);
+        let sums: Vec<(usize, usize)> = (0..self.num_bins)
+            .step_by(per_row)
+            .map(|start| {
+                let end = (start + per_row).min(self.num_bins);
+                (start, self.hands_per_bin[start..end].iter().sum())
+            })
+            .collect();
+        let max = sums.iter().map(|(_, sum)| *sum).max().unwrap_or(0).max(1);
+        let mut result = String::new();
+        for (start, sum) in sums {
+            let end = (start + per_row).min(self.num_bins) - 1;
+            let bar = "#".repeat(sum * width / max);
+            result.push_str(&format!(
+                "{:>5}-{:<5} |{:<width$}| {}\n",
+                start,
+                end,
+                bar,
+                sum,
+                width = width
+            ));
+        }
+        result
+    }
+}
+
+impl fmt::Display for AbstractionStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} hands in {} bins, {} to {} per bin (Gini coefficient {:.3})",
+            self.total_hands,
+            self.num_bins,
+            self.min_bin_size,
+            self.max_bin_size,
+            self.gini_coefficient
+        )
+    }
+}
+
+// One of the 169 canonical preflop hands. rank1 is always the higher rank.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PreflopHand {
+    pub rank1: u8,
+    pub rank2: u8,
+    pub suited: bool,
+    pub combo_count: u8,
+    pub strength: f64,
+}
+
+// Equity of each preflop hand against a random hand, averaged over all boards.
+// This is what you'd get by averaging EquityTable::lookup over every runout,
+// but that takes forever so it was precomputed with 60k Monte Carlo samples per
+// hand, by `preflop-strengths 60000` (compute_preflop_strengths() with seed 0).
+// Laid out like the usual 13x13 grid: rows and columns go from A down to 2,
+// suited hands are above the diagonal and offsuit hands are below it.
+#[rustfmt::skip]
+const PREFLOP_STRENGTHS: [f64; 169] = [
+    0.853, 0.672, 0.661, 0.655, 0.648, 0.631, 0.620, 0.614, 0.598, 0.601, 0.590, 0.584, 0.573,
+    0.653, 0.825, 0.637, 0.628, 0.618, 0.598, 0.585, 0.576, 0.565, 0.559, 0.547, 0.537, 0.529,
+    0.642, 0.616, 0.801, 0.601, 0.596, 0.575, 0.560, 0.546, 0.535, 0.526, 0.517, 0.513, 0.500,
+    0.637, 0.606, 0.581, 0.774, 0.576, 0.560, 0.536, 0.522, 0.504, 0.501, 0.488, 0.480, 0.476,
+    0.625, 0.599, 0.573, 0.553, 0.749, 0.539, 0.524, 0.505, 0.490, 0.475, 0.467, 0.459, 0.450,
+    0.608, 0.580, 0.553, 0.534, 0.514, 0.721, 0.507, 0.493, 0.474, 0.460, 0.439, 0.434, 0.422,
+    0.597, 0.559, 0.539, 0.517, 0.496, 0.482, 0.695, 0.478, 0.463, 0.446, 0.427, 0.411, 0.405,
+    0.589, 0.552, 0.517, 0.494, 0.480, 0.462, 0.453, 0.663, 0.456, 0.434, 0.416, 0.400, 0.385,
+    0.575, 0.543, 0.508, 0.480, 0.461, 0.444, 0.432, 0.422, 0.634, 0.432, 0.413, 0.398, 0.378,
+    0.581, 0.532, 0.502, 0.468, 0.444, 0.425, 0.411, 0.405, 0.399, 0.601, 0.413, 0.395, 0.380,
+    0.566, 0.524, 0.490, 0.461, 0.434, 0.408, 0.397, 0.388, 0.374, 0.383, 0.570, 0.390, 0.367,
+    0.557, 0.516, 0.480, 0.455, 0.421, 0.398, 0.376, 0.368, 0.358, 0.363, 0.350, 0.534, 0.361,
+    0.549, 0.503, 0.471, 0.445, 0.417, 0.393, 0.370, 0.345, 0.340, 0.341, 0.332, 0.325, 0.503,
+];
+
+// Returns all 169 canonical preflop hands, indexed the same way as
+// preflop_bin_index().
+pub fn preflop_matrix_169() -> [PreflopHand; 169] {
+    let mut matrix = [PreflopHand {
+        rank1: 0,
+        rank2: 0,
+        suited: false,
+        combo_count: 0,
+        strength: 0.0,
+    }; 169];
+    for row in 0..13 {
+        for col in 0..13 {
+            let index = row * 13 + col;
+            let row_rank = 14 - row as u8;
+            let col_rank = 14 - col as u8;
+            let combo_count = if row == col {
+                6
+            } else if row < col {
+                4
+            } else {
+                12
+            };
+            matrix[index] = PreflopHand {
+                rank1: row_rank.max(col_rank),
+                rank2: row_rank.min(col_rank),
+                suited: row < col,
+                combo_count: combo_count,
+                strength: PREFLOP_STRENGTHS[index],
+            };
         }
     }
+    matrix
+}
+
+// Maps a 2-card hand to its cell in the 13x13 preflop grid
+pub fn preflop_bin_index(cards: &[Card]) -> usize {
+    let high = cards[0].rank.max(cards[1].rank) as usize;
+    let low = cards[0].rank.min(cards[1].rank) as usize;
+    if cards[0].suit == cards[1].suit {
+        // Suited hands are above the diagonal
+        (14 - high) * 13 + (14 - low)
+    } else {
+        (14 - low) * 13 + (14 - high)
+    }
 }
 
-fn load_abstraction(path: &str, n_cards: usize, n_buckets: i32) -> HandData {
+// Recomputes PREFLOP_STRENGTHS: each cell's equity against a random hand over
+// n_samples random runouts. The hands are scored with the 5-card table so this
+// runs without the 7-card one, and each cell has its own rng seeded from seed,
+// so the output doesn't change from run to run. Print it with
+// preflop_strengths_source().
+pub fn compute_preflop_strengths(n_samples: usize, seed: u64) -> Vec<f64> {
+    let cells: Vec<usize> = (0..169).collect();
+    let bar = card_utils::pbar(cells.len() as u64);
+    let strengths = cells
+        .par_iter()
+        .map(|&index| {
+            let hand = card_utils::deck()
+                .into_iter()
+                .combinations(2)
+                .find(|cards| preflop_bin_index(cards) == index)
+                .unwrap();
+            let mut deck = card_utils::deck();
+            deck.retain(|c| !hand.contains(c));
+            let mut rng = StdRng::seed_from_u64(seed + index as u64);
+            let mut n_wins = 0.0;
+            for _ in 0..n_samples {
+                // The opponent's hand, then the board
+                let dealt: Vec<Card> = deck.choose_multiple(&mut rng, 7).cloned().collect();
+                let my_strength =
+                    card_utils::five_card_hand_strength(&[&hand[..], &dealt[2..]].concat());
+                let opp_strength = card_utils::five_card_hand_strength(&dealt);
+                if my_strength > opp_strength {
+                    n_wins += 1.0;
+                } else if my_strength == opp_strength {
+                    n_wins += 0.5;
+                }
+            }
+            bar.inc(1);
+            n_wins / n_samples as f64
+        })
+        .collect();
+    bar.finish();
+    strengths
+}
+
+// Formats strengths the way PREFLOP_STRENGTHS is written, one grid row per line
+pub fn preflop_strengths_source(strengths: &[f64]) -> String {
+    let mut out = String::from("#[rustfmt::skip]\nconst PREFLOP_STRENGTHS: [f64; 169] = [\n");
+    for row in strengths.chunks(13) {
+        let cells: Vec<String> = row.iter().map(|s| format!("{:.3},", s)).collect();
+        out.push_str(&format!("    {}\n", cells.join(" ")));
+    }
+    out.push_str("];\n");
+    out
+}
+
+// Reads the abstraction table at path, or builds (and saves) it if there
+// isn't a usable one there
+pub fn load_abstraction(path: &str, n_cards: usize, n_buckets: i32) -> HandData {
     match File::open(path) {
-        Err(_error) => make_abstraction(n_cards, n_buckets),
-        Ok(file) => HandData::read_serialized(file),
+        Err(error) => {
+            println!("[WARN] Can't open the abstraction at {}: {}", path, error);
+            make_abstraction(n_cards, n_buckets)
+        }
+        Ok(file) => {
+            let table = HandData::read_serialized(file);
+            if table.len() == 0 {
+                // Probably left behind by an interrupted run
+                println!("[WARN] The abstraction at {} is empty", path);
+                return make_abstraction(n_cards, n_buckets);
+            }
+            table
+        }
     }
 }
 
@@ -121,34 +519,274 @@ fn make_abstraction(n_cards: usize, n_buckets: i32) -> HandData {
         7 => println!("[INFO] Preparing the river abstraction."),
         _ => panic!("Bad number of cards"),
     };
-    let hand_ehs2 = get_sorted_hand_ehs2(n_cards);
     let mut clusters = HandData::new();
-    for (idx, (hand, _ehs2)) in hand_ehs2.iter().enumerate() {
-        // Bucket the hand according to the percentile of its E[HS^2]
-        let bucket: i32 = ((n_buckets as f64) * (idx as f64) / (hand_ehs2.len() as f64)) as i32;
-        clusters.insert(hand, bucket);
+    if n_cards == 5 {
+        // The flop is clustered on potential-aware distributions, since a lot
+        // of a flop hand's value comes from how its equity moves on the turn
+        for (hand, bucket) in make_flop_clusters(n_buckets) {
+            clusters.insert(&hand, bucket);
+        }
+    } else if n_cards == 6 {
+        for (hand, bucket) in make_turn_clusters(n_buckets) {
+            clusters.insert(&hand, bucket);
+        }
+    } else {
+        let hand_ehs2 = get_sorted_hand_ehs2(n_cards);
+        for (idx, (hand, _ehs2)) in hand_ehs2.iter().enumerate() {
+            // Bucket the hand according to the percentile of its E[HS^2]
+            let bucket: i32 = ((n_buckets as f64) * (idx as f64) / (hand_ehs2.len() as f64)) as i32;
+            clusters.insert(hand, bucket);
+        }
     }
+    let paths = &config().paths;
     let path = match n_cards {
-        5 => FLOP_PATH,
-        6 => TURN_PATH,
-        7 => RIVER_PATH,
+        5 => &paths.flop_abstraction,
+        6 => &paths.turn_abstraction,
+        7 => &paths.river_abstraction,
         _ => panic!("Bad hand length"),
     };
     clusters.serialize(path);
     clusters
 }
 
+// Clusters every canonical flop hand on its potential-aware distribution.
+fn make_flop_clusters(n_buckets: i32) -> HashMap<u64, i32> {
+    let distributions = make_flop_equity();
+    println!("[INFO] Clustering the flop hands.");
+    let config = KMeansConfig {
+        k: n_buckets as usize,
+        ..KMeansConfig::default()
+    };
+    cluster(&distributions, &config)
+}
+
+// Clusters every canonical turn hand on its river equity distribution.
+fn make_turn_clusters(n_buckets: i32) -> HashMap<u64, i32> {
+    let distributions = make_turn_equity();
+    println!("[INFO] Clustering the turn hands.");
+    let config = KMeansConfig {
+        k: n_buckets as usize,
+        segments: 1,
+        ..KMeansConfig::default()
+    };
+    cluster(&distributions, &config)
+}
+
+// Returns the potential-aware distribution of every canonical flop hand. The
+// hands are independent, so they're spread over rayon's threads. Unlike the
+// turn there's no checkpointing, since there are far fewer flop hands.
+pub fn make_flop_equity() -> HashMap<u64, Vec<f64>> {
+    make_flop_equity_with(
+        &card_utils::load_flop_canonical(),
+        potential_aware_distribution,
+    )
+}
+
+// Like make_flop_equity(), but for the given hands and with the given
+// distribution instead of the potential-aware one, which needs the equity
+// table
+pub fn make_flop_equity_with(
+    canonical_hands: &HashSet<u64>,
+    distribution: impl Fn(&[Card]) -> Vec<f64> + Sync,
+) -> HashMap<u64, Vec<f64>> {
+    println!("[INFO] Preparing the flop equity distributions.");
+    let bar = card_utils::pbar(canonical_hands.len() as u64);
+    let distributions: HashMap<u64, Vec<f64>> = canonical_hands
+        .par_iter()
+        .map(|h| {
+            let distribution = distribution(&card_utils::hand2cards(h.clone()));
+            bar.inc(1);
+            (h.clone(), distribution)
+        })
+        .collect();
+    bar.finish();
+    distributions
+}
+
+// Returns the river equity distribution (EQUITY_BINS bins) of every canonical
+// turn hand. This takes a long time, so the distributions are added to the
+// turn equity file every TURN_EQUITY_CHECKPOINT hands and a later run picks up
+// where the last one stopped.
+pub fn make_turn_equity() -> HashMap<u64, Vec<f64>> {
+    let path = &config().paths.turn_equity;
+    let mut distributions = read_turn_equity(path);
+    if distributions.len() == N_TURN_CANONICAL as usize {
+        return distributions;
+    }
+
+    println!("[INFO] Preparing the turn equity distributions.");
+    let mut remaining: Vec<u64> = card_utils::load_turn_canonical()
+        .into_iter()
+        .filter(|h| !distributions.contains_key(h))
+        .collect();
+    // Sorted so that checkpoints fill in the hands in the same order every run
+    remaining.sort();
+    let bar = card_utils::pbar(remaining.len() as u64);
+    for chunk in remaining.chunks(TURN_EQUITY_CHECKPOINT) {
+        let done: Vec<(u64, Vec<f64>)> = chunk
+            .par_iter()
+            .map(|h| {
+                // Both halves of a turn hand's potential-aware distribution
+                // are its river equity distribution
+                let mut distribution =
+                    potential_aware_distribution(&card_utils::hand2cards(h.clone()));
+                distribution.truncate(EQUITY_BINS);
+                bar.inc(1);
+                (h.clone(), distribution)
+            })
+            .collect();
+        append_turn_equity(path, &done);
+        distributions.extend(done);
+    }
+    bar.finish();
+    distributions
+}
+
+// The turn equity file is a series of bincode chunks of (hand, distribution)
+// pairs, one per checkpoint, so a checkpoint only has to write the hands it
+// added rather than the whole map. A HashMap is encoded the same way as a
+// chunk, so files from before this hold a single chunk.
+pub fn read_turn_equity(path: &str) -> HashMap<u64, Vec<f64>> {
+    let mut distributions = HashMap::new();
+    let file = match OpenOptions::new().read(true).write(true).open(path) {
+        Err(_e) => return distributions,
+        Ok(file) => file,
+    };
+    let mut reader = BufReader::new(&file);
+    let mut complete = 0;
+    while let Ok(chunk) = bincode::deserialize_from::<_, Vec<(u64, Vec<f64>)>>(&mut reader) {
+        distributions.extend(chunk);
+        complete = reader.stream_position().unwrap();
+    }
+    // A run that was stopped while writing a chunk leaves part of it at the
+    // end, which would hide anything appended after it
+    file.set_len(complete)
+        .expect("Could not truncate the turn equity file");
+    distributions
+}
+
+pub fn append_turn_equity(path: &str, chunk: &[(u64, Vec<f64>)]) {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .expect("Could not open the turn equity file");
+    let mut writer = BufWriter::new(file);
+    bincode::serialize_into(&mut writer, chunk).unwrap();
+    writer.flush().unwrap();
+}
+
+// Returns a histogram with EQUITY_BINS bins of the given equities, normalized
+// to sum to 1.
+fn equity_histogram(equities: &[f64]) -> Vec<f64> {
+    let mut histogram = vec![0.0; EQUITY_BINS];
+    for equity in equities {
+        let bin = ((equity * EQUITY_BINS as f64) as usize).min(EQUITY_BINS - 1);
+        histogram[bin] += 1.0;
+    }
+    for x in histogram.iter_mut() {
+        *x /= equities.len() as f64;
+    }
+    histogram
+}
+
+// Histogram of the equity of a flop or turn hand against each opponent hand,
+// from n_rollouts runouts per opponent hand. Unlike
+// potential_aware_distribution() it doesn't need the equity table.
+pub fn equity_distribution(cards: &[Card], n_rollouts: usize, rng: &mut impl Rng) -> Vec<f64> {
+    match cards.len() {
+        5 => flop_equity_distribution(cards.try_into().unwrap(), n_rollouts, rng),
+        6 => turn_equity_distribution(cards.try_into().unwrap(), n_rollouts, rng),
+        _ => panic!("Bad number of cards"),
+    }
+}
+
+// Histogram (EQUITY_BINS bins) of the equity of the hole cards against every
+// opponent hand on the flop, where hand is the hole cards followed by the
+// board. Each opponent hand's equity is estimated from n_rollouts random turn
+// and river cards.
+pub fn flop_equity_distribution(
+    hand: &[Card; 5],
+    n_rollouts: usize,
+    rng: &mut impl Rng,
+) -> Vec<f64> {
+    equity_distribution_vs_combos(hand, n_rollouts, rng)
+}
+
+// Same as flop_equity_distribution(), but on the turn, so each rollout is just
+// a river card
+pub fn turn_equity_distribution(
+    hand: &[Card; 6],
+    n_rollouts: usize,
+    rng: &mut impl Rng,
+) -> Vec<f64> {
+    equity_distribution_vs_combos(hand, n_rollouts, rng)
+}
+
+fn equity_distribution_vs_combos(hand: &[Card], n_rollouts: usize, rng: &mut impl Rng) -> Vec<f64> {
+    if n_rollouts == 0 {
+        panic!("Need at least one rollout");
+    }
+    let (hole, board) = hand.split_at(2);
+    let equities: Vec<f64> = card_utils::deck()
+        .into_iter()
+        .filter(|c| !hand.contains(c))
+        .combinations(2)
+        .map(|opp_hand| card_utils::monte_carlo_equity_vs(hole, &opp_hand, board, n_rollouts, rng))
+        .collect();
+    equity_histogram(&equities)
+}
+
+// Potential-aware distribution of a flop or turn hand, made of two equity
+// histograms laid end to end. The first is the distribution of river equity
+// over every runout from here, and the second is the distribution of expected
+// equity after the next card is dealt. On the turn the next card is the river,
+// so both halves are the same.
+pub fn potential_aware_distribution(cards: &[Card]) -> Vec<f64> {
+    if cards.len() != 5 && cards.len() != 6 {
+        panic!("Bad number of cards");
+    }
+    let mut deck = card_utils::deck();
+    deck.retain(|c| !cards.contains(&c));
+
+    // Runouts are stored as indices into the deck
+    let runouts: Vec<Vec<usize>> = (0..deck.len()).combinations(7 - cards.len()).collect();
+    let full_hands: Vec<Vec<Card>> = runouts
+        .iter()
+        .map(|runout| {
+            let mut hand = cards.to_vec();
+            hand.extend(runout.iter().map(|&i| deck[i].clone()));
+            hand
+        })
+        .collect();
+    let equities = card_utils::lookup_river_equities(&full_hands);
+
+    // Average the river equity over the runouts that contain each next card
+    let mut sums = vec![0.0; deck.len()];
+    let mut counts = vec![0.0; deck.len()];
+    for (runout, equity) in runouts.iter().zip(&equities) {
+        for &i in runout {
+            sums[i] += equity;
+            counts[i] += 1.0;
+        }
+    }
+    let next_street: Vec<f64> = sums.iter().zip(&counts).map(|(s, c)| s / c).collect();
+
+    [equity_histogram(&equities), equity_histogram(&next_street)].concat()
+}
+
 // Writes text files of canonical hands sorted by E[HS^2] from low to high, split
 // into different files depending on the first card in the canonical hand.
 pub fn write_sorted_hands() {
     let hands = get_sorted_hand_ehs2(7);
     println!("[INFO] Writing sorted river hands for the LightAbstraction");
-    fs::create_dir(RIVER_SORTED_DIR);
+    let dir = &config().paths.river_sorted_dir;
+    fs::create_dir_all(dir).expect("Could not create the sorted hands directory");
     let bar = card_utils::pbar(hands.len() as u64);
     for card in card_utils::deck() {
         // We find every canonical river hand that starts with card, and add it
         // to this text file in order of E[HS^2].
-        let fname = format!("{}/{}.txt", RIVER_SORTED_DIR, card);
+        let fname = format!("{}/{}.txt", dir, card);
         let mut buffer = match OpenOptions::new().append(true).open(&fname) {
             Err(_e) => File::create(fname).expect("Could not create file"),
             Ok(f) => f,
@@ -180,9 +818,10 @@ pub struct LightAbstraction {
 
 impl LightAbstraction {
     pub fn new() -> LightAbstraction {
+        let paths = &config().paths;
         LightAbstraction {
-            flop: load_abstraction(FLOP_PATH, 5, FLOP_BUCKETS),
-            turn: load_abstraction(TURN_PATH, 6, TURN_BUCKETS),
+            flop: load_abstraction(&paths.flop_abstraction, 5, FLOP_BUCKETS),
+            turn: load_abstraction(&paths.turn_abstraction, 6, TURN_BUCKETS),
         }
     }
 
@@ -215,7 +854,7 @@ impl LightAbstraction {
 fn hand_lookup(cards: &[Card]) -> Result<i32, ErrorKind> {
     let target_hand = card_utils::cards2hand(cards);
     let first_card_str = cards[0].to_string();
-    let path = format!("{}/{}.txt", RIVER_SORTED_DIR, first_card_str);
+    let path = format!("{}/{}.txt", config().paths.river_sorted_dir, first_card_str);
     match File::open(path) {
         Err(_e) => {
             write_sorted_hands();
@@ -238,5 +877,172 @@ fn hand_lookup(cards: &[Card]) -> Result<i32, ErrorKind> {
     Err(ErrorKind::NotFound)
 }
 
+// Settings for k-means clustering of equity distributions.
+pub struct KMeansConfig {
+    pub k: usize,
+    pub max_iter: usize,
+    // Clustering stops once no mean moves further than this between iterations
+    pub tolerance: f64,
+    pub seed: u64,
+    // Number of equal-length histograms concatenated in each distribution.
+    // Distances are summed over them so no mass moves between histograms.
+    pub segments: usize,
+}
+
+impl Default for KMeansConfig {
+    fn default() -> KMeansConfig {
+        KMeansConfig {
+            k: FLOP_BUCKETS as usize,
+            max_iter: 100,
+            tolerance: 1e-6,
+            seed: 0,
+            segments: 2,
+        }
+    }
+}
+
+// Clusters hands by their equity distributions using k-means, with the initial
+// means chosen by k-means++. Distances are Earth Mover's Distances, which
+// respect the ordering of the equity bins unlike Euclidean distance. Returns a
+// map from each hand to its cluster ID. Potential-aware distributions are
+// 2 * EQUITY_BINS long with segments = 2.
+pub fn cluster(distributions: &HashMap<u64, Vec<f64>>, config: &KMeansConfig) -> HashMap<u64, i32> {
+    // Sort the hands so that results only depend on the seed and not on the
+    // HashMap iteration order.
+    let mut hands: Vec<u64> = distributions.keys().cloned().collect();
+    hands.sort();
+    let data: Vec<&Vec<f64>> = hands.iter().map(|h| &distributions[h]).collect();
+    let k = config.k.min(data.len());
+    if k == 0 {
+        return HashMap::new();
+    }
+
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let mut means = init_means(&data, k, config.segments, &mut rng);
+    let mut assignments = nearest_means(&data, &means, config.segments);
+    for _ in 0..config.max_iter {
+        let new_means = update_means(&data, &assignments, &means);
+        let movement = means
+            .iter()
+            .zip(&new_means)
+            .map(|(old, new)| segmented_distance(old, new, config.segments))
+            .fold(0.0, f64::max);
+        means = new_means;
+        assignments = nearest_means(&data, &means, config.segments);
+        if movement < config.tolerance {
+            break;
+        }
+    }
+
+    let mut clusters = HashMap::new();
+    for (hand, cluster) in hands.iter().zip(assignments) {
+        clusters.insert(hand.clone(), cluster as i32);
+    }
+    clusters
+}
+
+// 1-D Earth Mover's Distance (Wasserstein-1) between two histograms over the
+// same ordered bins, measured in bins. On a line the EMD is just the total
+// absolute difference between the two cumulative distributions. Missing bins
+// in the shorter histogram are treated as empty.
+pub fn earth_movers_distance(a: &[f64], b: &[f64]) -> f64 {
+    let mut cumulative_diff = 0.0;
+    let mut distance = 0.0;
+    for i in 0..a.len().max(b.len()) {
+        cumulative_diff += a.get(i).unwrap_or(&0.0) - b.get(i).unwrap_or(&0.0);
+        distance += cumulative_diff.abs();
+    }
+    distance
+}
+
+// Sum of the Earth Mover's Distances between matching histograms when each
+// distribution is made of `segments` histograms laid end to end.
+fn segmented_distance(a: &[f64], b: &[f64], segments: usize) -> f64 {
+    let len = (a.len().max(b.len()) / segments.max(1)).max(1);
+    a.chunks(len)
+        .zip(b.chunks(len))
+        .map(|(x, y)| earth_movers_distance(x, y))
+        .sum()
+}
+
+// k-means++ initialization: the first mean is a random point, and each
+// following mean is sampled with probability proportional to its squared
+// distance from the nearest mean chosen so far.
+fn init_means(data: &[&Vec<f64>], k: usize, segments: usize, rng: &mut StdRng) -> Vec<Vec<f64>> {
+    let first = (rng.gen::<f64>() * data.len() as f64) as usize;
+    let mut means = vec![data[first.min(data.len() - 1)].clone()];
+    let mut min_sq_dists: Vec<f64> = data
+        .iter()
+        .map(|d| segmented_distance(d, &means[0], segments).powi(2))
+        .collect();
+    while means.len() < k {
+        let total: f64 = min_sq_dists.iter().sum();
+        let next = if total == 0.0 {
+            // Every point already sits on a mean, so just pick one at random
+            (rng.gen::<f64>() * data.len() as f64) as usize
+        } else {
+            let mut target = rng.gen::<f64>() * total;
+            let mut chosen = data.len() - 1;
+            for (i, d) in min_sq_dists.iter().enumerate() {
+                if target < *d {
+                    chosen = i;
+                    break;
+                }
+                target -= d;
+            }
+            chosen
+        };
+        let mean = data[next.min(data.len() - 1)].clone();
+        for (i, d) in data.iter().enumerate() {
+            let sq_dist = segmented_distance(d, &mean, segments).powi(2);
+            if sq_dist < min_sq_dists[i] {
+                min_sq_dists[i] = sq_dist;
+            }
+        }
+        means.push(mean);
+    }
+    means
+}
+
+// Returns the index of the closest mean for every point.
+fn nearest_means(data: &[&Vec<f64>], means: &[Vec<f64>], segments: usize) -> Vec<usize> {
+    data.par_iter()
+        .map(|d| {
+            let mut nearest = 0;
+            let mut min_dist = std::f64::INFINITY;
+            for (i, mean) in means.iter().enumerate() {
+                let dist = segmented_distance(d, mean, segments);
+                if dist < min_dist {
+                    min_dist = dist;
+                    nearest = i;
+                }
+            }
+            nearest
+        })
+        .collect()
+}
+
+// Moves each mean to the centroid of its cluster. Empty clusters keep their
+// previous mean.
+fn update_means(data: &[&Vec<f64>], assignments: &[usize], means: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    let mut sums = vec![vec![0.0; means[0].len()]; means.len()];
+    let mut counts = vec![0; means.len()];
+    for (d, &cluster) in data.iter().zip(assignments) {
+        for (sum, x) in sums[cluster].iter_mut().zip(d.iter()) {
+            *sum += x;
+        }
+        counts[cluster] += 1;
+    }
+    let mut new_means = Vec::new();
+    for (i, sum) in sums.into_iter().enumerate() {
+        if counts[i] == 0 {
+            new_means.push(means[i].clone());
+        } else {
+            new_means.push(sum.iter().map(|x| x / counts[i] as f64).collect());
+        }
+    }
+    new_means
+}
+
 // TODO: Should I consider multiplicity of canonical hands for percentile bucketing?
 // Might not be a big deal if bucket sizes vary.
@@ -1,21 +1,23 @@
 use crate::itertools::Itertools;
 // use crate::rand::prelude::IteratorRandom;
 use bio::stats::combinatorics::combinations;
+use lru::LruCache;
 use rand::prelude::SliceRandom;
+use rand::Rng;
 use rayon::prelude::*;
 use serde::Deserialize;
 use serde::Serialize;
+use std::cell::{Cell, RefCell};
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Read, Write};
-
-const HAND_TABLE_PATH: &str = "products/strengths7.txt";
-const LIGHT_HAND_TABLE_PATH: &str = "products/strengths.json";
-const EQUITY_TABLE_PATH: &str = "products/equity_table.txt";
-const FLOP_CANONICAL_PATH: &str = "products/flop_canonical.txt";
-const TURN_CANONICAL_PATH: &str = "products/turn_canonical.txt";
-const RIVER_CANONICAL_PATH: &str = "products/river_canonical.txt";
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
 
 // TODO: To reduce memory usage if needed, incorporate the equity information
 // and hand strength information in one big lookup table, like HashMap<u64, (f64, i32)>
@@ -23,6 +25,65 @@ lazy_static! {
     pub static ref HAND_TABLE: HandTable = HandTable::new();
     // pub static ref HAND_TABLE: LightHandTable = LightHandTable::new();
     static ref EQUITY_TABLE: EquityTable = EquityTable::new();
+    static ref SHORT_DECK_HAND_TABLE: HandTable = HandTable::with_variant(DeckVariant::ShortDeck);
+    static ref FIVE_CARD_HAND_TABLE: HandTable =
+        HandTable::load_or_build(&table_paths().light_hand_table);
+}
+
+// Where the tables in this file are read from and written to. The config
+// replaces the defaults with its [paths] when it's loaded, which has to happen
+// before any table is. card_utils doesn't read the config itself, so that the
+// benches can include this file on its own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TablePaths {
+    pub hand_table: String,
+    pub light_hand_table: String,
+    pub short_deck_hand_table: String,
+    pub equity_table: String,
+    pub equity_table_text: String,
+    pub flop_canonical: String,
+    pub turn_canonical: String,
+    pub river_canonical: String,
+    pub river_canonical_bin: String,
+}
+
+impl Default for TablePaths {
+    fn default() -> TablePaths {
+        TablePaths {
+            hand_table: String::from("products/strengths7.txt"),
+            light_hand_table: String::from("products/strengths.json"),
+            short_deck_hand_table: String::from("products/strengths_short.json"),
+            equity_table: String::from("products/equity_table.bin"),
+            equity_table_text: String::from("products/equity_table.txt"),
+            flop_canonical: String::from("products/flop_canonical.txt"),
+            turn_canonical: String::from("products/turn_canonical.txt"),
+            river_canonical: String::from("products/river_canonical.txt"),
+            river_canonical_bin: String::from("products/canonical_river.bin"),
+        }
+    }
+}
+
+static TABLE_PATHS: OnceLock<TablePaths> = OnceLock::new();
+
+pub fn table_paths() -> &'static TablePaths {
+    TABLE_PATHS.get_or_init(TablePaths::default)
+}
+
+// Like set_config(), this only works before the paths have been used,
+// otherwise they're handed back.
+pub fn set_table_paths(paths: TablePaths) -> Result<(), TablePaths> {
+    TABLE_PATHS.set(paths)
+}
+
+// Loads the hand strength and equity tables at the same time on two threads,
+// instead of one after the other whenever each is first used. For startup
+// code that knows it'll need both. If the equity table has to be built it
+// waits for the hand table, which it needs anyway.
+pub fn init_tables() {
+    rayon::join(
+        || lazy_static::initialize(&HAND_TABLE),
+        || lazy_static::initialize(&EQUITY_TABLE),
+    );
 }
 
 pub const CLUBS: i32 = 0;
@@ -36,35 +97,94 @@ pub struct Card {
     pub suit: u8,
 }
 
+// Reasons a card string can fail to parse.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CardParseError {
+    UnknownRank(char),
+    UnknownSuit(char),
+    InputTooShort,
+    InputTooLong(String),
+}
+
+impl fmt::Display for CardParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CardParseError::UnknownRank(c) => write!(f, "unknown card rank '{}'", c),
+            CardParseError::UnknownSuit(c) => write!(f, "unknown card suit '{}'", c),
+            CardParseError::InputTooShort => {
+                write!(f, "card string is too short, expected a rank and a suit")
+            }
+            CardParseError::InputTooLong(card) => write!(
+                f,
+                "card string '{}' is too long, expected a rank and a suit",
+                card
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CardParseError {}
+
 impl Card {
+    // Convenience wrapper around try_new() for card strings that are known to
+    // be valid. Panics on bad input.
     pub fn new(card: &str) -> Card {
-        let rank = match &card[0..1] {
-            "2" => 2,
-            "3" => 3,
-            "4" => 4,
-            "5" => 5,
-            "6" => 6,
-            "7" => 7,
-            "8" => 8,
-            "9" => 9,
-            "T" => 10,
-            "J" => 11,
-            "Q" => 12,
-            "K" => 13,
-            "A" => 14,
-            _ => panic!("bad card string '{}'", card),
+        Card::try_new(card).unwrap()
+    }
+
+    // Parses a card string like "Ah" or "7c". Anything after the suit is an
+    // error, so a whole hand like "AsKh" isn't quietly read as its first card.
+    pub fn try_new(card: &str) -> Result<Card, CardParseError> {
+        let mut chars = card.chars();
+        let (rank, suit) = match (chars.next(), chars.next()) {
+            (Some(rank), Some(suit)) => (rank, suit),
+            _ => return Err(CardParseError::InputTooShort),
         };
-        let suit = match &card[1..2] {
-            "c" => CLUBS,
-            "d" => DIAMONDS,
-            "h" => HEARTS,
-            "s" => SPADES,
-            _ => panic!("bad card string"),
+        if chars.next().is_some() {
+            return Err(CardParseError::InputTooLong(card.to_string()));
+        }
+        let rank = match parse_rank(rank) {
+            Some(r) => r,
+            None => return Err(CardParseError::UnknownRank(rank)),
         };
-        return Card {
+        let suit = match suit {
+            'c' => CLUBS,
+            'd' => DIAMONDS,
+            'h' => HEARTS,
+            's' => SPADES,
+            _ => return Err(CardParseError::UnknownSuit(suit)),
+        };
+        Ok(Card {
             rank: rank,
             suit: suit as u8,
-        };
+        })
+    }
+}
+
+fn parse_rank(rank: char) -> Option<u8> {
+    match rank {
+        '2' => Some(2),
+        '3' => Some(3),
+        '4' => Some(4),
+        '5' => Some(5),
+        '6' => Some(6),
+        '7' => Some(7),
+        '8' => Some(8),
+        '9' => Some(9),
+        'T' => Some(10),
+        'J' => Some(11),
+        'Q' => Some(12),
+        'K' => Some(13),
+        'A' => Some(14),
+        _ => None,
+    }
+}
+
+impl FromStr for Card {
+    type Err = CardParseError;
+
+    fn from_str(card: &str) -> Result<Card, CardParseError> {
+        Card::try_new(card)
     }
 }
 
@@ -82,24 +202,28 @@ impl Ord for Card {
     }
 }
 
+fn rank_str(rank: u8) -> &'static str {
+    match rank {
+        2 => "2",
+        3 => "3",
+        4 => "4",
+        5 => "5",
+        6 => "6",
+        7 => "7",
+        8 => "8",
+        9 => "9",
+        10 => "T",
+        11 => "J",
+        12 => "Q",
+        13 => "K",
+        14 => "A",
+        _ => panic!("Bad rank value"),
+    }
+}
+
 impl fmt::Display for Card {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let rank = match self.rank {
-            2 => "2",
-            3 => "3",
-            4 => "4",
-            5 => "5",
-            6 => "6",
-            7 => "7",
-            8 => "8",
-            9 => "9",
-            10 => "T",
-            11 => "J",
-            12 => "Q",
-            13 => "K",
-            14 => "A",
-            _ => panic!("Bad rank value"),
-        };
+        let rank = rank_str(self.rank);
         let suit = match self.suit as i32 {
             CLUBS => "c",
             DIAMONDS => "d",
@@ -111,27 +235,206 @@ impl fmt::Display for Card {
     }
 }
 
-pub fn deck() -> Vec<Card> {
-    let mut deck = Vec::new();
-    let ranks = std::ops::Range { start: 2, end: 15 };
-    for rank in ranks {
-        for suit in 0..4 {
-            deck.push(Card {
-                rank: rank,
-                suit: suit,
-            });
+// A set of two-card starting hands, parsed from standard range notation such
+// as "AA,KK+,AKs,AQo+,22-TT".
+#[derive(Debug, Clone, PartialEq)]
+pub struct HandRange(Vec<[Card; 2]>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeParseError {
+    InvalidToken(String),
+}
+
+impl fmt::Display for RangeParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RangeParseError::InvalidToken(token) => write!(f, "invalid range token '{}'", token),
         }
     }
-    return deck;
 }
 
-pub fn deepcopy(vec: &Vec<&Card>) -> Vec<Card> {
-    let vec = vec.clone();
-    let mut result: Vec<Card> = Vec::new();
-    for v in vec {
-        result.push(v.clone());
+impl std::error::Error for RangeParseError {}
+
+impl HandRange {
+    pub fn from_combos(combos: Vec<[Card; 2]>) -> HandRange {
+        HandRange(combos)
     }
-    result
+
+    pub fn combos(&self) -> &[[Card; 2]] {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn contains(&self, hand: &[Card]) -> bool {
+        hand.len() == 2
+            && self.0.iter().any(|combo| {
+                (combo[0] == hand[0] && combo[1] == hand[1])
+                    || (combo[0] == hand[1] && combo[1] == hand[0])
+            })
+    }
+
+    // Returns the range without any combos that use one of the dead cards.
+    pub fn remove_blockers(&self, dead_cards: &[Card]) -> HandRange {
+        let combos = self
+            .0
+            .iter()
+            .filter(|combo| !dead_cards.contains(&combo[0]) && !dead_cards.contains(&combo[1]))
+            .cloned()
+            .collect();
+        HandRange(combos)
+    }
+}
+
+impl FromStr for HandRange {
+    type Err = RangeParseError;
+
+    fn from_str(range: &str) -> Result<HandRange, RangeParseError> {
+        let mut combos: Vec<[Card; 2]> = Vec::new();
+        for token in range.split(',') {
+            let token = token.trim();
+            if token.is_empty() {
+                continue;
+            }
+            let classes = match parse_range_token(token) {
+                Some(c) => c,
+                None => return Err(RangeParseError::InvalidToken(token.to_string())),
+            };
+            for (high, low, suited) in classes {
+                for combo in rank_combos(high, low, suited) {
+                    if !combos.contains(&combo) {
+                        combos.push(combo);
+                    }
+                }
+            }
+        }
+        Ok(HandRange(combos))
+    }
+}
+
+// A hand class is (high rank, low rank, suitedness), where suitedness is None
+// when both suited and offsuit combos are included. Parses "AK", "AKs", "AKo"
+// and pairs like "TT".
+fn parse_hand_class(class: &str) -> Option<(u8, u8, Option<bool>)> {
+    let chars: Vec<char> = class.chars().collect();
+    if chars.len() != 2 && chars.len() != 3 {
+        return None;
+    }
+    let rank1 = parse_rank(chars[0])?;
+    let rank2 = parse_rank(chars[1])?;
+    let suited = match chars.get(2) {
+        None => None,
+        Some('s') => Some(true),
+        Some('o') => Some(false),
+        Some(_) => return None,
+    };
+    if rank1 == rank2 && suited.is_some() {
+        // Pairs can't be suited or offsuit
+        return None;
+    }
+    Some((rank1.max(rank2), rank1.min(rank2), suited))
+}
+
+// Expands a single range token into the hand classes it covers.
+fn parse_range_token(token: &str) -> Option<Vec<(u8, u8, Option<bool>)>> {
+    if let Some(dash) = token.find('-') {
+        // "22-TT" or "A2s-A5s"
+        let first = parse_hand_class(&token[..dash])?;
+        let last = parse_hand_class(&token[dash + 1..])?;
+        let (high, low, suited) = first;
+        if high == low && last.0 == last.1 {
+            let (from, to) = (high.min(last.0), high.max(last.0));
+            Some((from..=to).map(|r| (r, r, None)).collect())
+        } else if high != low && last.0 == high && last.1 != high && last.2 == suited {
+            let (from, to) = (low.min(last.1), low.max(last.1));
+            Some((from..=to).map(|r| (high, r, suited)).collect())
+        } else {
+            None
+        }
+    } else if token.ends_with('+') {
+        // "KK+" is KK and better pairs, "AQo+" raises the kicker up to AKo
+        let (high, low, suited) = parse_hand_class(&token[..token.len() - 1])?;
+        if high == low {
+            Some((high..=14).map(|r| (r, r, None)).collect())
+        } else {
+            Some((low..high).map(|r| (high, r, suited)).collect())
+        }
+    } else {
+        Some(vec![parse_hand_class(token)?])
+    }
+}
+
+// All the combos of a hand class, with the higher card first.
+fn rank_combos(high: u8, low: u8, suited: Option<bool>) -> Vec<[Card; 2]> {
+    let mut combos = Vec::new();
+    for suit1 in 0..4 {
+        for suit2 in 0..4 {
+            let keep = if high == low {
+                // Only count each pair of suits once
+                suit1 < suit2
+            } else {
+                match suited {
+                    Some(true) => suit1 == suit2,
+                    Some(false) => suit1 != suit2,
+                    None => true,
+                }
+            };
+            if keep {
+                combos.push([
+                    Card {
+                        rank: high,
+                        suit: suit1,
+                    },
+                    Card {
+                        rank: low,
+                        suit: suit2,
+                    },
+                ]);
+            }
+        }
+    }
+    combos
+}
+
+// The 52 cards, ordered by rank and then suit like deck() has always been
+pub const STANDARD_DECK: [Card; 52] = standard_deck();
+
+const fn standard_deck() -> [Card; 52] {
+    const BLANK: Card = Card { rank: 0, suit: 0 };
+    let mut deck = [BLANK; 52];
+    let mut i = 0;
+    while i < 52 {
+        deck[i] = Card {
+            rank: 2 + (i / 4) as u8,
+            suit: (i % 4) as u8,
+        };
+        i += 1;
+    }
+    deck
+}
+
+// Copies the standard deck in one go instead of pushing the cards one by one
+// (see benches/deck.rs)
+pub fn deck() -> Vec<Card> {
+    STANDARD_DECK.to_vec()
+}
+
+// Goes through the deck without copying it, for when the cards are only read
+pub fn deck_iter() -> impl Iterator<Item = &'static Card> {
+    STANDARD_DECK.iter()
+}
+
+pub fn shuffled_deck(rng: &mut impl Rng) -> Vec<Card> {
+    let mut deck = deck();
+    deck.shuffle(rng);
+    deck
+}
+
+// The 36-card deck for short deck (6+) poker, which removes the 2s through 5s.
+pub fn short_deck() -> Vec<Card> {
+    deck().into_iter().filter(|c| c.rank >= 6).collect()
 }
 
 pub fn cards2str(cards: &[Card]) -> String {
@@ -143,35 +446,71 @@ pub fn cards2str(cards: &[Card]) -> String {
 }
 
 pub fn strvec2cards(strvec: &[&str]) -> Vec<Card> {
+    try_strvec2cards(strvec).unwrap()
+}
+
+// Same as strvec2cards, but returns an error for the first malformed card
+// string instead of panicking.
+pub fn try_strvec2cards(strvec: &[&str]) -> Result<Vec<Card>, CardParseError> {
     let mut cardvec = Vec::new();
     for card in strvec {
-        cardvec.push(Card::new(card));
+        cardvec.push(Card::try_new(card)?);
+    }
+    Ok(cardvec)
+}
+
+// Parses a compact hand string like "AhKd2c" into its cards. Rust's orphan
+// rules don't allow implementing FromStr for Vec<Card>, so this is the
+// equivalent for whole hands.
+pub fn str2cards(hand_str: &str) -> Result<Vec<Card>, CardParseError> {
+    let chars: Vec<char> = hand_str.chars().collect();
+    let mut cardvec = Vec::new();
+    for card in chars.chunks(2) {
+        let card: String = card.iter().collect();
+        cardvec.push(card.parse()?);
     }
-    cardvec
+    Ok(cardvec)
 }
 
+const PBAR_TEMPLATE: &str = "[{elapsed_precise}/{eta_precise}] {wide_bar} {pos:>7}/{len:7} {msg}";
+// Make sure the drawing doesn't dominate computation for large n
+const PBAR_DRAW_HZ: u8 = 25;
+
 pub fn pbar(n: u64) -> indicatif::ProgressBar {
-    let bar = indicatif::ProgressBar::new(n);
-    bar.set_style(
-        indicatif::ProgressStyle::default_bar()
-            .template("[{elapsed_precise}/{eta_precise}] {wide_bar} {pos:>7}/{len:7} {msg}"),
+    let bar = indicatif::ProgressBar::with_draw_target(
+        Some(n),
+        indicatif::ProgressDrawTarget::stderr_with_hz(PBAR_DRAW_HZ),
     );
-    // make sure the drawing doesn't dominate computation for large n
-    bar.set_draw_delta(n / 100_000);
+    // A bad template only costs us the formatting, so fall back to the default
+    let style = indicatif::ProgressStyle::with_template(PBAR_TEMPLATE)
+        .unwrap_or_else(|_| indicatif::ProgressStyle::default_bar());
+    bar.set_style(style);
+    bar
+}
+
+pub fn pbar_with_message(n: u64, msg: &str) -> indicatif::ProgressBar {
+    let bar = pbar(n);
+    bar.set_message(msg.to_string());
     bar
 }
 
 // canonical / archetypal hand methods
 // thanks to stackoverflow user Daniel Slutzbach: https://stackoverflow.com/a/3831682
 
-// returns true if the given list of ints contains duplicate elements.
-fn contains_duplicates(list: &[u8]) -> bool {
-    for i in 0..list.len() {
-        for j in i + 1..list.len() {
-            if &list[i] == &list[j] {
-                return true;
-            }
+// returns true if the given list of ints contains duplicate elements. Keeps a
+// bitset of the values seen so far, which is O(n) without allocating
+// anything. A HashSet is O(n) too, but the allocation makes it slower than
+// the old nested loop on the short lists this gets (see
+// benches/contains_duplicates.rs).
+pub fn contains_duplicates(list: &[u8]) -> bool {
+    let mut seen = [0_u64; 4];
+    for &x in list {
+        let word = (x / 64) as usize;
+        let bit = 1 << (x % 64);
+        if seen[word] & bit != 0 {
+            return true;
         }
+        seen[word] |= bit;
     }
     false
 }
@@ -257,11 +596,13 @@ pub fn is_canonical(cards: &[Card], streets: bool) -> bool {
     true
 }
 
-fn sort_canonical(cards: &[Card], streets: bool) -> Vec<Card> {
+// Sorts the first n_hole cards and the rest of the cards separately. With
+// n_hole = 0 all the cards are sorted together.
+fn sort_canonical(cards: &[Card], n_hole: usize) -> Vec<Card> {
     let mut sorted;
-    if streets && cards.len() > 2 {
-        let mut preflop = (&cards[..2]).to_vec();
-        let mut board = (&cards[2..]).to_vec();
+    if n_hole > 0 && cards.len() > n_hole {
+        let mut preflop = (&cards[..n_hole]).to_vec();
+        let mut board = (&cards[n_hole..]).to_vec();
         preflop.sort_by_key(|c| (c.suit.clone(), c.rank));
         board.sort_by_key(|c| (c.suit.clone(), c.rank));
         sorted = [preflop, board].concat();
@@ -280,143 +621,1087 @@ fn sort_canonical(cards: &[Card], streets: bool) -> Vec<Card> {
 // flush of diamonds. This function maps the set of all hands to the much
 // smaller set of distinct isomorphic hands.
 pub fn canonical_hand(cards: &[Card], streets: bool) -> Vec<Card> {
-    let cards = &sort_canonical(&cards, streets);
-    // Separate the cards by suit
-    let mut by_suits: Vec<Vec<u8>> = Vec::new();
-    for suit in 0..4 {
-        let ranks = c![card.rank, for card in cards, if card.suit == suit];
-        by_suits.push(ranks.to_vec());
+    canonical_hand_with_hole(cards, if streets { 2 } else { 0 })
+}
+
+// Same as canonical_hand(), but each thread remembers the canonical forms of
+// the last CANONICAL_CACHE_SIZE hands it was given. Training keeps seeing the
+// same hands, so the abstraction lookups go through this.
+pub fn canonical_hand_cached(cards: &[Card], streets: bool) -> Vec<Card> {
+    // The key only has room for 8 cards
+    if cards.len() > 8 {
+        return canonical_hand(cards, streets);
+    }
+    let key = canonical_cache_key(cards, streets);
+    let cached = CANONICAL_CACHE.with(|cache| cache.borrow_mut().get(&key).cloned());
+    match cached {
+        Some(canonical) => {
+            CANONICAL_CACHE_HITS.with(|hits| hits.set(hits.get() + 1));
+            canonical
+        }
+        None => {
+            CANONICAL_CACHE_MISSES.with(|misses| misses.set(misses.get() + 1));
+            let canonical = canonical_hand(cards, streets);
+            CANONICAL_CACHE.with(|cache| cache.borrow_mut().put(key, canonical.clone()));
+            canonical
+        }
+    }
+}
+
+const CANONICAL_CACHE_SIZE: usize = 4096;
+
+thread_local! {
+    static CANONICAL_CACHE: RefCell<LruCache<(u64, usize, bool), Vec<Card>>> = RefCell::new(
+        LruCache::new(NonZeroUsize::new(CANONICAL_CACHE_SIZE).unwrap())
+    );
+    // Counted per thread like the caches, since shared counters would be
+    // contended on every call
+    static CANONICAL_CACHE_HITS: Cell<u64> = Cell::new(0);
+    static CANONICAL_CACHE_MISSES: Cell<u64> = Cell::new(0);
+}
+
+// Hits and misses of this thread's canonical_hand_cached() cache so far
+pub fn canonical_cache_stats() -> (u64, u64) {
+    (
+        CANONICAL_CACHE_HITS.with(|hits| hits.get()),
+        CANONICAL_CACHE_MISSES.with(|misses| misses.get()),
+    )
+}
+
+// The cards packed into a u64 after sorting the hole cards and the board like
+// canonical_hand() does, so every ordering of a hand gets the same key. Each
+// card takes a byte, 15 * suit + rank, which is never zero, so a hand can't
+// get the same key as a shorter one.
+fn canonical_cache_key(cards: &[Card], streets: bool) -> (u64, usize, bool) {
+    let n = cards.len();
+    let split = if streets && n > 2 { 2 } else { 0 };
+    let mut sorted = [(0_u8, 0_u8); 8];
+    for (i, card) in cards.iter().enumerate() {
+        sorted[i] = (card.suit, card.rank);
+    }
+    sorted[..split].sort_unstable();
+    sorted[split..n].sort_unstable();
+    let mut packed = 0;
+    for (i, (suit, rank)) in sorted[..n].iter().enumerate() {
+        packed += ((15 * suit + rank) as u64) << (8 * i);
+    }
+    (packed, n, streets)
+}
+
+// Like canonical_hand(), but the first n_hole cards are kept apart from the
+// board instead of assuming 2 hole cards. This is for games like Omaha.
+pub fn canonical_hand_with_hole(cards: &[Card], n_hole: usize) -> Vec<Card> {
+    // This runs for every table lookup in training, so it avoids allocating
+    // anything but the result. Most of the time used to go to the Vecs for
+    // the suits and the sorting.
+    let split = if n_hole > 0 && cards.len() > n_hole {
+        n_hole
+    } else {
+        0
+    };
+    let mut canonical = cards.to_vec();
+    sort_streets(&mut canonical, split);
+
+    // Separate the cards by suit. by_suits[suit] holds the ranks of that suit
+    // in the order they appear.
+    let mut by_suits = [[0_u8; 13]; 4];
+    let mut lens = [0; 4];
+    for card in &canonical {
+        let suit = card.suit as usize;
+        by_suits[suit][lens[suit]] = card.rank;
+        lens[suit] += 1;
+    }
+
+    // Define a mapping from old suits to new suits. suit_mapping[old_suit] = new_suit.
+    // The suits are taken in size order with lexicographic tie breaking.
+    let mut suit_mapping = [0; 4];
+    let mut used = [false; 4];
+    for new_suit in 0..4 {
+        let mut max = (0..4).find(|s| !used[*s]).unwrap();
+        for old_suit in 0..4 {
+            if used[old_suit] {
+                continue;
+            }
+            // The next suit must have the largest length, using lower lexicographic ordering
+            // to break ties.
+            let longer = lens[old_suit] > lens[max];
+            let lower = lens[old_suit] == lens[max]
+                && by_suits[old_suit][..lens[old_suit]] < by_suits[max][..lens[max]];
+            if longer || lower {
+                max = old_suit;
+            }
+        }
+        suit_mapping[max] = new_suit;
+        used[max] = true;
+    }
+    for card in canonical.iter_mut() {
+        card.suit = suit_mapping[card.suit as usize];
+    }
+    sort_streets(&mut canonical, split);
+    canonical
+}
+
+// Sorts the cards before and after split separately, in place
+fn sort_streets(cards: &mut [Card], split: usize) {
+    let (hole, board) = cards.split_at_mut(split);
+    hole.sort_unstable_by_key(|c| (c.suit, c.rank));
+    board.sort_unstable_by_key(|c| (c.suit, c.rank));
+}
+
+// Which deck the game is dealt from. Short deck changes the hand rankings, so
+// it needs its own hand strength table.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeckVariant {
+    Standard,
+    ShortDeck,
+}
+
+// Same as cards2hand(&canonical_hand(cards, streets)), but without any heap
+// allocation, since it's on the hot path of every table lookup. Works on at
+// most 8 cards, the most that fit in the u64 representation.
+pub fn canonical_hand_id(cards: &[Card], streets: bool) -> u64 {
+    let n = cards.len();
+    assert!(n <= 8, "Too many cards for a u64 hand");
+    let split = if streets && n > 2 { 2 } else { 0 };
+    // (suit, rank) pairs, sorted the same way as sort_canonical()
+    let mut sorted = [(0_u8, 0_u8); 8];
+    for (i, card) in cards.iter().enumerate() {
+        sorted[i] = (card.suit, card.rank);
+    }
+    sorted[..split].sort_unstable();
+    sorted[split..n].sort_unstable();
+
+    // The ranks of each suit in the order they appear
+    let mut by_suits = [[0_u8; 8]; 4];
+    let mut lens = [0; 4];
+    for (suit, rank) in &sorted[..n] {
+        let suit = *suit as usize;
+        by_suits[suit][lens[suit]] = *rank;
+        lens[suit] += 1;
+    }
+
+    // Same suit mapping as canonical_hand(): longest suits first, with
+    // lexicographically lower ranks breaking ties
+    let mut suit_mapping = [0; 4];
+    let mut used = [false; 4];
+    for new_suit in 0..4 {
+        let mut max = (0..4).find(|s| !used[*s]).unwrap();
+        for old_suit in 0..4 {
+            if used[old_suit] {
+                continue;
+            }
+            let longer = lens[old_suit] > lens[max];
+            let lower = lens[old_suit] == lens[max]
+                && by_suits[old_suit][..lens[old_suit]] < by_suits[max][..lens[max]];
+            if longer || lower {
+                max = old_suit;
+            }
+        }
+        suit_mapping[max] = new_suit;
+        used[max] = true;
+    }
+
+    for card in sorted[..n].iter_mut() {
+        card.0 = suit_mapping[card.0 as usize];
+    }
+    sorted[..split].sort_unstable();
+    sorted[split..n].sort_unstable();
+    let mut id = 0;
+    for (i, (suit, rank)) in sorted[..n].iter().enumerate() {
+        id += ((15 * suit + rank) as u64) << (8 * i);
+    }
+    id
+}
+
+// Whether one hand can be turned into the other by relabelling the suits, ie
+// whether they have the same canonical form. With streets the hole cards are
+// kept apart from the board, like canonical_hand().
+pub fn hands_isomorphic(h1: &[Card], h2: &[Card], streets: bool) -> bool {
+    if h1.len() != h2.len() {
+        return false;
+    }
+    if h1.len() <= 8 {
+        return canonical_hand_id(h1, streets) == canonical_hand_id(h2, streets);
+    }
+    let n_hole = if streets { 2 } else { 0 };
+    canonical_hand_with_hole(h1, n_hole) == canonical_hand_with_hole(h2, n_hole)
+}
+
+// A suit permutation p, with p[old_suit] = new_suit, that turns the cards in
+// from into the cards in to. Order doesn't matter, and suits that aren't in
+// from can go anywhere. None if the hands aren't isomorphic.
+pub fn suit_permutation_that_maps(from: &[Card], to: &[Card]) -> Option<[u8; 4]> {
+    if from.len() != to.len() {
+        return None;
+    }
+    let mut target: Vec<(u8, u8)> = to.iter().map(|c| (c.suit, c.rank)).collect();
+    target.sort_unstable();
+    for perm in (0..4_u8).permutations(4) {
+        let mut mapped: Vec<(u8, u8)> = from
+            .iter()
+            .map(|c| (perm[c.suit as usize], c.rank))
+            .collect();
+        mapped.sort_unstable();
+        if mapped == target {
+            return Some([perm[0], perm[1], perm[2], perm[3]]);
+        }
+    }
+    None
+}
+
+// For fast poker hand comparison, look up relative strength values in a table
+pub struct HandTable {
+    strengths: StrengthLookup,
+}
+
+enum StrengthLookup {
+    // Canonical 7-card hand -> strength, all in memory
+    Memory(HandData),
+    // A memory-mapped file of (canonical 5-card hand, strength) records sorted
+    // by hand, from build_mmap_index()
+    Mapped(memmap2::Mmap),
+    // Canonical 5-card hand -> strength, for tables too small to bother with
+    // 7-card hands
+    FiveCard(HashMap<Vec<Card>, i32>),
+}
+
+impl HandTable {
+    // Loads the 7-card table. If it hasn't been built yet, the 5-card table is
+    // used instead, which gets built too if it's missing.
+    pub fn new() -> HandTable {
+        let paths = table_paths();
+        if !Path::new(&paths.hand_table).exists() {
+            println!(
+                "[INFO] No 7-card hand table at {}, using the 5-card one",
+                paths.hand_table
+            );
+            return HandTable::load_or_build(&paths.light_hand_table);
+        }
+        HandTable {
+            strengths: StrengthLookup::Memory(HandTable::load_hand_strengths()),
+        }
+    }
+
+    // Wraps 7-card strengths that are already in memory, like a subset of the
+    // full table for tests
+    pub fn from_hand_data(strengths: HandData) -> HandTable {
+        HandTable {
+            strengths: StrengthLookup::Memory(strengths),
+        }
+    }
+
+    // Ranks every 5-card hand from scratch instead of reading a file. Lookups
+    // are slower than with the 7-card table, since every 5-card subset of the
+    // hand has to be checked.
+    pub fn build_from_deck() -> HandTable {
+        HandTable {
+            strengths: StrengthLookup::FiveCard(build_standard_strengths()),
+        }
+    }
+
+    // Reads the 5-card strengths in the strengths.json format from path, or
+    // builds them and saves them there if there's no file yet.
+    pub fn load_or_build(path: &str) -> HandTable {
+        HandTable {
+            strengths: StrengthLookup::FiveCard(load_or_build_strengths(path)),
+        }
+    }
+
+    // Loads the strengths for the given deck. The short deck table is a 5-card
+    // table, which gets built the first time it's needed.
+    pub fn with_variant(variant: DeckVariant) -> HandTable {
+        match variant {
+            DeckVariant::Standard => HandTable::new(),
+            DeckVariant::ShortDeck => {
+                let path = &table_paths().short_deck_hand_table;
+                if File::open(path).is_err() {
+                    write_short_deck_strengths(path);
+                }
+                HandTable {
+                    strengths: StrengthLookup::FiveCard(read_strengths_json(path)),
+                }
+            }
+        }
+    }
+
+    // Opt-in alternative to new() that memory-maps a 5-card index file instead
+    // of loading the whole 7-card table onto the heap. The OS only pages in
+    // the parts of the file that get used, at the cost of checking all 21
+    // 5-card subsets of a 7-card hand.
+    pub fn mmap(path: &str) -> Result<HandTable, io::Error> {
+        let file = File::open(path)?;
+        let index = unsafe { memmap2::Mmap::map(&file)? };
+        if index.len() % INDEX_RECORD_SIZE != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "hand strength index has a partial record",
+            ));
+        }
+        Ok(HandTable {
+            strengths: StrengthLookup::Mapped(index),
+        })
+    }
+
+    pub fn hand_strength(&self, hand: &[Card]) -> i32 {
+        match &self.strengths {
+            StrengthLookup::Memory(strengths) => {
+                strengths.get(&canonical_hand_id(hand, false)).clone()
+            }
+            StrengthLookup::Mapped(index) => hand
+                .iter()
+                .cloned()
+                .combinations(5)
+                .map(|five_card| {
+                    let canonical = canonical_hand(&five_card, false);
+                    lookup_index(index, cards2hand(&canonical)).expect("Hand not found in index")
+                })
+                .max()
+                .unwrap(),
+            StrengthLookup::FiveCard(strengths) => hand
+                .iter()
+                .cloned()
+                .combinations(5)
+                .map(|five_card| {
+                    let canonical = canonical_hand(&five_card, false);
+                    strengths.get(&canonical).unwrap().clone()
+                })
+                .max()
+                .unwrap(),
+        }
+    }
+
+    // Like hand_strength(), but also takes 5 and 6-card hands when this is the
+    // 7-card table, by looking those up in the 5-card table instead
+    pub fn any_size_strength(&self, hand: &[Card]) -> i32 {
+        match &self.strengths {
+            StrengthLookup::Memory(_) if hand.len() < 7 => five_card_hand_strength(hand),
+            _ => self.hand_strength(hand),
+        }
+    }
+
+    fn load_hand_strengths() -> HandData {
+        match File::open(&table_paths().hand_table) {
+            Err(_e) => panic!("Hand table not found"),
+            Ok(file) => HandData::read_serialized(file),
+        }
+    }
+}
+
+// Slower 5-card lookup table which uses a lot less memory than the normal fast
+// HandTable. This has the benefit of reducing startup time.
+pub struct LightHandTable {
+    strengths: HashMap<Vec<Card>, i32>,
+}
+
+impl LightHandTable {
+    pub fn new() -> LightHandTable {
+        LightHandTable {
+            strengths: LightHandTable::load_hand_strengths(),
+        }
+    }
+
+    pub fn hand_strength(&self, hand: &[Card]) -> i32 {
+        // Return the best hand out of all 5-card subsets
+        let mut max_strength = 0;
+        for five_card in hand.iter().cloned().combinations(5) {
+            let canonical = canonical_hand(&five_card, false);
+            let strength = self.strengths.get(&canonical).unwrap().clone();
+            if strength > max_strength {
+                max_strength = strength;
+            }
+        }
+        max_strength
+    }
+
+    fn load_hand_strengths() -> HashMap<Vec<Card>, i32> {
+        load_or_build_strengths(&table_paths().light_hand_table)
+    }
+}
+
+fn load_or_build_strengths(path: &str) -> HashMap<Vec<Card>, i32> {
+    if Path::new(path).exists() {
+        return read_strengths_json(path);
+    }
+    println!("[INFO] No hand table at {}, building one.", path);
+    let strengths = build_standard_strengths();
+    write_strengths_json(&strengths, path);
+    strengths
+}
+
+// Reads the 5-card hand strengths from the JSON file, keyed by the hand's cards
+fn read_strengths_json(path: &str) -> HashMap<Vec<Card>, i32> {
+    let str_map: HashMap<String, i32> = match File::open(path) {
+        Err(e) => panic!("Hand table {} not found: {}", path, e),
+        Ok(mut file) => {
+            // Load up the hand table from the JSON
+            let mut buffer = String::new();
+            file.read_to_string(&mut buffer).expect("Error");
+            serde_json::from_str(&buffer).unwrap()
+        }
+    };
+    // Translate the card strings to Vec<Card> keys
+    let mut vec_map: HashMap<Vec<Card>, i32> = HashMap::new();
+    for (hand, strength) in str_map {
+        let cards = vec![
+            &hand[0..2],
+            &hand[2..4],
+            &hand[4..6],
+            &hand[6..8],
+            &hand[8..10],
+        ];
+        let cards = strvec2cards(&cards);
+        vec_map.insert(cards, strength);
+    }
+    vec_map
+}
+
+// Returns the strength of the best 5-card hand under short deck rankings.
+pub fn short_deck_hand_strength(hand: &[Card]) -> i32 {
+    SHORT_DECK_HAND_TABLE.hand_strength(hand)
+}
+
+// Returns the strength of a hand of at least 5 cards from the 5-card table.
+// HAND_TABLE is the 7-card table when it's been built, which can't look up
+// hands of any other size.
+pub fn five_card_hand_strength(hand: &[Card]) -> i32 {
+    FIVE_CARD_HAND_TABLE.hand_strength(hand)
+}
+
+// The (count, rank) groups of a 5-card hand, biggest groups first and then
+// highest ranks first, along with just the ranks in that order and whether
+// the hand is a flush.
+fn rank_groups(hand: &[Card]) -> (Vec<(usize, u8)>, Vec<u8>, bool) {
+    let mut counts: HashMap<u8, usize> = HashMap::new();
+    for card in hand {
+        *counts.entry(card.rank).or_insert(0) += 1;
+    }
+    let mut groups: Vec<(usize, u8)> = counts.iter().map(|(r, c)| (*c, *r)).collect();
+    groups.sort_by(|a, b| b.cmp(a));
+    let ranks: Vec<u8> = groups.iter().map(|g| g.1).collect();
+    let flush = hand.iter().all(|c| c.suit == hand[0].suit);
+    (groups, ranks, flush)
+}
+
+// Ranks a 5-card hand under the usual rules as its category followed by the
+// ranks that break ties within the category. The ace plays low in A-2-3-4-5.
+fn standard_rank(hand: &[Card]) -> (u8, Vec<u8>) {
+    let (groups, ranks, flush) = rank_groups(hand);
+    let straight_high = if ranks.len() < 5 {
+        None
+    } else if ranks[0] - ranks[4] == 4 {
+        Some(ranks[0])
+    } else if ranks == [14, 5, 4, 3, 2] {
+        Some(5)
+    } else {
+        None
+    };
+
+    match (straight_high, groups[0].0, groups.get(1).map(|g| g.0)) {
+        (Some(high), _, _) if flush => (8, vec![high]),
+        (_, 4, _) => (7, ranks),
+        (_, 3, Some(2)) => (6, ranks),
+        _ if flush => (5, ranks),
+        (Some(high), _, _) => (4, vec![high]),
+        (_, 3, _) => (3, ranks),
+        (_, 2, Some(2)) => (2, ranks),
+        (_, 2, _) => (1, ranks),
+        _ => (0, ranks),
+    }
+}
+
+// Every canonical 5-card hand and its strength under the usual rules, the
+// same as strengths.json. Hands that tie share the strength of the first of
+// them in order, so the 134,459 hands have 7462 different strengths.
+pub fn build_standard_strengths() -> HashMap<Vec<Card>, i32> {
+    let hands: HashSet<u64> = deck()
+        .into_iter()
+        .combinations(5)
+        .map(|hand| canonical_hand_id(&hand, false))
+        .collect();
+    let mut ranked: Vec<((u8, Vec<u8>), Vec<Card>)> = hands
+        .into_iter()
+        .map(|hand| {
+            let cards = hand2cards(hand);
+            (standard_rank(&cards), cards)
+        })
+        .collect();
+    ranked.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut strengths = HashMap::new();
+    let mut strength = 0;
+    for (i, (rank, hand)) in ranked.iter().enumerate() {
+        if i > 0 && ranked[i - 1].0 != *rank {
+            strength = i as i32;
+        }
+        strengths.insert(hand.clone(), strength);
+    }
+    strengths
+}
+
+fn write_strengths_json(strengths: &HashMap<Vec<Card>, i32>, path: &str) {
+    let strengths: HashMap<String, i32> = strengths
+        .iter()
+        .map(|(hand, strength)| (cards2str(hand), *strength))
+        .collect();
+    let file = File::create(path).unwrap();
+    serde_json::to_writer_pretty(BufWriter::new(file), &strengths).unwrap();
+}
+
+// Ranks a 5-card hand under short deck rules as its category followed by the
+// ranks that break ties within the category. Flushes beat full houses, three
+// of a kind beats straights, and the ace plays low in A-6-7-8-9.
+fn short_deck_rank(hand: &[Card]) -> (u8, Vec<u8>) {
+    let (groups, ranks, flush) = rank_groups(hand);
+    let straight_high = if ranks.len() < 5 {
+        None
+    } else if ranks[0] - ranks[4] == 4 {
+        Some(ranks[0])
+    } else if ranks == [14, 9, 8, 7, 6] {
+        Some(9)
+    } else {
+        None
+    };
+
+    match (straight_high, groups[0].0, groups.get(1).map(|g| g.0)) {
+        (Some(high), _, _) if flush => (8, vec![high]),
+        (_, 4, _) => (7, ranks),
+        _ if flush => (6, ranks),
+        (_, 3, Some(2)) => (5, ranks),
+        (_, 3, _) => (4, ranks),
+        (Some(high), _, _) => (3, vec![high]),
+        (_, 2, Some(2)) => (2, ranks),
+        (_, 2, _) => (1, ranks),
+        _ => (0, ranks),
+    }
+}
+
+// Writes the short deck 5-card strengths in the same JSON format as the
+// standard strengths.json. Hands that tie get the same strength.
+pub fn write_short_deck_strengths(path: &str) {
+    println!("[INFO] Preparing the short deck hand strengths.");
+    let hands: HashSet<Vec<Card>> = short_deck()
+        .into_iter()
+        .combinations(5)
+        .map(|hand| canonical_hand(&hand, false))
+        .collect();
+    let mut ranked: Vec<((u8, Vec<u8>), Vec<Card>)> = hands
+        .into_iter()
+        .map(|hand| (short_deck_rank(&hand), hand))
+        .collect();
+    ranked.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut strengths: HashMap<Vec<Card>, i32> = HashMap::new();
+    let mut strength = 0;
+    for (i, (rank, hand)) in ranked.iter().enumerate() {
+        if i > 0 && ranked[i - 1].0 != *rank {
+            strength += 1;
+        }
+        strengths.insert(hand.clone(), strength);
+    }
+    write_strengths_json(&strengths, path);
+}
+
+// Each record in the hand strength index is a little-endian u64 hand followed
+// by a little-endian i32 strength.
+const INDEX_RECORD_SIZE: usize = 12;
+
+// Writes the 5-card strengths from the JSON file as a flat index for
+// HandTable::mmap(), sorted by hand so that it can be binary searched.
+pub fn build_mmap_index(src_json: &str, dst_bin: &str) {
+    let strengths = read_strengths_json(src_json);
+    let mut records: Vec<(u64, i32)> = strengths
+        .iter()
+        .map(|(cards, strength)| (cards2hand(&canonical_hand(cards, false)), *strength))
+        .collect();
+    records.sort();
+    let mut buffer = Vec::with_capacity(records.len() * INDEX_RECORD_SIZE);
+    for (hand, strength) in records {
+        buffer.extend_from_slice(&hand.to_le_bytes());
+        buffer.extend_from_slice(&strength.to_le_bytes());
+    }
+    let mut file = File::create(dst_bin).unwrap();
+    file.write_all(&buffer).unwrap();
+}
+
+fn lookup_index(index: &[u8], hand: u64) -> Option<i32> {
+    let record = |i: usize| &index[i * INDEX_RECORD_SIZE..(i + 1) * INDEX_RECORD_SIZE];
+    let mut lo = 0;
+    let mut hi = index.len() / INDEX_RECORD_SIZE;
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        let mut key = [0; 8];
+        key.copy_from_slice(&record(mid)[..8]);
+        let key = u64::from_le_bytes(key);
+        if key < hand {
+            lo = mid + 1;
+        } else if key > hand {
+            hi = mid;
+        } else {
+            let mut strength = [0; 4];
+            strength.copy_from_slice(&record(mid)[8..]);
+            return Some(i32::from_le_bytes(strength));
+        }
+    }
+    None
+}
+
+// Writes a file containing all canonical river hand strengths. This can be used
+// if you want to convert 5-card lookup table to a 7-card lookup table for a
+// lookup speed boost. I wish I had more RAM.
+pub fn bootstrap_river_strengths() {
+    let canonical = load_river_canonical();
+    // The 7-card table is what we're building, so the strengths have to come
+    // from the best 5-card subset of each hand
+    let five_card_table = LightHandTable::new();
+    write_river_strengths(&canonical, &five_card_table, &table_paths().hand_table);
+}
+
+// Writes the strengths of the given canonical 7-card hands to path, in the
+// format HandData::read_serialized() reads
+pub fn write_river_strengths(
+    canonical: &HashSet<u64>,
+    five_card_table: &LightHandTable,
+    path: &str,
+) {
+    let mut buffer = File::create(path).unwrap();
+    let bar = pbar(canonical.len() as u64);
+    for hand in canonical {
+        let strength = five_card_table.hand_strength(&hand2cards(hand.clone()));
+        let to_write = format!("{} {}\n", hand2str(hand.clone()), strength);
+        buffer.write(to_write.as_bytes()).unwrap();
+        bar.inc(1);
+    }
+    bar.finish();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HandCategory {
+    HighCard,
+    Pair,
+    TwoPair,
+    ThreeOfAKind,
+    Straight,
+    Flush,
+    FullHouse,
+    FourOfAKind,
+    StraightFlush,
+    RoyalFlush,
+}
+
+// The hand strengths are ordered by category, so each category covers a range
+// of strengths. These are the weakest strengths in each category, in the same
+// order as HandCategory.
+const CATEGORY_MIN_STRENGTHS: [i32; 10] = [
+    0, 63850, 121050, 127914, 132204, 132704, 133981, 134293, 134449, 134458,
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HandDetail {
+    pub category: HandCategory,
+    pub strength: i32,
+    // Ranks that decide between two hands of the same category, most
+    // important first. For example a pair of kings with A 9 4 is [13, 14, 9, 4].
+    pub kickers: Vec<u8>,
+}
+
+pub fn category_from_strength(strength: i32) -> HandCategory {
+    let categories = [
+        HandCategory::HighCard,
+        HandCategory::Pair,
+        HandCategory::TwoPair,
+        HandCategory::ThreeOfAKind,
+        HandCategory::Straight,
+        HandCategory::Flush,
+        HandCategory::FullHouse,
+        HandCategory::FourOfAKind,
+        HandCategory::StraightFlush,
+        HandCategory::RoyalFlush,
+    ];
+    let index = CATEGORY_MIN_STRENGTHS
+        .iter()
+        .rposition(|min| strength >= *min)
+        .expect("Invalid hand strength");
+    categories[index]
+}
+
+pub fn hand_category(hand: &[Card]) -> HandCategory {
+    category_from_strength(HAND_TABLE.any_size_strength(hand))
+}
+
+pub fn hand_details(hand: &[Card]) -> HandDetail {
+    let strength = HAND_TABLE.any_size_strength(hand);
+    let category = category_from_strength(strength);
+    HandDetail {
+        category: category,
+        strength: strength,
+        kickers: kickers(hand, category),
+    }
+}
+
+// Figures out the tie-breaking ranks of the best 5-card hand of the given
+// category that can be made from the cards.
+fn kickers(hand: &[Card], category: HandCategory) -> Vec<u8> {
+    // Ranks grouped by how many times they appear, biggest groups first and
+    // then highest ranks first
+    let mut counts: HashMap<u8, usize> = HashMap::new();
+    for card in hand {
+        *counts.entry(card.rank).or_insert(0) += 1;
+    }
+    let mut groups: Vec<(usize, u8)> = counts.iter().map(|(r, c)| (*c, *r)).collect();
+    groups.sort_by(|a, b| b.cmp(a));
+    let ranks_except = |used: &[u8], n: usize| -> Vec<u8> {
+        let mut rest: Vec<u8> = groups
+            .iter()
+            .map(|(_, r)| *r)
+            .filter(|r| !used.contains(r))
+            .collect();
+        rest.sort_by(|a, b| b.cmp(a));
+        rest.truncate(n);
+        rest
+    };
+    let flush_ranks = || {
+        let suit = (0..4)
+            .find(|s| hand.iter().filter(|c| c.suit == *s).count() >= 5)
+            .expect("No flush in hand");
+        let mut ranks: Vec<u8> = hand
+            .iter()
+            .filter(|c| c.suit == suit)
+            .map(|c| c.rank)
+            .collect();
+        ranks.sort_by(|a, b| b.cmp(a));
+        ranks
+    };
+
+    match category {
+        HandCategory::HighCard => ranks_except(&[], 5),
+        HandCategory::Pair | HandCategory::ThreeOfAKind | HandCategory::FourOfAKind => {
+            let top = groups[0].1;
+            let n_kickers = 5 - groups[0].0;
+            [vec![top], ranks_except(&[top], n_kickers)].concat()
+        }
+        HandCategory::TwoPair => {
+            // With 7 cards there can be three pairs, in which case the lowest
+            // pair can still play as the kicker
+            let pairs = [groups[0].1, groups[1].1];
+            [pairs.to_vec(), ranks_except(&pairs, 1)].concat()
+        }
+        HandCategory::FullHouse => {
+            let trips = groups[0].1;
+            let pair = groups
+                .iter()
+                .filter(|(c, r)| *c >= 2 && *r != trips)
+                .map(|(_, r)| *r)
+                .max()
+                .unwrap();
+            vec![trips, pair]
+        }
+        HandCategory::Straight => {
+            let ranks: Vec<u8> = hand.iter().map(|c| c.rank).collect();
+            vec![straight_high_card(&ranks).expect("No straight in hand")]
+        }
+        HandCategory::Flush => {
+            let mut ranks = flush_ranks();
+            ranks.truncate(5);
+            ranks
+        }
+        HandCategory::StraightFlush | HandCategory::RoyalFlush => {
+            vec![straight_high_card(&flush_ranks()).expect("No straight flush in hand")]
+        }
     }
+}
 
-    // Define a mapping from old suits to new suits. suit_mapping[old_suit] = new_suit.
-    let mut suit_mapping = [0, 0, 0, 0];
+// Everything about a hand's value in one place: its strength, what it is, the
+// ranks that make it up and the kickers that break ties.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HandEvaluation {
+    pub strength: i32,
+    pub category: HandCategory,
+    // The ranks that make up the hand itself, eg the pair's rank, both ranks
+    // of a full house or the top card of a straight
+    pub primary_ranks: Vec<u8>,
+    // The rest of the tie-breaking ranks, highest first. The lower cards of a
+    // flush or high card hand count as kickers.
+    pub kicker_ranks: Vec<u8>,
+}
 
-    // Retrieve the suits in size order with lexicographic tie breaking
+pub fn hand_evaluation(hand: &[Card]) -> HandEvaluation {
+    hand_evaluation_with(&HAND_TABLE, hand)
+}
 
-    let mut unused_suits = vec![0, 1, 2, 3];
-    for new_suit in 0..4 {
-        let mut max = unused_suits[0];
-        for old_suit in &unused_suits {
-            let old_suit = *old_suit as usize;
-            // The next suit must have the largest length, using lower lexicographic ordering
-            // to break ties.
-            if by_suits[old_suit].len() > by_suits[max].len() {
-                max = old_suit;
-            } else if by_suits[old_suit].len() == by_suits[max].len()
-                && by_suits[old_suit] < by_suits[max]
-            {
-                max = old_suit;
-            }
-        }
-        suit_mapping[max] = new_suit;
-        // Wipe the current suit in by_suits so it doesn't get used twice
-        unused_suits.retain(|s| s != &max);
+// Like hand_evaluation(), but with the strength from the given table. Takes
+// hands of 5 to 7 cards whichever table it is.
+pub fn hand_evaluation_with(table: &HandTable, hand: &[Card]) -> HandEvaluation {
+    let strength = table.any_size_strength(hand);
+    let category = category_from_strength(strength);
+    let mut primary_ranks = kickers(hand, category);
+    let n_primary = match category {
+        HandCategory::TwoPair | HandCategory::FullHouse => 2,
+        _ => 1,
+    };
+    let kicker_ranks = primary_ranks.split_off(n_primary);
+    HandEvaluation {
+        strength: strength,
+        category: category,
+        primary_ranks: primary_ranks,
+        kicker_ranks: kicker_ranks,
     }
-    let mut canonical = Vec::new();
-    for card in cards {
-        canonical.push(Card {
-            rank: card.rank,
-            suit: suit_mapping[card.suit as usize],
-        });
+}
+
+fn rank_name(rank: u8) -> &'static str {
+    match rank {
+        2 => "Two",
+        3 => "Three",
+        4 => "Four",
+        5 => "Five",
+        6 => "Six",
+        7 => "Seven",
+        8 => "Eight",
+        9 => "Nine",
+        10 => "Ten",
+        11 => "Jack",
+        12 => "Queen",
+        13 => "King",
+        14 => "Ace",
+        _ => panic!("Bad rank value"),
     }
-    canonical = sort_canonical(&canonical, streets);
-    canonical
 }
 
-// For fast poker hand comparison, look up relative strength values in a table
-pub struct HandTable {
-    strengths: HandData,
+fn rank_name_plural(rank: u8) -> String {
+    match rank {
+        6 => "Sixes".to_string(),
+        _ => format!("{}s", rank_name(rank)),
+    }
 }
 
-impl HandTable {
-    pub fn new() -> HandTable {
-        HandTable {
-            strengths: HandTable::load_hand_strengths(),
+// eg "Pair of Kings, A-9-4 kickers" or "Full house, Sevens full of Nines"
+impl fmt::Display for HandEvaluation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let primary = &self.primary_ranks;
+        match self.category {
+            HandCategory::HighCard => write!(f, "High card, {}-high", rank_name(primary[0]))?,
+            HandCategory::Pair => write!(f, "Pair of {}", rank_name_plural(primary[0]))?,
+            HandCategory::TwoPair => write!(
+                f,
+                "Two pair, {} and {}",
+                rank_name_plural(primary[0]),
+                rank_name_plural(primary[1])
+            )?,
+            HandCategory::ThreeOfAKind => {
+                write!(f, "Three of a kind, {}", rank_name_plural(primary[0]))?
+            }
+            HandCategory::Straight => write!(f, "Straight, {}-high", rank_name(primary[0]))?,
+            HandCategory::Flush => write!(f, "Flush, {}-high", rank_name(primary[0]))?,
+            HandCategory::FullHouse => write!(
+                f,
+                "Full house, {} full of {}",
+                rank_name_plural(primary[0]),
+                rank_name_plural(primary[1])
+            )?,
+            HandCategory::FourOfAKind => {
+                write!(f, "Four of a kind, {}", rank_name_plural(primary[0]))?
+            }
+            HandCategory::StraightFlush => {
+                write!(f, "Straight flush, {}-high", rank_name(primary[0]))?
+            }
+            HandCategory::RoyalFlush => write!(f, "Royal flush")?,
         }
+        if !self.kicker_ranks.is_empty() {
+            let kickers: Vec<&str> = self.kicker_ranks.iter().map(|r| rank_str(*r)).collect();
+            let plural = if kickers.len() > 1 { "s" } else { "" };
+            write!(f, ", {} kicker{}", kickers.join("-"), plural)?;
+        }
+        Ok(())
     }
+}
 
-    pub fn hand_strength(&self, hand: &[Card]) -> i32 {
-        let canonical = canonical_hand(&hand, false);
-        let compact = cards2hand(&canonical);
-        let strength = self.strengths.get(&compact).clone();
-        strength
+// Returns the top rank of the best straight that can be made from the ranks,
+// where the wheel (A 2 3 4 5) counts as 5-high.
+fn straight_high_card(ranks: &[u8]) -> Option<u8> {
+    let has = |rank: u8| ranks.contains(&rank) || (rank == 1 && ranks.contains(&14));
+    (5..=14)
+        .rev()
+        .find(|high| (high - 4..=*high).all(|r| has(r)))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoardTexture {
+    // All the cards are the same suit
+    pub monotone: bool,
+    // Exactly two suits on the board
+    pub two_tone: bool,
+    // No two cards share a suit
+    pub rainbow: bool,
+    pub paired: bool,
+    pub double_paired: bool,
+    // At least one card ten or higher
+    pub has_broadway: bool,
+    // From 0.0 when no two ranks are within a straight of each other, to 1.0
+    // when all the ranks are consecutive
+    pub connectedness: f64,
+    // Whether a player could be drawing to a flush or a straight. Always false
+    // on the river since there are no cards to come.
+    pub flush_draw_possible: bool,
+    pub straight_draw_possible: bool,
+}
+
+// Describes the texture of a flop, turn or river board.
+pub fn board_texture(board: &[Card]) -> BoardTexture {
+    let mut suit_counts = [0; 4];
+    let mut rank_counts: HashMap<u8, usize> = HashMap::new();
+    for card in board {
+        suit_counts[card.suit as usize] += 1;
+        *rank_counts.entry(card.rank).or_insert(0) += 1;
     }
+    let n_suits = suit_counts.iter().filter(|c| **c > 0).count();
+    let n_pairs = rank_counts.values().filter(|c| **c >= 2).count();
+    let mut ranks: Vec<u8> = rank_counts.keys().cloned().collect();
+    ranks.sort();
+
+    // Each gap between neighbouring ranks scores 1.0 when there's no gap, down
+    // to 0.0 once the ranks are too far apart to be in a straight together
+    let connectedness = if ranks.len() < 2 {
+        0.0
+    } else {
+        let total: f64 = ranks
+            .windows(2)
+            .map(|w| (1.0 - (w[1] - w[0] - 1) as f64 / 4.0).max(0.0))
+            .sum();
+        total / (ranks.len() - 1) as f64
+    };
 
-    fn load_hand_strengths() -> HandData {
-        match File::open(HAND_TABLE_PATH) {
-            Err(_e) => panic!("Hand table not found"),
-            Ok(file) => HandData::read_serialized(file),
-        }
+    // A player can only be drawing to a straight if two of the board's ranks
+    // fit in the same five-rank window. The ace also plays low.
+    let has = |rank: u8| ranks.contains(&rank) || (rank == 1 && ranks.contains(&14));
+    let straight_window = (5..=14).any(|high| (high - 4..=high).filter(|r| has(*r)).count() >= 2);
+    let cards_to_come = board.len() < 5;
+
+    BoardTexture {
+        monotone: n_suits == 1,
+        two_tone: n_suits == 2,
+        rainbow: n_suits == board.len(),
+        paired: n_pairs >= 1,
+        double_paired: n_pairs >= 2,
+        has_broadway: ranks.iter().any(|r| *r >= 10),
+        connectedness: connectedness,
+        flush_draw_possible: cards_to_come && suit_counts.iter().any(|c| *c >= 2),
+        straight_draw_possible: cards_to_come && straight_window,
     }
 }
 
-// Slower 5-card lookup table which uses a lot less memory than the normal fast
-// HandTable. This has the benefit of reducing startup time.
-pub struct LightHandTable {
-    strengths: HashMap<Vec<Card>, i32>,
+// The draws a hand has on the flop or turn. Each field lists the cards left in
+// the deck that complete that kind of draw, so it's empty when the hand
+// doesn't have one. Backdoor draws need two more cards and only count on the
+// flop; their cards are the ones that would turn them into a regular draw.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DrawInfo {
+    pub flush_draws: Vec<Card>,
+    // Includes double gutshots, which have just as many outs
+    pub open_ended_straight_draws: Vec<Card>,
+    pub gutshot_straight_draws: Vec<Card>,
+    pub backdoor_flush_draws: Vec<Card>,
+    pub backdoor_straight_draws: Vec<Card>,
 }
 
-impl LightHandTable {
-    pub fn new() -> LightHandTable {
-        LightHandTable {
-            strengths: LightHandTable::load_hand_strengths(),
-        }
+// Finds the flush and straight draws of the hole cards on the board. Draws
+// have to use at least one hole card, and a hand that already has a straight
+// isn't drawing to one.
+pub fn draws(hand: &[Card], board: &[Card]) -> DrawInfo {
+    let mut info = DrawInfo::default();
+    if board.len() < 3 || board.len() >= 5 {
+        return info;
     }
+    let cards = [hand, board].concat();
+    let mut deck = deck();
+    deck.retain(|c| !cards.contains(&c));
 
-    pub fn hand_strength(&self, hand: &[Card]) -> i32 {
-        // Return the best hand out of all 5-card subsets
-        let mut max_strength = 0;
-        for five_card in hand.iter().combinations(5) {
-            let canonical = canonical_hand(&deepcopy(&five_card), false);
-            let strength = self.strengths.get(&canonical).unwrap().clone();
-            if strength > max_strength {
-                max_strength = strength;
-            }
+    for suit in 0..4 {
+        let n_suited = cards.iter().filter(|c| c.suit == suit).count();
+        if !hand.iter().any(|c| c.suit == suit) {
+            continue;
+        }
+        let suited: Vec<Card> = deck.iter().filter(|c| c.suit == suit).cloned().collect();
+        if n_suited == 4 {
+            info.flush_draws.extend(suited);
+        } else if n_suited == 3 && board.len() == 3 {
+            info.backdoor_flush_draws.extend(suited);
         }
-        max_strength
     }
 
-    fn load_hand_strengths() -> HashMap<Vec<Card>, i32> {
-        let str_map: HashMap<String, i32> = match File::open(LIGHT_HAND_TABLE_PATH) {
-            Err(e) => panic!("Hand table not found"),
-            Ok(mut file) => {
-                // Load up the hand table from the JSON
-                let mut buffer = String::new();
-                file.read_to_string(&mut buffer).expect("Error");
-                serde_json::from_str(&buffer).unwrap()
-            }
-        };
-        // Translate the card strings to Vec<Card> keys
-        let mut vec_map: HashMap<Vec<Card>, i32> = HashMap::new();
-        for (hand, strength) in str_map {
-            let cards = vec![
-                &hand[0..2],
-                &hand[2..4],
-                &hand[4..6],
-                &hand[6..8],
-                &hand[8..10],
-            ];
-            let cards = strvec2cards(&cards);
-            vec_map.insert(cards, strength);
-        }
-        vec_map
+    let ranks: Vec<u8> = cards.iter().map(|c| c.rank).collect();
+    let board_ranks: Vec<u8> = board.iter().map(|c| c.rank).collect();
+    if straight_high_card(&ranks).is_some() {
+        return info;
+    }
+    // Whether adding the ranks makes a straight that needs the hole cards
+    let completes = |extra: &[u8]| {
+        straight_high_card(&[&ranks[..], extra].concat()).is_some()
+            && straight_high_card(&[&board_ranks[..], extra].concat()).is_none()
+    };
+    let outs: Vec<u8> = (2..=14).filter(|r| completes(&[*r])).collect();
+    let cards_of = |ranks: &[u8]| -> Vec<Card> {
+        deck.iter()
+            .filter(|c| ranks.contains(&c.rank))
+            .cloned()
+            .collect()
+    };
+    if outs.len() >= 2 {
+        info.open_ended_straight_draws = cards_of(&outs);
+    } else if outs.len() == 1 {
+        info.gutshot_straight_draws = cards_of(&outs);
     }
+    if board.len() == 3 {
+        let backdoor: Vec<u8> = (2..=14)
+            .filter(|r| {
+                !outs.contains(r) && (2..=14).any(|r2| !outs.contains(&r2) && completes(&[*r, r2]))
+            })
+            .collect();
+        info.backdoor_straight_draws = cards_of(&backdoor);
+    }
+    info
 }
 
-// Writes a file containing all canonical river hand strengths. This can be used
-// if you want to convert 5-card lookup table to a 7-card lookup table for a
-// lookup speed boost. I wish I had more RAM.
-fn bootstrap_river_strengths() {
-    let canonical = load_river_canonical();
-    let mut buffer = File::create("products/strengths7.txt").unwrap();
-    let bar = pbar(canonical.len() as u64);
-    for hand in canonical {
-        let strength = HAND_TABLE.hand_strength(&hand2cards(hand));
-        let to_write = format!("{} {}\n", hand2str(hand.clone()), strength);
-        buffer.write(to_write.as_bytes()).unwrap();
-        bar.inc(1);
+// Returns the cards left in the deck that would make the hand stronger if
+// they came next.
+pub fn outs(hand: &[Card], board: &[Card]) -> Vec<Card> {
+    outs_with(&HAND_TABLE, hand, board)
+}
+
+// Like outs(), but with the strengths from the given table
+pub fn outs_with(table: &HandTable, hand: &[Card], board: &[Card]) -> Vec<Card> {
+    improving_cards(table, hand, board)
+        .into_iter()
+        .map(|(card, _strength)| card)
+        .collect()
+}
+
+// Same as outs(), but only the cards that improve the hand to at least the
+// target category. For example the outs of a flush draw are the cards that
+// make it at least a Flush.
+pub fn outs_to_category(hand: &[Card], board: &[Card], target: HandCategory) -> Vec<Card> {
+    improving_cards(&HAND_TABLE, hand, board)
+        .into_iter()
+        .filter(|(_card, strength)| category_from_strength(*strength) >= target)
+        .map(|(card, _strength)| card)
+        .collect()
+}
+
+// The cards that improve the hand, paired with the strength they improve it to.
+// Before the river the hand is only 5 or 6 cards, which the 7-card table can't
+// look up on its own.
+fn improving_cards(table: &HandTable, hand: &[Card], board: &[Card]) -> Vec<(Card, i32)> {
+    let cards = [hand, board].concat();
+    let strength = table.any_size_strength(&cards);
+    let mut improving = Vec::new();
+    for card in deck() {
+        if cards.contains(&card) {
+            continue;
+        }
+        let new_strength = table.any_size_strength(&[&cards[..], &[card.clone()]].concat());
+        if new_strength > strength {
+            improving.push((card, new_strength));
+        }
     }
-    bar.finish();
+    improving
 }
 
 // u64 hand representation
@@ -432,14 +1717,30 @@ fn bootstrap_river_strengths() {
 // small memory footprint without needing to use Rust's lifetime parameters.
 
 pub fn card(hand: u64, card_index: i32) -> i32 {
+    debug_assert!(
+        (0..8).contains(&card_index),
+        "card index {} out of range",
+        card_index
+    );
     ((hand & 0xFF << 8 * card_index) >> 8 * card_index) as i32
 }
 
+// The smallest valid card byte is 2c (2) and the largest is As (15 * 3 + 14).
+fn debug_check_card(card: i32) {
+    debug_assert!(
+        (2..60).contains(&card) && (2..15).contains(&(card % 15)),
+        "invalid card byte {}",
+        card
+    );
+}
+
 pub fn suit(card: i32) -> i32 {
+    debug_check_card(card);
     card / 15 as i32
 }
 
 pub fn rank(card: i32) -> i32 {
+    debug_check_card(card);
     card % 15 as i32
 }
 
@@ -449,7 +1750,8 @@ pub fn len(hand: u64) -> i32 {
             return n as i32;
         }
     }
-    -1
+    // All 8 bytes are in use
+    8
 }
 
 pub fn str2hand(hand_str: &str) -> u64 {
@@ -480,7 +1782,9 @@ pub fn str2hand(hand_str: &str) -> u64 {
             _ => panic!("bad card string"),
         };
         let card = (15 * suit + rank) as u64;
-        result += card << 4 * i
+        // i steps through the string two characters at a time, so the card
+        // index is i / 2 and each card takes up one byte.
+        result += card << 8 * (i / 2);
     }
     result
 }
@@ -544,20 +1848,44 @@ pub fn cards2hand(cards: &[Card]) -> u64 {
 }
 
 pub fn load_flop_canonical() -> HashSet<u64> {
-    load_canonical(5, FLOP_CANONICAL_PATH)
+    load_canonical(5, &table_paths().flop_canonical)
 }
 
 pub fn load_turn_canonical() -> HashSet<u64> {
-    load_canonical(6, TURN_CANONICAL_PATH)
+    load_canonical(6, &table_paths().turn_canonical)
 }
 
 pub fn load_river_canonical() -> HashSet<u64> {
     println!("[INFO] Loading canonical river hands.");
-    let canonical = load_canonical(7, RIVER_CANONICAL_PATH);
+    let canonical = load_or_build_canonical_river();
     println!("[INFO] Done.");
     canonical
 }
 
+// Reads the canonical river hands from the bincode cache. If there's no cache
+// yet, the hands come from the old text cache if there is one, or get dealt
+// out from scratch otherwise (which takes hours), and are then cached.
+pub fn load_or_build_canonical_river() -> HashSet<u64> {
+    let paths = table_paths();
+    if let Ok(file) = File::open(&paths.river_canonical_bin) {
+        let reader = BufReader::new(file);
+        return bincode::deserialize_from(reader)
+            .expect("Failed to deserialize canonical river hands");
+    }
+    let canonical = if Path::new(&paths.river_canonical).exists() {
+        load_canonical(7, &paths.river_canonical)
+    } else {
+        deal_canonical(7)
+    };
+    let writer = BufWriter::new(File::create(&paths.river_canonical_bin).unwrap());
+    bincode::serialize_into(writer, &canonical).unwrap();
+    println!(
+        "[INFO] Wrote canonical hands to {}.",
+        paths.river_canonical_bin
+    );
+    canonical
+}
+
 fn load_canonical(n_cards: usize, path: &str) -> HashSet<u64> {
     let mut canonical = HashSet::new();
     match File::open(path) {
@@ -581,7 +1909,7 @@ fn load_canonical(n_cards: usize, path: &str) -> HashSet<u64> {
     canonical
 }
 
-fn deal_canonical(n_cards: usize) -> HashSet<u64> {
+pub fn deal_canonical(n_cards: usize) -> HashSet<u64> {
     match n_cards {
         5 => println!("[INFO] Finding all canonical flop hands."),
         6 => println!("[INFO] Finding all canonical turn hands."),
@@ -589,24 +1917,56 @@ fn deal_canonical(n_cards: usize) -> HashSet<u64> {
         _ => panic!("Bad number of cards"),
     };
 
-    let mut canonical: HashSet<u64> = HashSet::new();
     let deck = deck();
-    let bar = pbar((combinations(52, 2) * combinations(50, (n_cards - 2) as u64)) as u64);
-    for preflop in deck.iter().combinations(2) {
-        let mut subdeck = deck.clone();
-        subdeck.retain(|c| !preflop.contains(&c));
-        for board in subdeck.iter().combinations(n_cards - 2) {
-            let hand = [deepcopy(&preflop), deepcopy(&board)].concat();
-            let hand_str = cards2str(&canonical_hand(&hand, true));
-            let hand = str2hand(&hand_str);
-            canonical.insert(hand);
-            bar.inc(1);
-        }
-    }
+    let n_boards = combinations(50, (n_cards - 2) as u64) as u64;
+    let bar = pbar(combinations(52, 2) as u64 * n_boards);
+    // Each preflop hand is dealt out on its own rayon task, and the canonical
+    // hands found for each one are merged together at the end.
+    let preflops: Vec<Vec<Card>> = deck.iter().cloned().combinations(2).collect();
+    let canonical = preflops
+        .par_iter()
+        .map(|preflop| {
+            let mut found: HashSet<u64> = HashSet::new();
+            let mut subdeck = deck.clone();
+            subdeck.retain(|c| !preflop.contains(c));
+            for board in subdeck.iter().cloned().combinations(n_cards - 2) {
+                let hand = [&preflop[..], &board].concat();
+                found.insert(cards2hand(&canonical_hand(&hand, true)));
+            }
+            // Update the progress bar once per preflop hand to keep the
+            // threads from fighting over it
+            bar.inc(n_boards);
+            found
+        })
+        .reduce(HashSet::new, |mut a, b| {
+            a.extend(b);
+            a
+        });
     bar.finish();
     canonical
 }
 
+// Samples k canonical hands of n cards uniformly at random, without listing
+// all of them like deal_canonical() does. Every canonical hand is one
+// particular deal, so dealing random hands and keeping only the ones that are
+// already canonical is uniform over canonical hands. Canonicalizing every deal
+// instead would favour hands with more suit isomorphs. About 1 in 19 flop
+// deals is kept.
+pub fn sample_canonical(n: u32, k: usize, rng: &mut impl Rng, streets: bool) -> Vec<Vec<Card>> {
+    let n = n as usize;
+    let split = if streets && n > 2 { 2 } else { 0 };
+    let deck = deck();
+    let mut hands = Vec::with_capacity(k);
+    while hands.len() < k {
+        let mut hand: Vec<Card> = deck.choose_multiple(rng, n).cloned().collect();
+        hand = sort_canonical(&hand, split);
+        if is_canonical(&hand, streets) {
+            hands.push(hand);
+        }
+    }
+    hands
+}
+
 // Returns the second moment of the hand's equity distribution.
 pub fn expected_hs2(hand: u64) -> f64 {
     // For river hands, this just returns HS^2 since there is no distribution
@@ -622,9 +1982,13 @@ pub fn expected_hs2(hand: u64) -> f64 {
         return equity.powi(2);
     }
 
-    for rollout in deck.iter().combinations(7 - hand.len()) {
-        let full_hand = [hand.clone(), deepcopy(&rollout)].concat();
-        let equity = EQUITY_TABLE.lookup(&full_hand);
+    let full_hands: Vec<Vec<Card>> = deck
+        .iter()
+        .cloned()
+        .combinations(7 - hand.len())
+        .map(|rollout| [hand.clone(), rollout].concat())
+        .collect();
+    for equity in EQUITY_TABLE.lookup_batch(&full_hands) {
         sum += equity.powi(2);
         count += 1.0;
     }
@@ -632,7 +1996,28 @@ pub fn expected_hs2(hand: u64) -> f64 {
     average
 }
 
-fn river_equity(hand: &[Card]) -> f64 {
+// Looks up the river equities of many 7-card hands in the shared equity table.
+pub fn lookup_river_equities(hands: &[Vec<Card>]) -> Vec<f64> {
+    EQUITY_TABLE.lookup_batch(hands)
+}
+
+// Settings for building the river equity table. Exact equities enumerate every
+// opponent hand, while approximate ones sample n_samples of them.
+pub struct EquityConfig {
+    pub exact: bool,
+    pub n_samples: usize,
+}
+
+impl Default for EquityConfig {
+    fn default() -> EquityConfig {
+        EquityConfig {
+            exact: true,
+            n_samples: 1000,
+        }
+    }
+}
+
+pub fn river_equity(hand: &[Card]) -> f64 {
     let mut deck = deck();
     // Remove the already-dealt cards from the deck
     deck.retain(|c| !hand.contains(&c));
@@ -643,12 +2028,12 @@ fn river_equity(hand: &[Card]) -> f64 {
 
     let rng = &mut rand::thread_rng();
 
-    for opp_preflop in deck.iter().combinations(2) {
+    for opp_preflop in deck.iter().cloned().combinations(2) {
         n_runs += 1;
 
         // Create the poker hands by concatenating cards
         let my_hand = hand.to_vec();
-        let opp_hand = [deepcopy(&opp_preflop), board.clone()].concat();
+        let opp_hand = [opp_preflop, board.clone()].concat();
 
         let my_strength = HAND_TABLE.hand_strength(&my_hand);
         let opp_strength = HAND_TABLE.hand_strength(&opp_hand);
@@ -663,6 +2048,284 @@ fn river_equity(hand: &[Card]) -> f64 {
     equity
 }
 
+// Approximates river_equity() by sampling random opponent hands instead of
+// enumerating all of them.
+pub fn river_equity_mc(hand: &[Card], n_samples: usize, rng: &mut impl Rng) -> f64 {
+    let mut deck = deck();
+    deck.retain(|c| !hand.contains(&c));
+
+    let board = &hand[2..];
+    let my_strength = HAND_TABLE.hand_strength(hand);
+    let mut n_wins = 0.0;
+    for _ in 0..n_samples {
+        let opp_preflop: Vec<Card> = deck.choose_multiple(rng, 2).cloned().collect();
+        let opp_hand = [&opp_preflop[..], board].concat();
+        let opp_strength = HAND_TABLE.hand_strength(&opp_hand);
+        if my_strength > opp_strength {
+            n_wins += 1.0;
+        } else if my_strength == opp_strength {
+            n_wins += 0.5;
+        }
+    }
+    n_wins / (n_samples as f64)
+}
+
+// Estimates the equity of the hole cards against a random hand on any street,
+// without the equity table. Each sample deals the opponent two cards and runs
+// out the rest of the board.
+pub fn monte_carlo_equity(
+    hand: &[Card],
+    board: &[Card],
+    n_samples: usize,
+    rng: &mut impl Rng,
+) -> f64 {
+    monte_carlo_equity_vs(hand, &[], board, n_samples, rng)
+}
+
+// Same as monte_carlo_equity(), but against the given opponent hole cards, so
+// only the board gets run out. With no opponent cards it's a random hand.
+pub fn monte_carlo_equity_vs(
+    hand: &[Card],
+    opp_hand: &[Card],
+    board: &[Card],
+    n_samples: usize,
+    rng: &mut impl Rng,
+) -> f64 {
+    let mut deck = deck();
+    deck.retain(|c| !hand.contains(c) && !opp_hand.contains(c) && !board.contains(c));
+
+    let n_opp = 2 - opp_hand.len();
+    let n_runout = 5 - board.len();
+    let mut n_wins = 0.0;
+    for _ in 0..n_samples {
+        let dealt: Vec<Card> = deck
+            .choose_multiple(rng, n_opp + n_runout)
+            .cloned()
+            .collect();
+        let full_board = [board, &dealt[n_opp..]].concat();
+        let my_strength = HAND_TABLE.hand_strength(&[hand, &full_board].concat());
+        let opp_strength =
+            HAND_TABLE.hand_strength(&[opp_hand, &dealt[..n_opp], &full_board].concat());
+        if my_strength > opp_strength {
+            n_wins += 1.0;
+        } else if my_strength == opp_strength {
+            n_wins += 0.5;
+        }
+    }
+    n_wins / (n_samples as f64)
+}
+
+// The fraction of opponent hands that the hole cards beat at showdown, with
+// ties counting half, over every opponent hand and every runout of the rest of
+// the board. This is exact but slow before the turn, and far too slow
+// preflop, where relative_hand_strength_mc() should be used instead.
+pub fn relative_hand_strength(hand: &[Card], board: &[Card]) -> f64 {
+    let mut deck = deck();
+    deck.retain(|c| !hand.contains(&c) && !board.contains(&c));
+
+    let runouts: Vec<Vec<Card>> = deck.iter().cloned().combinations(5 - board.len()).collect();
+    let (wins, total) = runouts
+        .par_iter()
+        .map(|runout| {
+            let full_board = [board, runout].concat();
+            let my_strength = HAND_TABLE.hand_strength(&[hand, &full_board].concat());
+            let mut wins = 0.0;
+            let mut total = 0.0;
+            for opp in deck.iter().cloned().combinations(2) {
+                if runout.contains(&opp[0]) || runout.contains(&opp[1]) {
+                    continue;
+                }
+                let opp_strength = HAND_TABLE.hand_strength(&[opp, full_board.clone()].concat());
+                if my_strength > opp_strength {
+                    wins += 1.0;
+                } else if my_strength == opp_strength {
+                    wins += 0.5;
+                }
+                total += 1.0;
+            }
+            (wins, total)
+        })
+        .reduce(|| (0.0, 0.0), |a, b| (a.0 + b.0, a.1 + b.1));
+    wins / total
+}
+
+// Monte Carlo version of relative_hand_strength(). Sampling an opponent hand
+// and a runout is exactly what monte_carlo_equity() does.
+pub fn relative_hand_strength_mc(
+    hand: &[Card],
+    board: &[Card],
+    n: usize,
+    rng: &mut impl Rng,
+) -> f64 {
+    monte_carlo_equity(hand, board, n, rng)
+}
+
+const MULTIWAY_EQUITY_SAMPLES: usize = 10_000;
+
+// Each player's share of the pot when 2 to 9 players see the rest of the board
+// out, found by sampling runouts. Ties split the pot evenly, so the equities
+// sum to 1.
+pub fn multiway_equity(hands: &[Vec<Card>], board: &[Card]) -> Vec<f64> {
+    let deck = multiway_deck(hands, board);
+    if board.len() == 5 {
+        return multiway_equity_exact(hands, board);
+    }
+    let mut rng = rand::thread_rng();
+    let mut equities = vec![0.0; hands.len()];
+    for _ in 0..MULTIWAY_EQUITY_SAMPLES {
+        let runout: Vec<Card> = deck
+            .choose_multiple(&mut rng, 5 - board.len())
+            .cloned()
+            .collect();
+        split_pot(hands, &[board, &runout].concat(), &mut equities);
+    }
+    equities
+        .iter()
+        .map(|e| e / MULTIWAY_EQUITY_SAMPLES as f64)
+        .collect()
+}
+
+// Same as multiway_equity(), but goes through every possible runout. Only
+// practical from the turn on, or the flop with few players.
+pub fn multiway_equity_exact(hands: &[Vec<Card>], board: &[Card]) -> Vec<f64> {
+    let deck = multiway_deck(hands, board);
+    let mut equities = vec![0.0; hands.len()];
+    let mut n_runouts = 0;
+    for runout in deck.iter().cloned().combinations(5 - board.len()) {
+        split_pot(hands, &[board, &runout].concat(), &mut equities);
+        n_runouts += 1;
+    }
+    equities.iter().map(|e| e / n_runouts as f64).collect()
+}
+
+// The equity of each combo in my_range against opp_range, averaged over the
+// opponent combos that it and the board don't block. Each matchup is run out
+// with multiway_equity(). A combo that clashes with the board, or that blocks
+// every opponent combo, has no equity and gets NaN.
+pub fn range_vs_range_equity(
+    my_range: &HandRange,
+    opp_range: &HandRange,
+    board: &[Card],
+) -> Vec<f64> {
+    range_matchups(my_range, opp_range, board)
+        .iter()
+        .map(|(total, n)| total / *n as f64)
+        .collect()
+}
+
+// The average equity of my_range against opp_range over every matchup that
+// the cards allow, so a combo counts for more when fewer opponent combos are
+// blocked.
+pub fn range_equity(my_range: &HandRange, opp_range: &HandRange, board: &[Card]) -> f64 {
+    let (total, n) = range_matchups(my_range, opp_range, board)
+        .iter()
+        .fold((0.0, 0), |(total, n), (t, m)| (total + t, n + m));
+    total / n as f64
+}
+
+// How much of its raw equity a starting hand tends to realize postflop, as a
+// multiplier between 0.5 and 1.5. Suited and connected hands make disguised
+// flushes and straights, so they win more than their share, while low,
+// disconnected hands are often forced to fold before showdown. Roughly
+// calibrated against published heads-up realization numbers: AA is about 1.0,
+// JTs about 1.25, K2o about 0.75 and 72o about 0.65.
+pub fn equity_realization_factor(hand: &[Card; 2]) -> f64 {
+    let high = hand[0].rank.max(hand[1].rank);
+    let low = hand[0].rank.min(hand[1].rank);
+    let suited = hand[0].suit == hand[1].suit;
+    let mut factor: f64 = 1.0;
+    if high != low {
+        // The number of ranks between the two cards, where an ace can also
+        // play low for the wheel
+        let mut gap = high - low - 1;
+        if high == 14 {
+            gap = gap.min(low - 2);
+        }
+        if suited {
+            factor += match gap {
+                0..=1 => 0.15,
+                2..=3 => 0.1,
+                _ => 0.05,
+            };
+        }
+        factor += match gap {
+            0 => 0.1,
+            1 => 0.075,
+            2 => 0.05,
+            3 => 0.0,
+            // Offsuit hands this far apart rarely make anything but one pair
+            _ if !suited => -0.15,
+            _ => 0.0,
+        };
+    }
+    // Cards below 8 make weak pairs and the bottom end of straights
+    for &rank in &[high, low] {
+        if rank < 8 {
+            factor -= 0.1;
+        }
+    }
+    factor.clamp(0.5, 1.5)
+}
+
+// For each of my combos, the summed equity over the opponent combos it can
+// face, and how many of them there are.
+fn range_matchups(
+    my_range: &HandRange,
+    opp_range: &HandRange,
+    board: &[Card],
+) -> Vec<(f64, usize)> {
+    let opp_range = opp_range.remove_blockers(board);
+    my_range
+        .combos()
+        .par_iter()
+        .map(|combo| {
+            if board.contains(&combo[0]) || board.contains(&combo[1]) {
+                return (0.0, 0);
+            }
+            let mut total = 0.0;
+            let mut n = 0;
+            for opp in opp_range.remove_blockers(combo).combos() {
+                total += multiway_equity(&[combo.to_vec(), opp.to_vec()], board)[0];
+                n += 1;
+            }
+            (total, n)
+        })
+        .collect()
+}
+
+// Checks that the hands and board make sense together, and returns the cards
+// that are left to deal.
+fn multiway_deck(hands: &[Vec<Card>], board: &[Card]) -> Vec<Card> {
+    assert!(
+        (2..=9).contains(&hands.len()),
+        "Need 2 to 9 players, got {}",
+        hands.len()
+    );
+    assert!(board.len() <= 5, "Too many board cards");
+    let dealt: Vec<&Card> = hands.iter().flatten().chain(board).collect();
+    for (i, card) in dealt.iter().enumerate() {
+        assert!(!dealt[i + 1..].contains(card), "{} was dealt twice", card);
+    }
+    let mut deck = deck();
+    deck.retain(|c| !dealt.contains(&c));
+    deck
+}
+
+// Adds each player's share of one pot to equities, given the full board.
+fn split_pot(hands: &[Vec<Card>], board: &[Card], equities: &mut [f64]) {
+    let strengths: Vec<i32> = hands
+        .iter()
+        .map(|h| HAND_TABLE.hand_strength(&[&h[..], board].concat()))
+        .collect();
+    let best = *strengths.iter().max().unwrap();
+    let n_winners = strengths.iter().filter(|s| **s == best).count() as f64;
+    for (equity, strength) in equities.iter_mut().zip(&strengths) {
+        if *strength == best {
+            *equity += 1.0 / n_winners;
+        }
+    }
+}
+
 // For many applications (abstraction, hand strength, equity lookup) I need to
 // be able to store and lookup an integer corresponding to each hand
 pub struct HandData {
@@ -680,6 +2343,12 @@ impl HandData {
         self.data.get(hand).unwrap().clone()
     }
 
+    // Same as get(), but returns None instead of panicking if the hand is
+    // missing.
+    pub fn try_get(&self, hand: &u64) -> Option<i32> {
+        self.data.get(hand).cloned()
+    }
+
     pub fn insert(&mut self, hand: &u64, data: i32) {
         self.data.insert(hand.clone(), data);
     }
@@ -688,6 +2357,10 @@ impl HandData {
         self.data.len()
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = (&u64, &i32)> {
+        self.data.iter()
+    }
+
     // There are multiple places where I have to serialize a HashMap of cards->i32
     // with some sort of data such as hand strength or abstraction ID. This loads
     // that data from a file desciptor and returns the HashMap lookup table.
@@ -715,85 +2388,274 @@ impl HandData {
     }
 }
 
-struct EquityTable {
+// How many river equities have been looked up, for the server's metrics
+static EQUITY_TABLE_LOOKUPS: AtomicU64 = AtomicU64::new(0);
+
+pub fn equity_table_lookup_count() -> u64 {
+    EQUITY_TABLE_LOOKUPS.load(Ordering::Relaxed)
+}
+
+pub struct EquityTable {
     table: HashMap<u64, f64>,
 }
 
 impl EquityTable {
+    // Wraps equities that are already in memory, keyed by
+    // canonical_hand_id(hand, true)
+    pub fn from_table(table: HashMap<u64, f64>) -> EquityTable {
+        EquityTable { table: table }
+    }
+
     fn new() -> EquityTable {
-        match File::open(EQUITY_TABLE_PATH) {
+        let paths = table_paths();
+        if File::open(&paths.equity_table).is_err() && File::open(&paths.equity_table_text).is_ok()
+        {
+            migrate_equity_text_to_bincode(&paths.equity_table_text, &paths.equity_table);
+        }
+        match File::open(&paths.equity_table) {
             Err(_e) => {
-                let table = EquityTable::create();
+                let table = EquityTable::create(&EquityConfig::default());
                 EquityTable { table: table }
             }
             Ok(file) => {
-                // Read from the file
                 println!("[INFO] Loading the equity lookup table.");
-                let mut table = HashMap::new();
-                let reader = BufReader::new(file);
-                for line in reader.lines() {
-                    let line_str = line.unwrap();
-                    let mut data = line_str.split_whitespace();
-                    let hand = data.next().unwrap();
-                    let equity = data.next().unwrap();
-                    let hand = str2hand(hand);
-                    let equity: f64 = equity.to_string().parse().unwrap();
-                    table.insert(hand, equity);
-                }
+                let table = read_equity_table(file);
                 println!("[INFO] Done loading the equity lookup table.");
                 EquityTable { table: table }
             }
         }
     }
 
-    fn create() -> HashMap<u64, f64> {
+    fn create(config: &EquityConfig) -> HashMap<u64, f64> {
         println!("[INFO] Creating the river equity lookup table...");
         let canonical = load_river_canonical();
         let bar = pbar(canonical.len() as u64);
         let equities: Vec<(u64, f64)> = canonical
             .par_iter()
             .map(|h| {
-                let equity = river_equity(&hand2cards(h.clone()));
+                let hand = hand2cards(h.clone());
+                let equity = if config.exact {
+                    river_equity(&hand)
+                } else {
+                    river_equity_mc(&hand, config.n_samples, &mut rand::thread_rng())
+                };
                 bar.inc(1);
                 (h.clone(), equity)
             })
             .collect();
 
         bar.finish();
-        let mut table = HashMap::new();
-        // Serialize the equity table and construct the HashMap to return
-        let mut buffer = File::create(EQUITY_TABLE_PATH).unwrap();
-        for (hand, equity) in &equities {
-            let to_write = format!("{} {}\n", hand2str(hand.clone()), equity);
-            buffer.write(to_write.as_bytes()).unwrap();
-            table.insert(hand.clone(), equity.clone());
-        }
+        let table: HashMap<u64, f64> = equities.into_iter().collect();
+        write_equity_table(&table, &table_paths().equity_table);
         println!("[INFO] Done creating the river equity lookup table.");
         table
     }
 
     pub fn lookup(&self, hand: &[Card]) -> f64 {
-        let hand = cards2hand(&canonical_hand(hand, true));
+        EQUITY_TABLE_LOOKUPS.fetch_add(1, Ordering::Relaxed);
+        let hand = canonical_hand_id(hand, true);
         self.table.get(&hand).unwrap().clone()
     }
+
+    // Looks up many river hands at once, spread across rayon's threads. The
+    // equities come back in the same order as the hands.
+    pub fn lookup_batch(&self, hands: &[Vec<Card>]) -> Vec<f64> {
+        hands.par_iter().map(|hand| self.lookup(hand)).collect()
+    }
+}
+
+pub fn write_equity_table(table: &HashMap<u64, f64>, path: &str) {
+    let bincode: Vec<u8> = bincode::serialize(table).unwrap();
+    let mut file = File::create(path).unwrap();
+    file.write_all(&bincode).unwrap();
+}
+
+pub fn read_equity_table(file: File) -> HashMap<u64, f64> {
+    let reader = BufReader::new(file);
+    bincode::deserialize_from(reader).expect("Failed to deserialize equity table")
+}
+
+// Converts an equity table from the old text format to bincode, which is
+// much smaller on disk and faster to load.
+pub fn migrate_equity_text_to_bincode(text_path: &str, bin_path: &str) {
+    println!("[INFO] Converting {} to {}.", text_path, bin_path);
+    let file = File::open(text_path).expect("Equity table not found");
+    let mut table = HashMap::new();
+    let reader = BufReader::new(file);
+    for line in reader.lines() {
+        let line_str = line.unwrap();
+        let mut data = line_str.split_whitespace();
+        let hand = data.next().unwrap();
+        let equity = data.next().unwrap();
+        let hand = str2hand(hand);
+        let equity: f64 = equity.to_string().parse().unwrap();
+        table.insert(hand, equity);
+    }
+    write_equity_table(&table, bin_path);
+}
+
+// Compares the 7-card throughput of the LightHandTable, which checks all 21
+// 5-card subsets, with the direct 7-card lookup in the HandTable.
+pub fn benchmark_hand_evaluator() {
+    let light_table = LightHandTable::new();
+    benchmark_hands("LightHandTable", |hand| light_table.hand_strength(hand));
+    lazy_static::initialize(&HAND_TABLE);
+    benchmark_hands("HandTable", |hand| HAND_TABLE.hand_strength(hand));
 }
 
-fn benchmark_hand_evaluator() {
+fn benchmark_hands(name: &str, hand_strength: impl Fn(&[Card]) -> i32) {
     let n = 1_000_000;
+    // Deal the hands up front so the timing is just the lookups. Overlapping
+    // windows of one deck would keep hitting the same few hands.
     let mut deck = deck();
-    let mut rng = &mut rand::thread_rng();
-    deck.shuffle(&mut rng);
-    lazy_static::initialize(&HAND_TABLE);
+    let mut rng = rand::thread_rng();
+    let hands: Vec<Vec<Card>> = (0..n)
+        .map(|_| deck.partial_shuffle(&mut rng, 7).0.to_vec())
+        .collect();
     let bar = pbar(n as u64);
     let now = std::time::Instant::now();
-    for i in 0..n {
-        let index = i % (52 - 7);
-        let hand = &deck[index..index + 7];
-        let strength = HAND_TABLE.hand_strength(hand);
+    for hand in &hands {
+        std::hint::black_box(hand_strength(std::hint::black_box(hand)));
         bar.inc(1);
     }
     bar.finish();
-    let secs = now.elapsed().as_secs();
-    let rate = (n as f64) / (secs as f64);
-    println!("{} hands evaluated per second.", rate);
+    let secs = now.elapsed().as_secs_f64();
+    let rate = (n as f64) / secs;
+    println!("{}: {} hands evaluated per second.", name, rate);
+}
+
+// Compares canonical_hand_id() with canonicalizing into a Vec<Card> first.
+pub fn benchmark_canonical_hand_id() {
+    let n = 1_000_000;
+    let mut deck = deck();
+    deck.shuffle(&mut rand::thread_rng());
+    let hands: Vec<&[Card]> = (0..n).map(|i| &deck[i % 45..i % 45 + 7]).collect();
+
+    let now = std::time::Instant::now();
+    let mut total: u64 = 0;
+    for hand in &hands {
+        total = total.wrapping_add(cards2hand(&canonical_hand(hand, true)));
+    }
+    let vec_secs = now.elapsed().as_secs_f64();
+
+    let now = std::time::Instant::now();
+    let mut id_total: u64 = 0;
+    for hand in &hands {
+        id_total = id_total.wrapping_add(canonical_hand_id(hand, true));
+    }
+    let id_secs = now.elapsed().as_secs_f64();
+    assert_eq!(total, id_total);
+
+    println!(
+        "canonical_hand: {} hands per second.",
+        (n as f64) / vec_secs
+    );
+    println!(
+        "canonical_hand_id: {} hands per second ({:.1}x).",
+        (n as f64) / id_secs,
+        vec_secs / id_secs
+    );
+}
+
+// Compares looking up river equities one at a time with lookup_batch().
+pub fn benchmark_equity_lookup() {
+    let n = 1_000_000;
+    let mut deck = deck();
+    let mut rng = &mut rand::thread_rng();
+    let hands: Vec<Vec<Card>> = (0..n)
+        .map(|_| {
+            deck.shuffle(&mut rng);
+            deck[..7].to_vec()
+        })
+        .collect();
+    lazy_static::initialize(&EQUITY_TABLE);
+
+    let now = std::time::Instant::now();
+    for hand in &hands {
+        EQUITY_TABLE.lookup(hand);
+    }
+    let sequential_rate = (n as f64) / now.elapsed().as_secs_f64();
+    println!("lookup: {} hands per second.", sequential_rate);
+
+    let now = std::time::Instant::now();
+    EQUITY_TABLE.lookup_batch(&hands);
+    let batch_rate = (n as f64) / now.elapsed().as_secs_f64();
+    println!(
+        "lookup_batch: {} hands per second ({:.1}x speedup on {} threads).",
+        batch_rate,
+        batch_rate / sequential_rate,
+        rayon::current_num_threads()
+    );
+}
+
+// Compares monte_carlo_equity() with the equity table on random river hands,
+// first for speed and then for how quickly the estimates converge to the
+// table's exact equities as the number of samples grows.
+pub fn benchmark_monte_carlo_equity() {
+    let n_hands = 1000;
+    let mut deck = deck();
+    let mut rng = &mut rand::thread_rng();
+    let hands: Vec<Vec<Card>> = (0..n_hands)
+        .map(|_| {
+            deck.shuffle(&mut rng);
+            deck[..7].to_vec()
+        })
+        .collect();
+    lazy_static::initialize(&EQUITY_TABLE);
+    lazy_static::initialize(&HAND_TABLE);
+
+    let now = std::time::Instant::now();
+    let exact: Vec<f64> = hands.iter().map(|h| EQUITY_TABLE.lookup(h)).collect();
+    let lookup_rate = (n_hands as f64) / now.elapsed().as_secs_f64();
+    println!("EquityTable::lookup: {} hands per second.", lookup_rate);
+
+    println!("n_samples  hands/s  mean abs error");
+    for n_samples in &[10, 100, 1000, 10000] {
+        let now = std::time::Instant::now();
+        let mut total_error = 0.0;
+        for (hand, exact) in hands.iter().zip(&exact) {
+            let estimate = monte_carlo_equity(&hand[..2], &hand[2..], *n_samples, &mut rng);
+            total_error += (estimate - exact).abs();
+        }
+        let rate = (n_hands as f64) / now.elapsed().as_secs_f64();
+        println!(
+            "{:>9}  {:>7.0}  {:.4}",
+            n_samples,
+            rate,
+            total_error / n_hands as f64
+        );
+    }
+}
+
+// Betting math that doesn't depend on the cards. Bets and pots are in chips,
+// and the pot doesn't include the bet being made.
+pub mod odds {
+    // The fraction of the final pot that the bet makes up. A bluff of this size
+    // breaks even if the opponent folds this often.
+    pub fn pot_odds(bet: i32, pot: i32) -> f64 {
+        bet as f64 / (pot + bet) as f64
+    }
+
+    // How often the defender has to continue against a bet so that a pure
+    // bluff can't profit.
+    pub fn minimum_defense_frequency(bet: i32, pot: i32) -> f64 {
+        1.0 - pot_odds(bet, pot)
+    }
+
+    // The equity needed for calling a bet to be zero-EV: we risk the bet to
+    // win the pot plus the bet.
+    pub fn breakeven_equity(bet: i32, pot: i32) -> f64 {
+        bet as f64 / (pot + 2 * bet) as f64
+    }
+
+    // Kelly-optimal value bet, assuming we get called. Maximizing
+    //
+    //      equity * ln(stack + pot + bet) + (1 - equity) * ln(stack - bet)
+    //
+    // gives bet = (2 * equity - 1) * stack - (1 - equity) * pot, which is
+    // clamped to what we can actually bet.
+    pub fn kelly_bet(equity: f64, pot: i32, stack: i32) -> i32 {
+        let bet = (2.0 * equity - 1.0) * stack as f64 - (1.0 - equity) * pot as f64;
+        (bet.round() as i32).max(0).min(stack)
+    }
 }
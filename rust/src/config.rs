@@ -0,0 +1,224 @@
+// Settings that used to be hard-coded: where the lookup tables live, how to
+// train and how to run the server. They're read from a TOML file, where any
+// setting that's left out keeps its default, eg
+//
+//     [paths]
+//     hand_table = "/data/strengths7.txt"
+//     bet_abstraction = "bets/wide.toml"
+//
+//     [training]
+//     iterations = 10000000
+//     checkpoint_interval = 500000
+//     algorithm = "CFRPlus"
+//
+//     [server]
+//     port = 8080
+//
+// The bet abstraction and the lookup tables are loaded the first time they're
+// needed, so a different config has to be set with set_config() before then.
+use crate::backend::session::SESSION_TIMEOUT;
+use crate::card_utils::{self, TablePaths};
+use crate::trainer::{TrainingConfig, TrainingConfigError};
+use std::fmt;
+use std::io;
+use std::sync::OnceLock;
+
+pub const CONFIG_PATH: &str = "config.toml";
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub paths: PathConfig,
+    pub training: TrainingConfig,
+    pub server: ServerConfig,
+}
+
+// Where the lookup tables, abstractions and strategies are read from and
+// written to
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct PathConfig {
+    pub hand_table: String,
+    pub light_hand_table: String,
+    pub short_deck_hand_table: String,
+    pub equity_table: String,
+    // Equity tables used to be stored as text, one "hand equity" pair per line
+    pub equity_table_text: String,
+    pub plo_equity_table: String,
+    pub flop_canonical: String,
+    pub turn_canonical: String,
+    // River hands used to be cached as text like the flop and turn, but there
+    // are so many of them that they're stored with bincode now
+    pub river_canonical: String,
+    pub river_canonical_bin: String,
+    pub flop_abstraction: String,
+    pub turn_abstraction: String,
+    pub river_abstraction: String,
+    pub river_sorted_dir: String,
+    pub turn_equity: String,
+    pub nodes: String,
+    pub blueprint: String,
+    pub bet_abstraction: String,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    // Game sessions without a request for this long are dropped
+    pub session_timeout_secs: u64,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Training(TrainingConfigError),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "config I/O error: {}", e),
+            ConfigError::Parse(e) => write!(f, "bad config file: {}", e),
+            ConfigError::Training(e) => write!(f, "bad config file: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> ConfigError {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(e: toml::de::Error) -> ConfigError {
+        ConfigError::Parse(e)
+    }
+}
+
+impl From<TrainingConfigError> for ConfigError {
+    fn from(e: TrainingConfigError) -> ConfigError {
+        ConfigError::Training(e)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            paths: PathConfig::default(),
+            training: TrainingConfig::builder()
+                .checkpoint_path(crate::trainer::CHECKPOINT_PATH)
+                .build(),
+            server: ServerConfig::default(),
+        }
+    }
+}
+
+impl Default for PathConfig {
+    fn default() -> PathConfig {
+        let tables = TablePaths::default();
+        PathConfig {
+            hand_table: tables.hand_table,
+            light_hand_table: tables.light_hand_table,
+            short_deck_hand_table: tables.short_deck_hand_table,
+            equity_table: tables.equity_table,
+            equity_table_text: tables.equity_table_text,
+            plo_equity_table: String::from("products/plo_equity_table.bin"),
+            flop_canonical: tables.flop_canonical,
+            turn_canonical: tables.turn_canonical,
+            river_canonical: tables.river_canonical,
+            river_canonical_bin: tables.river_canonical_bin,
+            flop_abstraction: String::from("products/flop_abstraction.txt"),
+            turn_abstraction: String::from("products/turn_abstraction.txt"),
+            river_abstraction: String::from("products/river_abstraction.txt"),
+            river_sorted_dir: String::from("products/river_sorted_ehs2"),
+            turn_equity: String::from("products/turn_equity.bin"),
+            nodes: String::from("products/nodes.bin"),
+            blueprint: String::from("products/quantized_blueprint.bin"),
+            bet_abstraction: String::from("products/bet_abstraction.toml"),
+        }
+    }
+}
+
+impl PathConfig {
+    // The paths that card_utils needs
+    pub fn table_paths(&self) -> TablePaths {
+        TablePaths {
+            hand_table: self.hand_table.clone(),
+            light_hand_table: self.light_hand_table.clone(),
+            short_deck_hand_table: self.short_deck_hand_table.clone(),
+            equity_table: self.equity_table.clone(),
+            equity_table_text: self.equity_table_text.clone(),
+            flop_canonical: self.flop_canonical.clone(),
+            turn_canonical: self.turn_canonical.clone(),
+            river_canonical: self.river_canonical.clone(),
+            river_canonical_bin: self.river_canonical_bin.clone(),
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            host: String::from("127.0.0.1"),
+            port: 8000,
+            session_timeout_secs: SESSION_TIMEOUT.as_secs(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Config, ConfigError> {
+        let text = std::fs::read_to_string(path)?;
+        let config: Config = toml::from_str(&text)?;
+        config.training.validate()?;
+        Ok(config)
+    }
+}
+
+impl ServerConfig {
+    // The address to bind to, eg "127.0.0.1:8000"
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+// The config everything uses. It's read from CONFIG_PATH the first time it's
+// needed, or the defaults are used if there's no file there.
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(|| {
+        let config = match Config::load(CONFIG_PATH) {
+            Ok(config) => config,
+            Err(ConfigError::Io(_)) => Config::default(),
+            Err(e) => panic!("{}", e),
+        };
+        share_table_paths(config.paths.table_paths());
+        config
+    })
+}
+
+// Uses the given config instead of the one in CONFIG_PATH. Like
+// set_bet_abstraction(), this only works before anything has used the
+// config, otherwise the config is handed back.
+pub fn set_config(config: Config) -> Result<(), Config> {
+    let paths = config.paths.table_paths();
+    CONFIG.set(config)?;
+    share_table_paths(paths);
+    Ok(())
+}
+
+// card_utils keeps its own copy of the table paths
+fn share_table_paths(paths: TablePaths) {
+    if let Err(paths) = card_utils::set_table_paths(paths) {
+        if card_utils::table_paths() != &paths {
+            eprintln!("[WARN] A lookup table was used before the config was loaded");
+        }
+    }
+}
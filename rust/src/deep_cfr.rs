@@ -0,0 +1,475 @@
+// Deep CFR (Brown et al. 2019, https://arxiv.org/abs/1811.00164). Instead of
+// keeping regrets and strategy sums for every infoset in a table, each player
+// has an advantage network that predicts the regrets of an infoset from its
+// features, and a strategy network learns the average strategy. This lets
+// the networks generalize between similar infosets, so the game doesn't have
+// to be abstracted down until the table fits in memory.
+//
+// Each iteration runs external sampling traversals like external_sample() in
+// the trainer, except that the strategy at every node comes from regret
+// matching on the acting player's advantage network. The traverser's sampled
+// advantages and the opponent's strategies are stored in reservoir buffers,
+// and the networks are retrained on them every train_every iterations. Like
+// in Linear CFR, each sample counts in proportion to its iteration.
+use crate::card_abstraction::{preflop_matrix_169, FLOP_BUCKETS, RIVER_BUCKETS, TURN_BUCKETS};
+use crate::card_utils;
+use crate::card_utils::Card;
+use crate::trainer_utils::*;
+use candle_core::{DType, Device, Tensor};
+use candle_nn::{AdamW, Linear, Module, Optimizer, ParamsAdamW, VarBuilder, VarMap};
+use rand::prelude::SliceRandom;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+
+// How many of the most recent actions of each street go into the features
+const ACTIONS_PER_STREET: usize = 4;
+pub const N_FEATURES: usize = 4 + 1 + 1 + 4 + 4 * ACTIONS_PER_STREET;
+
+pub trait AdvantageNet {
+    // The predicted advantage of each of the infoset's next actions
+    fn predict(&self, infoset: &InfoSet) -> Vec<f64>;
+    // Samples pair an infoset with the advantage of each of its next actions
+    // and the iteration they're from
+    fn train(&mut self, samples: &[(InfoSet, Vec<f64>, u64)]);
+}
+
+pub trait StrategyNet {
+    // The probability of each of the infoset's next actions
+    fn predict(&self, infoset: &InfoSet) -> Vec<f64>;
+    // Samples pair an infoset with the probability of each of its next actions
+    // and the iteration they're from
+    fn train(&mut self, samples: &[(InfoSet, Vec<f64>, u64)]);
+}
+
+// Describes an infoset to the networks. Everything is scaled to roughly
+// [0, 1]:
+//
+//      4   one-hot street
+//      1   acting player
+//      1   hand strength: the preflop equity, or the bucket's position among
+//          the postflop buckets, which are sorted by E[HS^2]
+//      4   pot, amount to call and both stacks, as fractions of a stack
+//      16  the last ACTIONS_PER_STREET actions of each street, as the chips
+//          they put in over a stack. Folds are -1 and missing actions are 0.
+pub fn featurize(infoset: &InfoSet) -> Vec<f32> {
+    let history = &infoset.history;
+    let mut features = vec![0.0; N_FEATURES];
+    features[history.street] = 1.0;
+    features[4] = history.player as f32;
+    features[5] = match history.street {
+        PREFLOP => preflop_matrix_169()[infoset.card_bucket() as usize].strength,
+        FLOP => infoset.card_bucket() as f64 / FLOP_BUCKETS as f64,
+        TURN => infoset.card_bucket() as f64 / TURN_BUCKETS as f64,
+        _ => infoset.card_bucket() as f64 / RIVER_BUCKETS as f64,
+    } as f32;
+    let stack = STACK_SIZE as f32;
+    let stacks = history.stack_sizes();
+    features[6] = history.pot() as f32 / stack;
+    features[7] = history.to_call() as f32 / stack;
+    features[8] = stacks[history.player] as f32 / stack;
+    features[9] = stacks[1 - history.player] as f32 / stack;
+    for street in PREFLOP..=history.street {
+        let actions = history.street_actions(street);
+        let recent = &actions[actions.len().saturating_sub(ACTIONS_PER_STREET)..];
+        for (i, action) in recent.iter().enumerate() {
+            features[10 + street * ACTIONS_PER_STREET + i] = match action.action {
+                ActionType::Fold => -1.0,
+                _ => action.amount as f32 / stack,
+            };
+        }
+    }
+    features
+}
+
+// Settings for FullyConnectedNet
+pub struct NetConfig {
+    // Sizes of the hidden layers
+    pub hidden: Vec<usize>,
+    pub learning_rate: f64,
+    pub batch_size: usize,
+    // Minibatches per call to train()
+    pub train_steps: usize,
+    pub seed: u64,
+}
+
+impl Default for NetConfig {
+    fn default() -> NetConfig {
+        NetConfig {
+            hidden: vec![128, 128],
+            learning_rate: 1e-3,
+            batch_size: 256,
+            train_steps: 500,
+            seed: 0,
+        }
+    }
+}
+
+// A plain multilayer perceptron with ReLUs, from N_FEATURES inputs to one
+// output for each action the bet abstraction can give. Training minimizes the
+// squared error on the outputs that correspond to real actions, weighted by
+// each sample's iteration, and the rest are ignored.
+pub struct FullyConnectedNet {
+    // The optimizer updates the layers' weights in place
+    layers: Vec<Linear>,
+    n_outputs: usize,
+    optimizer: AdamW,
+    config: NetConfig,
+    rng: StdRng,
+}
+
+impl FullyConnectedNet {
+    pub fn new(config: NetConfig) -> FullyConnectedNet {
+        let varmap = VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+        let n_outputs = bet_abstraction().max_actions();
+        let sizes = [&[N_FEATURES], &config.hidden[..], &[n_outputs]].concat();
+        let layers = sizes
+            .windows(2)
+            .enumerate()
+            .map(|(i, size)| {
+                candle_nn::linear(size[0], size[1], vb.pp(format!("layer{}", i)))
+                    .expect("Could not create layer")
+            })
+            .collect();
+        let params = ParamsAdamW {
+            lr: config.learning_rate,
+            ..Default::default()
+        };
+        let optimizer = AdamW::new(varmap.all_vars(), params).expect("Could not create optimizer");
+        let rng = StdRng::seed_from_u64(config.seed);
+        FullyConnectedNet {
+            layers: layers,
+            n_outputs: n_outputs,
+            optimizer: optimizer,
+            config: config,
+            rng: rng,
+        }
+    }
+
+    fn forward(&self, features: &Tensor) -> candle_core::Result<Tensor> {
+        let mut x = features.clone();
+        for (i, layer) in self.layers.iter().enumerate() {
+            x = layer.forward(&x)?;
+            if i + 1 < self.layers.len() {
+                x = x.relu()?;
+            }
+        }
+        Ok(x)
+    }
+
+    // The raw outputs for the infoset's next actions
+    fn outputs(&self, infoset: &InfoSet) -> Vec<f64> {
+        let outputs = Tensor::from_vec(featurize(infoset), (1, N_FEATURES), &Device::Cpu)
+            .and_then(|x| self.forward(&x))
+            .and_then(|y| y.squeeze(0))
+            .and_then(|y| y.to_vec1::<f32>())
+            .expect("Network evaluation failed");
+        let n_actions = infoset.next_actions().len();
+        outputs[..n_actions].iter().map(|&y| y as f64).collect()
+    }
+
+    fn fit(&mut self, samples: &[(InfoSet, Vec<f64>, u64)]) {
+        if samples.is_empty() {
+            return;
+        }
+        let data: Vec<(Vec<f32>, Vec<f32>, Vec<f32>)> = samples
+            .iter()
+            .map(|(i, t, iteration)| encode_sample(i, t, *iteration, self.n_outputs))
+            .collect();
+        let batch_size = self.config.batch_size.min(data.len());
+        for _ in 0..self.config.train_steps {
+            let batch: Vec<_> = data.choose_multiple(&mut self.rng, batch_size).collect();
+            let loss = self.batch_loss(&batch).expect("Network training failed");
+            self.optimizer
+                .backward_step(&loss)
+                .expect("Network training failed");
+        }
+    }
+
+    // Mean squared error over the real actions of each sample, with each
+    // sample weighted by its iteration
+    fn batch_loss(&self, batch: &[&(Vec<f32>, Vec<f32>, Vec<f32>)]) -> candle_core::Result<Tensor> {
+        let n = batch.len();
+        let mut features: Vec<f32> = Vec::with_capacity(n * N_FEATURES);
+        let mut targets: Vec<f32> = Vec::with_capacity(n * self.n_outputs);
+        let mut weights: Vec<f32> = Vec::with_capacity(n * self.n_outputs);
+        for (f, t, w) in batch {
+            features.extend(f);
+            targets.extend(t);
+            weights.extend(w);
+        }
+        let features = Tensor::from_vec(features, (n, N_FEATURES), &Device::Cpu)?;
+        let targets = Tensor::from_vec(targets, (n, self.n_outputs), &Device::Cpu)?;
+        let weights = Tensor::from_vec(weights, (n, self.n_outputs), &Device::Cpu)?;
+        let errors = (self.forward(&features)? - targets)?;
+        (errors.sqr()? * &weights)?
+            .sum_all()?
+            .div(&weights.sum_all()?)
+    }
+}
+
+// Features, targets padded to n_outputs and the weight of each output in the
+// loss, which is the iteration for the real actions and 0 for the padding.
+// Traversals run outside of train() are from iteration 0, and count as
+// iteration 1 so that they aren't ignored.
+fn encode_sample(
+    infoset: &InfoSet,
+    targets: &[f64],
+    iteration: u64,
+    n_outputs: usize,
+) -> (Vec<f32>, Vec<f32>, Vec<f32>) {
+    assert!(
+        targets.len() <= n_outputs,
+        "Too many actions for the network"
+    );
+    let mut padded = vec![0.0; n_outputs];
+    let mut weights = vec![0.0; n_outputs];
+    for (i, target) in targets.iter().enumerate() {
+        padded[i] = *target as f32;
+        weights[i] = iteration.max(1) as f32;
+    }
+    (featurize(infoset), padded, weights)
+}
+
+impl AdvantageNet for FullyConnectedNet {
+    fn predict(&self, infoset: &InfoSet) -> Vec<f64> {
+        self.outputs(infoset)
+    }
+
+    fn train(&mut self, samples: &[(InfoSet, Vec<f64>, u64)]) {
+        self.fit(samples);
+    }
+}
+
+impl StrategyNet for FullyConnectedNet {
+    // The outputs are trained on probabilities but aren't guaranteed to be
+    // any, so negative outputs are clipped and the rest normalized
+    fn predict(&self, infoset: &InfoSet) -> Vec<f64> {
+        normalize_positive(&self.outputs(infoset))
+    }
+
+    fn train(&mut self, samples: &[(InfoSet, Vec<f64>, u64)]) {
+        self.fit(samples);
+    }
+}
+
+// Regret matching: probabilities proportional to the positive values, or
+// uniform if there aren't any
+pub fn normalize_positive(values: &[f64]) -> Vec<f64> {
+    let total: f64 = values.iter().map(|v| v.max(0.0)).sum();
+    if total > 0.0 {
+        values.iter().map(|v| v.max(0.0) / total).collect()
+    } else {
+        vec![1.0 / values.len() as f64; values.len()]
+    }
+}
+
+// Keeps a uniform sample of everything ever added once it's full, so that
+// old iterations stay represented
+struct ReservoirBuffer {
+    samples: Vec<(InfoSet, Vec<f64>, u64)>,
+    capacity: usize,
+    seen: u64,
+}
+
+impl ReservoirBuffer {
+    fn new(capacity: usize) -> ReservoirBuffer {
+        ReservoirBuffer {
+            samples: Vec::new(),
+            capacity: capacity,
+            seen: 0,
+        }
+    }
+
+    fn add(&mut self, sample: (InfoSet, Vec<f64>, u64), rng: &mut impl Rng) {
+        self.seen += 1;
+        if self.samples.len() < self.capacity {
+            self.samples.push(sample);
+        } else {
+            let i = (rng.gen::<f64>() * self.seen as f64) as usize;
+            if i < self.capacity {
+                self.samples[i] = sample;
+            }
+        }
+    }
+}
+
+pub struct DeepCFRConfig {
+    // External sampling traversals per player in each iteration
+    pub traversals: u64,
+    // The advantage networks are retrained every train_every iterations
+    pub train_every: u64,
+    pub buffer_size: usize,
+    pub seed: u64,
+}
+
+impl Default for DeepCFRConfig {
+    fn default() -> DeepCFRConfig {
+        DeepCFRConfig {
+            traversals: 1000,
+            train_every: 1,
+            buffer_size: 1_000_000,
+            seed: 0,
+        }
+    }
+}
+
+pub struct DeepCFRTrainer {
+    pub advantage_net_p0: Box<dyn AdvantageNet>,
+    pub advantage_net_p1: Box<dyn AdvantageNet>,
+    pub strategy_net: Box<dyn StrategyNet>,
+    advantage_memory: [ReservoirBuffer; 2],
+    strategy_memory: ReservoirBuffer,
+    // Builds the infoset of the player to act, normally InfoSet::from_deck().
+    // Tests swap it out so they don't need the card abstraction.
+    infoset: fn(&[Card], &ActionHistory) -> InfoSet,
+    config: DeepCFRConfig,
+    iteration: u64,
+    rng: StdRng,
+}
+
+impl DeepCFRTrainer {
+    pub fn new(config: DeepCFRConfig) -> DeepCFRTrainer {
+        DeepCFRTrainer::with_nets(
+            Box::new(FullyConnectedNet::new(NetConfig::default())),
+            Box::new(FullyConnectedNet::new(NetConfig {
+                seed: 1,
+                ..NetConfig::default()
+            })),
+            Box::new(FullyConnectedNet::new(NetConfig {
+                seed: 2,
+                ..NetConfig::default()
+            })),
+            config,
+        )
+    }
+
+    pub fn with_nets(
+        advantage_net_p0: Box<dyn AdvantageNet>,
+        advantage_net_p1: Box<dyn AdvantageNet>,
+        strategy_net: Box<dyn StrategyNet>,
+        config: DeepCFRConfig,
+    ) -> DeepCFRTrainer {
+        DeepCFRTrainer {
+            advantage_net_p0: advantage_net_p0,
+            advantage_net_p1: advantage_net_p1,
+            strategy_net: strategy_net,
+            advantage_memory: [
+                ReservoirBuffer::new(config.buffer_size),
+                ReservoirBuffer::new(config.buffer_size),
+            ],
+            strategy_memory: ReservoirBuffer::new(config.buffer_size),
+            infoset: InfoSet::from_deck,
+            rng: StdRng::seed_from_u64(config.seed),
+            config: config,
+            iteration: 0,
+        }
+    }
+
+    pub fn with_infoset_fn(mut self, infoset: fn(&[Card], &ActionHistory) -> InfoSet) -> Self {
+        self.infoset = infoset;
+        self
+    }
+
+    pub fn iteration(&self) -> u64 {
+        self.iteration
+    }
+
+    // Number of samples stored for each advantage network and for the
+    // strategy network
+    pub fn memory_sizes(&self) -> [usize; 3] {
+        [
+            self.advantage_memory[DEALER].samples.len(),
+            self.advantage_memory[OPPONENT].samples.len(),
+            self.strategy_memory.samples.len(),
+        ]
+    }
+
+    pub fn train(&mut self, iters: u64) {
+        lazy_static::initialize(&HAND_TABLE);
+        println!("[INFO] Beginning Deep CFR training.");
+        let bar = card_utils::pbar(iters);
+        let mut deck = card_utils::deck();
+        for _ in 0..iters {
+            self.iteration += 1;
+            for player in &[DEALER, OPPONENT] {
+                for _ in 0..self.config.traversals {
+                    deck.shuffle(&mut self.rng);
+                    self.traverse(*player, &deck, ActionHistory::new());
+                }
+            }
+            if self.iteration % self.config.train_every == 0 {
+                self.train_advantage_nets();
+            }
+            bar.inc(1);
+        }
+        bar.finish();
+        self.train_strategy_net();
+    }
+
+    pub fn train_advantage_nets(&mut self) {
+        self.advantage_net_p0
+            .train(&self.advantage_memory[DEALER].samples);
+        self.advantage_net_p1
+            .train(&self.advantage_memory[OPPONENT].samples);
+    }
+
+    pub fn train_strategy_net(&mut self) {
+        self.strategy_net.train(&self.strategy_memory.samples);
+    }
+
+    // The average strategy learned by the strategy network
+    pub fn strategy(&self, infoset: &InfoSet) -> HashMap<Action, f64> {
+        let probs = self.strategy_net.predict(infoset);
+        infoset.next_actions().into_iter().zip(probs).collect()
+    }
+
+    // The current strategy, from regret matching on the advantage network of
+    // the player to act
+    fn current_strategy(&self, infoset: &InfoSet) -> Vec<f64> {
+        let advantages = match infoset.history.player {
+            DEALER => self.advantage_net_p0.predict(infoset),
+            _ => self.advantage_net_p1.predict(infoset),
+        };
+        normalize_positive(&advantages)
+    }
+
+    // One external sampling traversal from history, returning the sampled
+    // value for player. The deck is laid out as in InfoSet::from_deck().
+    pub fn traverse(&mut self, player: usize, deck: &[Card], history: ActionHistory) -> f64 {
+        if history.hand_over() {
+            return terminal_utility(deck, history, player);
+        }
+        let infoset = (self.infoset)(deck, &history);
+        let actions = infoset.next_actions();
+        let strategy = self.current_strategy(&infoset);
+
+        if history.player != player {
+            let i = *(0..actions.len())
+                .collect::<Vec<usize>>()
+                .choose_weighted(&mut self.rng, |&i| strategy[i])
+                .unwrap();
+            self.strategy_memory
+                .add((infoset, strategy, self.iteration), &mut self.rng);
+            let mut next_history = history.clone();
+            next_history.add(&actions[i]);
+            return self.traverse(player, deck, next_history);
+        }
+
+        let mut utilities = Vec::new();
+        for action in &actions {
+            let mut next_history = history.clone();
+            next_history.add(action);
+            utilities.push(self.traverse(player, deck, next_history));
+        }
+        let node_utility: f64 = utilities.iter().zip(&strategy).map(|(u, p)| u * p).sum();
+        // Stored as fractions of a stack so the networks' targets stay small
+        let advantages = utilities
+            .iter()
+            .map(|u| (u - node_utility) / STACK_SIZE as f64)
+            .collect();
+        self.advantage_memory[player].add((infoset, advantages, self.iteration), &mut self.rng);
+        node_utility
+    }
+}
@@ -13,17 +13,431 @@ use rand::seq::SliceRandom;
 use rayon::iter::IntoParallelIterator;
 use rayon::iter::ParallelIterator;
 use rayon::prelude::*;
+use std::cell::RefCell;
 use std::collections::HashMap;
 
 const ITERS: u64 = 10_000;
 const BETS: [f64; 2] = [1.0, ALL_IN];
+// Number of card deals sampled for the best response calculation
+const BR_DEALS: usize = 1_000;
 
+// Returns the average exploitability of the blueprint in BB/h: the mean of
+// both players' best response values against it. This is 0 for a Nash
+// equilibrium.
+pub fn exploitability(nodes: &HashMap<CompactInfoSet, Node>) -> f64 {
+    lazy_static::initialize(&HAND_TABLE);
+    lazy_static::initialize(&ABSTRACTION);
+    println!("[INFO] Calculating exploitability...");
+    let deals = sample_deals(BR_DEALS);
+    let strategy = |infoset: &CompactInfoSet, history: &ActionHistory| {
+        blueprint_strategy(nodes, infoset, history)
+    };
+    let exploit = exploitability_on_deals(&deals, &blueprint_bucket, &strategy);
+    println!("Exploitability: {} BB/h\n", exploit);
+    exploit
+}
+
+pub fn is_epsilon_nash(nodes: &HashMap<CompactInfoSet, Node>, epsilon: f64) -> bool {
+    exploitability(nodes) < epsilon
+}
+
+pub fn exploitability_on_deals(
+    deals: &[Vec<Card>],
+    bucket: &dyn Fn(&[Card], usize, usize) -> i32,
+    strategy: &dyn Fn(&CompactInfoSet, &ActionHistory) -> HashMap<Action, f64>,
+) -> f64 {
+    let p0 = best_response_on_deals(deals, DEALER, bucket, strategy);
+    let p1 = best_response_on_deals(deals, OPPONENT, bucket, strategy);
+    (p0 + p1) / 2.0 / (BIG_BLIND as f64)
+}
+
+// Exploitability in BB/h split up by the street on which the hand ended, to
+// see where a strategy is leaking the most.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreetExploitability {
+    pub preflop: f64,
+    pub flop: f64,
+    pub turn: f64,
+    pub river: f64,
+}
+
+impl StreetExploitability {
+    pub fn total(&self) -> f64 {
+        self.preflop + self.flop + self.turn + self.river
+    }
+}
+
+pub fn exploitability_by_street(nodes: &NodeMap) -> StreetExploitability {
+    lazy_static::initialize(&HAND_TABLE);
+    lazy_static::initialize(&ABSTRACTION);
+    println!("[INFO] Calculating exploitability by street...");
+    let deals = sample_deals(BR_DEALS);
+    let strategy = |infoset: &CompactInfoSet, history: &ActionHistory| {
+        blueprint_strategy(nodes, infoset, history)
+    };
+    let exploit = exploitability_by_street_on_deals(&deals, &blueprint_bucket, &strategy);
+    println!("Exploitability: {:?} BB/h\n", exploit);
+    exploit
+}
+
+pub fn exploitability_by_street_on_deals(
+    deals: &[Vec<Card>],
+    bucket: &dyn Fn(&[Card], usize, usize) -> i32,
+    strategy: &dyn Fn(&CompactInfoSet, &ActionHistory) -> HashMap<Action, f64>,
+) -> StreetExploitability {
+    let p0 = best_response_by_street(deals, DEALER, bucket, strategy);
+    let p1 = best_response_by_street(deals, OPPONENT, bucket, strategy);
+    let street = |s: usize| (p0[s] + p1[s]) / 2.0 / (BIG_BLIND as f64);
+    StreetExploitability {
+        preflop: street(PREFLOP),
+        flop: street(FLOP),
+        turn: street(TURN),
+        river: street(RIVER),
+    }
+}
+
+// Returns the expected value, in chips, of a best response for the given player
+// against the blueprint strategy of the other player. This walks the whole
+// abstract game tree once for a sample of card deals at the same time. At the
+// best responder's nodes, every deal in the same card bucket has to take the
+// same action, so we pick the action with the highest value summed over those
+// deals, weighted by how likely the opponent is to have played to this node.
+// With a limited sample the best responder can overfit to the sampled deals,
+// so the result is a bit optimistic.
+pub fn best_response(nodes: &HashMap<CompactInfoSet, Node>, player: u8) -> f64 {
+    lazy_static::initialize(&HAND_TABLE);
+    lazy_static::initialize(&ABSTRACTION);
+    let deals = sample_deals(BR_DEALS);
+    let strategy = |infoset: &CompactInfoSet, history: &ActionHistory| {
+        blueprint_strategy(nodes, infoset, history)
+    };
+    best_response_on_deals(&deals, player as usize, &blueprint_bucket, &strategy)
+}
+
+// A spot where the best response gains on the blueprint. ev_loss is how much
+// the blueprint loses there in BB/h, compared to playing best_action with that
+// bucket and best responding after.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExploitableSpot {
+    pub infoset_str: String,
+    pub best_action: Action,
+    // The blueprint's most likely action
+    pub strategy_action: Action,
+    pub ev_loss: f64,
+}
+
+// Each infoset where the blueprint would do better playing the best response
+// action, with that action and how much better in BB/h. Most exploitable
+// first.
+pub fn per_action_regret(nodes: &NodeMap) -> Vec<(CompactInfoSet, Action, f64)> {
+    exploitable_spots(nodes)
+        .into_iter()
+        .map(|(infoset, spot)| (infoset, spot.best_action, spot.ev_loss))
+        .collect()
+}
+
+pub fn top_exploitable_spots(nodes: &NodeMap, n: usize) -> Vec<ExploitableSpot> {
+    exploitable_spots(nodes)
+        .into_iter()
+        .take(n)
+        .map(|(_, spot)| spot)
+        .collect()
+}
+
+fn exploitable_spots(nodes: &NodeMap) -> Vec<(CompactInfoSet, ExploitableSpot)> {
+    lazy_static::initialize(&HAND_TABLE);
+    lazy_static::initialize(&ABSTRACTION);
+    println!("[INFO] Looking for exploitable spots...");
+    let deals = sample_deals(BR_DEALS);
+    let strategy = |infoset: &CompactInfoSet, history: &ActionHistory| {
+        blueprint_strategy(nodes, infoset, history)
+    };
+    exploitable_spots_on_deals(&deals, &blueprint_bucket, &strategy)
+}
+
+// Runs the best response for both players and keeps track of how much the
+// strategy loses at each of the best responder's infosets. Values further
+// down the tree assume the best responder keeps best responding, so a leak
+// also shows up at the infosets leading to it.
+pub fn exploitable_spots_on_deals(
+    deals: &[Vec<Card>],
+    bucket: &dyn Fn(&[Card], usize, usize) -> i32,
+    strategy: &dyn Fn(&CompactInfoSet, &ActionHistory) -> HashMap<Action, f64>,
+) -> Vec<(CompactInfoSet, ExploitableSpot)> {
+    let spots = RefCell::new(Vec::new());
+    for &player in &[DEALER, OPPONENT] {
+        best_response_recording(deals, player, bucket, strategy, Some(&spots));
+    }
+    let mut spots = spots.into_inner();
+    spots.sort_by(|(_, a), (_, b)| b.ev_loss.partial_cmp(&a.ev_loss).unwrap());
+    spots
+}
+
+fn sample_deals(n: usize) -> Vec<Vec<Card>> {
+    let mut rng = rand::thread_rng();
+    (0..n)
+        .map(|_| {
+            let mut deck = card_utils::deck();
+            deck.shuffle(&mut rng);
+            deck
+        })
+        .collect()
+}
+
+fn blueprint_bucket(deck: &[Card], player: usize, street: usize) -> i32 {
+    ABSTRACTION.bin(&get_hand(deck, player, street))
+}
+
+fn blueprint_strategy(
+    nodes: &HashMap<CompactInfoSet, Node>,
+    infoset: &CompactInfoSet,
+    history: &ActionHistory,
+) -> HashMap<Action, f64> {
+    match nodes.get(infoset) {
+        Some(node) => node.cumulative_strategy(),
+        // Nodes the trainer never reached play uniformly at random
+        None => Node::from_actions(&history.next_actions(bet_abstraction())).cumulative_strategy(),
+    }
+}
+
+// Best response value averaged over the given deals, where bucket(deck, player,
+// street) gives a player's card bucket and strategy() gives the fixed player's
+// strategy at an infoset.
+pub fn best_response_on_deals(
+    deals: &[Vec<Card>],
+    player: usize,
+    bucket: &dyn Fn(&[Card], usize, usize) -> i32,
+    strategy: &dyn Fn(&CompactInfoSet, &ActionHistory) -> HashMap<Action, f64>,
+) -> f64 {
+    best_response_by_street(deals, player, bucket, strategy)
+        .iter()
+        .sum()
+}
+
+// Same as best_response_on_deals(), but split up by the street on which the
+// hand ended (the street of the last action before the showdown or fold).
+pub fn best_response_by_street(
+    deals: &[Vec<Card>],
+    player: usize,
+    bucket: &dyn Fn(&[Card], usize, usize) -> i32,
+    strategy: &dyn Fn(&CompactInfoSet, &ActionHistory) -> HashMap<Action, f64>,
+) -> [f64; 4] {
+    best_response_recording(deals, player, bucket, strategy, None)
+}
+
+// best_response_by_street(), adding the best responder's gain at each of its
+// infosets to spots if there is one
+fn best_response_recording(
+    deals: &[Vec<Card>],
+    player: usize,
+    bucket: &dyn Fn(&[Card], usize, usize) -> i32,
+    strategy: &dyn Fn(&CompactInfoSet, &ActionHistory) -> HashMap<Action, f64>,
+    spots: Option<&RefCell<Vec<(CompactInfoSet, ExploitableSpot)>>>,
+) -> [f64; 4] {
+    // The buckets only depend on the street, so look them up once per deal
+    let buckets: Vec<[[i32; 4]; 2]> = deals
+        .iter()
+        .map(|deck| {
+            let mut b = [[0; 4]; 2];
+            for p in 0..2 {
+                for street in PREFLOP..=RIVER {
+                    b[p][street] = bucket(deck, p, street);
+                }
+            }
+            b
+        })
+        .collect();
+    let reach = vec![1.0; deals.len()];
+    let bar = card_utils::pbar(count_decision_nodes(&ActionHistory::new()));
+    let values = br_traverse(
+        deals,
+        &buckets,
+        player,
+        &ActionHistory::new(),
+        Vec::new(),
+        PREFLOP,
+        &reach,
+        strategy,
+        spots,
+        &bar,
+    );
+    bar.finish();
+    let mut by_street = [0.0; 4];
+    for value in values {
+        for street in PREFLOP..=RIVER {
+            by_street[street] += value[street] / deals.len() as f64;
+        }
+    }
+    by_street
+}
+
+// Number of non-terminal histories in the abstract game tree below (and
+// including) this one, used to size the progress bar.
+fn count_decision_nodes(history: &ActionHistory) -> u64 {
+    if history.hand_over() {
+        return 0;
+    }
+    let mut count = 1;
+    for action in history.next_actions(bet_abstraction()) {
+        let mut next_history = history.clone();
+        next_history.add(&action);
+        count += count_decision_nodes(&next_history);
+    }
+    count
+}
+
+fn street_name(street: usize) -> &'static str {
+    match street {
+        PREFLOP => "preflop",
+        FLOP => "flop",
+        TURN => "turn",
+        RIVER => "river",
+        _ => panic!("Invalid street"),
+    }
+}
+
+// Returns the best responder's value for each deal at this history, given the
+// probability of the opponent playing to this history with each deal. Values
+// are split up by the street the hand ends on, where last_street is the street
+// of the action that led here.
+fn br_traverse(
+    deals: &[Vec<Card>],
+    buckets: &[[[i32; 4]; 2]],
+    player: usize,
+    history: &ActionHistory,
+    compressed: Vec<u8>,
+    last_street: usize,
+    opp_reach: &[f64],
+    strategy: &dyn Fn(&CompactInfoSet, &ActionHistory) -> HashMap<Action, f64>,
+    spots: Option<&RefCell<Vec<(CompactInfoSet, ExploitableSpot)>>>,
+    bar: &indicatif::ProgressBar,
+) -> Vec<[f64; 4]> {
+    if history.hand_over() {
+        return deals
+            .iter()
+            .zip(opp_reach)
+            .map(|(deck, reach)| {
+                let mut value = [0.0; 4];
+                // Deals the opponent never plays to here don't matter
+                if *reach > 0.0 {
+                    value[last_street] = terminal_utility(deck, history.clone(), player);
+                }
+                value
+            })
+            .collect();
+    }
+    bar.inc(1);
+    bar.set_message(street_name(history.street));
+    let actions = history.next_actions(bet_abstraction());
+    let child_values = |i: usize, reach: &[f64]| {
+        let mut next_history = history.clone();
+        next_history.add(&actions[i]);
+        let mut next_compressed = compressed.clone();
+        next_compressed.push(i as u8);
+        br_traverse(
+            deals,
+            buckets,
+            player,
+            &next_history,
+            next_compressed,
+            history.street,
+            reach,
+            strategy,
+            spots,
+            bar,
+        )
+    };
+
+    if history.player == player {
+        let action_values: Vec<Vec<[f64; 4]>> = (0..actions.len())
+            .map(|i| child_values(i, opp_reach))
+            .collect();
+        // Every deal in the same bucket has to take the same action
+        let mut totals: HashMap<i32, Vec<f64>> = HashMap::new();
+        for d in 0..deals.len() {
+            let bucket = buckets[d][player][history.street];
+            let total = totals.entry(bucket).or_insert(vec![0.0; actions.len()]);
+            for i in 0..actions.len() {
+                total[i] += opp_reach[d] * action_values[i][d].iter().sum::<f64>();
+            }
+        }
+        let mut best: HashMap<i32, usize> = HashMap::new();
+        for (bucket, total) in totals {
+            let mut best_action = 0;
+            for i in 0..total.len() {
+                if total[i] > total[best_action] {
+                    best_action = i;
+                }
+            }
+            if let Some(spots) = spots {
+                // What the strategy gets here, if it best responds after
+                let infoset = CompactInfoSet::new(compressed.clone(), bucket);
+                let own_strategy = strategy(&infoset, history);
+                let strategy_value: f64 = (0..actions.len())
+                    .map(|i| own_strategy.get(&actions[i]).cloned().unwrap_or(0.0) * total[i])
+                    .sum();
+                let loss =
+                    (total[best_action] - strategy_value) / deals.len() as f64 / BIG_BLIND as f64;
+                if loss > 0.0 {
+                    let spot = ExploitableSpot {
+                        infoset_str: format!("bucket {}|{}", bucket, history),
+                        best_action: actions[best_action].clone(),
+                        strategy_action: most_likely_action(&own_strategy),
+                        ev_loss: loss,
+                    };
+                    spots.borrow_mut().push((infoset, spot));
+                }
+            }
+            best.insert(bucket, best_action);
+        }
+        (0..deals.len())
+            .map(|d| action_values[best[&buckets[d][player][history.street]]][d])
+            .collect()
+    } else {
+        // Look up the opponent's strategy once per bucket
+        let opponent = 1 - player;
+        let mut strategies: HashMap<i32, HashMap<Action, f64>> = HashMap::new();
+        for d in 0..deals.len() {
+            let bucket = buckets[d][opponent][history.street];
+            if !strategies.contains_key(&bucket) {
+                let infoset = CompactInfoSet::new(compressed.clone(), bucket);
+                strategies.insert(bucket, strategy(&infoset, history));
+            }
+        }
+        let mut values = vec![[0.0; 4]; deals.len()];
+        for i in 0..actions.len() {
+            let probs: Vec<f64> = (0..deals.len())
+                .map(|d| {
+                    let bucket = buckets[d][opponent][history.street];
+                    strategies[&bucket].get(&actions[i]).cloned().unwrap_or(0.0)
+                })
+                .collect();
+            let reach: Vec<f64> = opp_reach.iter().zip(&probs).map(|(r, p)| r * p).collect();
+            if reach.iter().all(|r| *r == 0.0) {
+                // Nobody plays this action, so there's no need to look at it
+                let mut next_history = history.clone();
+                next_history.add(&actions[i]);
+                bar.inc(count_decision_nodes(&next_history));
+                continue;
+            }
+            let action_values = child_values(i, &reach);
+            for d in 0..deals.len() {
+                for street in PREFLOP..=RIVER {
+                    values[d][street] += probs[d] * action_values[d][street];
+                }
+            }
+        }
+        values
+    }
+}
+
+// Lower bound on exploitability from Local Best Response.
 // TODO: Instead of taking in a HashMap<InfoSet, Node> table of strategies, pass
 // in a "bot" instance where you give it an infoset and it gives you a strategy or action
-pub fn exploitability(nodes: &HashMap<InfoSet, Node>) -> f64 {
+pub fn lbr_exploitability(nodes: &HashMap<InfoSet, Node>) -> f64 {
     lazy_static::initialize(&HAND_TABLE);
     lazy_static::initialize(&ABSTRACTION);
-    println!("[INFO] Calculating exploitability...");
+    println!("[INFO] Calculating local best response exploitability...");
     let bar = card_utils::pbar(ITERS);
 
     // Single thread
@@ -110,8 +524,7 @@ fn construct_opponent_range(deck: &[Card], exploiter: usize) -> HashMap<Vec<Card
     // can't have the exploiter's cards
     let mut deck = deck.to_vec();
     deck.retain(|c| !exploiter_hole.contains(&c));
-    for hand in deck.iter().combinations(2) {
-        let hand = card_utils::deepcopy(&hand);
+    for hand in deck.iter().cloned().combinations(2) {
         range.insert(hand, 1.0);
     }
     range = normalize(&range);
@@ -166,7 +579,10 @@ fn local_best_response(
         action: ActionType::Call,
         amount: history.to_call(),
     };
-    let bet_abstraction = vec![BETS.to_vec(); 4]; // Bet abstraction is the same for every stree street
+    // Bet abstraction is the same for every stree street
+    let bet_abstraction = BetAbstraction {
+        fractions: vec![BETS.to_vec(); 4],
+    };
     for action in history.next_actions(&bet_abstraction) {
         if action.action != ActionType::Bet {
             continue;
@@ -299,8 +715,8 @@ fn wp_rollout(opp_range: &HashMap<Vec<Card>, f64>, exploiter_hole: &[Card], boar
         subdeck.retain(|c| !opp_hole.contains(c));
         let mut wins = 0.0;
         let mut n = 0.0;
-        for rollout in subdeck.iter().combinations(5 - board.len()) {
-            let full_board: Vec<Card> = [board, &card_utils::deepcopy(&rollout)].concat();
+        for rollout in subdeck.iter().cloned().combinations(5 - board.len()) {
+            let full_board: Vec<Card> = [board, &rollout].concat();
             let opp_hand = [opp_hole.clone(), full_board.clone()].concat();
             let exploiter_hand = [exploiter_hole.clone(), &full_board].concat();
             let exploiter_strength = HAND_TABLE.hand_strength(&exploiter_hand);
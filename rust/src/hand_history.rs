@@ -0,0 +1,768 @@
+// Parser for PokerStars hand history files, so that real hands can be
+// replayed against the bot. Amounts are kept in the units of the file (dollars
+// for cash games, chips for tournaments) until they're converted into an
+// ActionHistory.
+use crate::card_utils::{try_strvec2cards, Card, CardParseError};
+use crate::trainer_utils::*;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HandHistory {
+    pub id: u64,
+    // As written in the header, eg "2020/01/15 12:34:56 ET"
+    pub date_time: String,
+    pub small_blind: f64,
+    pub big_blind: f64,
+    pub button_seat: usize,
+    pub players: Vec<Player>,
+    pub actions: Vec<PlayerAction>,
+    // The board cards dealt on each street, indexed by PREFLOP to RIVER
+    pub board: Vec<Vec<Card>>,
+    pub total_pot: f64,
+    // The main pot followed by any side pots. Empty if there was only one pot.
+    pub pots: Vec<f64>,
+    pub rake: f64,
+    // Who collected what. A player who wins several pots appears once per pot.
+    pub winners: Vec<(String, f64)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Player {
+    pub name: String,
+    pub seat: usize,
+    pub stack: f64,
+    // Only known for the hero and for players who showed or mucked face up
+    pub hole_cards: Option<Vec<Card>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerAction {
+    pub street: usize,
+    pub player: String,
+    pub action: HandAction,
+    pub all_in: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HandAction {
+    PostSmallBlind(f64),
+    PostBigBlind(f64),
+    PostAnte(f64),
+    Fold,
+    Check,
+    Call(f64),
+    Bet(f64),
+    // Raises are written as "raises $by to $to"
+    Raise { by: f64, to: f64 },
+    // The part of a bet nobody called, given back to the bettor
+    UncalledBetReturned(f64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    // A line that couldn't be understood, with its line number counting from 1
+    InvalidLine(usize, String),
+    InvalidCards(usize, CardParseError),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::InvalidLine(line, text) => {
+                write!(f, "line {}: can't parse '{}'", line, text)
+            }
+            ParseError::InvalidCards(line, e) => write!(f, "line {}: {}", line, e),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// Parses every hand in the text of a PokerStars hand history file. Lines that
+// don't affect the hand (chat, players joining or sitting out, etc) are
+// skipped.
+pub fn parse_pokerstars(text: &str) -> Result<Vec<HandHistory>, ParseError> {
+    let mut hands = Vec::new();
+    let mut current: Option<HandParser> = None;
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line.trim();
+        if line.starts_with("PokerStars Hand #") || line.starts_with("PokerStars Game #") {
+            if let Some(parser) = current.take() {
+                hands.push(parser.hand);
+            }
+            current = Some(HandParser::new(line, line_number)?);
+        } else if let Some(parser) = current.as_mut() {
+            parser.parse_line(line, line_number)?;
+        }
+    }
+    if let Some(parser) = current {
+        hands.push(parser.hand);
+    }
+    Ok(hands)
+}
+
+// Writes a heads-up hand as PokerStars hand history text, which
+// parse_pokerstars() can read back in. The dealer is on the button in seat 1
+// and the opponent is in seat 2. hand has the dealer's hole cards followed by
+// the opponent's, like the start of a deck, and the opponent's can be left
+// out if they're unknown. result is what the dealer won (negative if they
+// lost, 0 for a split pot). Amounts are in chips with no rake. The hand has no
+// real ID or date, so those are written as 0 and the epoch.
+pub fn to_pokerstars_format(
+    history: &ActionHistory,
+    hand: &[Card],
+    board: &[Card],
+    players: [&str; 2],
+    stacks: [i32; 2],
+    result: i32,
+) -> String {
+    let cards_str = |cards: &[Card]| {
+        let cards: Vec<String> = cards.iter().map(|c| c.to_string()).collect();
+        format!("[{}]", cards.join(" "))
+    };
+    let hole_cards = |player: usize| hand.get(2 * player..2 * player + 2);
+    let street_names = ["Preflop", "Flop", "Turn", "River"];
+    let mut lines = vec![
+        format!(
+            "PokerStars Hand #0:  Hold'em No Limit ({}/{}) - 1970/01/01 00:00:00 ET",
+            SMALL_BLIND, BIG_BLIND
+        ),
+        "Table 'Blueprint' 2-max Seat #1 is the button".to_string(),
+        format!("Seat 1: {} ({} in chips)", players[DEALER], stacks[DEALER]),
+        format!(
+            "Seat 2: {} ({} in chips)",
+            players[OPPONENT], stacks[OPPONENT]
+        ),
+        format!("{}: posts small blind {}", players[DEALER], SMALL_BLIND),
+        format!("{}: posts big blind {}", players[OPPONENT], BIG_BLIND),
+        "*** HOLE CARDS ***".to_string(),
+        format!("Dealt to {} {}", players[DEALER], cards_str(&hand[..2])),
+    ];
+    let street_marker = |street: usize| {
+        let (name, dealt) = match street {
+            FLOP => ("FLOP", 0),
+            TURN => ("TURN", 3),
+            _ => ("RIVER", 4),
+        };
+        if street == FLOP {
+            format!("*** FLOP *** {}", cards_str(&board[..3]))
+        } else {
+            format!(
+                "*** {} *** {} {}",
+                name,
+                cards_str(&board[..dealt]),
+                cards_str(&board[dealt..dealt + 1])
+            )
+        }
+    };
+
+    // Replay the hand, keeping track of the real chips put in. The blinds are
+    // posted up front here, unlike in an ActionHistory.
+    let blinds = [SMALL_BLIND, BIG_BLIND];
+    let mut total = blinds;
+    let mut street_start = [0, 0];
+    let mut replay = ActionHistory::new();
+    let mut folded = None;
+    for street in PREFLOP..=RIVER {
+        for action in history.street_actions(street) {
+            let player = replay.player;
+            let name = players[player];
+            let other = 1 - player;
+            let put_in = |total: [i32; 2], p: usize| total[p] - street_start[p];
+            let before = put_in(total, player);
+            replay.add(action);
+            let after = (STACK_SIZE - replay.stack_sizes()[player]).max(blinds[player]);
+            let added = after - total[player];
+            let all_in = if replay.stack_sizes()[player] == 0 {
+                " and is all-in"
+            } else {
+                ""
+            };
+            let line = match action.action {
+                ActionType::Fold => {
+                    folded = Some(player);
+                    format!("{}: folds", name)
+                }
+                ActionType::Call if added == 0 => format!("{}: checks", name),
+                ActionType::Call => format!("{}: calls {}{}", name, added, all_in),
+                ActionType::Bet if street == PREFLOP || put_in(total, other) > before => format!(
+                    "{}: raises {} to {}{}",
+                    name,
+                    before + added - put_in(total, other),
+                    before + added,
+                    all_in
+                ),
+                ActionType::Bet => format!("{}: bets {}{}", name, added, all_in),
+            };
+            total[player] = after;
+            lines.push(line);
+            if replay.street != street && replay.street <= RIVER && !replay.hand_over() {
+                street_start = total;
+                lines.push(street_marker(replay.street));
+            }
+        }
+    }
+
+    // Give back the part of a bet that nobody called
+    let (big, small) = if total[DEALER] > total[OPPONENT] {
+        (DEALER, OPPONENT)
+    } else {
+        (OPPONENT, DEALER)
+    };
+    if total[big] > total[small] {
+        lines.push(format!(
+            "Uncalled bet ({}) returned to {}",
+            total[big] - total[small],
+            players[big]
+        ));
+        total[big] = total[small];
+    }
+    let pot = total[DEALER] + total[OPPONENT];
+
+    // Deal out the rest of the board if the players were all-in
+    let last_street = if folded.is_some() {
+        replay.street.min(RIVER)
+    } else {
+        let mut street = replay.street.min(RIVER + 1);
+        while street <= RIVER {
+            lines.push(street_marker(street));
+            street += 1;
+        }
+        RIVER
+    };
+    let winners: Vec<usize> = match folded {
+        Some(player) => vec![1 - player],
+        None if result > 0 => vec![DEALER],
+        None if result < 0 => vec![OPPONENT],
+        None => vec![DEALER, OPPONENT],
+    };
+    let won = pot / winners.len() as i32;
+    if folded.is_none() {
+        lines.push("*** SHOW DOWN ***".to_string());
+        for &player in &[OPPONENT, DEALER] {
+            match hole_cards(player) {
+                Some(cards) => {
+                    lines.push(format!("{}: shows {}", players[player], cards_str(cards)))
+                }
+                None => lines.push(format!("{}: mucks hand", players[player])),
+            }
+        }
+    }
+    for &player in &winners {
+        lines.push(format!("{} collected {} from pot", players[player], won));
+    }
+
+    lines.push("*** SUMMARY ***".to_string());
+    lines.push(format!("Total pot {} | Rake 0", pot));
+    let n_board = match last_street {
+        PREFLOP => 0,
+        FLOP => 3,
+        TURN => 4,
+        _ => 5,
+    };
+    if n_board > 0 {
+        lines.push(format!("Board {}", cards_str(&board[..n_board])));
+    }
+    for &player in &[DEALER, OPPONENT] {
+        let seat = match player {
+            DEALER => format!("Seat 1: {} (button) (small blind)", players[DEALER]),
+            _ => format!("Seat 2: {} (big blind)", players[OPPONENT]),
+        };
+        let outcome = if folded == Some(player) {
+            if last_street == PREFLOP {
+                "folded before Flop".to_string()
+            } else {
+                format!("folded on the {}", street_names[last_street])
+            }
+        } else if folded.is_some() {
+            format!("collected ({})", won)
+        } else {
+            let result = if winners.contains(&player) {
+                format!("won ({})", won)
+            } else {
+                "lost".to_string()
+            };
+            match hole_cards(player) {
+                Some(cards) => format!("showed {} and {}", cards_str(cards), result),
+                None if winners.contains(&player) => format!("collected ({})", won),
+                None => "mucked".to_string(),
+            }
+        };
+        lines.push(format!("{} {}", seat, outcome));
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+impl HandHistory {
+    pub fn player(&self, name: &str) -> Option<&Player> {
+        self.players.iter().find(|p| p.name == name)
+    }
+
+    // The two players of a heads-up hand, indexed by DEALER and OPPONENT
+    pub fn heads_up_players(&self) -> [&Player; 2] {
+        assert_eq!(
+            self.players.len(),
+            2,
+            "Only heads-up hands can be converted to an ActionHistory"
+        );
+        let dealer = self
+            .players
+            .iter()
+            .position(|p| p.seat == self.button_seat)
+            .expect("The button isn't seated");
+        [&self.players[dealer], &self.players[1 - dealer]]
+    }
+
+    // Replays a heads-up hand as an ActionHistory. Amounts are scaled so that
+    // the hand's big blind is our BIG_BLIND, and the stacks are our usual
+    // STACK_SIZE no matter how deep the real ones were.
+    pub fn to_action_history(&self) -> ActionHistory {
+        let mut history = ActionHistory::new();
+        for (_, action) in self.converted_actions() {
+            history.add(&action);
+        }
+        history
+    }
+
+    // Pairs each action of a heads-up hand with its equivalent in an
+    // ActionHistory, leaving out the blinds, returned bets and anything after
+    // the ActionHistory thinks the hand is over.
+    pub fn converted_actions(&self) -> Vec<(&PlayerAction, Action)> {
+        let dealer = &self.heads_up_players()[DEALER].name;
+        let scale = |amount: f64| (amount * BIG_BLIND as f64 / self.big_blind).round() as i32;
+
+        let mut converted = Vec::new();
+        let mut history = ActionHistory::new();
+        // What each player has put in on the current street, in our units.
+        // The blinds aren't posted up front in an ActionHistory, so they
+        // don't count.
+        let mut put_in = [0, 0];
+        let mut street = history.street;
+        for action in &self.actions {
+            if history.hand_over() {
+                break;
+            }
+            if history.street != street {
+                street = history.street;
+                put_in = [0, 0];
+            }
+            let player = if &action.player == dealer {
+                DEALER
+            } else {
+                OPPONENT
+            };
+            let stack = history.stack_sizes()[player];
+            let next = match action.action {
+                HandAction::Fold => FOLD,
+                HandAction::Check | HandAction::Call(_) => Action {
+                    action: ActionType::Call,
+                    amount: history.to_call().min(stack),
+                },
+                HandAction::Bet(amount) => Action {
+                    action: ActionType::Bet,
+                    amount: scale(amount).min(stack),
+                },
+                HandAction::Raise { to, .. } => Action {
+                    action: ActionType::Bet,
+                    amount: (scale(to) - put_in[player]).min(stack),
+                },
+                _ => continue,
+            };
+            put_in[player] += next.amount;
+            history.add(&next);
+            converted.push((action, next));
+        }
+        converted
+    }
+}
+
+// Keeps track of where we are in a hand while going through its lines
+struct HandParser {
+    hand: HandHistory,
+    street: usize,
+    in_summary: bool,
+}
+
+impl HandParser {
+    // Starts a new hand from a header line like
+    //
+    //   PokerStars Hand #208959234567:  Hold'em No Limit ($0.01/$0.02 USD) - 2020/01/15 12:34:56 ET
+    //
+    // Tournament headers have the blinds after the level instead, eg
+    // "Level I (10/20) - 2020/01/15 12:34:56 ET".
+    fn new(line: &str, line_number: usize) -> Result<HandParser, ParseError> {
+        let invalid = || ParseError::InvalidLine(line_number, line.to_string());
+        let after_hash = &line[line.find('#').ok_or_else(invalid)? + 1..];
+        let id = after_hash
+            .split(':')
+            .next()
+            .and_then(|id| id.parse().ok())
+            .ok_or_else(invalid)?;
+        let date_start = line.rfind(" - ").ok_or_else(invalid)?;
+        let date_time = line[date_start + 3..].trim().to_string();
+        let before_date = &line[..date_start];
+        let stakes_start = before_date.rfind('(').ok_or_else(invalid)?;
+        let stakes_end = before_date.rfind(')').ok_or_else(invalid)?;
+        let stakes = before_date[stakes_start + 1..stakes_end]
+            .split_whitespace()
+            .next()
+            .ok_or_else(invalid)?;
+        let mut blinds = stakes.split('/').map(parse_amount);
+        let (small_blind, big_blind) = match (blinds.next(), blinds.next()) {
+            (Some(Some(sb)), Some(Some(bb))) => (sb, bb),
+            _ => return Err(invalid()),
+        };
+
+        Ok(HandParser {
+            hand: HandHistory {
+                id: id,
+                date_time: date_time,
+                small_blind: small_blind,
+                big_blind: big_blind,
+                button_seat: 0,
+                players: Vec::new(),
+                actions: Vec::new(),
+                board: vec![Vec::new(); 4],
+                total_pot: 0.0,
+                pots: Vec::new(),
+                rake: 0.0,
+                winners: Vec::new(),
+            },
+            street: PREFLOP,
+            in_summary: false,
+        })
+    }
+
+    fn parse_line(&mut self, line: &str, line_number: usize) -> Result<(), ParseError> {
+        let invalid = || ParseError::InvalidLine(line_number, line.to_string());
+        if line.starts_with("***") {
+            return self.parse_section(line, line_number);
+        }
+        if line.starts_with("Table '") {
+            let seat = line.split("Seat #").nth(1).ok_or_else(invalid)?;
+            self.hand.button_seat = seat
+                .split_whitespace()
+                .next()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(invalid)?;
+            return Ok(());
+        }
+        if self.in_summary {
+            return self.parse_summary_line(line, line_number);
+        }
+        if line.starts_with("Seat ") {
+            return self.parse_seat(line, line_number);
+        }
+        if line.starts_with("Dealt to ") {
+            let rest = &line["Dealt to ".len()..];
+            let bracket = rest.find(" [").ok_or_else(invalid)?;
+            let cards = parse_cards(&rest[bracket..], line_number)?;
+            self.set_hole_cards(&rest[..bracket], cards);
+            return Ok(());
+        }
+        if line.starts_with("Uncalled bet (") {
+            let amount = between(line, "(", ")")
+                .and_then(parse_amount)
+                .ok_or_else(invalid)?;
+            let player = line.split(" returned to ").nth(1).ok_or_else(invalid)?;
+            self.push_action(player, HandAction::UncalledBetReturned(amount), false);
+            return Ok(());
+        }
+        if let Some(player) = self.player_prefix(line, " collected ") {
+            let amount = line[player.len() + " collected ".len()..]
+                .split_whitespace()
+                .next()
+                .and_then(parse_amount)
+                .ok_or_else(invalid)?;
+            self.hand.winners.push((player, amount));
+            return Ok(());
+        }
+        if let Some(player) = self.player_prefix(line, ": ") {
+            let action = &line[player.len() + 2..];
+            return self.parse_action(&player, action, line_number);
+        }
+        // Anything else is chat or table chatter
+        Ok(())
+    }
+
+    // Street markers like "*** TURN *** [7c 2d 9s] [Jh]". The new cards are
+    // in the last pair of brackets.
+    fn parse_section(&mut self, line: &str, line_number: usize) -> Result<(), ParseError> {
+        let street = if line.starts_with("*** FLOP ***") {
+            FLOP
+        } else if line.starts_with("*** TURN ***") {
+            TURN
+        } else if line.starts_with("*** RIVER ***") {
+            RIVER
+        } else {
+            if line.starts_with("*** SUMMARY ***") {
+                self.in_summary = true;
+            }
+            return Ok(());
+        };
+        let new_cards = &line[line.rfind('[').unwrap_or(line.len())..];
+        self.hand.board[street] = parse_cards(new_cards, line_number)?;
+        self.street = street;
+        Ok(())
+    }
+
+    // "Seat 1: Alice ($2 in chips)", possibly followed by "is sitting out"
+    fn parse_seat(&mut self, line: &str, line_number: usize) -> Result<(), ParseError> {
+        let invalid = || ParseError::InvalidLine(line_number, line.to_string());
+        let colon = line.find(": ").ok_or_else(invalid)?;
+        let seat = line[5..colon].parse().map_err(|_| invalid())?;
+        let rest = &line[colon + 2..];
+        let chips = rest.find(" in chips").ok_or_else(invalid)?;
+        let open = rest[..chips].rfind(" (").ok_or_else(invalid)?;
+        let stack = parse_amount(&rest[open + 2..chips]).ok_or_else(invalid)?;
+        self.hand.players.push(Player {
+            name: rest[..open].to_string(),
+            seat: seat,
+            stack: stack,
+            hole_cards: None,
+        });
+        Ok(())
+    }
+
+    fn parse_action(
+        &mut self,
+        player: &str,
+        action: &str,
+        line_number: usize,
+    ) -> Result<(), ParseError> {
+        let invalid = || ParseError::InvalidLine(line_number, format!("{}: {}", player, action));
+        let all_in = action.ends_with("and is all-in");
+        let words: Vec<&str> = action.split_whitespace().collect();
+        let amount = |i: usize| {
+            words
+                .get(i)
+                .and_then(|w| parse_amount(w))
+                .ok_or_else(invalid)
+        };
+        let parsed = match words.get(0).cloned().unwrap_or("") {
+            "folds" => HandAction::Fold,
+            "checks" => HandAction::Check,
+            "calls" => HandAction::Call(amount(1)?),
+            "bets" => HandAction::Bet(amount(1)?),
+            "raises" => HandAction::Raise {
+                by: amount(1)?,
+                to: amount(3)?,
+            },
+            "posts" => match words.get(1).cloned() {
+                // Posting both blinds at once, when coming in out of position
+                Some("small") if words.get(2) == Some(&"&") => HandAction::PostBigBlind(amount(5)?),
+                Some("small") => HandAction::PostSmallBlind(amount(3)?),
+                Some("big") => HandAction::PostBigBlind(amount(3)?),
+                Some("the") => HandAction::PostAnte(amount(3)?),
+                _ => return Err(invalid()),
+            },
+            "shows" => {
+                let cards = parse_cards(action, line_number)?;
+                self.set_hole_cards(player, cards);
+                return Ok(());
+            }
+            // Mucked cards are only known if they're listed in the summary.
+            // Everything else (doesn't show, sits out, chat...) doesn't matter.
+            _ => return Ok(()),
+        };
+        self.push_action(player, parsed, all_in);
+        Ok(())
+    }
+
+    fn parse_summary_line(&mut self, line: &str, line_number: usize) -> Result<(), ParseError> {
+        let invalid = || ParseError::InvalidLine(line_number, line.to_string());
+        if line.starts_with("Total pot ") {
+            // eg "Total pot $10 Main pot $6. Side pot $4. | Rake $0"
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let amount_at = |i: usize| {
+                words
+                    .get(i)
+                    .and_then(|w| parse_amount(w.trim_end_matches('.')))
+                    .ok_or_else(invalid)
+            };
+            for (i, word) in words.iter().enumerate() {
+                let is_pot = words.get(i + 1).map_or(false, |w| w.starts_with("pot"));
+                match *word {
+                    "Total" if is_pot => self.hand.total_pot = amount_at(i + 2)?,
+                    "Main" | "Side" if is_pot => self.hand.pots.push(amount_at(i + 2)?),
+                    "Rake" => self.hand.rake = amount_at(i + 1)?,
+                    _ => {}
+                }
+            }
+        } else if line.starts_with("Seat ") && line.contains(" mucked [") {
+            // eg "Seat 1: Alice (button) (small blind) mucked [Ah Kd]"
+            let rest = &line[line.find(": ").ok_or_else(invalid)? + 2..];
+            let name = self.player_prefix(rest, " ").ok_or_else(invalid)?;
+            let cards = parse_cards(&line[line.find(" mucked [").unwrap()..], line_number)?;
+            self.set_hole_cards(&name, cards);
+        }
+        Ok(())
+    }
+
+    // If the line starts with a seated player's name followed by separator,
+    // returns the name. Names can have spaces in them, so this checks against
+    // the names from the seat lines instead of splitting the line.
+    fn player_prefix(&self, line: &str, separator: &str) -> Option<String> {
+        self.hand
+            .players
+            .iter()
+            .map(|p| &p.name)
+            .filter(|name| line.starts_with(&format!("{}{}", name, separator)))
+            .max_by_key(|name| name.len())
+            .cloned()
+    }
+
+    fn push_action(&mut self, player: &str, action: HandAction, all_in: bool) {
+        self.hand.actions.push(PlayerAction {
+            street: self.street,
+            player: player.to_string(),
+            action: action,
+            all_in: all_in,
+        });
+    }
+
+    fn set_hole_cards(&mut self, name: &str, cards: Vec<Card>) {
+        if let Some(player) = self.hand.players.iter_mut().find(|p| p.name == name) {
+            player.hole_cards = Some(cards);
+        }
+    }
+}
+
+// Parses amounts like "$0.02", "€1,000.50" or "1500"
+fn parse_amount(text: &str) -> Option<f64> {
+    let cleaned: String = text
+        .chars()
+        .filter(|c| c.is_ascii_digit() || *c == '.')
+        .collect();
+    cleaned.parse().ok()
+}
+
+fn between<'a>(text: &'a str, open: &str, close: &str) -> Option<&'a str> {
+    let start = text.find(open)? + open.len();
+    let end = start + text[start..].find(close)?;
+    Some(&text[start..end])
+}
+
+// Parses the cards in the first pair of brackets, eg "[Ah Kd]"
+fn parse_cards(text: &str, line_number: usize) -> Result<Vec<Card>, ParseError> {
+    let inside = between(text, "[", "]")
+        .ok_or_else(|| ParseError::InvalidLine(line_number, text.to_string()))?;
+    let cards: Vec<&str> = inside.split_whitespace().collect();
+    try_strvec2cards(&cards).map_err(|e| ParseError::InvalidCards(line_number, e))
+}
+
+// A heads-up hand recorded as it's played, from the deal to the showdown,
+// so that hands the bot plays can be saved and replayed later. Players are
+// indexed by DEALER and OPPONENT. The blinds (stakes) are posted when the hand
+// is created, and each action's amount is the chips it adds to the pot on top
+// of them.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RecordedHand {
+    pub hand_id: u64,
+    // Seconds since the Unix epoch
+    pub timestamp: u64,
+    // The small and big blind
+    pub stakes: [i32; 2],
+    pub players: [String; 2],
+    // The stacks at the start of the hand
+    pub stacks: [i32; 2],
+    pub hole_cards: [[Card; 2]; 2],
+    pub board: Vec<Card>,
+    pub actions: Vec<(u8, Action)>,
+    pub pot: i32,
+    // None until the hand is over, and for a split pot
+    pub winner: Option<u8>,
+    // What each player won or lost
+    pub net_result: [i32; 2],
+}
+
+impl RecordedHand {
+    pub fn new(
+        hand_id: u64,
+        timestamp: u64,
+        stakes: [i32; 2],
+        players: [String; 2],
+        stacks: [i32; 2],
+        hole_cards: [[Card; 2]; 2],
+    ) -> RecordedHand {
+        RecordedHand {
+            hand_id: hand_id,
+            timestamp: timestamp,
+            stakes: stakes,
+            players: players,
+            stacks: stacks,
+            hole_cards: hole_cards,
+            board: Vec::new(),
+            actions: Vec::new(),
+            pot: stakes[0] + stakes[1],
+            winner: None,
+            net_result: [0, 0],
+        }
+    }
+
+    pub fn apply_action(&mut self, player: u8, action: Action) {
+        assert!(player < 2, "Bad player {}", player);
+        assert!(self.winner.is_none(), "The hand is already over");
+        self.pot += action.amount;
+        let folded = action.action == ActionType::Fold;
+        self.actions.push((player, action));
+        if folded {
+            // The rest of the winner's bet was never called, so they only win
+            // what the folding player put in
+            let loser = player as usize;
+            let lost = self.committed()[loser];
+            self.winner = Some(1 - player);
+            self.net_result[loser] = -lost;
+            self.net_result[1 - loser] = lost;
+        }
+    }
+
+    pub fn deal_flop(&mut self, cards: [Card; 3]) {
+        assert!(self.board.is_empty(), "The flop was already dealt");
+        self.board.extend(cards.iter().cloned());
+    }
+
+    pub fn deal_turn(&mut self, card: Card) {
+        assert_eq!(self.board.len(), 3, "The turn comes after the flop");
+        self.board.push(card);
+    }
+
+    pub fn deal_river(&mut self, card: Card) {
+        assert_eq!(self.board.len(), 4, "The river comes after the turn");
+        self.board.push(card);
+    }
+
+    // Settles the pot between the two hands. Only the chips both players put
+    // in are at stake, since the rest of a bigger all-in goes back.
+    pub fn showdown(&mut self) {
+        assert_eq!(self.board.len(), 5, "The board isn't complete");
+        assert!(self.winner.is_none(), "Somebody folded");
+        let strengths: Vec<i32> = self
+            .hole_cards
+            .iter()
+            .map(|hand| HAND_TABLE.hand_strength(&[&hand[..], &self.board[..]].concat()))
+            .collect();
+        let committed = self.committed();
+        let at_stake = committed[0].min(committed[1]);
+        if strengths[0] != strengths[1] {
+            let winner = if strengths[0] > strengths[1] { 0 } else { 1 };
+            self.winner = Some(winner as u8);
+            self.net_result[winner] = at_stake;
+            self.net_result[1 - winner] = -at_stake;
+        }
+    }
+
+    // Chips each player has put in. Heads-up the dealer posts the small
+    // blind.
+    pub fn committed(&self) -> [i32; 2] {
+        let mut committed = [0, 0];
+        committed[DEALER] = self.stakes[0];
+        committed[OPPONENT] = self.stakes[1];
+        for (player, action) in &self.actions {
+            committed[*player as usize] += action.amount;
+        }
+        committed
+    }
+}
@@ -0,0 +1,187 @@
+// Hand reading: estimates which hands a player holds from what they did. Every
+// combo starts out equally likely, and each of the player's actions scales
+// the weight of a combo by how likely the strategy is to take that action
+// with it (Bayes' rule, since the cards are dealt uniformly).
+use crate::card_utils::{deck, Card, HandRange};
+use crate::trainer_utils::*;
+use itertools::Itertools;
+use std::collections::HashMap;
+
+// Board cards out on each street
+const BOARD_SIZES: [usize; 4] = [0, 3, 4, 5];
+
+pub struct RangeEstimator<'a> {
+    strategy: StrategySource<'a>,
+    // Buckets the hole cards followed by the board, like ABSTRACTION.bin()
+    bucket: fn(&[Card]) -> i32,
+}
+
+// Where the action probabilities come from: the full nodes from training, or
+// the quantized blueprint the bot plays from
+enum StrategySource<'a> {
+    Nodes(&'a NodeMap),
+    Blueprint(&'a CompressedStrategy),
+}
+
+// A range where each combo has the probability that the player holds it
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightedRange {
+    combos: Vec<([Card; 2], f64)>,
+}
+
+impl<'a> RangeEstimator<'a> {
+    pub fn new(strategy: &'a NodeMap) -> RangeEstimator<'a> {
+        RangeEstimator {
+            strategy: StrategySource::Nodes(strategy),
+            bucket: |cards| ABSTRACTION.bin(cards),
+        }
+    }
+
+    pub fn from_blueprint(blueprint: &'a CompressedStrategy) -> RangeEstimator<'a> {
+        RangeEstimator {
+            strategy: StrategySource::Blueprint(blueprint),
+            bucket: |cards| ABSTRACTION.bin(cards),
+        }
+    }
+
+    pub fn with_bucket_fn(mut self, bucket: fn(&[Card]) -> i32) -> Self {
+        self.bucket = bucket;
+        self
+    }
+
+    // The range of the given player after the history. The board has to have
+    // the cards for the street the history is on, and no combo can use a
+    // board or dead card. If the strategy never plays like this with any
+    // combo, every combo is left equally likely.
+    pub fn estimate_range(
+        &self,
+        history: &ActionHistory,
+        player: usize,
+        board: &[Card],
+        dead_cards: &[Card],
+    ) -> WeightedRange {
+        let blocked = [board, dead_cards].concat();
+        let combos: Vec<[Card; 2]> = deck()
+            .into_iter()
+            .filter(|c| !blocked.contains(c))
+            .combinations(2)
+            .map(|combo| [combo[0].clone(), combo[1].clone()])
+            .collect();
+        let mut weights = vec![1.0; combos.len()];
+
+        // Replay the history in the bet abstraction, since that's what the
+        // strategy knows about
+        let translated = history.translate(bet_abstraction());
+        let mut replayed = ActionHistory::new();
+        for street in PREFLOP..=translated.street.min(RIVER) {
+            for action in translated.street_actions(street) {
+                if replayed.player == player {
+                    self.update_weights(&combos, &mut weights, &replayed, action, board);
+                }
+                replayed.add(action);
+            }
+        }
+
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 {
+            weights = vec![1.0; combos.len()];
+        }
+        let total: f64 = weights.iter().sum();
+        WeightedRange {
+            combos: combos
+                .into_iter()
+                .zip(weights.into_iter().map(|w| w / total))
+                .collect(),
+        }
+    }
+
+    // Multiplies each combo's weight by the probability of taking the action.
+    // Lots of combos share a bucket, so the strategy is only looked up once
+    // per bucket.
+    fn update_weights(
+        &self,
+        combos: &[[Card; 2]],
+        weights: &mut [f64],
+        history: &ActionHistory,
+        action: &Action,
+        board: &[Card],
+    ) {
+        let board = &board[..BOARD_SIZES[history.street]];
+        let mut probs: HashMap<i32, f64> = HashMap::new();
+        for (combo, weight) in combos.iter().zip(weights.iter_mut()) {
+            if *weight == 0.0 {
+                continue;
+            }
+            let bucket = (self.bucket)(&[&combo[..], board].concat());
+            let prob = probs
+                .entry(bucket)
+                .or_insert_with(|| self.action_prob(history, bucket, action));
+            *weight *= *prob;
+        }
+    }
+
+    // Infosets the strategy never reached are played uniformly
+    fn action_prob(&self, history: &ActionHistory, bucket: i32, action: &Action) -> f64 {
+        let infoset = InfoSet::new(history.clone(), bucket);
+        let prob = match &self.strategy {
+            StrategySource::Nodes(nodes) => nodes
+                .get(&infoset.compress())
+                .map(|node| node.cumulative_strategy().get(action).cloned()),
+            StrategySource::Blueprint(blueprint) => {
+                blueprint.get(&infoset.compress()).map(|probs| {
+                    infoset
+                        .next_actions()
+                        .iter()
+                        .zip(dequantize_probs(probs))
+                        .find(|(a, _)| *a == action)
+                        .map(|(_, prob)| prob)
+                })
+            }
+        };
+        match prob {
+            Some(prob) => prob.unwrap_or(0.0),
+            None => 1.0 / infoset.next_actions().len() as f64,
+        }
+    }
+}
+
+impl WeightedRange {
+    // Every combo of the range equally likely
+    pub fn uniform(range: &HandRange) -> WeightedRange {
+        let weight = 1.0 / range.len() as f64;
+        WeightedRange {
+            combos: range.combos().iter().map(|c| (c.clone(), weight)).collect(),
+        }
+    }
+
+    pub fn combos(&self) -> &[([Card; 2], f64)] {
+        &self.combos
+    }
+
+    pub fn len(&self) -> usize {
+        self.combos.len()
+    }
+
+    // The probability of the given hole cards, in either order
+    pub fn weight(&self, hand: &[Card]) -> f64 {
+        self.combos
+            .iter()
+            .find(|(combo, _)| {
+                (combo[0] == hand[0] && combo[1] == hand[1])
+                    || (combo[0] == hand[1] && combo[1] == hand[0])
+            })
+            .map(|(_, weight)| *weight)
+            .unwrap_or(0.0)
+    }
+
+    // The combos with at least the given probability
+    pub fn hand_range(&self, min_weight: f64) -> HandRange {
+        let combos = self
+            .combos
+            .iter()
+            .filter(|(_, weight)| *weight >= min_weight)
+            .map(|(combo, _)| combo.clone())
+            .collect();
+        HandRange::from_combos(combos)
+    }
+}
@@ -0,0 +1,74 @@
+// The Independent Chip Model, for turning tournament chips into prize money.
+// Chips aren't worth the same at every stack size: doubling up doesn't double
+// your prize equity, but busting takes all of it. Uses the Malmuth-Harville
+// model, where each place goes to each remaining player with probability
+// proportional to their stack.
+
+// Each player's expected prize money. prize_pool[k] is the prize for place
+// k + 1, and players with no chips left get nothing. Going through all the
+// finishing orders is O(n!), but the chance of the top places going to a set
+// of players only depends on the set, so this is a DP over the 2^n subsets of
+// players.
+pub fn icm_equity(stacks: &[i32], prize_pool: &[f64]) -> Vec<f64> {
+    let n = stacks.len();
+    assert!(n <= 20, "Too many players for ICM");
+    let total: i64 = stacks.iter().map(|&s| s.max(0) as i64).sum();
+    let mut equity = vec![0.0; n];
+    if total == 0 {
+        return equity;
+    }
+    // prob[mask] is the probability that the players in mask take the top
+    // mask.count_ones() places, in any order
+    let mut prob = vec![0.0; 1 << n];
+    prob[0] = 1.0;
+    for mask in 0..(1usize << n) {
+        let place = mask.count_ones() as usize;
+        if prob[mask] == 0.0 || place >= prize_pool.len() {
+            continue;
+        }
+        let left: i64 = (0..n)
+            .filter(|&i| mask & (1 << i) == 0)
+            .map(|i| stacks[i].max(0) as i64)
+            .sum();
+        if left == 0 {
+            continue;
+        }
+        for i in 0..n {
+            if mask & (1 << i) != 0 || stacks[i] <= 0 {
+                continue;
+            }
+            let p = prob[mask] * stacks[i] as f64 / left as f64;
+            equity[i] += p * prize_pool[place];
+            prob[mask | (1 << i)] += p;
+        }
+    }
+    equity
+}
+
+// Converts a chip EV into prize money for a spot where the player either wins
+// or loses stack_delta chips against the opponent, eg an all-in. The chance of
+// winning is backed out from the chip EV, and the result is how much the
+// player's ICM equity goes up or down on average.
+pub fn icm_adjusted_ev(
+    chip_ev: f64,
+    stack_delta: i32,
+    stacks: &[i32],
+    prize_pool: &[f64],
+    player: usize,
+    opponent: usize,
+) -> f64 {
+    // Nobody can lose more than they have
+    let delta = stack_delta.min(stacks[player]).min(stacks[opponent]);
+    if delta <= 0 {
+        return 0.0;
+    }
+    let win_prob = ((chip_ev / delta as f64 + 1.0) / 2.0).clamp(0.0, 1.0);
+    let moved = |delta: i32| {
+        let mut stacks = stacks.to_vec();
+        stacks[player] += delta;
+        stacks[opponent] -= delta;
+        icm_equity(&stacks, prize_pool)[player]
+    };
+    let now = icm_equity(stacks, prize_pool)[player];
+    win_prob * moved(delta) + (1.0 - win_prob) * moved(-delta) - now
+}
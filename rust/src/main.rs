@@ -9,6 +9,8 @@ extern crate rand;
 extern crate lazy_static;
 extern crate bincode;
 extern crate bio;
+extern crate candle_core;
+extern crate candle_nn;
 extern crate qstring;
 extern crate rayon;
 
@@ -16,7 +18,15 @@ mod backend;
 mod bot;
 mod card_abstraction;
 mod card_utils;
+mod config;
+mod deep_cfr;
 mod exploiter;
+mod hand_history;
+mod hand_reading;
+mod icm;
+mod plo;
+mod replay;
+mod simulation;
 mod tests;
 mod trainer;
 mod trainer_utils;
@@ -28,14 +38,63 @@ use std::mem::size_of_val;
 use std::collections::HashMap;
 
 fn main() {
-    // backend::main().expect("Could not launch server");
+    // Settings come from config.toml, unless another file is given with
+    // --config. This has to happen before any of the tables get loaded.
+    let mut args: Vec<String> = std::env::args().collect();
+    if args.len() >= 3 && args[1] == "--config" {
+        let config = config::Config::load(&args[2]).expect("Could not load the config file");
+        config::set_config(config).unwrap();
+        args.drain(1..3);
+    }
+    let config = config::config();
 
-    trainer::train(1_000_000);
+    // backend::main(config.server.clone()).expect("Could not launch server");
+
+    if args.len() == 3 && args[1] == "replay" {
+        if let Err(e) = replay::replay_file(&args[2]) {
+            eprintln!("{}", e);
+        }
+        return;
+    }
+    if args.len() == 4 && args[1] == "migrate-strategy" {
+        match trainer::migrate_strategy_v1_to_v2(&args[2], &args[3]) {
+            Ok(()) => println!("Saved the strategy to {}", args[3]),
+            Err(e) => eprintln!("{}", e),
+        }
+        return;
+    }
+    if args.len() == 3 && args[1] == "simulate" {
+        let n_hands = args[2].parse().expect("Number of hands should be a number");
+        let result = simulation::simulate_heads_up(n_hands, &mut rand::thread_rng());
+        println!("{}", result);
+        return;
+    }
+    if args.len() == 3 && args[1] == "preflop-strengths" {
+        let n_samples = args[2].parse().expect("Number of samples should be a number");
+        let strengths = card_abstraction::compute_preflop_strengths(n_samples, 0);
+        print!("{}", card_abstraction::preflop_strengths_source(&strengths));
+        return;
+    }
+
+    trainer::train_with_config(config.training.clone(), None);
     // validation::preflop_matrix();
     // validation::donk_percentage();
 
-    // trainer::train(100_000_000);
-    // let nodes = trainer::load_nodes();
-    // trainer::view_preflop(&nodes);
-    // crate::trainer_utils::write_compact_blueprint(&nodes);
+    // trainer::train_with_config(
+    //     trainer::TrainingConfig::builder()
+    //         .iterations(100_000_000)
+    //         .checkpoint_path(trainer::CHECKPOINT_PATH)
+    //         .build(),
+    //     None,
+    // );
+    // match trainer::load_strategy() {
+    //     Ok(nodes) => {
+    //         trainer::print_preflop_matrix(&nodes);
+    //         crate::trainer_utils::write_compact_blueprint(&nodes);
+    //     }
+    //     Err(e) => eprintln!("{}", e),
+    // }
+
+    // let mut deep_cfr = deep_cfr::DeepCFRTrainer::new(deep_cfr::DeepCFRConfig::default());
+    // deep_cfr.train(100);
 }
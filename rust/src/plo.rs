@@ -0,0 +1,123 @@
+// Pot-Limit Omaha hand evaluation. In PLO each player gets 4 hole cards and
+// must make their hand from exactly 2 of them plus exactly 3 board cards, so
+// the usual best-5-of-7 lookup doesn't apply directly.
+use crate::card_utils::{canonical_hand_with_hole, deck, five_card_hand_strength, Card};
+use crate::config::config;
+use crate::itertools::Itertools;
+use rand::prelude::SliceRandom;
+use rand::Rng;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+
+// Returns the strength of the best hand made from 2 hole cards and 3 board
+// cards. The board can be the flop, turn or river. Each candidate is a 5-card
+// hand, so this uses the 5-card table rather than HAND_TABLE.
+pub fn plo_hand_strength(hole_cards: &[Card; 4], board: &[Card]) -> i32 {
+    let mut max_strength = 0;
+    for hole in hole_cards.iter().combinations(2) {
+        for three in board.iter().combinations(3) {
+            let hand: Vec<Card> = hole
+                .iter()
+                .chain(three.iter())
+                .map(|&c| c.clone())
+                .collect();
+            let strength = five_card_hand_strength(&hand);
+            if strength > max_strength {
+                max_strength = strength;
+            }
+        }
+    }
+    max_strength
+}
+
+// Maps the hole cards to their suit-isomorphic canonical version, eg AsKsQdJd
+// and AhKhQcJc both become AcKcQdJd.
+pub fn plo_canonical_hole(cards: &[Card; 4]) -> [Card; 4] {
+    let canonical = canonical_hand_with_hole(cards, 0);
+    [
+        canonical[0].clone(),
+        canonical[1].clone(),
+        canonical[2].clone(),
+        canonical[3].clone(),
+    ]
+}
+
+// Estimates the river equity of the hole cards against a random 4-card hand by
+// sampling n_samples opponent hands.
+pub fn plo_river_equity(
+    hole_cards: &[Card; 4],
+    board: &[Card],
+    n_samples: usize,
+    rng: &mut impl Rng,
+) -> f64 {
+    let mut deck = deck();
+    deck.retain(|c| !hole_cards.contains(&c) && !board.contains(&c));
+
+    let my_strength = plo_hand_strength(hole_cards, board);
+    let mut n_wins = 0.0;
+    for _ in 0..n_samples {
+        let opp: Vec<Card> = deck.choose_multiple(rng, 4).cloned().collect();
+        let opp = [
+            opp[0].clone(),
+            opp[1].clone(),
+            opp[2].clone(),
+            opp[3].clone(),
+        ];
+        let opp_strength = plo_hand_strength(&opp, board);
+        if my_strength > opp_strength {
+            n_wins += 1.0;
+        } else if my_strength == opp_strength {
+            n_wins += 0.5;
+        }
+    }
+    n_wins / (n_samples as f64)
+}
+
+// River equity lookup table for PLO, analogous to the hold'em EquityTable.
+// There are far too many PLO river hands to enumerate up front, so the table
+// starts with whatever was saved last time and gets filled in as equities are
+// computed. 9 cards don't fit in the u64 hand representation, so the keys are
+// the canonical cards themselves, hole cards first.
+// TODO: Build the full table once there's a PLO card abstraction to go with it.
+pub struct PloEquityTable {
+    table: HashMap<Vec<Card>, f64>,
+}
+
+impl PloEquityTable {
+    pub fn new() -> PloEquityTable {
+        match File::open(&config().paths.plo_equity_table) {
+            Err(_e) => PloEquityTable {
+                table: HashMap::new(),
+            },
+            Ok(file) => PloEquityTable {
+                table: bincode::deserialize_from(BufReader::new(file))
+                    .expect("Failed to deserialize PLO equity table"),
+            },
+        }
+    }
+
+    pub fn lookup(&self, hole_cards: &[Card; 4], board: &[Card]) -> Option<f64> {
+        self.table
+            .get(&PloEquityTable::key(hole_cards, board))
+            .cloned()
+    }
+
+    pub fn insert(&mut self, hole_cards: &[Card; 4], board: &[Card], equity: f64) {
+        self.table
+            .insert(PloEquityTable::key(hole_cards, board), equity);
+    }
+
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    pub fn save(&self) {
+        let file = File::create(&config().paths.plo_equity_table).unwrap();
+        bincode::serialize_into(BufWriter::new(file), &self.table).unwrap();
+    }
+
+    fn key(hole_cards: &[Card; 4], board: &[Card]) -> Vec<Card> {
+        canonical_hand_with_hole(&[&hole_cards[..], board].concat(), 4)
+    }
+}
@@ -0,0 +1,230 @@
+// Steps through recorded hand histories one action at a time, showing what
+// the bot would have done in each spot. From the command line:
+//
+//      cargo run --release -- replay hands.txt
+//
+// Only heads-up hands can be replayed, since that's all the bot plays. The
+// bot's recommendation is only shown for players whose hole cards are known.
+use crate::bot::action_distribution;
+use crate::card_utils::{cards2str, Card};
+use crate::hand_history::{parse_pokerstars, HandAction, HandHistory, ParseError, PlayerAction};
+use crate::trainer_utils::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+
+// Gives the strategy for the player to act from their hole cards, the board
+// and the history so far. Normally the blueprint, through action_distribution.
+pub type ReplayStrategy = fn(&[Card], &[Card], &ActionHistory) -> HashMap<Action, f64>;
+
+pub struct HandReplayer {
+    history: HandHistory,
+    // How many actions have been replayed so far
+    position: usize,
+    // Every action that made it into the ActionHistory, with its translation
+    actions: Vec<(PlayerAction, Action)>,
+    strategy: ReplayStrategy,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayStep {
+    // DEALER or OPPONENT
+    pub player: usize,
+    pub action: PlayerAction,
+    // The action as it went into the ActionHistory
+    pub translated: Action,
+    // The state after the action
+    pub state: ActionHistory,
+    // The board when the action was taken
+    pub board: Vec<Card>,
+    // What the bot would have done instead and how likely it was to do it.
+    // None if the player's hole cards aren't known.
+    pub bot_action: Option<(Action, f64)>,
+}
+
+impl HandReplayer {
+    pub fn new(history: HandHistory) -> HandReplayer {
+        HandReplayer::with_strategy(history, action_distribution)
+    }
+
+    pub fn with_strategy(history: HandHistory, strategy: ReplayStrategy) -> HandReplayer {
+        let actions = history
+            .converted_actions()
+            .into_iter()
+            .map(|(action, translated)| (action.clone(), translated))
+            .collect();
+        HandReplayer {
+            history: history,
+            position: 0,
+            actions: actions,
+            strategy: strategy,
+        }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn len(&self) -> usize {
+        self.actions.len()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.position == self.actions.len()
+    }
+
+    // The ActionHistory after the actions replayed so far
+    pub fn current_state(&self) -> ActionHistory {
+        self.state_at(self.position)
+    }
+
+    pub fn next_action(&mut self) -> Option<ReplayStep> {
+        if self.is_finished() {
+            return None;
+        }
+        let step = self.step(self.position);
+        self.position += 1;
+        Some(step)
+    }
+
+    // Undoes the last action, returning the step that was undone
+    pub fn previous_action(&mut self) -> Option<ReplayStep> {
+        if self.position == 0 {
+            return None;
+        }
+        self.position -= 1;
+        Some(self.step(self.position))
+    }
+
+    // The bot's most likely action for the player to act. Panics if their
+    // hole cards aren't known or the hand is over.
+    pub fn bot_action_at_current_state(&self) -> Action {
+        let state = self.current_state();
+        assert!(!state.hand_over(), "The hand is over");
+        match self.recommendation(&state) {
+            Some((action, _)) => action,
+            None => panic!("The hole cards of the player to act aren't known"),
+        }
+    }
+
+    fn state_at(&self, position: usize) -> ActionHistory {
+        let mut state = ActionHistory::new();
+        for (_, action) in &self.actions[..position] {
+            state.add(action);
+        }
+        state
+    }
+
+    fn step(&self, position: usize) -> ReplayStep {
+        let before = self.state_at(position);
+        let (action, translated) = &self.actions[position];
+        let mut state = before.clone();
+        state.add(translated);
+        ReplayStep {
+            player: before.player,
+            action: action.clone(),
+            translated: translated.clone(),
+            state: state,
+            board: self.board(before.street),
+            bot_action: self.recommendation(&before),
+        }
+    }
+
+    // The bot's most likely action in this state along with its probability
+    fn recommendation(&self, state: &ActionHistory) -> Option<(Action, f64)> {
+        let hand = self.history.heads_up_players()[state.player]
+            .hole_cards
+            .as_ref()?;
+        let distribution = (self.strategy)(hand, &self.board(state.street), state);
+        let action = most_likely_action(&distribution);
+        let prob = distribution[&action];
+        Some((action, prob))
+    }
+
+    // The board cards out by the given street
+    fn board(&self, street: usize) -> Vec<Card> {
+        self.history.board[..=street.min(RIVER)].concat()
+    }
+}
+
+impl fmt::Display for ReplayStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let street = match self.board.len() {
+            0 => "preflop",
+            3 => "flop",
+            4 => "turn",
+            _ => "river",
+        };
+        let action = match &self.action.action {
+            HandAction::Fold => String::from("folds"),
+            HandAction::Check => String::from("checks"),
+            HandAction::Call(amount) => format!("calls {}", amount),
+            HandAction::Bet(amount) => format!("bets {}", amount),
+            HandAction::Raise { to, .. } => format!("raises to {}", to),
+            other => format!("{:?}", other),
+        };
+        write!(
+            f,
+            "{:>7} [{}] {} {} ({})",
+            street,
+            cards2str(&self.board),
+            self.action.player,
+            action,
+            self.translated
+        )?;
+        match &self.bot_action {
+            Some((bot_action, prob)) => {
+                write!(f, " | bot: {} ({:.0}%)", bot_action, prob * 100.0)
+            }
+            None => write!(f, " | bot: hole cards unknown"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    Parse(ParseError),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "can't read hand history: {}", e),
+            ReplayError::Parse(e) => write!(f, "can't parse hand history: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<io::Error> for ReplayError {
+    fn from(error: io::Error) -> ReplayError {
+        ReplayError::Io(error)
+    }
+}
+
+impl From<ParseError> for ReplayError {
+    fn from(error: ParseError) -> ReplayError {
+        ReplayError::Parse(error)
+    }
+}
+
+// Prints every step of every heads-up hand in a PokerStars hand history file
+pub fn replay_file(path: &str) -> Result<(), ReplayError> {
+    let text = fs::read_to_string(path)?;
+    for hand in parse_pokerstars(&text)? {
+        if hand.players.len() != 2 {
+            println!("Skipping hand #{}, it isn't heads-up", hand.id);
+            continue;
+        }
+        println!("Hand #{}", hand.id);
+        let mut replayer = HandReplayer::new(hand);
+        while let Some(step) = replayer.next_action() {
+            println!("{}", step);
+        }
+        println!();
+    }
+    Ok(())
+}
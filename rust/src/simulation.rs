@@ -0,0 +1,163 @@
+// Headless self-play: deals random hands and plays them out between two bots,
+// keeping track of who won what. From the command line:
+//
+//      cargo run --release -- simulate 10000
+//
+// plays the bot against itself. The hero switches seats every hand, so
+// the dealer's positional edge cancels out. Any two bots that play the same
+// strategy should then break even, and total_ev / n_hands should go to 0 as
+// more hands are played.
+use crate::bot::bot_action;
+use crate::card_utils::{deck, Card};
+use crate::trainer_utils::*;
+use rand::prelude::SliceRandom;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fmt;
+
+// Board cards out on each street
+const BOARD_SIZES: [usize; 4] = [0, 3, 4, 5];
+
+// Picks the action for the player to act from their hole cards, the board and
+// the history so far. Normally bot_action().
+pub type SimulationStrategy = fn(&[Card], &[Card], &ActionHistory) -> Action;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationResult {
+    pub n_hands: u64,
+    // The hero's winnings over all the hands, in big blinds
+    pub total_ev: f64,
+    // total_ev split up by the street the last action was on
+    pub ev_by_street: [f64; 4],
+    // How often each type of action was taken, out of every action by either
+    // player
+    pub action_frequencies: HashMap<ActionType, f64>,
+    // Fraction of hands that went to showdown
+    pub showdown_rate: f64,
+    // Fraction of hands where both players got all their chips in
+    pub all_in_rate: f64,
+}
+
+// How one hand went, from the hero's side
+struct HandOutcome {
+    utility: f64,
+    street: usize,
+    actions: Vec<ActionType>,
+    showdown: bool,
+    all_in: bool,
+}
+
+pub fn simulate_heads_up(n_hands: u64, rng: &mut impl Rng) -> SimulationResult {
+    simulate_heads_up_with(n_hands, rng, [bot_action, bot_action])
+}
+
+// Plays strategies[0] (the hero) against strategies[1]. The hero is the
+// dealer in even hands and the opponent in odd ones. Hands are played in
+// parallel, each with its own deck seeded from rng, so the result only
+// depends on rng (as long as the strategies are deterministic).
+pub fn simulate_heads_up_with(
+    n_hands: u64,
+    rng: &mut impl Rng,
+    strategies: [SimulationStrategy; 2],
+) -> SimulationResult {
+    let seed: u64 = rng.gen();
+    let outcomes: Vec<HandOutcome> = (0..n_hands)
+        .into_par_iter()
+        .map(|i| {
+            let mut rng = StdRng::seed_from_u64(seed.rotate_left(32) ^ i);
+            let mut deck = deck();
+            deck.shuffle(&mut rng);
+            let hero = (i % 2) as usize;
+            play_hand(&deck, hero, strategies)
+        })
+        .collect();
+
+    let mut total_ev = 0.0;
+    let mut ev_by_street = [0.0; 4];
+    let mut action_counts: HashMap<ActionType, f64> = HashMap::new();
+    let mut showdowns = 0;
+    let mut all_ins = 0;
+    for outcome in &outcomes {
+        total_ev += outcome.utility;
+        ev_by_street[outcome.street] += outcome.utility;
+        for action in &outcome.actions {
+            *action_counts.entry(action.clone()).or_insert(0.0) += 1.0;
+        }
+        showdowns += outcome.showdown as u64;
+        all_ins += outcome.all_in as u64;
+    }
+    let hands = n_hands.max(1) as f64;
+    SimulationResult {
+        n_hands: n_hands,
+        total_ev: total_ev,
+        ev_by_street: ev_by_street,
+        action_frequencies: normalize(&action_counts),
+        showdown_rate: showdowns as f64 / hands,
+        all_in_rate: all_ins as f64 / hands,
+    }
+}
+
+// Plays out one hand with the given deck, which is laid out like in get_hand()
+fn play_hand(deck: &[Card], hero: usize, strategies: [SimulationStrategy; 2]) -> HandOutcome {
+    let mut history = ActionHistory::new();
+    let mut actions = Vec::new();
+    let mut street = PREFLOP;
+    while !history.hand_over() {
+        let player = history.player;
+        let hole = &deck[2 * player..2 * player + 2];
+        let board = &deck[4..4 + BOARD_SIZES[history.street]];
+        let strategy = if player == hero {
+            strategies[0]
+        } else {
+            strategies[1]
+        };
+        let action = strategy(hole, board, &history);
+        street = history.street;
+        actions.push(action.action.clone());
+        history.add(&action);
+    }
+    let showdown = history.last_action().unwrap().action != ActionType::Fold;
+    HandOutcome {
+        utility: terminal_utility(deck, history.clone(), hero) / BIG_BLIND as f64,
+        street: street,
+        actions: actions,
+        showdown: showdown,
+        all_in: history.stack_sizes() == [0, 0],
+    }
+}
+
+impl SimulationResult {
+    // The hero's win rate in big blinds per hand
+    pub fn bb_per_hand(&self) -> f64 {
+        self.total_ev / self.n_hands.max(1) as f64
+    }
+}
+
+impl fmt::Display for SimulationResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} hands, hero wins {:.4} BB/hand ({:.1} BB total)",
+            self.n_hands,
+            self.bb_per_hand(),
+            self.total_ev
+        )?;
+        let streets = ["preflop", "flop", "turn", "river"];
+        for (name, ev) in streets.iter().zip(self.ev_by_street.iter()) {
+            writeln!(f, "{:>8}: {:.1} BB", name, ev)?;
+        }
+        for action in &[ActionType::Fold, ActionType::Call, ActionType::Bet] {
+            let frequency = self.action_frequencies.get(action).unwrap_or(&0.0);
+            writeln!(
+                f,
+                "{:>8}: {:.1}%",
+                format!("{:?}", action),
+                frequency * 100.0
+            )?;
+        }
+        writeln!(f, "showdown: {:.1}%", self.showdown_rate * 100.0)?;
+        write!(f, "  all-in: {:.1}%", self.all_in_rate * 100.0)
+    }
+}
@@ -1,8 +1,59 @@
-use crate::card_abstraction::{Abstraction, LightAbstraction};
+use crate::backend::metrics::{self, MetricsRegistry};
+use crate::backend::session::{self, SessionStore};
+use crate::backend::{api, game_session_with};
+use crate::bot::{
+    action_distribution_with, action_evs_against, blueprint_fallback_count, bot_action_dls_with,
+    bot_action_with_blueprint, legal_distribution, sample_action_with, solve_river_subgame,
+};
+use crate::card_abstraction::{
+    append_turn_equity, cluster, earth_movers_distance, equity_distribution,
+    flop_equity_distribution, load_abstraction, make_flop_equity_with,
+    potential_aware_distribution, preflop_bin_index, preflop_matrix_169, read_turn_equity,
+    turn_equity_distribution, validate_table, Abstraction, AbstractionError, AbstractionStats,
+    KMeansConfig, LightAbstraction, EQUITY_BINS, FLOP_BUCKETS, N_FLOP_CANONICAL, N_RIVER_CANONICAL,
+    PREFLOP_BUCKETS,
+};
 use crate::card_utils::*;
-use crate::trainer::load_blueprint;
+use crate::config::{config, Config, ConfigError};
+use crate::deep_cfr::{
+    featurize, AdvantageNet, DeepCFRConfig, DeepCFRTrainer, FullyConnectedNet, NetConfig,
+    StrategyNet, N_FEATURES,
+};
+use crate::exploiter::{
+    always_call, best_response_on_deals, exploitability_by_street_on_deals,
+    exploitability_on_deals, exploitable_spots_on_deals,
+};
+use crate::hand_history::{
+    parse_pokerstars, to_pokerstars_format, HandAction, ParseError, PlayerAction, RecordedHand,
+};
+use crate::hand_reading::{RangeEstimator, WeightedRange};
+use crate::icm::{icm_adjusted_ev, icm_equity};
+use crate::plo::{plo_canonical_hole, plo_hand_strength, PloEquityTable};
+use crate::replay::HandReplayer;
+use crate::simulation::simulate_heads_up_with;
+use crate::trainer::{
+    compute_reach_probabilities, export_strategy_csv, external_sample_regrets, import_strategy_csv,
+    load_blueprint, load_blueprint_from, load_checkpoint, load_strategy_from,
+    migrate_strategy_v1_to_v2, outcome_sample_regrets, preflop_matrix_string, prune_strategy,
+    save_blueprint, save_checkpoint, save_strategy, strategy_diff, strategy_diff_by_street,
+    strategy_size_bytes, train_iteration_with, train_iterations_with,
+    train_parallel_iterations_with, CFRAlgorithm, CheckpointError, ExploitabilityCallback,
+    StrategyLoadErrorKind, TrainingCallback, TrainingConfig, TrainingConfigError,
+    BLUEPRINT_FORMAT_VERSION, STRATEGY_FORMAT_VERSION,
+};
 use crate::trainer_utils::*;
+use crate::validation::{check_strategy_coverage, hand_strength_monotonicity_check};
+use actix_web::{web, App, HttpRequest};
+use actix_web_actors::ws;
+use futures::{SinkExt, StreamExt};
+use itertools::Itertools;
 use rand::prelude::SliceRandom;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::time::{Duration, Instant};
 
 #[test]
 fn uint_hands() {
@@ -29,6 +80,156 @@ fn uint_hands() {
     assert_eq!(hand2cards(cards2hand(&cards)), cards);
 }
 
+#[test]
+fn uint_hand_round_trip() {
+    let hand_str = "Ac2d7h9cTd2s8c";
+    for n in 1..8 {
+        let prefix = &hand_str[..2 * n];
+        let hand = str2hand(prefix);
+        assert_eq!(len(hand), n as i32);
+        assert_eq!(hand2str(hand), prefix);
+        assert_eq!(hand, cards2hand(&hand2cards(hand)));
+        assert_eq!(hand, cards2hand(&str2cards(prefix).unwrap()));
+    }
+}
+
+#[test]
+fn uint_cards_round_trip() {
+    for card in deck() {
+        let hand = cards2hand(&[card.clone()]);
+        assert_eq!(hand, (15 * card.suit + card.rank) as u64);
+        assert_eq!(hand2cards(hand), vec![card]);
+    }
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut deck = deck();
+    for _ in 0..1000 {
+        deck.shuffle(&mut rng);
+        for n in 2..=7 {
+            let cards = &deck[..n];
+            assert_eq!(hand2cards(cards2hand(cards)), cards);
+        }
+    }
+}
+
+#[test]
+fn uint_card_helpers() {
+    // 2c is the lowest card byte and As the highest
+    assert_eq!(card(str2hand("2c"), 0), 2);
+    assert_eq!(card(str2hand("As"), 0), 59);
+    assert_eq!(suit(2), CLUBS);
+    assert_eq!(rank(2), 2);
+    assert_eq!(suit(59), SPADES);
+    assert_eq!(rank(59), 14);
+    assert_eq!(suit(15 * DIAMONDS + 10), DIAMONDS);
+    assert_eq!(rank(15 * HEARTS + 11), 11);
+
+    assert_eq!(len(0), 0);
+    assert_eq!(len(str2hand("Kh")), 1);
+    assert_eq!(len(str2hand("KhQh")), 2);
+    assert_eq!(len(u64::MAX), 8);
+}
+
+#[test]
+fn card_parse_errors() {
+    assert_eq!(
+        Card::try_new("Ah"),
+        Ok(Card {
+            rank: 14,
+            suit: HEARTS as u8
+        })
+    );
+    assert_eq!(
+        Card::try_new("Tc"),
+        Ok(Card {
+            rank: 10,
+            suit: CLUBS as u8
+        })
+    );
+    assert_eq!(Card::try_new("1h"), Err(CardParseError::UnknownRank('1')));
+    assert_eq!(Card::try_new("ah"), Err(CardParseError::UnknownRank('a')));
+    assert_eq!(Card::try_new("Ax"), Err(CardParseError::UnknownSuit('x')));
+    assert_eq!(Card::try_new("A"), Err(CardParseError::InputTooShort));
+    assert_eq!(Card::try_new(""), Err(CardParseError::InputTooShort));
+    assert_eq!(
+        Card::try_new("AsKh"),
+        Err(CardParseError::InputTooLong(String::from("AsKh")))
+    );
+    assert_eq!(
+        Card::try_new("10h"),
+        Err(CardParseError::InputTooLong(String::from("10h")))
+    );
+
+    assert_eq!(
+        CardParseError::UnknownRank('1').to_string(),
+        "unknown card rank '1'"
+    );
+    assert_eq!(
+        CardParseError::UnknownSuit('x').to_string(),
+        "unknown card suit 'x'"
+    );
+    assert_eq!(
+        CardParseError::InputTooShort.to_string(),
+        "card string is too short, expected a rank and a suit"
+    );
+    assert_eq!(
+        CardParseError::InputTooLong(String::from("AsKh")).to_string(),
+        "card string 'AsKh' is too long, expected a rank and a suit"
+    );
+
+    assert_eq!(
+        try_strvec2cards(&["Ah", "Kd"]),
+        Ok(vec![Card::new("Ah"), Card::new("Kd")])
+    );
+    assert_eq!(
+        try_strvec2cards(&["Ah", "Zd"]),
+        Err(CardParseError::UnknownRank('Z'))
+    );
+    assert_eq!(
+        try_strvec2cards(&["Ah", "AsKh"]),
+        Err(CardParseError::InputTooLong(String::from("AsKh")))
+    );
+    assert_eq!(
+        try_strvec2cards(&["Ah", ""]),
+        Err(CardParseError::InputTooShort)
+    );
+}
+
+#[test]
+fn parse_cards_from_str() {
+    assert_eq!("Ah".parse::<Card>(), Ok(Card::new("Ah")));
+    assert_eq!("2c".parse::<Card>(), Ok(Card::new("2c")));
+    assert_eq!("Xh".parse::<Card>(), Err(CardParseError::UnknownRank('X')));
+    assert_eq!("A".parse::<Card>(), Err(CardParseError::InputTooShort));
+
+    assert_eq!(
+        str2cards("AhKd2c"),
+        Ok(vec![Card::new("Ah"), Card::new("Kd"), Card::new("2c")])
+    );
+    assert_eq!(str2cards(""), Ok(vec![]));
+    assert_eq!(str2cards("AhK"), Err(CardParseError::InputTooShort));
+    assert_eq!(str2cards("AhKx"), Err(CardParseError::UnknownSuit('x')));
+    assert_eq!(str2cards("A"), Err(CardParseError::InputTooShort));
+    assert_eq!(str2cards("AhZd2c"), Err(CardParseError::UnknownRank('Z')));
+    assert_eq!(str2cards("AhKd2cQ"), Err(CardParseError::InputTooShort));
+}
+
+#[test]
+fn cloned_combinations() {
+    // Cloning the cards before taking combinations, which replaced deepcopy(),
+    // gives owned hands with the same cards as the borrowed combinations
+    let deck = deck();
+    let borrowed: Vec<Vec<&Card>> = deck.iter().combinations(2).collect();
+    let mut owned: Vec<Vec<Card>> = deck.iter().cloned().combinations(2).collect();
+    assert_eq!(owned.len(), borrowed.len());
+    for (owned, borrowed) in owned.iter().zip(borrowed.iter()) {
+        assert_eq!(owned.iter().collect::<Vec<&Card>>(), *borrowed);
+    }
+    // and changing them leaves the deck alone
+    owned[0][0] = Card::new("Ah");
+    assert_eq!(*borrowed[0][0], Card::new("2c"));
+    assert_eq!(deck[0], Card::new("2c"));
+}
+
 #[test]
 fn hand_comparisons() {
     let table = LightHandTable::new();
@@ -121,11 +322,338 @@ fn test_compact_infoset() {
     }
 }
 
+#[test]
+fn test_action_distribution() {
+    let hand = str2cards("AsKd").unwrap();
+    let history = ActionHistory::new();
+    // A blueprint that never folds AK, and calls 60% of the time and makes
+    // the smallest raise the rest
+    let infoset = InfoSet::from_hand(&hand, &history);
+    let actions = infoset.next_actions();
+    let call_index = actions
+        .iter()
+        .position(|a| a.action == ActionType::Call)
+        .unwrap();
+    let raise_index = actions
+        .iter()
+        .position(|a| a.action == ActionType::Bet)
+        .unwrap();
+    let mut probs = vec![0; actions.len()];
+    probs[call_index] = 150;
+    probs[raise_index] = 100;
+    let mut blueprint = CompressedStrategy::new();
+    blueprint.insert(infoset.compress(), probs);
+
+    let distribution = action_distribution_with(&blueprint, &hand, &[], &history);
+    let total: f64 = distribution.values().sum();
+    assert!((total - 1.0).abs() < 1e-6);
+    assert!(distribution.values().all(|&p| p > 0.0));
+    assert_eq!(distribution.len(), 2);
+    assert!((distribution[&actions[call_index]] - 0.6).abs() < 1e-6);
+
+    // Sampled actions should show up about as often as their probability
+    let mut rng = StdRng::seed_from_u64(0);
+    let n_samples = 10_000;
+    let mut counts: HashMap<Action, usize> = HashMap::new();
+    for _ in 0..n_samples {
+        let action = sample_action_with(&blueprint, &hand, &[], &history, &mut rng);
+        *counts.entry(action).or_insert(0) += 1;
+    }
+    assert!(counts.keys().all(|a| distribution.contains_key(a)));
+    for (action, prob) in &distribution {
+        let freq = *counts.get(action).unwrap_or(&0) as f64 / n_samples as f64;
+        assert!((freq - prob).abs() < 0.02, "{} {} {}", action, freq, prob);
+    }
+}
+
+#[test]
+fn test_action_evs() {
+    let mut history = ActionHistory::new();
+    for amount in &[BIG_BLIND, BIG_BLIND, 0, 0, 0, 0] {
+        history.add(&Action {
+            action: ActionType::Call,
+            amount: *amount,
+        });
+    }
+    assert_eq!(history.street, RIVER);
+    // A royal flush against an opponent who calls everything, so the more
+    // we bet the more we win
+    let hand = str2cards("Th3d").unwrap();
+    let board = str2cards("AhKhQhJh2c").unwrap();
+    let always_call = |_: &[Card], _: &ActionHistory| -> Option<HashMap<Action, f64>> { None };
+    let evs = action_evs_against(&hand, &board, &history, 10, &always_call);
+    assert_eq!(evs.len(), history.legal_actions().len());
+    let all_in = Action {
+        action: ActionType::Bet,
+        amount: history.stack_sizes()[history.player],
+    };
+    for (action, ev) in &evs {
+        if action != &all_in {
+            assert!(evs[&all_in] > *ev, "{} {}", action, ev);
+        }
+    }
+    // Shoving wins the opponent's whole stack on top of the blinds they called
+    assert_eq!(evs[&all_in], (STACK_SIZE / BIG_BLIND) as f64);
+}
+
+// Checks and calls down to the river
+fn river_history() -> ActionHistory {
+    let mut history = ActionHistory::new();
+    for amount in &[BIG_BLIND, BIG_BLIND, 0, 0, 0, 0] {
+        history.add(&Action {
+            action: ActionType::Call,
+            amount: *amount,
+        });
+    }
+    history
+}
+
+#[test]
+fn test_featurize() {
+    let preflop = InfoSet::new(ActionHistory::new(), 0);
+    let features = featurize(&preflop);
+    assert_eq!(features.len(), N_FEATURES);
+    assert_eq!(&features[..4], &[1.0, 0.0, 0.0, 0.0]);
+    // Bucket 0 is AA
+    assert!(features[5] > 0.8);
+
+    let river = InfoSet::new(river_history(), 500);
+    let features = featurize(&river);
+    assert_eq!(&features[..4], &[0.0, 0.0, 0.0, 1.0]);
+    assert_eq!(features[5], 0.5);
+    assert!(features.iter().all(|f| f.abs() <= 1.0));
+}
+
+#[test]
+fn test_fully_connected_net() {
+    let mut net = FullyConnectedNet::new(NetConfig {
+        hidden: vec![32],
+        learning_rate: 1e-2,
+        batch_size: 2,
+        train_steps: 500,
+        seed: 0,
+    });
+    let preflop = InfoSet::new(ActionHistory::new(), 0);
+    let river = InfoSet::new(river_history(), 500);
+    let n_preflop = preflop.next_actions().len();
+    let n_river = river.next_actions().len();
+    let samples = vec![
+        (preflop.clone(), vec![0.5; n_preflop], 1),
+        (river.clone(), vec![-0.25; n_river], 1),
+    ];
+    AdvantageNet::train(&mut net, &samples);
+    for (infoset, targets, _) in &samples {
+        let predicted = AdvantageNet::predict(&net, infoset);
+        assert_eq!(predicted.len(), targets.len());
+        for (p, t) in predicted.iter().zip(targets) {
+            assert!((p - t).abs() < 0.05, "{} {}", p, t);
+        }
+    }
+    // As a strategy the outputs are clipped and normalized
+    let strategy = StrategyNet::predict(&net, &preflop);
+    assert!((strategy.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+}
+
+// Samples are weighted by their iteration, so when they disagree the later
+// ones count for more
+#[test]
+fn test_fully_connected_net_iteration_weights() {
+    let mut net = FullyConnectedNet::new(NetConfig {
+        hidden: vec![32],
+        learning_rate: 1e-2,
+        batch_size: 2,
+        train_steps: 500,
+        seed: 0,
+    });
+    let preflop = InfoSet::new(ActionHistory::new(), 0);
+    let n_actions = preflop.next_actions().len();
+    let samples = vec![
+        (preflop.clone(), vec![0.0; n_actions], 1),
+        (preflop.clone(), vec![1.0; n_actions], 3),
+    ];
+    AdvantageNet::train(&mut net, &samples);
+    // The weighted mean of the targets
+    for p in AdvantageNet::predict(&net, &preflop) {
+        assert!((p - 0.75).abs() < 0.05, "{}", p);
+    }
+}
+
+#[test]
+fn test_deep_cfr_traversal() {
+    let small_net = |seed| {
+        FullyConnectedNet::new(NetConfig {
+            hidden: vec![16],
+            train_steps: 10,
+            seed: seed,
+            ..NetConfig::default()
+        })
+    };
+    let config = DeepCFRConfig {
+        buffer_size: 50,
+        ..DeepCFRConfig::default()
+    };
+    // Everything in one bucket, so the card abstraction isn't needed
+    let mut trainer = DeepCFRTrainer::with_nets(
+        Box::new(small_net(0)),
+        Box::new(small_net(1)),
+        Box::new(small_net(2)),
+        config,
+    )
+    .with_infoset_fn(|_, history| InfoSet::new(history.clone(), 0));
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut deck = deck();
+    for _ in 0..20 {
+        for player in &[DEALER, OPPONENT] {
+            deck.shuffle(&mut rng);
+            let value = trainer.traverse(*player, &deck, river_history());
+            assert!(value.abs() <= STACK_SIZE as f64);
+        }
+    }
+    let [dealer, opponent, strategy] = trainer.memory_sizes();
+    assert!(dealer > 0 && opponent > 0 && strategy > 0);
+    // The reservoirs don't grow past their capacity
+    assert!(dealer <= 50 && opponent <= 50 && strategy <= 50);
+
+    trainer.train_advantage_nets();
+    trainer.train_strategy_net();
+    let infoset = InfoSet::new(river_history(), 0);
+    let strategy = trainer.strategy(&infoset);
+    assert_eq!(strategy.len(), infoset.next_actions().len());
+    assert!((strategy.values().sum::<f64>() - 1.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_legal_distribution() {
+    let history = ActionHistory::new();
+    let bet = |amount| Action {
+        action: ActionType::Bet,
+        amount: amount,
+    };
+    let call = Action {
+        action: ActionType::Call,
+        amount: BIG_BLIND,
+    };
+    let mut strategy = HashMap::new();
+    strategy.insert(FOLD, 0.0);
+    strategy.insert(call.clone(), 0.5);
+    // Both too small to be legal, so they end up as the same legal bet
+    strategy.insert(bet(1), 0.125);
+    strategy.insert(bet(2), 0.125);
+    strategy.insert(bet(STACK_SIZE), 0.25);
+    let distribution = legal_distribution(&strategy, &history);
+    let legal = history.legal_actions();
+    assert_eq!(distribution.len(), 3);
+    assert!(distribution.keys().all(|a| legal.contains(a)));
+    assert_eq!(distribution[&call], 0.5);
+    assert_eq!(distribution[&bet(STACK_SIZE)], 0.25);
+}
+
+#[test]
+fn test_check_strategy_coverage() {
+    // A strategy that only knows the very first preflop decision
+    let root = ActionHistory::new().compress(bet_abstraction());
+    let mut nodes: NodeMap = HashMap::new();
+    for bucket in 0..PREFLOP_BUCKETS {
+        nodes.insert(
+            CompactInfoSet::new(root.clone(), bucket),
+            Node::from_actions(&[]),
+        );
+    }
+    let report = check_strategy_coverage(&nodes, &[PREFLOP as u8]);
+    assert_eq!(report.covered, PREFLOP_BUCKETS as u64);
+    assert!(report.total_infosets > report.covered);
+    assert_eq!(report.missing.len(), 100);
+    assert!(report
+        .missing
+        .iter()
+        .all(|infoset| !nodes.contains_key(infoset)));
+    assert!(report.coverage() > 0.0 && report.coverage() < 1.0);
+
+    // None of the flop infosets are covered
+    let report = check_strategy_coverage(&nodes, &[FLOP as u8]);
+    assert!(report.total_infosets > 0);
+    assert_eq!(report.covered, 0);
+    assert_eq!(check_strategy_coverage(&nodes, &[]).coverage(), 1.0);
+}
+
 #[test]
 fn test_ranges() {
     // TODO: Write a test for this maybe
 }
 
+#[test]
+fn test_validate_abstraction_table() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut deck = deck();
+    let mut table = HandData::new();
+    while table.len() < 100 {
+        deck.shuffle(&mut rng);
+        let hand = cards2hand(&canonical_hand(&deck[..5], true));
+        let bin = table.len() as i32 * FLOP_BUCKETS / 100;
+        table.insert(&hand, bin);
+    }
+    assert_eq!(validate_table("flop", &table, 5, FLOP_BUCKETS, 100), Ok(()));
+    assert_eq!(
+        validate_table("flop", &table, 5, FLOP_BUCKETS, 101),
+        Err(AbstractionError::WrongCount {
+            street: "flop",
+            expected: 101,
+            found: 100
+        })
+    );
+
+    // Each kind of corruption on its own
+    let corruptions = [
+        (
+            "2c3c4c5c6c",
+            FLOP_BUCKETS,
+            AbstractionError::BinOutOfRange {
+                street: "flop",
+                hand: String::from("2c3c4c5c6c"),
+                bin: FLOP_BUCKETS,
+            },
+        ),
+        (
+            "2c3c4c5c6c",
+            -1,
+            AbstractionError::BinOutOfRange {
+                street: "flop",
+                hand: String::from("2c3c4c5c6c"),
+                bin: -1,
+            },
+        ),
+        (
+            "2c3c4c5c",
+            0,
+            AbstractionError::WrongNumberOfCards {
+                street: "flop",
+                hand: String::from("2c3c4c5c"),
+            },
+        ),
+        // Spades instead of clubs, which isn't how canonical_hand() names suits
+        (
+            "2s3s4s5s6s",
+            0,
+            AbstractionError::NotCanonical {
+                street: "flop",
+                hand: String::from("2s3s4s5s6s"),
+            },
+        ),
+    ];
+    for (hand, bin, error) in corruptions.iter() {
+        let mut corrupted = HandData::new();
+        for (hand, bin) in table.iter() {
+            corrupted.insert(hand, *bin);
+        }
+        corrupted.insert(&cards2hand(&str2cards(hand).unwrap()), *bin);
+        assert_eq!(
+            validate_table("flop", &corrupted, 5, FLOP_BUCKETS, 101),
+            Err(error.clone())
+        );
+    }
+}
+
 // Makes sure that LightAbstraction and Abstraction return the same bins for
 // all cards.
 #[test]
@@ -160,3 +688,3916 @@ fn test_light_abstraction() {
     }
     bar.finish();
 }
+
+#[test]
+fn test_kmeans_cluster() {
+    // Two clearly separated groups of equity distributions
+    let mut distributions = HashMap::new();
+    distributions.insert(1, vec![0.9, 0.1, 0.0]);
+    distributions.insert(2, vec![0.8, 0.2, 0.0]);
+    distributions.insert(3, vec![0.85, 0.15, 0.0]);
+    distributions.insert(4, vec![0.0, 0.1, 0.9]);
+    distributions.insert(5, vec![0.0, 0.2, 0.8]);
+    let config = KMeansConfig {
+        k: 2,
+        max_iter: 20,
+        tolerance: 1e-9,
+        seed: 42,
+        segments: 1,
+    };
+    let clusters = cluster(&distributions, &config);
+    assert_eq!(clusters.len(), 5);
+    assert_eq!(clusters[&1], clusters[&2]);
+    assert_eq!(clusters[&1], clusters[&3]);
+    assert_eq!(clusters[&4], clusters[&5]);
+    assert_ne!(clusters[&1], clusters[&4]);
+    // Same seed, same result
+    assert_eq!(clusters, cluster(&distributions, &config));
+
+    // Asking for more clusters than hands puts every hand in its own cluster
+    let config = KMeansConfig { k: 10, ..config };
+    let clusters = cluster(&distributions, &config);
+    let mut ids: Vec<&i32> = clusters.values().collect();
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids.len(), 5);
+    assert!(cluster(&HashMap::new(), &config).is_empty());
+}
+
+#[test]
+fn test_abstraction_stats() {
+    let balanced = AbstractionStats::from_bin_sizes(vec![5; 4]);
+    assert_eq!(balanced.total_hands, 20);
+    assert_eq!(balanced.gini_coefficient, 0.0);
+    let lopsided = AbstractionStats::from_bin_sizes(vec![0, 0, 0, 20]);
+    assert!((lopsided.gini_coefficient - 0.75).abs() < 1e-12);
+    assert_eq!((lopsided.min_bin_size, lopsided.max_bin_size), (0, 20));
+    assert_eq!(
+        AbstractionStats::from_bin_sizes(vec![]).gini_coefficient,
+        0.0
+    );
+
+    let mut table = HandData::new();
+    for hand in 0..10 {
+        table.insert(&hand, if hand < 7 { 0 } else { 2 });
+    }
+    let stats = AbstractionStats::from_table(&table, 3);
+    assert_eq!(stats.hands_per_bin, vec![7, 0, 3]);
+    assert_eq!(stats.num_bins, 3);
+    let histogram = stats.histogram(3, 7);
+    let lines: Vec<&str> = histogram.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].contains("|#######| 7"));
+    assert!(lines[1].contains("|       | 0"));
+    assert!(lines[2].contains("|###    | 3"));
+
+    // Hands spread evenly along one dimension should end up in bins of about
+    // the same size, and a lot more evenly than piling them into a few bins
+    let distributions: HashMap<u64, Vec<f64>> = (0..200)
+        .map(|i| (i, vec![i as f64 / 200.0, 1.0 - i as f64 / 200.0]))
+        .collect();
+    let config = KMeansConfig {
+        k: 10,
+        segments: 1,
+        ..KMeansConfig::default()
+    };
+    let mut table = HandData::new();
+    for (hand, bin) in cluster(&distributions, &config) {
+        table.insert(&hand, bin);
+    }
+    let kmeans = AbstractionStats::from_table(&table, 10);
+    assert_eq!(kmeans.total_hands, 200);
+    let piled = AbstractionStats::from_bin_sizes(vec![0, 0, 0, 0, 0, 0, 0, 50, 50, 100]);
+    assert!(kmeans.gini_coefficient < 0.2, "{}", kmeans);
+    assert!(kmeans.gini_coefficient < piled.gini_coefficient);
+}
+
+#[test]
+fn test_kmeans_cluster_segments() {
+    // Each distribution is two 3-bin histograms laid end to end
+    let mut distributions = HashMap::new();
+    distributions.insert(1, vec![0.9, 0.1, 0.0, 0.0, 0.1, 0.9]);
+    distributions.insert(2, vec![0.0, 0.1, 0.9, 0.9, 0.1, 0.0]);
+    distributions.insert(3, vec![0.8, 0.2, 0.0, 0.0, 0.2, 0.8]);
+    distributions.insert(4, vec![0.0, 0.2, 0.8, 0.8, 0.2, 0.0]);
+    let config = KMeansConfig {
+        k: 2,
+        max_iter: 20,
+        tolerance: 1e-9,
+        seed: 1,
+        segments: 2,
+    };
+    let clusters = cluster(&distributions, &config);
+    assert_eq!(clusters[&1], clusters[&3]);
+    assert_eq!(clusters[&2], clusters[&4]);
+    assert_ne!(clusters[&1], clusters[&2]);
+}
+
+#[test]
+fn test_potential_aware_distribution() {
+    let cards = strvec2cards(&["Ah", "Kh", "Qh", "7h", "2c"]);
+    let distribution = potential_aware_distribution(&cards);
+    assert_eq!(distribution.len(), 2 * EQUITY_BINS);
+    let (current, next) = distribution.split_at(EQUITY_BINS);
+    assert!((current.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+    assert!((next.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+
+    // On the turn the next street is the river, so both halves match
+    let cards = strvec2cards(&["Ah", "Kh", "Qh", "7h", "2c", "9d"]);
+    let distribution = potential_aware_distribution(&cards);
+    assert_eq!(distribution[..EQUITY_BINS], distribution[EQUITY_BINS..]);
+}
+
+// A few flop hands with a stand-in for the potential-aware distribution,
+// since that needs the equity table
+#[test]
+fn test_make_flop_equity() {
+    let distribution = |cards: &[Card]| {
+        let mut rng = StdRng::seed_from_u64(cards2hand(cards));
+        let current = flop_equity_distribution(cards.try_into().unwrap(), 1, &mut rng);
+        [current.clone(), current].concat()
+    };
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut deck = deck();
+    let hands: HashSet<u64> = (0..4)
+        .map(|_| {
+            let flop = deck.partial_shuffle(&mut rng, 5).0;
+            cards2hand(&canonical_hand(flop, true))
+        })
+        .collect();
+    let distributions = make_flop_equity_with(&hands, distribution);
+    assert_eq!(distributions.len(), hands.len());
+    for hand in &hands {
+        let d = &distributions[hand];
+        assert_eq!(d.len(), 2 * EQUITY_BINS);
+        assert!((d.iter().sum::<f64>() - 2.0).abs() < 1e-9);
+        assert_eq!(*d, distribution(&hand2cards(*hand)));
+    }
+}
+
+#[test]
+fn test_earth_movers_distance() {
+    let a = vec![0.2, 0.3, 0.5, 0.0];
+    assert_eq!(earth_movers_distance(&a, &a), 0.0);
+
+    // Moving all the mass n bins over costs n
+    let point = vec![1.0, 0.0, 0.0, 0.0];
+    let shift1 = vec![0.0, 1.0, 0.0, 0.0];
+    let shift3 = vec![0.0, 0.0, 0.0, 1.0];
+    assert!((earth_movers_distance(&point, &shift1) - 1.0).abs() < 1e-12);
+    assert!((earth_movers_distance(&point, &shift3) - 3.0).abs() < 1e-12);
+    assert!((earth_movers_distance(&shift3, &point) - 3.0).abs() < 1e-12);
+    // Shifting a spread out distribution works the same way
+    let spread = vec![0.5, 0.5, 0.0, 0.0];
+    let spread_shifted = vec![0.0, 0.0, 0.5, 0.5];
+    assert!((earth_movers_distance(&spread, &spread_shifted) - 2.0).abs() < 1e-12);
+
+    assert_eq!(earth_movers_distance(&[], &[]), 0.0);
+    assert_eq!(earth_movers_distance(&[1.0], &[1.0]), 0.0);
+    assert_eq!(earth_movers_distance(&[1.0], &[]), 1.0);
+}
+
+#[test]
+fn test_river_equity_mc() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let hands = vec![
+        // Pocket aces on a dry board
+        vec!["Ah", "As", "7c", "2d", "9s", "Jh", "4c"],
+        // Top set
+        vec!["Kh", "Ks", "Kd", "7c", "2s", "9h", "4d"],
+        // Nut flush
+        vec!["Ad", "5d", "Kd", "9d", "2d", "Qc", "3s"],
+    ];
+    for hand in hands {
+        let hand = strvec2cards(&hand);
+        let exact = river_equity(&hand);
+        let approx = river_equity_mc(&hand, 1000, &mut rng);
+        assert!((exact - approx).abs() < 0.02);
+    }
+}
+
+#[test]
+fn test_equity_distribution_for_street() {
+    let mut rng = StdRng::seed_from_u64(0);
+    // A royal flush on the flop beats every opponent hand
+    let royal = strvec2cards(&["Ah", "Kh", "Qh", "Jh", "Th"]);
+    let distribution = flop_equity_distribution(royal[..].try_into().unwrap(), 5, &mut rng);
+    assert_eq!(distribution.len(), EQUITY_BINS);
+    assert_eq!(distribution[EQUITY_BINS - 1], 1.0);
+
+    // Four high on the turn is behind most hands, but ahead of some
+    let hand = strvec2cards(&["3h", "4c", "Ks", "Qd", "8s", "9c"]);
+    let distribution = turn_equity_distribution(hand[..].try_into().unwrap(), 10, &mut rng);
+    assert!((distribution.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+    let behind: f64 = distribution[..EQUITY_BINS / 2].iter().sum();
+    assert!(behind > 0.5 && behind < 1.0);
+
+    let mut rng = StdRng::seed_from_u64(1);
+    let expected = turn_equity_distribution(hand[..].try_into().unwrap(), 10, &mut rng);
+    let mut rng = StdRng::seed_from_u64(1);
+    assert_eq!(equity_distribution(&hand, 10, &mut rng), expected);
+}
+
+#[test]
+fn test_monte_carlo_equity() {
+    let mut rng = StdRng::seed_from_u64(0);
+    // On the river it should agree with the exact equity
+    let hand = strvec2cards(&["Ah", "As", "7c", "2d", "9s", "Jh", "4c"]);
+    let exact = river_equity(&hand);
+    let approx = monte_carlo_equity(&hand[..2], &hand[2..], 2000, &mut rng);
+    assert!((exact - approx).abs() < 0.02);
+
+    // Aces are about 85% against a random hand preflop, and 72o about 35%
+    let aces = monte_carlo_equity(&strvec2cards(&["Ah", "As"]), &[], 5000, &mut rng);
+    assert!((aces - 0.85).abs() < 0.02);
+    let seven_deuce = monte_carlo_equity(&strvec2cards(&["7h", "2c"]), &[], 5000, &mut rng);
+    assert!((seven_deuce - 0.35).abs() < 0.02);
+
+    // Made quads on the flop are nearly always ahead
+    let board = strvec2cards(&["Kd", "Kc", "2s"]);
+    let quads = monte_carlo_equity(&strvec2cards(&["Kh", "Ks"]), &board, 1000, &mut rng);
+    assert!(quads > 0.99);
+}
+
+#[test]
+fn test_multiway_equity() {
+    // Heads up on the river, averaging over every opponent hand gives the
+    // usual river equity
+    let hand = strvec2cards(&["Ah", "Kd", "7c", "2d", "9s", "Jh", "Kc"]);
+    let (hole, board) = (hand[..2].to_vec(), hand[2..].to_vec());
+    let mut deck = deck();
+    deck.retain(|c| !hand.contains(c));
+    let opp_hands: Vec<Vec<Card>> = deck.iter().cloned().combinations(2).collect();
+    let total: f64 = opp_hands
+        .iter()
+        .map(|opp| multiway_equity_exact(&[hole.clone(), opp.clone()], &board)[0])
+        .sum();
+    assert!((total / opp_hands.len() as f64 - river_equity(&hand)).abs() < 1e-9);
+
+    // Sampling agrees with enumerating on the flop
+    let hands = vec![
+        strvec2cards(&["Ah", "Kh"]),
+        strvec2cards(&["Qs", "Qd"]),
+        strvec2cards(&["9c", "8c"]),
+    ];
+    let flop = strvec2cards(&["Qh", "7c", "2h"]);
+    let exact = multiway_equity_exact(&hands, &flop);
+    let sampled = multiway_equity(&hands, &flop);
+    for (e, s) in exact.iter().zip(&sampled) {
+        assert!((e - s).abs() < 0.02);
+    }
+    assert!((exact.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+
+    // Everyone plays the board and splits the pot
+    let broadway = strvec2cards(&["Tc", "Jd", "Qc", "Ks", "Ad"]);
+    let equities = multiway_equity(&hands, &broadway);
+    for equity in equities {
+        assert!((equity - 1.0 / 3.0).abs() < 1e-9);
+    }
+}
+
+#[test]
+#[should_panic]
+fn test_multiway_equity_duplicate_cards() {
+    let hands = vec![strvec2cards(&["Ah", "Kh"]), strvec2cards(&["Ah", "Qd"])];
+    multiway_equity(&hands, &[]);
+}
+
+#[test]
+fn test_equity_table_bincode() {
+    let mut table = HashMap::new();
+    table.insert(str2hand("AhAs7c2d9sJh4c"), 0.912_345_678_901_234_5);
+    table.insert(str2hand("Td3cKhTs6d2c8h"), 1.0 / 3.0);
+    table.insert(str2hand("2c3d4h5s7c8d9h"), 0.0);
+    let dir = std::env::temp_dir();
+    let bin_path = dir.join("test_equity_table.bin");
+    let bin_path = bin_path.to_str().unwrap();
+    write_equity_table(&table, bin_path);
+    let loaded = read_equity_table(std::fs::File::open(bin_path).unwrap());
+    assert_eq!(loaded.len(), table.len());
+    for (hand, equity) in &table {
+        assert_eq!(loaded[hand].to_bits(), equity.to_bits());
+    }
+
+    // Migrating from the old text format gives the same table
+    let text_path = dir.join("test_equity_table.txt");
+    let text_path = text_path.to_str().unwrap();
+    let mut text = String::new();
+    for (hand, equity) in &table {
+        text.push_str(&format!("{} {}\n", hand2str(*hand), equity));
+    }
+    std::fs::write(text_path, text).unwrap();
+    migrate_equity_text_to_bincode(text_path, bin_path);
+    let migrated = read_equity_table(std::fs::File::open(bin_path).unwrap());
+    for (hand, equity) in &table {
+        assert_eq!(migrated[hand].to_bits(), equity.to_bits());
+    }
+}
+
+#[test]
+fn test_equity_lookup_batch() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut deck = deck();
+    let hands: Vec<Vec<Card>> = (0..100)
+        .map(|_| deck.partial_shuffle(&mut rng, 7).0.to_vec())
+        .collect();
+    let mut equities = HashMap::new();
+    for (i, hand) in hands.iter().enumerate() {
+        equities.insert(canonical_hand_id(hand, true), i as f64 / 100.0);
+    }
+    let table = EquityTable::from_table(equities);
+    let sequential: Vec<f64> = hands.iter().map(|hand| table.lookup(hand)).collect();
+    assert_eq!(table.lookup_batch(&hands), sequential);
+    // Every hand got its own equity, so anything out of order would show
+    for (i, equity) in sequential.iter().enumerate() {
+        assert_eq!(*equity, i as f64 / 100.0);
+    }
+}
+
+#[test]
+fn test_load_abstraction() {
+    let mut table = HandData::new();
+    let hands = ["2c3c4c5c6c", "AcAd2c7h9s", "KsQsJsTs2d"];
+    for (bucket, hand) in hands.iter().enumerate() {
+        let canonical = canonical_hand(&str2cards(hand).unwrap(), true);
+        table.insert(&cards2hand(&canonical), bucket as i32);
+    }
+    // Unique per process so parallel test runs don't share the file
+    let name = format!("test_flop_abstraction_{}.txt", std::process::id());
+    let path = std::env::temp_dir().join(name);
+    let path = path.to_str().unwrap();
+    table.serialize(path);
+    let loaded = load_abstraction(path, 5, FLOP_BUCKETS);
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(loaded.len(), 3);
+    for (hand, bucket) in table.iter() {
+        assert_eq!(loaded.try_get(hand), Some(*bucket));
+    }
+}
+
+// The turn equity file gets a chunk added at each checkpoint, and a chunk
+// that was cut off partway through is dropped
+#[test]
+fn test_turn_equity_file() {
+    let name = format!("test_turn_equity_{}.bin", std::process::id());
+    let path = std::env::temp_dir().join(name);
+    let path = path.to_str().unwrap();
+    let first = vec![(1, vec![0.5, 0.5]), (2, vec![1.0, 0.0])];
+    let second = vec![(3, vec![0.25, 0.75])];
+    append_turn_equity(path, &first);
+    append_turn_equity(path, &second);
+    let complete_len = std::fs::metadata(path).unwrap().len();
+    let expected: HashMap<u64, Vec<f64>> = first.iter().chain(&second).cloned().collect();
+    assert_eq!(read_turn_equity(path), expected);
+
+    let mut bytes = std::fs::read(path).unwrap();
+    bytes.extend(&[7, 0, 0]);
+    std::fs::write(path, bytes).unwrap();
+    assert_eq!(read_turn_equity(path), expected);
+    assert_eq!(std::fs::metadata(path).unwrap().len(), complete_len);
+    // So the next chunk can still be read after the others
+    append_turn_equity(path, &[(4, vec![0.0, 1.0])]);
+    assert_eq!(read_turn_equity(path).len(), 4);
+    std::fs::remove_file(path).unwrap();
+
+    // Files from when the whole map was rewritten each time still load
+    std::fs::write(path, bincode::serialize(&expected).unwrap()).unwrap();
+    assert_eq!(read_turn_equity(path), expected);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_normalize() {
+    let mut map = HashMap::new();
+    map.insert("a", 1.0);
+    map.insert("b", 3.0);
+    let normalized = normalize(&map);
+    assert_eq!(normalized["a"], 0.25);
+    assert_eq!(normalized["b"], 0.75);
+    assert_eq!(normalize_checked(&map), Ok(normalized));
+
+    // Zero, negative and NaN sums give a uniform distribution, not NaNs
+    for &value in &[0.0, -1.0, f64::NAN] {
+        map.insert("a", value);
+        map.insert("b", value);
+        assert!(normalize(&map).values().all(|&p| p == 0.5));
+        assert!(matches!(
+            normalize_checked(&map),
+            Err(NormalizeError::BadSum(_))
+        ));
+    }
+
+    let empty: HashMap<&str, f64> = HashMap::new();
+    assert!(normalize(&empty).is_empty());
+    assert_eq!(normalize_checked(&empty), Err(NormalizeError::Empty));
+}
+
+#[test]
+fn test_cfr_plus_updates() {
+    let check = Action {
+        action: ActionType::Call,
+        amount: 0,
+    };
+    let bet = Action {
+        action: ActionType::Bet,
+        amount: 100,
+    };
+    let mut node = Node::from_actions(&[check.clone(), bet.clone()]);
+    // With no regrets the strategy is uniform
+    let strategy = node.current_strategy(1.0, CFRVariant::CFRPlus);
+    assert_eq!(strategy[&check], 0.5);
+
+    // Negative regrets are floored at zero, so one good iteration is enough
+    // to switch to the other action
+    node.add_regret(&check, -10.0, CFRVariant::CFRPlus);
+    node.add_regret(&bet, 5.0, CFRVariant::CFRPlus);
+    node.add_regret(&bet, -1.0, CFRVariant::CFRPlus);
+    node.add_regret(&check, 1.0, CFRVariant::CFRPlus);
+    let strategy = node.current_strategy(1.0, CFRVariant::CFRPlus);
+    assert_eq!(strategy[&check], 0.2);
+    assert_eq!(strategy[&bet], 0.8);
+
+    // The second iteration is weighted twice as much as the first in the
+    // cumulative strategy: (0.5 + 2 * 0.2) / 3 = 0.3
+    let cumulative = node.cumulative_strategy();
+    assert!((cumulative[&check] - 0.3).abs() < 1e-12);
+    assert!((cumulative[&bet] - 0.7).abs() < 1e-12);
+}
+
+#[test]
+fn test_linear_cfr_updates() {
+    let check = Action {
+        action: ActionType::Call,
+        amount: 0,
+    };
+    let bet = Action {
+        action: ActionType::Bet,
+        amount: 100,
+    };
+    let mut node = Node::from_actions(&[check.clone(), bet.clone()]);
+    let strategy = node.current_strategy(1.0, CFRVariant::Linear);
+    assert_eq!(strategy[&check], 0.5);
+    // Iteration 1's regrets count once
+    node.add_regret(&bet, 3.0, CFRVariant::Linear);
+    node.add_regret(&check, -3.0, CFRVariant::Linear);
+    let strategy = node.current_strategy(1.0, CFRVariant::Linear);
+    assert_eq!(strategy[&bet], 1.0);
+    // and iteration 2's count twice, so check: -3 + 2 * 3 = 3, bet: 3 - 2 * 1 = 1
+    node.add_regret(&check, 3.0, CFRVariant::Linear);
+    node.add_regret(&bet, -1.0, CFRVariant::Linear);
+    let strategy = node.strategy();
+    assert!((strategy[&check] - 0.75).abs() < 1e-12);
+
+    // The cumulative strategy is weighted the same way: (0.5 + 2 * 0) / 3
+    let cumulative = node.cumulative_strategy();
+    assert!((cumulative[&check] - 1.0 / 6.0).abs() < 1e-12);
+}
+
+#[test]
+fn test_outcome_sample_regrets() {
+    // A single decision with three actions and fixed payoffs
+    let fold = Action {
+        action: ActionType::Fold,
+        amount: 0,
+    };
+    let call = Action {
+        action: ActionType::Call,
+        amount: 100,
+    };
+    let bet = Action {
+        action: ActionType::Bet,
+        amount: 300,
+    };
+    let strategy: HashMap<Action, f64> =
+        vec![(fold.clone(), 0.2), (call.clone(), 0.5), (bet.clone(), 0.3)]
+            .into_iter()
+            .collect();
+    let payoffs: HashMap<Action, f64> = vec![
+        (fold.clone(), -1.0),
+        (call.clone(), 2.0),
+        (bet.clone(), 5.0),
+    ]
+    .into_iter()
+    .collect();
+    let node_value: f64 = strategy.iter().map(|(a, p)| p * payoffs[a]).sum();
+
+    // Averaged over which action gets sampled, the sampled regrets should be
+    // the exact regrets
+    let epsilon = 0.6;
+    let mut expected: HashMap<Action, f64> = HashMap::new();
+    for (sampled, prob) in &strategy {
+        let sample_prob = epsilon / 3.0 + (1.0 - epsilon) * prob;
+        let weighted_utility = payoffs[sampled] / sample_prob;
+        let regrets = outcome_sample_regrets(&strategy, sampled, weighted_utility, 1.0);
+        for (action, regret) in regrets {
+            *expected.entry(action).or_insert(0.0) += sample_prob * regret;
+        }
+    }
+    for (action, regret) in &expected {
+        let exact = payoffs[action] - node_value;
+        assert!(
+            (regret - exact).abs() < 1e-9,
+            "{} {} {}",
+            action,
+            regret,
+            exact
+        );
+    }
+}
+
+#[test]
+fn test_outcome_sampling_convergence() {
+    // The lopsided rock paper scissors from test_linear_cfr_convergence()
+    let payoffs = vec![
+        vec![0.0, -1.0, 2.0],
+        vec![1.0, 0.0, -1.0],
+        vec![-2.0, 1.0, 0.0],
+    ];
+    let mut rng = StdRng::seed_from_u64(0);
+    let short = sampled_matrix_game_exploitability(&payoffs, 100, Some(0.6), &mut rng);
+    let long = sampled_matrix_game_exploitability(&payoffs, 10000, Some(0.6), &mut rng);
+    assert!(
+        long < short && long < 0.1,
+        "100 iterations: {}, 10000 iterations: {}",
+        short,
+        long
+    );
+}
+
+#[test]
+fn test_external_sample_regrets() {
+    // We check or bet, then the opponent folds 40% of the time and calls the
+    // rest. External sampling samples one opponent action after each of ours.
+    let check = Action {
+        action: ActionType::Call,
+        amount: 0,
+    };
+    let bet = Action {
+        action: ActionType::Bet,
+        amount: 100,
+    };
+    let strategy: HashMap<Action, f64> = vec![(check.clone(), 0.7), (bet.clone(), 0.3)]
+        .into_iter()
+        .collect();
+    let fold_prob = 0.4;
+    // Our payoff after each of our actions when they fold or call
+    let payoffs: HashMap<Action, [f64; 2]> =
+        vec![(check.clone(), [1.0, 1.0]), (bet.clone(), [1.0, -3.0])]
+            .into_iter()
+            .collect();
+    let values: HashMap<Action, f64> = payoffs
+        .iter()
+        .map(|(a, p)| (a.clone(), fold_prob * p[0] + (1.0 - fold_prob) * p[1]))
+        .collect();
+    let exact_value: f64 = strategy.iter().map(|(a, p)| p * values[a]).sum();
+
+    // Average over every combination of sampled opponent actions
+    let mut expected: HashMap<Action, f64> = HashMap::new();
+    let mut expected_value = 0.0;
+    for check_response in 0..2 {
+        for bet_response in 0..2 {
+            let prob = [fold_prob, 1.0 - fold_prob];
+            let sample_prob = prob[check_response] * prob[bet_response];
+            let mut utilities = HashMap::new();
+            utilities.insert(check.clone(), payoffs[&check][check_response]);
+            utilities.insert(bet.clone(), payoffs[&bet][bet_response]);
+            let (regrets, value) = external_sample_regrets(&strategy, &utilities);
+            // The sampled regrets are weighted by our strategy like real ones
+            let weighted: f64 = regrets.iter().map(|(a, r)| strategy[a] * r).sum();
+            assert!(weighted.abs() < 1e-12);
+            for (action, regret) in regrets {
+                *expected.entry(action).or_insert(0.0) += sample_prob * regret;
+            }
+            expected_value += sample_prob * value;
+        }
+    }
+    assert!((expected_value - exact_value).abs() < 1e-12);
+    for (action, regret) in &expected {
+        let exact = values[action] - exact_value;
+        assert!(
+            (regret - exact).abs() < 1e-12,
+            "{} {} {}",
+            action,
+            regret,
+            exact
+        );
+    }
+}
+
+// Runs CFR self-play on a zero-sum matrix game with the row player getting
+// payoffs[row][column], and returns the exploitability of the average
+// strategies: how much the two best responses gain over the game's value
+fn matrix_game_exploitability(payoffs: &[Vec<f64>], iters: usize, variant: CFRVariant) -> f64 {
+    let actions = |n: usize| -> Vec<Action> {
+        (0..n)
+            .map(|i| Action {
+                action: ActionType::Bet,
+                amount: i as i32,
+            })
+            .collect()
+    };
+    let row_actions = actions(payoffs.len());
+    let column_actions = actions(payoffs[0].len());
+    let mut row = Node::from_actions(&row_actions);
+    let mut column = Node::from_actions(&column_actions);
+    let probs = |strategy: &HashMap<Action, f64>, actions: &[Action]| -> Vec<f64> {
+        actions.iter().map(|a| strategy[a]).collect()
+    };
+    for _ in 0..iters {
+        // Alternating updates, like the players' separate traversals in
+        // train_iteration()
+        let column_probs = probs(&column.strategy(), &column_actions);
+        let row_probs = probs(&row.current_strategy(1.0, variant), &row_actions);
+        let values: Vec<f64> = payoffs
+            .iter()
+            .map(|r| r.iter().zip(&column_probs).map(|(u, p)| u * p).sum())
+            .collect();
+        let value: f64 = values.iter().zip(&row_probs).map(|(v, p)| v * p).sum();
+        for (action, v) in row_actions.iter().zip(&values) {
+            row.add_regret(action, v - value, variant);
+        }
+
+        let row_probs = probs(&row.strategy(), &row_actions);
+        let column_probs = probs(&column.current_strategy(1.0, variant), &column_actions);
+        let values: Vec<f64> = (0..column_actions.len())
+            .map(|c| {
+                -(0..row_actions.len())
+                    .map(|r| payoffs[r][c] * row_probs[r])
+                    .sum::<f64>()
+            })
+            .collect();
+        let value: f64 = values.iter().zip(&column_probs).map(|(v, p)| v * p).sum();
+        for (action, v) in column_actions.iter().zip(&values) {
+            column.add_regret(action, v - value, variant);
+        }
+    }
+    let row_average = probs(&row.cumulative_strategy(), &row_actions);
+    let column_average = probs(&column.cumulative_strategy(), &column_actions);
+    matrix_exploitability(payoffs, &row_average, &column_average)
+}
+
+// How much the two best responses to these strategies gain over the value of
+// the matrix game
+fn matrix_exploitability(payoffs: &[Vec<f64>], row_average: &[f64], column_average: &[f64]) -> f64 {
+    let best_row = payoffs
+        .iter()
+        .map(|r| {
+            r.iter()
+                .zip(column_average)
+                .map(|(u, p)| u * p)
+                .sum::<f64>()
+        })
+        .fold(f64::MIN, f64::max);
+    let best_column = (0..column_average.len())
+        .map(|c| {
+            (0..row_average.len())
+                .map(|r| payoffs[r][c] * row_average[r])
+                .sum::<f64>()
+        })
+        .fold(f64::MAX, f64::min);
+    best_row - best_column
+}
+
+// Like matrix_game_exploitability(), but each player's regrets come from a
+// single sampled opponent action, the way the sampling trainers see them.
+// With Some(epsilon) the player's own action is sampled too, with epsilon
+// exploration, and the regrets come from outcome_sample_regrets(); with None
+// every action of the player is walked and they come from
+// external_sample_regrets().
+fn sampled_matrix_game_exploitability(
+    payoffs: &[Vec<f64>],
+    iters: usize,
+    epsilon: Option<f64>,
+    rng: &mut StdRng,
+) -> f64 {
+    // Each player's payoffs, indexed by their own action and then the
+    // opponent's
+    let views: Vec<Vec<Vec<f64>>> = vec![
+        payoffs.to_vec(),
+        (0..payoffs[0].len())
+            .map(|c| payoffs.iter().map(|r| -r[c]).collect())
+            .collect(),
+    ];
+    let actions: Vec<Vec<Action>> = views
+        .iter()
+        .map(|view| {
+            (0..view.len())
+                .map(|i| Action {
+                    action: ActionType::Bet,
+                    amount: i as i32,
+                })
+                .collect()
+        })
+        .collect();
+    let mut nodes: Vec<Node> = actions.iter().map(|a| Node::from_actions(a)).collect();
+    let sample = |probs: &[f64], rng: &mut StdRng| -> usize {
+        let indices: Vec<usize> = (0..probs.len()).collect();
+        *indices.choose_weighted(rng, |&i| probs[i]).unwrap()
+    };
+    for _ in 0..iters {
+        for player in 0..2 {
+            let opponent = 1 - player;
+            let opponent_strategy = nodes[opponent].strategy();
+            let opponent_probs: Vec<f64> = actions[opponent]
+                .iter()
+                .map(|a| opponent_strategy[a])
+                .collect();
+            let o = sample(&opponent_probs, rng);
+            let view = &views[player];
+            let strategy = nodes[player].current_strategy(1.0, CFRVariant::Vanilla);
+            let regrets = match epsilon {
+                Some(epsilon) => {
+                    let n = actions[player].len() as f64;
+                    let sample_probs: Vec<f64> = actions[player]
+                        .iter()
+                        .map(|a| epsilon / n + (1.0 - epsilon) * strategy[a])
+                        .collect();
+                    let i = sample(&sample_probs, rng);
+                    let weighted_utility = view[i][o] / sample_probs[i];
+                    outcome_sample_regrets(&strategy, &actions[player][i], weighted_utility, 1.0)
+                }
+                None => {
+                    let utilities: HashMap<Action, f64> = actions[player]
+                        .iter()
+                        .enumerate()
+                        .map(|(i, a)| (a.clone(), view[i][o]))
+                        .collect();
+                    external_sample_regrets(&strategy, &utilities).0
+                }
+            };
+            for (action, regret) in regrets {
+                nodes[player].add_regret(&action, regret, CFRVariant::Vanilla);
+            }
+        }
+    }
+    let averages: Vec<Vec<f64>> = nodes
+        .iter()
+        .zip(&actions)
+        .map(|(node, actions)| {
+            let average = node.cumulative_strategy();
+            actions.iter().map(|a| average[a]).collect()
+        })
+        .collect();
+    matrix_exploitability(payoffs, &averages[0], &averages[1])
+}
+
+#[test]
+fn test_external_sampling_convergence() {
+    // The lopsided rock paper scissors from test_linear_cfr_convergence()
+    let payoffs = vec![
+        vec![0.0, -1.0, 2.0],
+        vec![1.0, 0.0, -1.0],
+        vec![-2.0, 1.0, 0.0],
+    ];
+    let mut rng = StdRng::seed_from_u64(0);
+    let short = sampled_matrix_game_exploitability(&payoffs, 100, None, &mut rng);
+    let long = sampled_matrix_game_exploitability(&payoffs, 10000, None, &mut rng);
+    assert!(
+        long < short && long < 0.1,
+        "100 iterations: {}, 10000 iterations: {}",
+        short,
+        long
+    );
+}
+
+#[test]
+fn test_linear_cfr_convergence() {
+    // Rock paper scissors where rock beating scissors pays double, so the
+    // equilibrium isn't uniform
+    let payoffs = vec![
+        vec![0.0, -1.0, 2.0],
+        vec![1.0, 0.0, -1.0],
+        vec![-2.0, 1.0, 0.0],
+    ];
+    for &iters in &[100, 1000] {
+        let vanilla = matrix_game_exploitability(&payoffs, iters, CFRVariant::Vanilla);
+        let linear = matrix_game_exploitability(&payoffs, iters, CFRVariant::Linear);
+        assert!(
+            linear < vanilla,
+            "{} iterations: vanilla {}, linear {}",
+            iters,
+            vanilla,
+            linear
+        );
+    }
+}
+
+#[test]
+fn test_cfr_plus_convergence() {
+    // The same lopsided rock paper scissors as test_linear_cfr_convergence()
+    let payoffs = vec![
+        vec![0.0, -1.0, 2.0],
+        vec![1.0, 0.0, -1.0],
+        vec![-2.0, 1.0, 0.0],
+    ];
+    for &iters in &[100, 1000] {
+        let vanilla = matrix_game_exploitability(&payoffs, iters, CFRVariant::Vanilla);
+        let cfr_plus = matrix_game_exploitability(&payoffs, iters, CFRVariant::CFRPlus);
+        assert!(
+            cfr_plus < vanilla,
+            "{} iterations: vanilla {}, CFR+ {}",
+            iters,
+            vanilla,
+            cfr_plus
+        );
+    }
+}
+
+#[test]
+fn test_dcfr_convergence() {
+    // The same lopsided rock paper scissors as test_linear_cfr_convergence()
+    let payoffs = vec![
+        vec![0.0, -1.0, 2.0],
+        vec![1.0, 0.0, -1.0],
+        vec![-2.0, 1.0, 0.0],
+    ];
+    let variant = CFRVariant::DCFR(DCFRConfig::default());
+    for &iters in &[100, 1000] {
+        let vanilla = matrix_game_exploitability(&payoffs, iters, CFRVariant::Vanilla);
+        let dcfr = matrix_game_exploitability(&payoffs, iters, variant);
+        assert!(
+            dcfr < vanilla,
+            "{} iterations: vanilla {}, DCFR {}",
+            iters,
+            vanilla,
+            dcfr
+        );
+    }
+}
+
+#[test]
+fn test_strategy_size_bytes() {
+    let mut nodes = NodeMap::new();
+    assert_eq!(strategy_size_bytes(&nodes).bytes_per_entry, 0.0);
+
+    let preflop = ActionHistory::new();
+    let mut flop = ActionHistory::new();
+    for action in river_history().street_actions(PREFLOP) {
+        flop.add(action);
+    }
+    assert_eq!(flop.street, FLOP);
+    for bucket in 0..3 {
+        for history in &[&preflop, &flop] {
+            let infoset = InfoSet::new((*history).clone(), bucket);
+            nodes.insert(infoset.compress(), Node::new(&infoset));
+        }
+    }
+    let report = strategy_size_bytes(&nodes);
+    assert_eq!(report.entries, 6);
+    assert_eq!(report.by_street, [3, 3, 0, 0]);
+    let expected: usize = nodes
+        .iter()
+        .map(|(infoset, node)| infoset.size_bytes() + node.size_bytes())
+        .sum();
+    assert_eq!(report.total_bytes, expected);
+    assert_eq!(report.bytes_per_entry, expected as f64 / 6.0);
+    // The maps in each node are bigger than the node itself
+    assert!(report.bytes_per_entry > 2.0 * std::mem::size_of::<Node>() as f64);
+}
+
+// Adds a node for every preflop infoset up to depth actions in, for the given
+// buckets. Each hand calls, sometimes folds, makes the smallest raise and almost
+// never makes any other raise.
+fn add_preflop_nodes(nodes: &mut NodeMap, history: &ActionHistory, buckets: &[i32], depth: usize) {
+    if depth == 0 || history.hand_over() || history.street != PREFLOP {
+        return;
+    }
+    let actions = history.next_actions(bet_abstraction());
+    let mut strategy = HashMap::new();
+    let mut raised = false;
+    for action in &actions {
+        let prob = match action.action {
+            ActionType::Fold => 0.1,
+            ActionType::Call => 0.5,
+            _ if !raised => {
+                raised = true;
+                0.4
+            }
+            _ => 1e-9,
+        };
+        strategy.insert(action.clone(), prob);
+    }
+    for &bucket in buckets {
+        let infoset = InfoSet::new(history.clone(), bucket);
+        nodes.insert(infoset.compress(), Node::from_strategy(&strategy));
+    }
+    for action in &actions {
+        let mut next_history = history.clone();
+        next_history.add(action);
+        add_preflop_nodes(nodes, &next_history, buckets, depth - 1);
+    }
+}
+
+#[test]
+fn test_compute_reach_probabilities() {
+    let mut nodes = NodeMap::new();
+    add_preflop_nodes(&mut nodes, &ActionHistory::new(), &[0, 1], 2);
+    let reach = compute_reach_probabilities(&nodes);
+    assert_eq!(reach.len(), nodes.len());
+    let root = InfoSet::new(ActionHistory::new(), 0).compress();
+    assert_eq!(reach[&root], 1.0);
+    for action in ActionHistory::new().next_actions(bet_abstraction()) {
+        let mut history = ActionHistory::new();
+        history.add(&action);
+        if history.hand_over() {
+            continue;
+        }
+        // Only the dealer has acted, so the opponent is always here
+        let infoset = InfoSet::new(history, 1).compress();
+        assert_eq!(reach[&infoset], 1.0);
+    }
+}
+
+#[test]
+fn test_prune_strategy() {
+    let deals = vec![
+        strvec2cards(&["Ah", "Ad", "7c", "2d", "Ac", "Kc", "9h", "4s", "3d"]),
+        strvec2cards(&["7c", "2d", "Ah", "Ad", "Ac", "Kc", "9h", "4s", "3d"]),
+    ];
+    let preflop_bucket = |deck: &[Card], player: usize, _street: usize| {
+        preflop_bin_index(&get_hand(deck, player, PREFLOP)) as i32
+    };
+    let buckets: Vec<i32> = deals
+        .iter()
+        .flat_map(|deal| {
+            vec![
+                preflop_bucket(deal, DEALER, 0),
+                preflop_bucket(deal, OPPONENT, 0),
+            ]
+        })
+        .collect();
+    let mut nodes = NodeMap::new();
+    add_preflop_nodes(&mut nodes, &ActionHistory::new(), &buckets, 4);
+
+    let reach = compute_reach_probabilities(&nodes);
+    let pruned = prune_strategy(&nodes, 1e-6, &reach);
+    assert!(pruned.len() < nodes.len() / 2);
+    assert!(pruned.keys().all(|infoset| reach[infoset] >= 1e-6));
+
+    // Anything missing from the blueprint is played by calling
+    let strategy_from = |nodes: &NodeMap| {
+        let nodes = nodes.clone();
+        move |infoset: &CompactInfoSet, history: &ActionHistory| match nodes.get(infoset) {
+            Some(node) => node.cumulative_strategy(),
+            None => always_call(history),
+        }
+    };
+    let full_exploit = exploitability_on_deals(&deals, &preflop_bucket, &strategy_from(&nodes));
+    let pruned_exploit = exploitability_on_deals(&deals, &preflop_bucket, &strategy_from(&pruned));
+    assert!((pruned_exploit - full_exploit).abs() < 0.01 * full_exploit.abs());
+}
+
+#[test]
+fn test_compress_strategy() {
+    let deals = vec![
+        strvec2cards(&["Ah", "Ad", "7c", "2d", "Ac", "Kc", "9h", "4s", "3d"]),
+        strvec2cards(&["7c", "2d", "Ah", "Ad", "Ac", "Kc", "9h", "4s", "3d"]),
+        strvec2cards(&["Ks", "Qs", "Jd", "Td", "2c", "5c", "9h", "4s", "3d"]),
+    ];
+    let preflop_bucket = |deck: &[Card], player: usize, _street: usize| {
+        preflop_bin_index(&get_hand(deck, player, PREFLOP)) as i32
+    };
+    let buckets: Vec<i32> = deals
+        .iter()
+        .flat_map(|deal| {
+            vec![
+                preflop_bucket(deal, DEALER, 0),
+                preflop_bucket(deal, OPPONENT, 0),
+            ]
+        })
+        .collect();
+    // A strategy that folds to every raise and never bets can only lose the
+    // blinds, under 1 BB/h here, where one that puts chips in loses whole
+    // stacks to a best response that as good as sees the cards. Preflop it
+    // also folds 30% of the time when it could check, so that there's
+    // something for the quantization to round.
+    let passive = |history: &ActionHistory, mixed: bool| -> HashMap<Action, f64> {
+        let facing_raise = history
+            .last_action()
+            .map_or(false, |a| a.action == ActionType::Bet);
+        history
+            .next_actions(bet_abstraction())
+            .into_iter()
+            .map(|action| {
+                let prob = match action.action {
+                    ActionType::Fold if facing_raise => 1.0,
+                    ActionType::Fold if mixed => 0.3,
+                    ActionType::Call if facing_raise => 0.0,
+                    ActionType::Call if mixed => 0.7,
+                    ActionType::Call => 1.0,
+                    _ => 0.0,
+                };
+                (action, prob)
+            })
+            .collect()
+    };
+    let mut nodes = NodeMap::new();
+    add_preflop_nodes(&mut nodes, &ActionHistory::new(), &buckets, 3);
+    for (infoset, node) in nodes.iter_mut() {
+        *node = Node::from_strategy(&passive(&infoset.uncompress().history, true));
+    }
+
+    let compressed = compress_strategy(&nodes);
+    let decompressed = decompress_strategy(&compressed);
+    assert_eq!(decompressed.len(), nodes.len());
+    for (infoset, node) in &nodes {
+        let original = node.cumulative_strategy();
+        for (action, prob) in decompressed[infoset].cumulative_strategy() {
+            // Within one quantization step, even after renormalizing
+            assert!((prob - original[&action]).abs() < 1.0 / 255.0);
+        }
+    }
+
+    let strategy_from = |nodes: &NodeMap| {
+        let nodes = nodes.clone();
+        move |infoset: &CompactInfoSet, history: &ActionHistory| match nodes.get(infoset) {
+            Some(node) => node.cumulative_strategy(),
+            None => passive(history, false),
+        }
+    };
+    let exploit = exploitability_on_deals(&deals, &preflop_bucket, &strategy_from(&nodes));
+    let compressed_exploit =
+        exploitability_on_deals(&deals, &preflop_bucket, &strategy_from(&decompressed));
+    assert!(
+        (compressed_exploit - exploit).abs() < 0.01,
+        "{} {}",
+        exploit,
+        compressed_exploit
+    );
+
+    let size = bincode::serialize(&nodes).unwrap().len();
+    let compressed_size = bincode::serialize(&compressed).unwrap().len();
+    assert!(compressed_size * 100 <= size * 15);
+
+    // Rounding can leave the probabilities not quite adding up to 1
+    assert_eq!(quantize_probs(&[1.0 / 3.0; 3]), vec![85, 85, 85]);
+    assert_eq!(dequantize_probs(&[85, 85, 85]), vec![1.0 / 3.0; 3]);
+    assert_eq!(dequantize_probs(&[0, 0]), vec![0.5, 0.5]);
+}
+
+#[test]
+fn test_blueprint_versions() {
+    let mut blueprint = CompressedStrategy::new();
+    blueprint.insert(CompactInfoSet::new(vec![], 7), vec![200, 50, 5]);
+    let path = format!("test_blueprint_versions_{}.bin", std::process::id());
+    save_blueprint(&blueprint, &path).unwrap();
+    assert_eq!(load_blueprint_from(&path).unwrap(), blueprint);
+
+    // Blueprints from before the header are rejected instead of misread
+    let saved = std::fs::read(&path).unwrap();
+    std::fs::write(&path, bincode::serialize(&blueprint).unwrap()).unwrap();
+    match load_blueprint_from(&path).map_err(|e| e.source) {
+        Err(StrategyLoadErrorKind::VersionMismatch(0, BLUEPRINT_FORMAT_VERSION)) => {}
+        other => panic!("Expected a version mismatch, got {:?}", other),
+    }
+
+    let mut bytes = saved.clone();
+    bytes[4..8].copy_from_slice(&(BLUEPRINT_FORMAT_VERSION + 1).to_le_bytes());
+    std::fs::write(&path, &bytes).unwrap();
+    let result = load_blueprint_from(&path).map_err(|e| e.source);
+    match result {
+        Err(StrategyLoadErrorKind::VersionMismatch(found, BLUEPRINT_FORMAT_VERSION)) => {
+            assert_eq!(found, BLUEPRINT_FORMAT_VERSION + 1)
+        }
+        other => panic!("Expected a version mismatch, got {:?}", other),
+    }
+
+    // A strategy isn't a blueprint
+    let mut nodes: NodeMap = HashMap::new();
+    nodes.insert(
+        CompactInfoSet::new(vec![], 7),
+        Node::from_actions(&ActionHistory::new().next_actions(bet_abstraction())),
+    );
+    save_strategy(&nodes, &path).unwrap();
+    let result = load_blueprint_from(&path).map_err(|e| e.source);
+    std::fs::remove_file(&path).unwrap();
+    match result {
+        Err(StrategyLoadErrorKind::VersionMismatch(0, BLUEPRINT_FORMAT_VERSION)) => {}
+        other => panic!("Expected a version mismatch, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_dcfr_updates() {
+    let check = Action {
+        action: ActionType::Call,
+        amount: 0,
+    };
+    let bet = Action {
+        action: ActionType::Bet,
+        amount: 100,
+    };
+    let config = DCFRConfig {
+        alpha: 1.0,
+        beta: 1.0,
+        gamma: 1.0,
+    };
+    let variant = CFRVariant::DCFR(config);
+    let mut node = Node::from_actions(&[check.clone(), bet.clone()]);
+    node.current_strategy(1.0, variant);
+    node.current_strategy(1.0, variant);
+    // t = 2, so regrets are discounted by 2 / (2 + 1)
+    node.add_regret(&bet, 3.0, variant);
+    node.add_regret(&check, -3.0, variant);
+    let strategy = node.strategy();
+    assert_eq!(strategy[&bet], 1.0);
+    assert_eq!(strategy[&check], 0.0);
+    node.add_regret(&check, 5.0, variant);
+    // check: (-2 + 5) * 2/3 = 2, bet: 2
+    let strategy = node.strategy();
+    assert!((strategy[&check] - 0.5).abs() < 1e-12);
+
+    // The default parameters come from the DCFR paper
+    let default = DCFRConfig::default();
+    assert_eq!(
+        (default.alpha, default.beta, default.gamma),
+        (1.5, 0.0, 2.0)
+    );
+}
+
+#[test]
+fn test_hand_range_parsing() {
+    let len = |range: &str| range.parse::<HandRange>().unwrap().len();
+    assert_eq!(len("AA"), 6);
+    assert_eq!(len("KK+"), 12);
+    assert_eq!(len("AKs"), 4);
+    assert_eq!(len("AKo"), 12);
+    assert_eq!(len("AK"), 16);
+    assert_eq!(len("KA"), 16);
+    assert_eq!(len("AQo+"), 24);
+    assert_eq!(len("A2s+"), 48);
+    assert_eq!(len("22-TT"), 54);
+    assert_eq!(len("TT-22"), 54);
+    assert_eq!(len("A2s-A5s"), 16);
+    assert_eq!(len("AA,KK+,AKs,AQo+,22-TT"), 6 + 6 + 4 + 24 + 54);
+    assert_eq!(len(" AA , AKs,"), 10);
+    assert_eq!(len(""), 0);
+
+    let range: HandRange = "AKs,QQ".parse().unwrap();
+    assert!(range.contains(&strvec2cards(&["Ah", "Kh"])));
+    assert!(range.contains(&strvec2cards(&["Kh", "Ah"])));
+    assert!(range.contains(&strvec2cards(&["Qc", "Qs"])));
+    assert!(!range.contains(&strvec2cards(&["Ah", "Kd"])));
+    assert!(!range.contains(&strvec2cards(&["Ah"])));
+
+    for bad in &[
+        "AAs", "AKx", "A", "AKQ", "1K", "22-AKs", "AKs-QJs", "A2s-A5o",
+    ] {
+        assert_eq!(
+            bad.parse::<HandRange>(),
+            Err(RangeParseError::InvalidToken(bad.to_string()))
+        );
+    }
+    assert_eq!(
+        "AA,ZZ".parse::<HandRange>(),
+        Err(RangeParseError::InvalidToken("ZZ".to_string()))
+    );
+}
+
+#[test]
+fn test_hand_range_blockers() {
+    let range: HandRange = "AA,AKs".parse().unwrap();
+    // The ace of hearts blocks 3 combos of AA and AhKh
+    let unblocked = range.remove_blockers(&strvec2cards(&["Ah", "2c", "7d"]));
+    assert_eq!(unblocked.len(), 3 + 3);
+    assert!(!unblocked.contains(&strvec2cards(&["Ah", "Kh"])));
+    assert!(unblocked.contains(&strvec2cards(&["As", "Ks"])));
+    assert!(unblocked
+        .combos()
+        .iter()
+        .all(|combo| combo[0] != Card::new("Ah") && combo[1] != Card::new("Ah")));
+}
+
+#[test]
+fn test_preflop_matrix() {
+    let matrix = preflop_matrix_169();
+    let total: u32 = matrix.iter().map(|h| h.combo_count as u32).sum();
+    assert_eq!(total, 1326);
+
+    assert_eq!(preflop_bin_index(&strvec2cards(&["Ah", "As"])), 0);
+    assert_eq!(preflop_bin_index(&strvec2cards(&["Kd", "Ad"])), 1);
+    assert_eq!(preflop_bin_index(&strvec2cards(&["Ac", "Kd"])), 13);
+    assert_eq!(preflop_bin_index(&strvec2cards(&["2c", "2d"])), 168);
+    assert_eq!(matrix[0].strength, 0.853);
+    // Pocket pairs against a random hand, AA down to 22, from the usual
+    // equity charts
+    let pair_equities = [
+        0.852, 0.824, 0.799, 0.775, 0.751, 0.721, 0.691, 0.662, 0.633, 0.603, 0.570, 0.537, 0.503,
+    ];
+    for (i, equity) in pair_equities.iter().enumerate() {
+        assert!((matrix[i * 13 + i].strength - equity).abs() < 0.005);
+    }
+
+    // Every combo should land in the bucket that describes it
+    let mut counts = [0; 169];
+    for cards in deck().into_iter().combinations(2) {
+        let bucket = matrix[preflop_bin_index(&cards)];
+        assert_eq!(bucket.rank1, cards[0].rank.max(cards[1].rank));
+        assert_eq!(bucket.rank2, cards[0].rank.min(cards[1].rank));
+        assert_eq!(bucket.suited, cards[0].suit == cards[1].suit);
+        counts[preflop_bin_index(&cards)] += 1;
+    }
+    for (hand, count) in matrix.iter().zip(counts.iter()) {
+        assert_eq!(hand.combo_count, *count);
+    }
+}
+
+#[test]
+fn test_best_response_zero_sum() {
+    let preflop_bucket = |deck: &[Card], player: usize, _street: usize| {
+        preflop_bin_index(&get_hand(deck, player, PREFLOP)) as i32
+    };
+    let call_strategy = |_infoset: &CompactInfoSet, history: &ActionHistory| always_call(history);
+
+    // A royal flush on the board means every showdown is a tie, so always
+    // calling is an equilibrium and neither player can win anything from it.
+    let tie_deal = strvec2cards(&["2c", "3d", "7h", "8c", "As", "Ks", "Qs", "Js", "Ts"]);
+    let deals = vec![tie_deal];
+    let p0 = best_response_on_deals(&deals, DEALER, &preflop_bucket, &call_strategy);
+    let p1 = best_response_on_deals(&deals, OPPONENT, &preflop_bucket, &call_strategy);
+    assert_eq!(p0, 0.0);
+    assert_eq!(p1, 0.0);
+    assert_eq!(p0 + p1, 0.0);
+
+    // Always calling is very exploitable when the dealer has the nuts: the
+    // dealer shoves, and the opponent's best option is to give up the blind.
+    let nut_deal = strvec2cards(&["Ah", "Ad", "7c", "2d", "Ac", "Kc", "9h", "4s", "3d"]);
+    let deals = vec![nut_deal];
+    let p0 = best_response_on_deals(&deals, DEALER, &preflop_bucket, &call_strategy);
+    let p1 = best_response_on_deals(&deals, OPPONENT, &preflop_bucket, &call_strategy);
+    assert_eq!(p0, STACK_SIZE as f64);
+    assert_eq!(p1, -BIG_BLIND as f64);
+    assert!(p0 + p1 > 0.0);
+}
+
+#[test]
+fn test_exploitable_spots() {
+    let preflop_bucket = |deck: &[Card], player: usize, _street: usize| {
+        preflop_bin_index(&get_hand(deck, player, PREFLOP)) as i32
+    };
+    let call_strategy = |_infoset: &CompactInfoSet, history: &ActionHistory| always_call(history);
+
+    // Nothing to gain when every showdown is a tie
+    let tie_deal = strvec2cards(&["2c", "3d", "7h", "8c", "As", "Ks", "Qs", "Js", "Ts"]);
+    assert!(exploitable_spots_on_deals(&[tie_deal], &preflop_bucket, &call_strategy).is_empty());
+
+    // With the nuts the dealer should always shove. The biggest leaks are
+    // checking the river instead, where a shove wins the rest of the stack
+    let nut_deal = strvec2cards(&["Ah", "Ad", "7c", "2d", "Ac", "Kc", "9h", "4s", "3d"]);
+    let spots = exploitable_spots_on_deals(&[nut_deal.clone()], &preflop_bucket, &call_strategy);
+    assert!(!spots.is_empty());
+    for pair in spots.windows(2) {
+        assert!(pair[0].1.ev_loss >= pair[1].1.ev_loss);
+    }
+    let aces = preflop_bin_index(&nut_deal[..2]) as i32;
+    let (infoset, spot) = &spots[0];
+    assert_eq!(infoset.uncompress().history.street, RIVER);
+    assert_eq!(spot.best_action.action, ActionType::Bet);
+    assert_eq!(
+        spot.strategy_action,
+        Action {
+            action: ActionType::Call,
+            amount: 0
+        }
+    );
+    assert!(spot.infoset_str.starts_with(&format!("bucket {}|", aces)));
+    let expected = spot.best_action.amount as f64 / BIG_BLIND as f64;
+    assert!((spot.ev_loss - expected).abs() < 1e-9, "{:?}", spot);
+
+    // Calling preflop doesn't lose anything, since the opponent always calls
+    // and the dealer can still shove later
+    let root = CompactInfoSet::new(vec![], aces);
+    assert!(spots.iter().all(|(infoset, _)| infoset != &root));
+}
+
+#[test]
+fn test_exploitability() {
+    let preflop_bucket = |deck: &[Card], player: usize, _street: usize| {
+        preflop_bin_index(&get_hand(deck, player, PREFLOP)) as i32
+    };
+    let call_strategy = |_infoset: &CompactInfoSet, history: &ActionHistory| always_call(history);
+    let uniform_strategy = |_infoset: &CompactInfoSet, history: &ActionHistory| {
+        Node::from_actions(&history.next_actions(bet_abstraction())).cumulative_strategy()
+    };
+
+    // Always calling is an equilibrium when the board is a royal flush
+    let tie_deal = strvec2cards(&["2c", "3d", "7h", "8c", "As", "Ks", "Qs", "Js", "Ts"]);
+    let deals = vec![tie_deal];
+    let exploit = exploitability_on_deals(&deals, &preflop_bucket, &call_strategy);
+    assert!(exploit.abs() < 1e-9);
+
+    // Playing uniformly at random isn't, since it folds to bets a third of the time
+    let exploit = exploitability_on_deals(&deals, &preflop_bucket, &uniform_strategy);
+    assert!(exploit > 0.5);
+}
+
+fn api_server() -> actix_web::test::TestServer {
+    actix_web::test::start(|| {
+        App::new()
+            .route("/hand_strength", web::get().to(api::hand_strength))
+            .route("/hand_strength", web::post().to(api::hand_strength_json))
+            .route("/compare_hands", web::get().to(api::compare_hands))
+    })
+}
+
+#[actix_rt::test]
+async fn test_hand_strength_endpoint() {
+    let srv = api_server();
+    let mut response = srv
+        .get("/hand_strength?cards=AhKhQhJhTh")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!({"strength": 134458, "category": "RoyalFlush", "hand": "AhKhQhJhTh"})
+    );
+
+    let mut response = srv
+        .post("/hand_strength")
+        .send_json(&serde_json::json!({"cards": "2h2d2c2s3d"}))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["category"], "FourOfAKind");
+
+    for bad in &["AhKdQcJcTx", "AhKdQc", "AhAhQcJcTh"] {
+        let mut response = srv
+            .get(format!("/hand_strength?cards={}", bad))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert!(body["error"].is_string());
+    }
+}
+
+#[actix_rt::test]
+async fn test_compare_hands_endpoint() {
+    let srv = api_server();
+    for (hand1, hand2, winner) in &[
+        ("AhKhQhJhTh", "2h2d2c2s3d", "hand1"),
+        ("2h2d2c2s3d", "AhKhQhJhTh", "hand2"),
+        ("AhKdQcJcTh", "AsKcQdJdTs", "tie"),
+    ] {
+        let mut response = srv
+            .get(format!("/compare_hands?hand1={}&hand2={}", hand1, hand2))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["winner"], *winner);
+    }
+}
+
+fn session_server(store: SessionStore) -> actix_web::test::TestServer {
+    let store = web::Data::new(store);
+    actix_web::test::start(move || {
+        App::new()
+            .app_data(store.clone())
+            .route("/session", web::post().to(session::create_session))
+            .route("/session/{id}/deal", web::post().to(session::deal))
+            .route("/session/{id}/action", web::post().to(session::action))
+            .route("/session/{id}/state", web::get().to(session::state))
+            .route("/session/{id}", web::delete().to(session::end_session))
+    })
+}
+
+fn metrics_server(store: SessionStore) -> actix_web::test::TestServer {
+    let store = web::Data::new(store);
+    let metrics = web::Data::new(MetricsRegistry::new());
+    actix_web::test::start(move || {
+        App::new()
+            .app_data(store.clone())
+            .app_data(metrics.clone())
+            .service(metrics::resource("/metrics", &metrics).route(web::get().to(metrics::metrics)))
+            .service(
+                metrics::resource("/session", &metrics)
+                    .route(web::post().to(session::create_session)),
+            )
+            .service(
+                metrics::resource("/session/{id}/state", &metrics)
+                    .route(web::get().to(session::state)),
+            )
+    })
+}
+
+#[actix_rt::test]
+async fn test_metrics_endpoint() {
+    let srv = metrics_server(SessionStore::new(session::SESSION_TIMEOUT));
+    let mut response = srv.post("/session").send().await.unwrap();
+    let body: serde_json::Value = response.json().await.unwrap();
+    let id = body["id"].as_str().unwrap().to_string();
+    for _ in 0..2 {
+        let response = srv
+            .get(format!("/session/{}/state", id))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+    }
+    let response = srv.get("/session/nope/state").send().await.unwrap();
+    assert_eq!(response.status(), 404);
+
+    let mut response = srv.get("/metrics").send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    let body = response.body().await.unwrap();
+    let text = String::from_utf8(body.to_vec()).unwrap();
+    let lines = text.lines().map(|line| Ok(line.to_string()));
+    let scrape = prometheus_parse::Scrape::parse(lines).unwrap();
+    let value = |name: &str, labels: &[(&str, &str)]| {
+        scrape
+            .samples
+            .iter()
+            .find(|s| s.metric == name && labels.iter().all(|(k, v)| s.labels.get(k) == Some(v)))
+            .map(|s| s.value.clone())
+    };
+    let requests = |endpoint: &str, status: &str| {
+        value(
+            "poker_requests_total",
+            &[("endpoint", endpoint), ("status", status)],
+        )
+    };
+    // Requests are labelled by route, not by session id
+    assert_eq!(
+        requests("/session", "200"),
+        Some(prometheus_parse::Value::Counter(1.0))
+    );
+    assert_eq!(
+        requests("/session/:id/state", "200"),
+        Some(prometheus_parse::Value::Counter(2.0))
+    );
+    assert_eq!(
+        requests("/session/:id/state", "404"),
+        Some(prometheus_parse::Value::Counter(1.0))
+    );
+    assert_eq!(
+        value("poker_active_sessions", &[]),
+        Some(prometheus_parse::Value::Gauge(1.0))
+    );
+    let durations = value(
+        "poker_request_duration_seconds",
+        &[("endpoint", "/session/:id/state")],
+    );
+    assert!(matches!(
+        durations,
+        Some(prometheus_parse::Value::Histogram(_))
+    ));
+    assert!(value("poker_blueprint_fallbacks_total", &[]).is_some());
+    assert!(value("poker_equity_table_lookups_total", &[]).is_some());
+}
+
+// Goes through a session where the human folds preflop, so the bot never has
+// to act and no blueprint is needed
+#[actix_rt::test]
+async fn test_session_endpoints() {
+    let srv = session_server(SessionStore::new(session::SESSION_TIMEOUT));
+    let mut response = srv.post("/session").send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let id = body["id"].as_str().unwrap().to_string();
+
+    // The board is rejected preflop, and the hand that came with it isn't kept
+    let response = srv
+        .post(format!("/session/{}/deal", id))
+        .send_json(&serde_json::json!({"hand": ["Ah", "Kd"], "board": ["2c", "7d", "Th"]}))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let mut response = srv
+        .get(format!("/session/{}/state", id))
+        .send()
+        .await
+        .unwrap();
+    let state: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(state["waiting_for"], "nothing");
+
+    let mut response = srv
+        .post(format!("/session/{}/deal", id))
+        .send_json(&serde_json::json!({"hand": ["Ah", "Kd"]}))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let replies: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(replies[0]["type"], "state");
+    assert_eq!(replies[0]["waiting_for"], "action");
+
+    let mut response = srv
+        .post(format!("/session/{}/action", id))
+        .send_json(&serde_json::json!({"action": "bet", "amount": 1}))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["type"], "error");
+
+    let response = srv
+        .post(format!("/session/{}/action", id))
+        .send_json(&serde_json::json!({"action": "fold"}))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let mut response = srv
+        .get(format!("/session/{}/state", id))
+        .send()
+        .await
+        .unwrap();
+    let state: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(state["waiting_for"], "nothing");
+
+    let response = srv.delete(format!("/session/{}", id)).send().await.unwrap();
+    assert_eq!(response.status(), 204);
+    for path in &[
+        format!("/session/{}/state", id),
+        "/session/nonsense/state".to_string(),
+    ] {
+        let response = srv.get(path).send().await.unwrap();
+        assert_eq!(response.status(), 404);
+    }
+}
+
+#[test]
+fn test_session_expiry() {
+    let store = SessionStore::new(Duration::from_millis(50));
+    let old = store.create();
+    std::thread::sleep(Duration::from_millis(100));
+    let new = store.create();
+    assert_eq!(store.len(), 2);
+    store.remove_expired();
+    assert_eq!(store.len(), 1);
+    assert!(!store.remove(&old.to_string()));
+    assert!(store.remove(&new.to_string()));
+}
+
+// Sessions that have expired are gone even before the timer gets to them
+#[actix_rt::test]
+async fn test_expired_session_not_found() {
+    let srv = session_server(SessionStore::new(Duration::from_millis(50)));
+    let mut response = srv.post("/session").send().await.unwrap();
+    let body: serde_json::Value = response.json().await.unwrap();
+    let id = body["id"].as_str().unwrap().to_string();
+    let path = format!("/session/{}/state", id);
+    assert_eq!(srv.get(&path).send().await.unwrap().status(), 200);
+    actix_rt::time::delay_for(Duration::from_millis(100)).await;
+    assert_eq!(srv.get(&path).send().await.unwrap().status(), 404);
+}
+
+// Plays a full hand against the bot over a websocket, with the human always
+// checking or calling. The bot bets a big blind whenever it can and calls
+// otherwise, so the test doesn't need the blueprint.
+#[actix_rt::test]
+async fn test_websocket_full_hand() {
+    let strategy = |_: &[Card], _: &[Card], history: &ActionHistory| {
+        if history.to_call() == 0 && history.is_bet_legal(BIG_BLIND) {
+            Action {
+                action: ActionType::Bet,
+                amount: BIG_BLIND,
+            }
+        } else {
+            Action {
+                action: ActionType::Call,
+                amount: history.to_call(),
+            }
+        }
+    };
+    let mut srv = actix_web::test::start(move || {
+        App::new().route(
+            "/ws",
+            web::get().to(move |req: HttpRequest, stream: web::Payload| async move {
+                game_session_with(&req, stream, strategy)
+            }),
+        )
+    });
+    let mut framed = srv.ws_at("/ws").await.unwrap();
+    let board = ["2c", "7d", "Th", "Js", "3h"];
+    let mut message = serde_json::json!({"type": "deal", "hand": ["Ah", "Kd"]});
+    loop {
+        framed
+            .send(ws::Message::Text(message.to_string()))
+            .await
+            .unwrap();
+        // Every message gets an optional bot action and then the game state
+        let state = loop {
+            let reply: serde_json::Value = match framed.next().await.unwrap().unwrap() {
+                ws::Frame::Text(text) => serde_json::from_slice(&text).unwrap(),
+                frame => panic!("Unexpected frame {:?}", frame),
+            };
+            match reply["type"].as_str().unwrap() {
+                "bot_action" => {
+                    let action = reply["action"].as_str().unwrap();
+                    assert!(["fold", "check", "call", "bet", "raise"].contains(&action));
+                    let amount = reply["amount"].as_i64().unwrap();
+                    assert!(amount >= 0 && amount <= STACK_SIZE as i64);
+                }
+                "state" => break reply,
+                _ => panic!("Unexpected reply {}", reply),
+            }
+        };
+        message = match state["waiting_for"].as_str().unwrap() {
+            "action" => serde_json::json!({"type": "action", "action": "call"}),
+            "board" => {
+                let cards = match state["street"].as_str().unwrap() {
+                    "flop" => &board[..3],
+                    "turn" => &board[3..4],
+                    _ => &board[4..],
+                };
+                serde_json::json!({"type": "board", "cards": cards})
+            }
+            _ => break,
+        };
+    }
+}
+
+#[test]
+fn test_odds() {
+    let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+    // Half pot bet
+    assert!(close(odds::pot_odds(50, 100), 1.0 / 3.0));
+    assert!(close(odds::minimum_defense_frequency(50, 100), 2.0 / 3.0));
+    assert!(close(odds::breakeven_equity(50, 100), 0.25));
+    // Pot sized bet
+    assert!(close(odds::pot_odds(100, 100), 0.5));
+    assert!(close(odds::minimum_defense_frequency(100, 100), 0.5));
+    assert!(close(odds::breakeven_equity(100, 100), 1.0 / 3.0));
+    // 2x pot overbet
+    assert!(close(odds::pot_odds(200, 100), 2.0 / 3.0));
+    assert!(close(odds::minimum_defense_frequency(200, 100), 1.0 / 3.0));
+    assert!(close(odds::breakeven_equity(200, 100), 0.4));
+
+    assert_eq!(odds::kelly_bet(0.75, 100, 1000), 475);
+    assert_eq!(odds::kelly_bet(0.6, 0, 1000), 200);
+    // No edge, no bet
+    assert_eq!(odds::kelly_bet(0.5, 100, 1000), 0);
+    assert_eq!(odds::kelly_bet(0.2, 100, 1000), 0);
+    // The nuts should get it all in
+    assert_eq!(odds::kelly_bet(1.0, 100, 1000), 1000);
+}
+
+#[test]
+fn test_checkpoint_round_trip() {
+    let mut nodes: NodeMap = HashMap::new();
+    let mut history = ActionHistory::new();
+    for bucket in 0..3 {
+        let mut node = Node::from_actions(&history.next_actions(bet_abstraction()));
+        node.add_regret(&FOLD, bucket as f64, CFRVariant::Vanilla);
+        node.current_strategy(0.5, CFRVariant::Vanilla);
+        nodes.insert(CompactInfoSet::new(vec![bucket as u8], bucket), node);
+        history.add(&history.next_actions(bet_abstraction())[0]);
+    }
+    let path = "products/test_checkpoint_round_trip.bin";
+    save_checkpoint(&nodes, 1234, path).unwrap();
+    let (loaded, iteration) = load_checkpoint(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(iteration, 1234);
+    assert_eq!(loaded, nodes);
+
+    match load_checkpoint("products/no_such_checkpoint.bin") {
+        Err(CheckpointError::Io(_)) => {}
+        other => panic!("Expected an I/O error, got {:?}", other),
+    }
+    let path = "products/test_bad_checkpoint.bin";
+    std::fs::write(path, b"garbage").unwrap();
+    let result = load_checkpoint(path);
+    std::fs::remove_file(path).unwrap();
+    match result {
+        Err(CheckpointError::Serialization(_)) => {}
+        other => panic!("Expected a serialization error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_strategy_versions() {
+    let mut nodes: NodeMap = HashMap::new();
+    let history = ActionHistory::new();
+    let mut node = Node::from_actions(&history.next_actions(bet_abstraction()));
+    node.add_regret(&FOLD, 2.0, CFRVariant::Vanilla);
+    nodes.insert(CompactInfoSet::new(vec![], 7), node);
+
+    let path = "products/test_strategy_versions.bin";
+    save_strategy(&nodes, path).unwrap();
+    assert_eq!(load_strategy_from(path).unwrap(), nodes);
+
+    // Old strategies are just the nodes
+    let v1_path = "products/test_strategy_versions_v1.bin";
+    std::fs::write(v1_path, bincode::serialize(&nodes).unwrap()).unwrap();
+    let error = load_strategy_from(v1_path).unwrap_err();
+    assert_eq!(error.path, v1_path);
+    match error.source {
+        StrategyLoadErrorKind::VersionMismatch(1, STRATEGY_FORMAT_VERSION) => {}
+        other => panic!("Expected a version mismatch, got {:?}", other),
+    }
+    migrate_strategy_v1_to_v2(v1_path, path).unwrap();
+    std::fs::remove_file(v1_path).unwrap();
+    assert_eq!(load_strategy_from(path).unwrap(), nodes);
+
+    // A newer version than this build knows about
+    let saved = std::fs::read(path).unwrap();
+    let mut bytes = saved.clone();
+    bytes[4..8].copy_from_slice(&3u32.to_le_bytes());
+    std::fs::write(path, &bytes).unwrap();
+    match load_strategy_from(path).map_err(|e| e.source) {
+        Err(StrategyLoadErrorKind::VersionMismatch(3, STRATEGY_FORMAT_VERSION)) => {}
+        other => panic!("Expected a version mismatch, got {:?}", other),
+    }
+
+    // Right header, but cut off
+    std::fs::write(path, &saved[..10]).unwrap();
+    let result = load_strategy_from(path).map_err(|e| e.source);
+    std::fs::remove_file(path).unwrap();
+    match result {
+        Err(StrategyLoadErrorKind::CorruptData(_)) => {}
+        other => panic!("Expected corrupt data, got {:?}", other),
+    }
+
+    let error = load_strategy_from("products/no_such_strategy.bin").unwrap_err();
+    assert!(error.to_string().contains("products/no_such_strategy.bin"));
+    match error.source {
+        StrategyLoadErrorKind::FileNotFound => {}
+        other => panic!("Expected file not found, got {:?}", other),
+    }
+}
+
+// Puts every hand in the same bucket, so training tests don't need the
+// abstraction tables
+fn single_bucket_infoset(_: &[Card], history: &ActionHistory) -> InfoSet {
+    InfoSet::new(history.clone(), 0)
+}
+
+// Training, saving a checkpoint, loading it and training some more should
+// match training the whole way through
+#[test]
+fn test_checkpoint_resume() {
+    let variant = CFRVariant::DCFR(DCFRConfig::default());
+    let mut continuous: NodeMap = HashMap::new();
+    for i in 0..200 {
+        train_iteration_with(0, i, &mut continuous, variant, single_bucket_infoset);
+    }
+
+    let mut first_half: NodeMap = HashMap::new();
+    for i in 0..100 {
+        train_iteration_with(0, i, &mut first_half, variant, single_bucket_infoset);
+    }
+    let path = "products/test_checkpoint_resume.bin";
+    save_checkpoint(&first_half, 100, path).unwrap();
+    let (mut resumed, start) = load_checkpoint(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(start, 100);
+    for i in start..200 {
+        train_iteration_with(0, i, &mut resumed, variant, single_bucket_infoset);
+    }
+
+    // Action utilities get summed in HashMap order, so allow for rounding
+    assert_eq!(resumed.len(), continuous.len());
+    for (infoset, node) in &continuous {
+        let resumed_strategy = resumed[infoset].cumulative_strategy();
+        for (action, prob) in node.cumulative_strategy() {
+            assert!((resumed_strategy[&action] - prob).abs() < 1e-6);
+        }
+    }
+}
+
+#[test]
+fn test_average_strategies() {
+    let history = ActionHistory::new();
+    let actions = history.next_actions(bet_abstraction());
+    let n = actions.len() as f64;
+    let shared = CompactInfoSet::new(vec![], 0);
+    let only_first = CompactInfoSet::new(vec![], 1);
+
+    // The first run always folds, the second plays uniformly
+    let mut folder = Node::from_actions(&actions);
+    folder.add_regret(&FOLD, 10.0, CFRVariant::Vanilla);
+    folder.current_strategy(1.0, CFRVariant::Vanilla);
+    let mut uniform = Node::from_actions(&actions);
+    uniform.current_strategy(1.0, CFRVariant::Vanilla);
+
+    let mut run1: NodeMap = HashMap::new();
+    run1.insert(shared.clone(), folder.clone());
+    run1.insert(only_first.clone(), folder.clone());
+    let mut run2: NodeMap = HashMap::new();
+    run2.insert(shared.clone(), uniform.clone());
+
+    let averaged = average_strategies(&[run1, run2]);
+    assert_eq!(averaged.len(), 2);
+    // Missing nodes count as uniform, so both infosets average the same way
+    for infoset in &[shared, only_first] {
+        let strategy = averaged[infoset].cumulative_strategy();
+        for action in &actions {
+            let expected = if action == &FOLD {
+                0.5 + 0.5 / n
+            } else {
+                0.5 / n
+            };
+            assert!((strategy[action] - expected).abs() < 1e-9);
+        }
+        let total: f64 = strategy.values().sum();
+        assert!((total - 1.0).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn test_strategy_diff() {
+    let mut history = ActionHistory::new();
+    let actions = history.next_actions(bet_abstraction());
+    let n = actions.len() as f64;
+    let mut folder = Node::from_actions(&actions);
+    folder.add_regret(&FOLD, 10.0, CFRVariant::Vanilla);
+    folder.current_strategy(1.0, CFRVariant::Vanilla);
+    let mut uniform = Node::from_actions(&actions);
+    uniform.current_strategy(1.0, CFRVariant::Vanilla);
+
+    history.add(&actions[0]);
+    history.add(&Action {
+        action: ActionType::Call,
+        amount: history.to_call(),
+    });
+    let flop_actions = history.next_actions(bet_abstraction());
+    let mut flop_folder = Node::from_actions(&flop_actions);
+    flop_folder.add_regret(&flop_actions[0], 10.0, CFRVariant::Vanilla);
+    flop_folder.current_strategy(1.0, CFRVariant::Vanilla);
+    let flop_infoset = CompactInfoSet::new(history.compress(bet_abstraction()), 0);
+
+    let mut s1: NodeMap = HashMap::new();
+    s1.insert(CompactInfoSet::new(vec![], 0), folder.clone());
+    let mut s2: NodeMap = HashMap::new();
+    s2.insert(CompactInfoSet::new(vec![], 0), uniform.clone());
+    assert_eq!(strategy_diff(&s1, &s1), 0.0);
+
+    // Playing one action for sure is (n - 1) / n away from uniform, squared
+    let expected = ((n - 1.0) / n).sqrt();
+    assert!((strategy_diff(&s1, &s2) - expected).abs() < 1e-9);
+    assert!((strategy_diff(&s2, &s1) - expected).abs() < 1e-9);
+
+    // A node that only one side has is compared to uniform
+    s2.insert(flop_infoset, flop_folder);
+    let n_flop = flop_actions.len() as f64;
+    let by_street = strategy_diff_by_street(&s1, &s2);
+    assert!((by_street[PREFLOP] - expected).abs() < 1e-9);
+    assert!((by_street[FLOP] - ((n_flop - 1.0) / n_flop).sqrt()).abs() < 1e-9);
+    assert_eq!(by_street[TURN], 0.0);
+    let total: f64 = by_street.iter().map(|d| d * d).sum();
+    assert!((strategy_diff(&s1, &s2) - total.sqrt()).abs() < 1e-9);
+}
+
+#[test]
+fn test_strategy_csv_round_trip() {
+    // A few nodes along one line of play, each leaning toward a different action
+    let mut history = ActionHistory::new();
+    let mut nodes: NodeMap = HashMap::new();
+    for bucket in 0..4 {
+        let actions = history.next_actions(bet_abstraction());
+        let mut node = Node::from_actions(&actions);
+        node.add_regret(&actions[bucket as usize], 3.0, CFRVariant::Vanilla);
+        node.add_regret(&actions[actions.len() - 1], 1.0, CFRVariant::Vanilla);
+        node.current_strategy(1.0, CFRVariant::Vanilla);
+        nodes.insert(
+            CompactInfoSet::new(history.compress(bet_abstraction()), bucket),
+            node,
+        );
+        let call = Action {
+            action: ActionType::Call,
+            amount: history.to_call(),
+        };
+        history.add(&call);
+    }
+
+    let path = "products/test_strategy.csv";
+    export_strategy_csv(&nodes, path).unwrap();
+    let contents = std::fs::read_to_string(path).unwrap();
+    let lines: Vec<&str> = contents.lines().collect();
+    assert_eq!(lines.len(), nodes.len() + 1);
+    assert!(lines[0].starts_with("street,bucket,history,action1,action1_prob"));
+    assert!(lines[1].starts_with("0,0,\";;;;\",bet "));
+
+    let imported = import_strategy_csv(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(imported.len(), nodes.len());
+    for (infoset, node) in &nodes {
+        let expected = node.cumulative_strategy();
+        let strategy = imported[infoset].cumulative_strategy();
+        assert_eq!(strategy.len(), expected.len());
+        for (action, prob) in &expected {
+            assert!((strategy[action] - prob).abs() < 1e-12);
+        }
+    }
+    assert!(import_strategy_csv("products/no_such_strategy.csv").is_err());
+}
+
+#[test]
+fn test_exploitability_by_street() {
+    let preflop_bucket = |deck: &[Card], player: usize, _street: usize| {
+        preflop_bin_index(&get_hand(deck, player, PREFLOP)) as i32
+    };
+    let call_strategy = |_infoset: &CompactInfoSet, history: &ActionHistory| always_call(history);
+    let deals = vec![
+        strvec2cards(&["Ah", "Ad", "7c", "2d", "Ac", "Kc", "9h", "4s", "3d"]),
+        strvec2cards(&["7c", "2d", "Ah", "Ad", "Ac", "Kc", "9h", "4s", "3d"]),
+        strvec2cards(&["2c", "3d", "7h", "8c", "As", "Ks", "Qs", "Js", "Ts"]),
+    ];
+    let by_street = exploitability_by_street_on_deals(&deals, &preflop_bucket, &call_strategy);
+    let total = exploitability_on_deals(&deals, &preflop_bucket, &call_strategy);
+    assert!((by_street.total() - total).abs() < 1e-9);
+    assert!(total > 0.0);
+}
+
+#[test]
+fn test_pure_strategy() {
+    let mut history = ActionHistory::new();
+    let mut nodes: NodeMap = HashMap::new();
+    for bucket in 0..20 {
+        let actions = history.next_actions(bet_abstraction());
+        let mut node = Node::from_actions(&actions);
+        // Put most of the weight on one action, but keep the rest possible
+        let favorite = &actions[bucket as usize % actions.len()];
+        node.add_regret(favorite, 10.0, CFRVariant::Vanilla);
+        for action in &actions {
+            node.add_regret(action, 1.0, CFRVariant::Vanilla);
+        }
+        node.current_strategy(1.0, CFRVariant::Vanilla);
+        nodes.insert(CompactInfoSet::new(vec![], bucket), node);
+        if bucket % 5 == 4 {
+            history.add(&actions[0]);
+        }
+    }
+
+    let pure = to_pure_strategy(&nodes);
+    assert_eq!(pure.len(), nodes.len());
+    for (infoset, action) in &pure {
+        let strategy = nodes[infoset].cumulative_strategy();
+        assert!(strategy[action] > 0.0);
+        assert!(strategy.values().all(|p| *p <= strategy[action]));
+    }
+
+    // With epsilon = 1 every pick is random, so every action shows up
+    let mut rng = StdRng::seed_from_u64(0);
+    let infoset = CompactInfoSet::new(vec![], 0);
+    let n_actions = nodes[&infoset].cumulative_strategy().len();
+    let mut seen = std::collections::HashSet::new();
+    for _ in 0..200 {
+        let pure = to_pure_strategy_eps_greedy(&nodes, 1.0, &mut rng);
+        seen.insert(pure[&infoset].clone());
+    }
+    assert_eq!(seen.len(), n_actions);
+    // And with epsilon = 0 it's the same as the pure strategy
+    assert_eq!(
+        to_pure_strategy_eps_greedy(&nodes, 0.0, &mut rng),
+        to_pure_strategy(&nodes)
+    );
+}
+
+#[test]
+fn test_hand_categories() {
+    let details = |hand: &str| hand_details(&str2cards(hand).unwrap());
+    let check = |hand: &str, category: HandCategory, kickers: Vec<u8>| {
+        let d = details(hand);
+        assert_eq!(d.category, category, "{}", hand);
+        assert_eq!(d.kickers, kickers, "{}", hand);
+        assert_eq!(hand_category(&str2cards(hand).unwrap()), category);
+    };
+    check(
+        "Ac9d4hKs7c2d3h",
+        HandCategory::HighCard,
+        vec![14, 13, 9, 7, 4],
+    );
+    check("KcKd4hAs9c2d3h", HandCategory::Pair, vec![13, 14, 9, 4]);
+    check("KcKd4h4s9c9d3h", HandCategory::TwoPair, vec![13, 9, 4]);
+    check("7c7d7hAs9c2d3h", HandCategory::ThreeOfAKind, vec![7, 14, 9]);
+    check("Ac2d3h4s5cKdKh", HandCategory::Straight, vec![5]);
+    check("9cTdJhQsKcAd2h", HandCategory::Straight, vec![14]);
+    check("Ac9c4c2c7cKdKh", HandCategory::Flush, vec![14, 9, 7, 4, 2]);
+    check("7c7d7h9s9c2d2h", HandCategory::FullHouse, vec![7, 9]);
+    check("7c7d7h7s9c2d2h", HandCategory::FourOfAKind, vec![7, 9]);
+    check("5h6h7h8h9hAcAd", HandCategory::StraightFlush, vec![9]);
+    check("ThJhQhKhAh2c3d", HandCategory::RoyalFlush, vec![14]);
+
+    // The categories line up with the strength ordering
+    let pair = details("KcKd4hAs9c2d3h");
+    let two_pair = details("KcKd4h4s9c9d3h");
+    assert!(pair.strength < two_pair.strength);
+    assert!(pair.category < two_pair.category);
+    assert_eq!(category_from_strength(0), HandCategory::HighCard);
+    assert_eq!(category_from_strength(134458), HandCategory::RoyalFlush);
+}
+
+// The 7-card table only knows 7-card hands, so smaller ones have to be looked
+// up in the 5-card table
+#[test]
+fn test_any_size_strength() {
+    let seven = str2cards("KcKd4hAs9c2d3h").unwrap();
+    let mut strengths = HandData::new();
+    strengths.insert(
+        &canonical_hand_id(&seven, false),
+        five_card_hand_strength(&seven),
+    );
+    let table = HandTable::from_hand_data(strengths);
+    assert_eq!(table.any_size_strength(&seven), table.hand_strength(&seven));
+    for n in 5..7 {
+        let strength = table.any_size_strength(&seven[..n]);
+        assert_eq!(strength, five_card_hand_strength(&seven[..n]));
+        assert_eq!(category_from_strength(strength), HandCategory::Pair);
+    }
+    assert_eq!(hand_category(&seven[..5]), HandCategory::Pair);
+    assert_eq!(hand_details(&seven[..6]).kickers, vec![13, 14, 9, 4]);
+}
+
+// The 7-card table gets bootstrapped from the 5-card one, so reading back what
+// it writes has to give the same strengths
+#[test]
+fn test_write_river_strengths() {
+    let light_table = LightHandTable::new();
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut deck = deck();
+    let hands: Vec<Vec<Card>> = (0..200)
+        .map(|_| deck.partial_shuffle(&mut rng, 7).0.to_vec())
+        .collect();
+    let canonical: HashSet<u64> = hands
+        .iter()
+        .map(|hand| canonical_hand_id(hand, false))
+        .collect();
+    let path = "products/test_river_strengths.txt";
+    write_river_strengths(&canonical, &light_table, path);
+    let table = HandTable::from_hand_data(HandData::read_serialized(
+        std::fs::File::open(path).unwrap(),
+    ));
+    for hand in &hands {
+        assert_eq!(table.hand_strength(hand), light_table.hand_strength(hand));
+    }
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_hand_evaluation() {
+    let evaluate = |hand: &str| hand_evaluation(&str2cards(hand).unwrap());
+    let pair = evaluate("KcKd4hAs9c2d3h");
+    assert_eq!(pair.category, HandCategory::Pair);
+    assert_eq!(pair.primary_ranks, vec![13]);
+    assert_eq!(pair.kicker_ranks, vec![14, 9, 4]);
+    assert_eq!(pair.to_string(), "Pair of Kings, A-9-4 kickers");
+
+    let flush = evaluate("AcKcQc9c7c2d2h");
+    assert_eq!(flush.primary_ranks, vec![14]);
+    assert_eq!(flush.kicker_ranks, vec![13, 12, 9, 7]);
+    assert_eq!(flush.to_string(), "Flush, Ace-high, K-Q-9-7 kickers");
+
+    let two_pair = evaluate("KcKd4h4s9c9d3h");
+    assert_eq!(two_pair.to_string(), "Two pair, Kings and Nines, 4 kicker");
+    let boat = evaluate("6c6d6h9s9c2d2h");
+    assert_eq!(boat.to_string(), "Full house, Sixes full of Nines");
+    assert!(boat.kicker_ranks.is_empty());
+    assert_eq!(
+        evaluate("Ac2d3h4s5cKdKh").to_string(),
+        "Straight, Five-high"
+    );
+    let details = hand_details(&str2cards("6c6d6h9s9c2d2h").unwrap());
+    assert_eq!(boat.strength, details.strength);
+
+    // 5 and 6-card hands work with the 7-card table too
+    let seven = str2cards("6c6d6h9s9c2d3h").unwrap();
+    let mut strengths = HandData::new();
+    strengths.insert(
+        &canonical_hand_id(&seven, false),
+        five_card_hand_strength(&seven),
+    );
+    let table = HandTable::from_hand_data(strengths);
+    let seven_eval = hand_evaluation_with(&table, &seven);
+    for n in 5..7 {
+        let eval = hand_evaluation_with(&table, &seven[..n]);
+        assert_eq!(eval.strength, seven_eval.strength);
+        assert_eq!(eval.to_string(), "Full house, Sixes full of Nines");
+    }
+}
+
+#[test]
+fn test_pot_size() {
+    let call = |amount| Action {
+        action: ActionType::Call,
+        amount: amount,
+    };
+    let bet = |amount| Action {
+        action: ActionType::Bet,
+        amount: amount,
+    };
+
+    // Only the blinds are in
+    let mut history = ActionHistory::new();
+    assert_eq!(history.pot_size(), SMALL_BLIND + BIG_BLIND);
+    assert_eq!(history.effective_stack(), STACK_SIZE - BIG_BLIND);
+
+    // Limp and check, then bet and call on the flop. The big blind isn't
+    // taken out of the stack until the opponent acts, so checking it is a call.
+    history.add(&call(BIG_BLIND));
+    history.add(&call(BIG_BLIND));
+    assert_eq!(history.street, FLOP);
+    assert_eq!(history.pot_size(), 200);
+    history.add(&bet(150));
+    history.add(&call(150));
+    assert_eq!(history.pot_size(), 500);
+    assert_eq!(history.effective_stack(), STACK_SIZE - 250);
+
+    // Raise and reraise preflop, then call
+    let mut history = ActionHistory::new();
+    history.add(&bet(300));
+    assert_eq!(history.pot_size(), 400);
+    history.add(&bet(900));
+    history.add(&call(600));
+    assert_eq!(history.pot_size(), 1800);
+    assert_eq!(history.effective_stack(), STACK_SIZE - 900);
+
+    // The small blind stays in the pot when the dealer folds
+    let mut history = ActionHistory::new();
+    history.add(&FOLD);
+    assert_eq!(history.pot_size(), SMALL_BLIND + BIG_BLIND);
+
+    // A shove that gets folded to only has the big blind matched
+    let mut history = ActionHistory::new();
+    history.add(&bet(STACK_SIZE));
+    assert_eq!(history.pot_size(), STACK_SIZE + BIG_BLIND);
+    history.add(&FOLD);
+    assert_eq!(history.pot_size(), STACK_SIZE + BIG_BLIND);
+}
+
+#[test]
+fn test_posted_blinds() {
+    let call = |amount| Action {
+        action: ActionType::Call,
+        amount: amount,
+    };
+    let bet = |amount| Action {
+        action: ActionType::Bet,
+        amount: amount,
+    };
+
+    // The small blind only has to complete, and then the big blind can check
+    let mut history = ActionHistory::with_blinds(SMALL_BLIND, BIG_BLIND);
+    assert_eq!(history.player, DEALER);
+    assert_eq!(history.to_call(), BIG_BLIND - SMALL_BLIND);
+    assert_eq!(history.pot_size(), SMALL_BLIND + BIG_BLIND);
+    assert_eq!(history.min_bet(), 150);
+    assert!(history.legal_actions().contains(&FOLD));
+    history.add(&call(BIG_BLIND - SMALL_BLIND));
+    assert_eq!(history.street, PREFLOP);
+    assert_eq!(history.player, OPPONENT);
+    assert_eq!(history.to_call(), 0);
+    assert!(!history.legal_actions().contains(&FOLD));
+    history.add(&call(0));
+    assert_eq!(history.street, FLOP);
+    assert_eq!(history.pot_size(), 2 * BIG_BLIND);
+    assert_eq!(history.effective_stack(), STACK_SIZE - BIG_BLIND);
+
+    // Raise to 3 BB and call
+    let mut history = ActionHistory::with_blinds(SMALL_BLIND, BIG_BLIND);
+    history.add(&bet(250));
+    assert_eq!(history.to_call(), 200);
+    history.add(&call(200));
+    assert_eq!(history.street, FLOP);
+    assert_eq!(history.pot_size(), 600);
+
+    // The small blind is dead when the dealer folds
+    let mut history = ActionHistory::with_blinds(SMALL_BLIND, BIG_BLIND);
+    history.add(&FOLD);
+    assert!(history.hand_over());
+    assert_eq!(history.pot_size(), SMALL_BLIND + BIG_BLIND);
+
+    // Translation keeps the blinds. The closest bet to 260 is 2x the 150 pot,
+    // on top of the small blind.
+    let mut history = ActionHistory::with_blinds(SMALL_BLIND, BIG_BLIND);
+    history.add(&bet(260));
+    let translated = history.translate(bet_abstraction());
+    assert_eq!(translated.last_action(), Some(bet(300)));
+    assert_eq!(translated.pot_size(), SMALL_BLIND + 300 + BIG_BLIND);
+
+    // Without posted blinds the first call is the whole big blind, as before
+    assert_eq!(ActionHistory::new().to_call(), BIG_BLIND);
+}
+
+// A whole hand, preflop to the river, should come back the same from JSON
+#[test]
+fn test_action_history_json() {
+    let call = |amount| Action {
+        action: ActionType::Call,
+        amount: amount,
+    };
+    let bet = |amount| Action {
+        action: ActionType::Bet,
+        amount: amount,
+    };
+    for &blinds in &[false, true] {
+        let mut history = if blinds {
+            ActionHistory::with_blinds(SMALL_BLIND, BIG_BLIND)
+        } else {
+            ActionHistory::new()
+        };
+        for amount in &[
+            Some(300),
+            None,
+            None,
+            Some(400),
+            None,
+            Some(500),
+            None,
+            None,
+        ] {
+            match amount {
+                Some(amount) => history.add(&bet(*amount)),
+                None => history.add(&call(history.to_call())),
+            }
+        }
+        history.add(&bet(1000));
+        assert_eq!(history.street, RIVER);
+
+        let json = serde_json::to_string(&history).unwrap();
+        let parsed: ActionHistory = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, history);
+        assert_eq!(parsed.to_call(), 1000);
+        let mut finished = parsed.clone();
+        finished.add(&call(1000));
+        assert!(finished.hand_over());
+
+        let infoset = InfoSet::new(history, 17);
+        let json = serde_json::to_string(&infoset).unwrap();
+        assert_eq!(serde_json::from_str::<InfoSet>(&json).unwrap(), infoset);
+        let action_json = serde_json::to_string(&bet(1000)).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Action>(&action_json).unwrap(),
+            bet(1000)
+        );
+    }
+
+    // Histories saved before the blinds could be posted still load
+    let json = serde_json::to_value(&ActionHistory::new()).unwrap();
+    let mut old = json.as_object().unwrap().clone();
+    old.remove("posted_blinds");
+    let parsed: ActionHistory = serde_json::from_value(serde_json::Value::Object(old)).unwrap();
+    assert_eq!(parsed, ActionHistory::new());
+
+    let variant = CFRVariant::DCFR(DCFRConfig::default());
+    let json = serde_json::to_string(&variant).unwrap();
+    assert_eq!(serde_json::from_str::<CFRVariant>(&json).unwrap(), variant);
+    let bets = BetAbstraction::default();
+    let json = serde_json::to_string(&bets).unwrap();
+    assert_eq!(serde_json::from_str::<BetAbstraction>(&json).unwrap(), bets);
+}
+
+#[test]
+fn test_legal_actions() {
+    let bet = |amount| Action {
+        action: ActionType::Bet,
+        amount: amount,
+    };
+    let history = ActionHistory::new();
+    let legal = history.legal_actions();
+    assert!(legal.contains(&FOLD));
+    assert!(legal.contains(&Action {
+        action: ActionType::Call,
+        amount: BIG_BLIND,
+    }));
+    assert!(legal.contains(&bet(STACK_SIZE)));
+    for action in &legal {
+        if action.action == ActionType::Bet {
+            assert!(history.min_bet() <= action.amount && action.amount <= STACK_SIZE);
+        }
+    }
+
+    // Facing a big bet, the only raise left is all-in
+    let mut history = ActionHistory::new();
+    history.add(&bet(15_000));
+    let legal = history.legal_actions();
+    assert_eq!(
+        legal,
+        vec![
+            bet(STACK_SIZE),
+            Action {
+                action: ActionType::Call,
+                amount: 15_000
+            },
+            FOLD
+        ]
+    );
+
+    // After checking there's nothing to fold to
+    let mut history = ActionHistory::new();
+    history.add(&Action {
+        action: ActionType::Call,
+        amount: BIG_BLIND,
+    });
+    history.add(&Action {
+        action: ActionType::Call,
+        amount: BIG_BLIND,
+    });
+    let legal = history.legal_actions();
+    assert!(!legal.contains(&FOLD));
+    assert!(legal.contains(&Action {
+        action: ActionType::Call,
+        amount: 0
+    }));
+}
+
+#[test]
+fn test_infoset_accessors() {
+    let infoset = |history: &ActionHistory| {
+        CompactInfoSet::new(history.compress(bet_abstraction()), 0).uncompress()
+    };
+    let mut history = ActionHistory::new();
+    let start = infoset(&history);
+    assert_eq!(start.street(), PREFLOP as u8);
+    assert_eq!(start.acting_player(), DEALER as u8);
+    assert_eq!(start.num_actions_this_street(), 0);
+
+    // Raise, then the big blind calls, which ends the preflop
+    history.add(&history.next_actions(bet_abstraction())[0]);
+    assert_eq!(infoset(&history).acting_player(), OPPONENT as u8);
+    assert_eq!(infoset(&history).num_actions_this_street(), 1);
+    history.add(&Action {
+        action: ActionType::Call,
+        amount: history.to_call(),
+    });
+    let flop = infoset(&history);
+    assert_eq!(flop.street(), FLOP as u8);
+    // The opponent acts first after the preflop
+    assert_eq!(flop.acting_player(), OPPONENT as u8);
+    assert_eq!(flop.num_actions_this_street(), 0);
+
+    history.add(&Action {
+        action: ActionType::Call,
+        amount: 0,
+    });
+    let after_check = infoset(&history);
+    assert_eq!(after_check.street(), FLOP as u8);
+    assert_eq!(after_check.acting_player(), DEALER as u8);
+    assert_eq!(after_check.num_actions_this_street(), 1);
+}
+
+#[derive(Default)]
+struct CountingCallback {
+    iterations: Vec<u64>,
+    checkpoints: Vec<String>,
+}
+
+impl TrainingCallback for CountingCallback {
+    fn on_iteration(&mut self, iteration: u64, strategy: &NodeMap) {
+        assert!(!strategy.is_empty());
+        self.iterations.push(iteration);
+    }
+
+    fn on_checkpoint_saved(&mut self, path: &str) {
+        self.checkpoints.push(path.to_string());
+    }
+}
+
+#[test]
+fn test_training_callback() {
+    let mut callback = CountingCallback::default();
+    let mut nodes: NodeMap = HashMap::new();
+    let path = "products/test_training_callback.bin";
+    train_iterations_with(
+        &mut nodes,
+        0..10,
+        CFRVariant::CFRPlus,
+        0,
+        Some(path),
+        4,
+        &mut callback,
+        single_bucket_infoset,
+    );
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(callback.iterations, (1..=10).collect::<Vec<u64>>());
+    assert_eq!(callback.checkpoints, vec![path, path]);
+}
+
+#[test]
+#[should_panic]
+fn test_exploitability_callback_zero_interval() {
+    ExploitabilityCallback::new(0);
+}
+
+// Checks that two runs ended up with the same nodes. Regrets and utilities get
+// summed in HashMap order, so this allows for rounding.
+fn assert_same_training(a: (&NodeMap, [f64; 2]), b: (&NodeMap, [f64; 2])) {
+    let ((a_nodes, a_utils), (b_nodes, b_utils)) = (a, b);
+    for player in 0..2 {
+        let diff = a_utils[player] - b_utils[player];
+        assert!(diff.abs() <= 1e-9 * a_utils[player].abs().max(1.0));
+    }
+    assert_eq!(a_nodes.len(), b_nodes.len());
+    for (infoset, node) in a_nodes {
+        assert_eq!(b_nodes[infoset].t, node.t);
+        let b_strategy = b_nodes[infoset].cumulative_strategy();
+        for (action, prob) in node.cumulative_strategy() {
+            assert!((b_strategy[&action] - prob).abs() < 1e-6);
+        }
+    }
+}
+
+// The result shouldn't depend on the number of threads, and with one deal per
+// batch it should be the same as training sequentially
+#[test]
+fn test_train_parallel() {
+    let mut one_thread: NodeMap = HashMap::new();
+    let mut callback = CountingCallback::default();
+    let one_thread_utils = train_parallel_iterations_with(
+        &mut one_thread,
+        0..200,
+        0,
+        1,
+        64,
+        &mut callback,
+        single_bucket_infoset,
+    );
+    assert_eq!(callback.iterations, vec![64, 128, 192, 200]);
+
+    let mut parallel: NodeMap = HashMap::new();
+    let mut callback = CountingCallback::default();
+    let parallel_utils = train_parallel_iterations_with(
+        &mut parallel,
+        0..200,
+        0,
+        4,
+        64,
+        &mut callback,
+        single_bucket_infoset,
+    );
+    assert_same_training((&one_thread, one_thread_utils), (&parallel, parallel_utils));
+
+    let mut sequential: NodeMap = HashMap::new();
+    let mut callback = CountingCallback::default();
+    let sequential_utils = train_iterations_with(
+        &mut sequential,
+        0..50,
+        CFRVariant::Vanilla,
+        0,
+        None,
+        1,
+        &mut callback,
+        single_bucket_infoset,
+    );
+    let mut parallel: NodeMap = HashMap::new();
+    let mut callback = CountingCallback::default();
+    let parallel_utils = train_parallel_iterations_with(
+        &mut parallel,
+        0..50,
+        0,
+        4,
+        1,
+        &mut callback,
+        single_bucket_infoset,
+    );
+    assert_same_training((&sequential, sequential_utils), (&parallel, parallel_utils));
+}
+
+#[test]
+fn test_training_config_builder() {
+    let config = TrainingConfig::builder().build();
+    assert_eq!(config, TrainingConfig::default());
+    assert_eq!(config.checkpoint_path, None);
+
+    let config = TrainingConfig::builder()
+        .algorithm(CFRAlgorithm::CFRPlus)
+        .iterations(500)
+        .checkpoint_path("products/test.bin")
+        .checkpoint_interval(100)
+        .seed(7)
+        .num_threads(2)
+        .build();
+    assert_eq!(config.algorithm, CFRAlgorithm::CFRPlus);
+    assert_eq!(config.iterations, 500);
+    assert_eq!(
+        config.checkpoint_path,
+        Some("products/test.bin".to_string())
+    );
+    assert_eq!(config.checkpoint_interval, 100);
+    assert_eq!(config.seed, 7);
+    assert_eq!(config.num_threads, 2);
+    assert_eq!(config.validate(), Ok(()));
+
+    let mut bad = config.clone();
+    bad.checkpoint_interval = 0;
+    assert_eq!(
+        bad.validate(),
+        Err(TrainingConfigError::ZeroCheckpointInterval)
+    );
+    let mut bad = config.clone();
+    bad.algorithm = CFRAlgorithm::OutcomeSampling(0.0);
+    assert_eq!(bad.validate(), Err(TrainingConfigError::BadEpsilon(0.0)));
+}
+
+#[test]
+#[should_panic]
+fn test_training_config_builder_zero_interval() {
+    TrainingConfig::builder().checkpoint_interval(0).build();
+}
+
+#[test]
+fn test_bet_abstraction_file() {
+    let dir = std::env::temp_dir();
+    let path = dir.join("test_bet_abstraction.toml");
+    let path = path.to_str().unwrap();
+
+    std::fs::write(
+        path,
+        "preflop = [2.0, 3.0, -1.0]\nflop = [0.5, 1.0]\nturn = [1.0]\nriver = [-1.0]\n",
+    )
+    .unwrap();
+    let bets = BetAbstraction::load_from_file(path).unwrap();
+    assert_eq!(bets.street(PREFLOP), &[2.0, 3.0, ALL_IN]);
+    assert_eq!(bets.street(FLOP), &[0.5, 1.0]);
+    assert_eq!(bets.street(RIVER), &[ALL_IN]);
+    // Opening raises are sized off the big blind in the pot
+    let actions = ActionHistory::new().next_actions(&bets);
+    let amounts: Vec<i32> = actions.iter().map(|a| a.amount).collect();
+    assert_eq!(amounts, vec![200, 300, STACK_SIZE, BIG_BLIND, 0]);
+    // Deep CFR's networks get an output for each of these
+    assert_eq!(bets.max_actions(), actions.len());
+    assert_eq!(BetAbstraction::default().max_actions(), 8);
+
+    std::fs::write(path, "preflop = [0.0]\nflop = []\nturn = []\nriver = []\n").unwrap();
+    match BetAbstraction::load_from_file(path) {
+        Err(BetAbstractionError::BadFraction(x)) => assert_eq!(x, 0.0),
+        other => panic!("Expected a bad fraction, got {:?}", other),
+    }
+    std::fs::write(path, "preflop = [1.0]\n").unwrap();
+    match BetAbstraction::load_from_file(path) {
+        Err(BetAbstractionError::Parse(_)) => {}
+        other => panic!("Expected a parse error, got {:?}", other),
+    }
+    std::fs::remove_file(path).unwrap();
+    match BetAbstraction::load_from_file(path) {
+        Err(BetAbstractionError::Io(_)) => {}
+        other => panic!("Expected an I/O error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_config_file() {
+    let path = std::env::temp_dir().join("test_config.toml");
+    let path = path.to_str().unwrap();
+
+    std::fs::write(
+        path,
+        "[paths]
+hand_table = \"/data/strengths7.txt\"
+bet_abstraction = \"bets/wide.toml\"
+
+[training]
+iterations = 5000
+algorithm = \"CFRPlus\"
+
+[server]
+port = 8080
+",
+    )
+    .unwrap();
+    let config = Config::load(path).unwrap();
+    let defaults = Config::default();
+    assert_eq!(config.paths.hand_table, "/data/strengths7.txt");
+    assert_eq!(config.paths.equity_table, defaults.paths.equity_table);
+    // card_utils gets its paths from the config
+    assert_eq!(
+        config.paths.table_paths().hand_table,
+        "/data/strengths7.txt"
+    );
+    assert_eq!(defaults.paths.table_paths(), TablePaths::default());
+    assert_eq!(config.training.iterations, 5000);
+    assert_eq!(config.training.algorithm, CFRAlgorithm::CFRPlus);
+    // Settings missing from the table keep their defaults too
+    assert_eq!(
+        config.training.checkpoint_path,
+        defaults.training.checkpoint_path
+    );
+    assert_eq!(
+        config.training.checkpoint_interval,
+        defaults.training.checkpoint_interval
+    );
+    assert_eq!(config.server.address(), "127.0.0.1:8080");
+    assert_eq!(config.paths.bet_abstraction, "bets/wide.toml");
+
+    // An empty file is the defaults, which match the old hard-coded settings
+    std::fs::write(path, "").unwrap();
+    let config = Config::load(path).unwrap();
+    assert_eq!(config, defaults);
+    assert_eq!(config.paths.hand_table, "products/strengths7.txt");
+    assert_eq!(config.server.address(), "127.0.0.1:8000");
+    assert_eq!(
+        config.training.checkpoint_path.as_deref(),
+        Some("products/checkpoint.bin")
+    );
+
+    // A zero interval would panic at the first checkpoint
+    std::fs::write(path, "[training]\ncheckpoint_interval = 0\n").unwrap();
+    match Config::load(path) {
+        Err(ConfigError::Training(TrainingConfigError::ZeroCheckpointInterval)) => {}
+        other => panic!("Expected a zero interval error, got {:?}", other),
+    }
+    std::fs::write(path, "[server]\nport = \"eighty\"\n").unwrap();
+    match Config::load(path) {
+        Err(ConfigError::Parse(_)) => {}
+        other => panic!("Expected a parse error, got {:?}", other),
+    }
+    std::fs::remove_file(path).unwrap();
+    match Config::load(path) {
+        Err(ConfigError::Io(_)) => {}
+        other => panic!("Expected an I/O error, got {:?}", other),
+    }
+}
+
+const HEADS_UP_HISTORY: &str =
+    "PokerStars Hand #208959234567:  Hold'em No Limit ($0.01/$0.02 USD) - 2020/01/15 12:34:56 ET
+Table 'Aludra III' 2-max Seat #1 is the button
+Seat 1: Alice ($2 in chips)
+Seat 2: Bob Smith ($2.14 in chips)
+Alice: posts small blind $0.01
+Bob Smith: posts big blind $0.02
+*** HOLE CARDS ***
+Dealt to Alice [Ah Kd]
+Alice: raises $0.04 to $0.06
+Bob Smith: calls $0.04
+*** FLOP *** [7c 2d 9s]
+Bob Smith: checks
+Alice: bets $0.08
+Bob Smith: raises $0.20 to $0.28
+Alice: calls $0.20
+*** TURN *** [7c 2d 9s] [Jh]
+Bob Smith: bets $1.80 and is all-in
+Alice: calls $1.66 and is all-in
+Uncalled bet ($0.14) returned to Bob Smith
+*** RIVER *** [7c 2d 9s Jh] [4c]
+*** SHOW DOWN ***
+Bob Smith: shows [9h 9c] (three of a kind, Nines)
+Alice: mucks hand
+Bob Smith collected $3.98 from pot
+*** SUMMARY ***
+Total pot $4 | Rake $0.02
+Board [7c 2d 9s Jh 4c]
+Seat 1: Alice (button) (small blind) mucked [Ah Kd]
+Seat 2: Bob Smith (big blind) showed [9h 9c] and won ($3.98) with three of a kind, Nines
+
+";
+
+const SIDE_POT_HISTORY: &str = concat!(
+    "PokerStars Hand #208959234568: Tournament #2861234567, $1+$0.10 USD Hold'em No Limit - ",
+    "Level I (10/20) - 2020/01/15 12:40:00 ET
+Table '2861234567 1' 9-max Seat #3 is the button
+Seat 1: Carol (500 in chips)
+Seat 2: Dave (1500 in chips)
+Seat 3: Erin (1500 in chips)
+Carol: posts small blind 10
+Dave: posts big blind 20
+*** HOLE CARDS ***
+Erin: raises 40 to 60
+Carol: raises 440 to 500 and is all-in
+Dave: raises 1000 to 1500 and is all-in
+Erin: calls 1440 and is all-in
+*** FLOP *** [2c 3c 4c]
+*** TURN *** [2c 3c 4c] [Kd]
+*** RIVER *** [2c 3c 4c Kd] [Ks]
+*** SHOW DOWN ***
+Dave: shows [As Ah] (two pair, Aces and Kings)
+Erin: shows [Qs Qh] (two pair, Queens and Kings)
+Carol: shows [Jd Jh] (two pair, Kings and Jacks)
+Dave collected 2000 from side pot
+Dave collected 1500 from main pot
+*** SUMMARY ***
+Total pot 3500 Main pot 1500. Side pot 2000. | Rake 0
+Board [2c 3c 4c Kd Ks]
+Seat 1: Carol (small blind) showed [Jd Jh] and lost with two pair, Kings and Jacks
+Seat 2: Dave (big blind) showed [As Ah] and won (3500) with two pair, Aces and Kings
+Seat 3: Erin (button) showed [Qs Qh] and lost with two pair, Queens and Kings
+"
+);
+
+#[test]
+fn test_parse_pokerstars() {
+    let text = format!("{}{}", HEADS_UP_HISTORY, SIDE_POT_HISTORY);
+    let hands = parse_pokerstars(&text).unwrap();
+    assert_eq!(hands.len(), 2);
+
+    let hand = &hands[0];
+    assert_eq!(hand.id, 208959234567);
+    assert_eq!(hand.date_time, "2020/01/15 12:34:56 ET");
+    assert_eq!((hand.small_blind, hand.big_blind), (0.01, 0.02));
+    assert_eq!(hand.button_seat, 1);
+    assert_eq!(hand.players.len(), 2);
+    let bob = hand.player("Bob Smith").unwrap();
+    assert_eq!(bob.stack, 2.14);
+    assert_eq!(bob.hole_cards, Some(strvec2cards(&["9h", "9c"])));
+    // Alice mucked, but her cards are known from the summary
+    let alice = hand.player("Alice").unwrap();
+    assert_eq!(alice.hole_cards, Some(strvec2cards(&["Ah", "Kd"])));
+    assert_eq!(hand.board[FLOP], strvec2cards(&["7c", "2d", "9s"]));
+    assert_eq!(hand.board[TURN], strvec2cards(&["Jh"]));
+    assert_eq!(hand.board[RIVER], strvec2cards(&["4c"]));
+    assert_eq!(
+        hand.actions[2],
+        PlayerAction {
+            street: PREFLOP,
+            player: "Alice".to_string(),
+            action: HandAction::Raise { by: 0.04, to: 0.06 },
+            all_in: false,
+        }
+    );
+    let shove = &hand.actions[8];
+    assert_eq!(shove.street, TURN);
+    assert_eq!(shove.action, HandAction::Bet(1.80));
+    assert!(shove.all_in);
+    assert_eq!(
+        hand.actions[10].action,
+        HandAction::UncalledBetReturned(0.14)
+    );
+    assert_eq!((hand.total_pot, hand.rake), (4.0, 0.02));
+    assert_eq!(hand.winners, vec![("Bob Smith".to_string(), 3.98)]);
+
+    let hand = &hands[1];
+    assert_eq!((hand.small_blind, hand.big_blind), (10.0, 20.0));
+    assert_eq!(hand.players.len(), 3);
+    assert_eq!(hand.pots, vec![1500.0, 2000.0]);
+    assert_eq!(hand.total_pot, 3500.0);
+    assert_eq!(
+        hand.winners,
+        vec![("Dave".to_string(), 2000.0), ("Dave".to_string(), 1500.0)]
+    );
+    assert!(hand.players.iter().all(|p| p.hole_cards.is_some()));
+
+    let bad_seat = HEADS_UP_HISTORY.replace("($2 in chips)", "(two dollars)");
+    assert_eq!(
+        parse_pokerstars(&bad_seat),
+        Err(ParseError::InvalidLine(
+            3,
+            "Seat 1: Alice (two dollars)".to_string()
+        ))
+    );
+}
+
+#[test]
+fn test_hand_history_to_action_history() {
+    let hand = &parse_pokerstars(HEADS_UP_HISTORY).unwrap()[0];
+    let history = hand.to_action_history();
+    // Everything is scaled up by 5000, so the big blind is our BIG_BLIND
+    let bet = |amount| Action {
+        action: ActionType::Bet,
+        amount: amount,
+    };
+    let call = |amount| Action {
+        action: ActionType::Call,
+        amount: amount,
+    };
+    let mut expected = ActionHistory::new();
+    for action in &[
+        bet(300),
+        call(300),
+        call(0),
+        bet(400),
+        bet(1400),
+        call(1000),
+        bet(9000),
+        call(9000),
+    ] {
+        expected.add(action);
+    }
+    assert_eq!(history, expected);
+    // The real stacks were only 100 big blinds, so with our deeper stacks the
+    // hand isn't over after the turn shove
+    assert_eq!(history.street, RIVER);
+}
+
+// Stands in for the blueprint, which isn't always built
+fn uniform_legal_strategy(
+    _hand: &[Card],
+    _board: &[Card],
+    history: &ActionHistory,
+) -> HashMap<Action, f64> {
+    let legal = history.legal_actions();
+    let prob = 1.0 / legal.len() as f64;
+    legal.into_iter().map(|a| (a, prob)).collect()
+}
+
+fn recorded_hand() -> RecordedHand {
+    let cards = strvec2cards(&["Ah", "Ad", "7c", "2d"]);
+    let hole_cards = [
+        [cards[0].clone(), cards[1].clone()],
+        [cards[2].clone(), cards[3].clone()],
+    ];
+    let players = [String::from("hero"), String::from("villain")];
+    RecordedHand::new(
+        7,
+        1_600_000_000,
+        [50, 100],
+        players,
+        [10000, 10000],
+        hole_cards,
+    )
+}
+
+#[test]
+fn test_recorded_hand() {
+    let action = |action: ActionType, amount: i32| Action {
+        action: action,
+        amount: amount,
+    };
+    let mut hand = recorded_hand();
+    assert_eq!(hand.pot, 150);
+    hand.apply_action(DEALER as u8, action(ActionType::Call, 50));
+    hand.apply_action(OPPONENT as u8, action(ActionType::Call, 0));
+    let flop = strvec2cards(&["Kc", "9h", "4s"]);
+    hand.deal_flop([flop[0].clone(), flop[1].clone(), flop[2].clone()]);
+    hand.apply_action(OPPONENT as u8, action(ActionType::Bet, 100));
+    hand.apply_action(DEALER as u8, action(ActionType::Call, 100));
+    hand.deal_turn(strvec2cards(&["3d"])[0].clone());
+    hand.deal_river(strvec2cards(&["Js"])[0].clone());
+    assert_eq!(hand.pot, 400);
+    assert_eq!(hand.committed(), [200, 200]);
+    hand.showdown();
+    assert_eq!(hand.winner, Some(DEALER as u8));
+    assert_eq!(hand.net_result, [200, -200]);
+
+    let json = serde_json::to_string(&hand).unwrap();
+    let loaded: RecordedHand = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded, hand);
+
+    // The dealer's raise isn't called, so they only win the big blind
+    let mut hand = recorded_hand();
+    hand.apply_action(DEALER as u8, action(ActionType::Bet, 250));
+    hand.apply_action(OPPONENT as u8, action(ActionType::Fold, 0));
+    assert_eq!(hand.winner, Some(DEALER as u8));
+    assert_eq!(hand.net_result, [100, -100]);
+}
+
+#[test]
+fn test_hand_replayer() {
+    let hand = parse_pokerstars(HEADS_UP_HISTORY).unwrap().remove(0);
+    let expected = hand.to_action_history();
+    let mut replayer = HandReplayer::with_strategy(hand, uniform_legal_strategy);
+    assert_eq!(replayer.len(), 8);
+
+    let mut states = vec![replayer.current_state()];
+    while !replayer.is_finished() {
+        let bot_action = replayer.bot_action_at_current_state();
+        let before = replayer.current_state();
+        assert!(before.legal_actions().contains(&bot_action));
+
+        let step = replayer.next_action().unwrap();
+        // Both players showed, so there's always a recommendation
+        let (recommended, prob) = step.bot_action.clone().unwrap();
+        assert_eq!(recommended, bot_action);
+        assert!(prob > 0.0 && prob <= 1.0);
+        assert_eq!(step.player, before.player);
+        assert_eq!(step.board.len(), [0, 3, 4, 5][before.street]);
+        assert_eq!(step.state, replayer.current_state());
+        states.push(step.state);
+    }
+    assert!(replayer.next_action().is_none());
+    assert_eq!(replayer.current_state(), expected);
+
+    // Stepping back undoes the actions in reverse
+    let last = replayer.previous_action().unwrap();
+    assert_eq!(last.state, expected);
+    assert_eq!(replayer.current_state(), states[states.len() - 2]);
+    while replayer.previous_action().is_some() {
+        assert_eq!(replayer.current_state(), states[replayer.position()]);
+    }
+    assert_eq!(replayer.position(), 0);
+    assert_eq!(replayer.current_state(), ActionHistory::new());
+}
+
+#[test]
+fn test_to_pokerstars_format() {
+    let bet = |amount| Action {
+        action: ActionType::Bet,
+        amount: amount,
+    };
+    let call = |amount| Action {
+        action: ActionType::Call,
+        amount: amount,
+    };
+    let mut history = ActionHistory::new();
+    for action in &[
+        bet(300),
+        call(300),
+        call(0),
+        bet(400),
+        bet(1400),
+        call(1000),
+        call(0),
+        bet(2000),
+        FOLD,
+    ] {
+        history.add(action);
+    }
+    let hand = str2cards("AsKd").unwrap();
+    let board = str2cards("7c2d9sJh3h").unwrap();
+    let text = to_pokerstars_format(
+        &history,
+        &hand,
+        &board,
+        ["Alice", "Bob"],
+        [STACK_SIZE, STACK_SIZE],
+        3400,
+    );
+    assert!(text.contains("Alice: raises 200 to 300"));
+    assert!(text.contains("Bob: raises 1000 to 1400"));
+    assert!(text.contains("Uncalled bet (2000) returned to Alice"));
+    assert!(text.contains("Total pot 3400 | Rake 0"));
+
+    let parsed = &parse_pokerstars(&text).unwrap()[0];
+    assert_eq!(parsed.players[0].hole_cards, Some(hand));
+    assert_eq!(parsed.board[TURN], board[3..4].to_vec());
+    assert_eq!(parsed.winners, vec![("Alice".to_string(), 3400.0)]);
+    assert_eq!(parsed.to_action_history(), history);
+}
+
+#[test]
+fn test_mmap_hand_table() {
+    let path = "products/test_strengths5.bin";
+    build_mmap_index("products/strengths.json", path);
+    let mapped = HandTable::mmap(path).unwrap();
+    let light = LightHandTable::new();
+    let mut deck = deck();
+    let mut rng = StdRng::seed_from_u64(0);
+    for _ in 0..1000 {
+        deck.shuffle(&mut rng);
+        assert_eq!(
+            mapped.hand_strength(&deck[..7]),
+            light.hand_strength(&deck[..7])
+        );
+        assert_eq!(
+            mapped.hand_strength(&deck[..5]),
+            light.hand_strength(&deck[..5])
+        );
+    }
+    std::fs::remove_file(path).unwrap();
+    assert!(HandTable::mmap("products/no_such_index.bin").is_err());
+}
+
+#[test]
+fn test_hand_strength_monotonicity() {
+    let path = "products/test_strengths5_monotonicity.bin";
+    build_mmap_index("products/strengths.json", path);
+    let table = HandTable::mmap(path).unwrap();
+    let result = hand_strength_monotonicity_check(&table);
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(result, Ok(()));
+}
+
+// Takes hours the first time, when the canonical river hands have to be dealt
+#[test]
+#[ignore]
+fn test_canonical_river_count() {
+    let canonical = load_or_build_canonical_river();
+    assert_eq!(canonical.len(), N_RIVER_CANONICAL as usize);
+}
+
+// Deals out every river hand and checks that canonical_hand() always gives a
+// hand that is_canonical() accepts. The hands that are already in canonical
+// form are each their own class, so counting them counts the classes without
+// keeping a 125M entry set around. Takes hours.
+#[test]
+#[ignore]
+fn test_canonical_river_exhaustive() {
+    let deck = deck();
+    let sort = |cards: Vec<&Card>| {
+        let mut cards: Vec<Card> = cards.into_iter().cloned().collect();
+        cards.sort_by_key(|c| (c.suit, c.rank));
+        cards
+    };
+    let preflops: Vec<Vec<&Card>> = deck.iter().combinations(2).collect();
+    let (n_canonical, n_failures) = preflops
+        .par_iter()
+        .map(|preflop| {
+            let mut n_canonical = 0;
+            let mut n_failures = 0;
+            let mut subdeck = deck.clone();
+            subdeck.retain(|c| !preflop.contains(&c));
+            for board in subdeck.iter().combinations(5) {
+                let hand = [sort(preflop.clone()), sort(board)].concat();
+                let canonical = canonical_hand(&hand, true);
+                if !is_canonical(&canonical, true) {
+                    n_failures += 1;
+                }
+                if canonical == hand {
+                    n_canonical += 1;
+                }
+            }
+            (n_canonical, n_failures)
+        })
+        .reduce(|| (0, 0), |a, b| (a.0 + b.0, a.1 + b.1));
+    assert_eq!(n_failures, 0);
+    assert_eq!(n_canonical, N_RIVER_CANONICAL as usize);
+}
+
+fn plo_hole(cards: &[&str]) -> [Card; 4] {
+    let cards = strvec2cards(cards);
+    [
+        cards[0].clone(),
+        cards[1].clone(),
+        cards[2].clone(),
+        cards[3].clone(),
+    ]
+}
+
+#[test]
+fn test_plo_hand_strength() {
+    // Two hole cards and three board cards make a royal flush
+    let hole = plo_hole(&["Ah", "Kh", "2c", "3d"]);
+    let board = strvec2cards(&["Qh", "Jh", "Th"]);
+    let strength = plo_hand_strength(&hole, &board);
+    assert_eq!(category_from_strength(strength), HandCategory::RoyalFlush);
+
+    // Four hearts in the hand with 8h7h6h on the board is only a flush, since
+    // J-T-8-7-6 isn't a straight. The best is the A-K high flush.
+    let hole = plo_hole(&["Ah", "Kh", "Jh", "Th"]);
+    let board = strvec2cards(&["8h", "7h", "6h"]);
+    let strength = plo_hand_strength(&hole, &board);
+    assert_eq!(category_from_strength(strength), HandCategory::Flush);
+    let best = strvec2cards(&["Ah", "Kh", "8h", "7h", "6h"]);
+    assert_eq!(strength, five_card_hand_strength(&best));
+
+    // Exactly two hole cards must be used: one heart in the hand doesn't make
+    // a flush with four on the board
+    let hole = plo_hole(&["Ah", "Ks", "Kd", "2c"]);
+    let board = strvec2cards(&["9h", "7h", "5h", "3h", "Kc"]);
+    let strength = plo_hand_strength(&hole, &board);
+    assert_eq!(category_from_strength(strength), HandCategory::ThreeOfAKind);
+}
+
+#[test]
+fn test_plo_canonical_hole() {
+    let a = plo_canonical_hole(&plo_hole(&["As", "Ks", "Qd", "Jd"]));
+    let b = plo_canonical_hole(&plo_hole(&["Qc", "Ah", "Jc", "Kh"]));
+    assert_eq!(a, b);
+    let c = plo_canonical_hole(&plo_hole(&["As", "Kd", "Qs", "Jd"]));
+    assert_ne!(a, c);
+
+    // Equity lookups are shared between isomorphic hands
+    let mut table = PloEquityTable::new();
+    let board = strvec2cards(&["2s", "5d", "8c", "Th", "3s"]);
+    let iso_board = strvec2cards(&["2h", "5c", "8d", "Ts", "3h"]);
+    table.insert(&plo_hole(&["As", "Ks", "Qd", "Jd"]), &board, 0.6);
+    assert_eq!(
+        table.lookup(&plo_hole(&["Ah", "Kh", "Qc", "Jc"]), &iso_board),
+        Some(0.6)
+    );
+}
+
+#[test]
+fn test_deck() {
+    let deck = deck();
+    assert_eq!(deck.len(), 52);
+    // Same order as always: by rank, then suit
+    assert_eq!(deck[0], Card { rank: 2, suit: 0 });
+    assert_eq!(deck[5], Card { rank: 3, suit: 1 });
+    assert_eq!(deck[51], Card { rank: 14, suit: 3 });
+    assert!(deck_iter().eq(deck.iter()));
+    let ids: Vec<u8> = deck.iter().map(|c| c.rank * 4 + c.suit).collect();
+    assert!(!contains_duplicates(&ids));
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut shuffled = shuffled_deck(&mut rng);
+    assert_ne!(shuffled, deck);
+    shuffled.sort_by_key(|c| (c.rank, c.suit));
+    assert_eq!(shuffled, deck);
+}
+
+#[test]
+fn test_short_deck_hand_strength() {
+    assert_eq!(short_deck().len(), 36);
+    let strength = |hand: &[&str]| short_deck_hand_strength(&strvec2cards(hand));
+
+    // The ace plays low in A-6-7-8-9, which is the lowest straight
+    let wheel = strength(&["Ah", "6c", "7d", "8s", "9h"]);
+    let six_high = strength(&["6c", "7d", "8s", "9h", "Th"]);
+    let two_pair = strength(&["Ah", "Ac", "Kd", "Ks", "Qh"]);
+    assert!(wheel > two_pair);
+    assert!(wheel < six_high);
+
+    // Three of a kind beats a straight
+    let trips = strength(&["6h", "6c", "6d", "8s", "9h"]);
+    assert!(trips > strength(&["Ah", "Kc", "Qd", "Js", "Th"]));
+
+    // A flush beats a full house
+    let flush = strength(&["6h", "7h", "8h", "9h", "Jh"]);
+    let full_house = strength(&["Ah", "Ac", "Ad", "Ks", "Kh"]);
+    assert!(flush > full_house);
+    assert!(strength(&["6h", "6c", "6d", "6s", "7h"]) > flush);
+
+    // Best 5 out of 7, and suits don't matter
+    assert_eq!(
+        strength(&["Ah", "6c", "7d", "8s", "9h", "Ts", "Js"]),
+        strength(&["7c", "8d", "9s", "Th", "Jc"])
+    );
+}
+
+#[test]
+fn test_board_texture() {
+    let texture = board_texture(&strvec2cards(&["9h", "8h", "7h"]));
+    assert!(texture.monotone && !texture.two_tone && !texture.rainbow);
+    assert!(!texture.paired && !texture.has_broadway);
+    assert_eq!(texture.connectedness, 1.0);
+    assert!(texture.flush_draw_possible && texture.straight_draw_possible);
+    // No more draws once the river is out
+    let texture = board_texture(&strvec2cards(&["9h", "8h", "7h", "2c", "Kd"]));
+    assert!(!texture.monotone && !texture.rainbow);
+    assert!(!texture.flush_draw_possible && !texture.straight_draw_possible);
+
+    // Dry rainbow flop
+    let texture = board_texture(&strvec2cards(&["Ks", "7d", "2c"]));
+    assert!(texture.rainbow && !texture.monotone && !texture.two_tone);
+    assert!(texture.has_broadway);
+    assert!(texture.connectedness < 0.25);
+    assert!(!texture.flush_draw_possible && !texture.straight_draw_possible);
+
+    // Paired boards
+    let texture = board_texture(&strvec2cards(&["8s", "8d", "3s"]));
+    assert!(texture.paired && !texture.double_paired && texture.two_tone);
+    assert!(texture.flush_draw_possible);
+    let texture = board_texture(&strvec2cards(&["8s", "8d", "6s", "6h"]));
+    assert!(texture.paired && texture.double_paired);
+    assert!(texture.straight_draw_possible);
+}
+
+#[test]
+fn test_draws() {
+    // Flush draw and open-ender
+    let info = draws(
+        &strvec2cards(&["6h", "7h"]),
+        &strvec2cards(&["8h", "9c", "2h"]),
+    );
+    assert_eq!(info.flush_draws.len(), 9);
+    assert!(info.flush_draws.iter().all(|c| c.suit as i32 == HEARTS));
+    let mut ranks: Vec<u8> = info
+        .open_ended_straight_draws
+        .iter()
+        .map(|c| c.rank)
+        .collect();
+    ranks.dedup();
+    assert_eq!(ranks, vec![5, 10]);
+    assert_eq!(info.open_ended_straight_draws.len(), 8);
+    assert!(info.gutshot_straight_draws.is_empty());
+
+    let info = draws(
+        &strvec2cards(&["Jc", "Tc"]),
+        &strvec2cards(&["9d", "8h", "2s"]),
+    );
+    assert!(info.flush_draws.is_empty());
+    assert_eq!(info.open_ended_straight_draws.len(), 8);
+    assert!(info
+        .open_ended_straight_draws
+        .iter()
+        .all(|c| c.rank == 7 || c.rank == 12));
+
+    // Gutshot to the 6, with backdoor clubs. The only backdoor straight that
+    // doesn't need the 6 is a jack and a ten, so a king and a 6 doesn't count.
+    let info = draws(
+        &strvec2cards(&["9c", "7c"]),
+        &strvec2cards(&["8h", "5s", "2c"]),
+    );
+    assert_eq!(info.gutshot_straight_draws.len(), 4);
+    assert!(info.gutshot_straight_draws.iter().all(|c| c.rank == 6));
+    assert!(info.open_ended_straight_draws.is_empty());
+    assert_eq!(info.backdoor_flush_draws.len(), 10);
+    let mut ranks: Vec<u8> = info
+        .backdoor_straight_draws
+        .iter()
+        .map(|c| c.rank)
+        .collect();
+    ranks.sort();
+    ranks.dedup();
+    assert_eq!(ranks, vec![10, 11]);
+    assert_eq!(info.backdoor_straight_draws.len(), 8);
+
+    // A made straight isn't a straight draw, but it can still draw to a flush
+    let info = draws(
+        &strvec2cards(&["Ah", "2h"]),
+        &strvec2cards(&["3h", "4h", "5c"]),
+    );
+    assert_eq!(info.flush_draws.len(), 9);
+    assert!(info.open_ended_straight_draws.is_empty());
+    assert!(info.gutshot_straight_draws.is_empty());
+
+    // No draws on the river
+    let river = strvec2cards(&["3h", "4h", "5c", "Kd", "Qs"]);
+    assert_eq!(
+        draws(&strvec2cards(&["Ah", "2h"]), &river),
+        DrawInfo::default()
+    );
+}
+
+#[test]
+fn test_outs() {
+    // Nine hearts left for the flush draw
+    let hand = strvec2cards(&["Ah", "Kh"]);
+    let board = strvec2cards(&["7h", "2h", "9c"]);
+    let flush_outs = outs_to_category(&hand, &board, HandCategory::Flush);
+    assert_eq!(flush_outs.len(), 9);
+    // Pairing either hole card also improves the hand
+    let all_outs = outs(&hand, &board);
+    assert!(all_outs.len() > 9);
+    assert!(flush_outs.iter().all(|c| all_outs.contains(c)));
+
+    // Open-ender and gutshot on rainbow boards
+    let board = strvec2cards(&["9d", "8h", "2s"]);
+    let oesd = outs_to_category(&strvec2cards(&["Jc", "Tc"]), &board, HandCategory::Straight);
+    assert_eq!(oesd.len(), 8);
+    let gutshot = outs_to_category(&strvec2cards(&["Jc", "7c"]), &board, HandCategory::Straight);
+    assert_eq!(gutshot.len(), 4);
+    assert!(gutshot.iter().all(|c| c.rank == 10));
+
+    // The 7-card table only gets asked about 7-card hands. Flop hands are 5
+    // and 6 cards, so an empty table works there.
+    let hand = strvec2cards(&["Ah", "Kh"]);
+    let board = strvec2cards(&["7h", "2h", "9c"]);
+    let empty = HandTable::from_hand_data(HandData::new());
+    assert_eq!(outs_with(&empty, &hand, &board), all_outs);
+    // On the turn it needs every river
+    let board = strvec2cards(&["7h", "2h", "9c", "3d"]);
+    let cards = [&hand[..], &board[..]].concat();
+    let mut strengths = HandData::new();
+    for card in deck().into_iter().filter(|c| !cards.contains(c)) {
+        let river = [&cards[..], &[card]].concat();
+        strengths.insert(
+            &canonical_hand_id(&river, false),
+            five_card_hand_strength(&river),
+        );
+    }
+    let table = HandTable::from_hand_data(strengths);
+    assert_eq!(outs_with(&table, &hand, &board), outs(&hand, &board));
+}
+
+#[test]
+fn test_relative_hand_strength() {
+    // The nuts beats everything
+    let hand = strvec2cards(&["Ah", "Kh"]);
+    let board = strvec2cards(&["Qh", "Jh", "Th", "2c", "3d"]);
+    assert_eq!(relative_hand_strength(&hand, &board), 1.0);
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let hand = strvec2cards(&["8s", "8d"]);
+    let board = strvec2cards(&["Kc", "9h", "4d", "2s"]);
+    let exact = relative_hand_strength(&hand, &board);
+    let estimate = relative_hand_strength_mc(&hand, &board, 20_000, &mut rng);
+    assert!((exact - estimate).abs() < 0.02);
+
+    // Pocket aces win about 85% of the time preflop
+    let aces = strvec2cards(&["As", "Ad"]);
+    let estimate = relative_hand_strength_mc(&aces, &[], 20_000, &mut rng);
+    assert!((estimate - 0.852).abs() < 0.02);
+}
+
+#[test]
+fn test_deal_canonical_flop() {
+    let canonical = deal_canonical(5);
+    assert_eq!(canonical.len(), N_FLOP_CANONICAL as usize);
+    for hand in &canonical {
+        let cards = hand2cards(*hand);
+        assert!(is_canonical(&cards, true), "{}", cards2str(&cards));
+    }
+}
+
+// Deals out every flop on all cores (which takes a while) if flop_canonical.txt
+// isn't there yet, and then reads it back
+#[test]
+#[ignore]
+fn test_load_flop_canonical() {
+    assert_eq!(load_flop_canonical().len(), N_FLOP_CANONICAL as usize);
+}
+
+#[test]
+fn test_canonical_hand_id() {
+    for card in deck() {
+        let hand = [card];
+        assert_eq!(
+            canonical_hand_id(&hand, false),
+            cards2hand(&canonical_hand(&hand, false))
+        );
+    }
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut deck = deck();
+    for _ in 0..10_000 {
+        deck.shuffle(&mut rng);
+        for n in 2..=8 {
+            let hand = &deck[..n];
+            for streets in &[false, true] {
+                assert_eq!(
+                    canonical_hand_id(hand, *streets),
+                    cards2hand(&canonical_hand(hand, *streets)),
+                    "{}",
+                    cards2str(hand)
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_contains_duplicates() {
+    assert!(!contains_duplicates(&[]));
+    assert!(!contains_duplicates(&[14]));
+    assert!(!contains_duplicates(&[2, 3, 4, 14]));
+    assert!(contains_duplicates(&[2, 3, 4, 3]));
+    // Values in different words of the bitset, and ones that share a bit
+    // position in different words
+    assert!(!contains_duplicates(&[0, 63, 64, 127, 128, 255]));
+    assert!(!contains_duplicates(&[1, 65, 129, 193]));
+    assert!(contains_duplicates(&[255, 0, 255]));
+}
+
+// The order of the cards within a street mustn't matter, for any number of
+// hole cards
+#[test]
+fn test_canonical_hand_with_hole() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut deck = deck();
+    for _ in 0..10_000 {
+        deck.shuffle(&mut rng);
+        for &(n, n_hole) in &[(5, 2), (6, 2), (7, 2), (7, 0), (9, 4)] {
+            let hand = &deck[..n];
+            let mut reordered = hand.to_vec();
+            reordered[..n_hole].reverse();
+            reordered[n_hole..].reverse();
+            let canonical = canonical_hand_with_hole(hand, n_hole);
+            assert_eq!(canonical, canonical_hand_with_hole(&reordered, n_hole));
+            if n_hole == 2 {
+                assert!(is_canonical(&canonical, true), "{}", cards2str(hand));
+            }
+        }
+    }
+}
+
+// The cache has to give the same answers as working it out, for hands in any
+// order
+#[test]
+fn test_canonical_hand_cache() {
+    let mut rng = StdRng::seed_from_u64(1);
+    let mut deck = deck();
+    for _ in 0..2000 {
+        deck.shuffle(&mut rng);
+        for &(n, streets) in &[(2, true), (3, true), (5, true), (7, true), (7, false)] {
+            let hand = &deck[..n];
+            let expected = canonical_hand_with_hole(hand, if streets { 2 } else { 0 });
+            assert_eq!(canonical_hand_cached(hand, streets), expected);
+            let split = if streets && n > 2 { 2 } else { 0 };
+            let mut reordered = hand.to_vec();
+            reordered[..split].reverse();
+            reordered[split..].reverse();
+            let (hits, _) = canonical_cache_stats();
+            assert_eq!(canonical_hand_cached(&reordered, streets), expected);
+            assert!(canonical_cache_stats().0 > hits);
+        }
+    }
+
+    // Hands that only differ by a card at the end need their own entries
+    let two_clubs = str2cards("AhKd2c").unwrap();
+    assert_eq!(canonical_hand_cached(&two_clubs[..2], true).len(), 2);
+    assert_eq!(canonical_hand_cached(&two_clubs, true).len(), 3);
+}
+
+// Needs the hand strength and equity tables
+#[test]
+#[ignore]
+fn test_init_tables() {
+    init_tables();
+    let royal_flush = str2cards("AhKhQhJhTh2c3d").unwrap();
+    assert_eq!(hand_category(&royal_flush), HandCategory::RoyalFlush);
+    assert_eq!(lookup_river_equities(&[royal_flush]), vec![1.0]);
+}
+
+#[test]
+fn test_hands_isomorphic() {
+    let hand = str2cards("AhKhQs2d3c").unwrap();
+    // Hearts to spades, spades to clubs, diamonds to hearts, clubs to diamonds
+    let relabelled = str2cards("AsKsQc2h3d").unwrap();
+    assert!(hands_isomorphic(&hand, &relabelled, true));
+    let p = suit_permutation_that_maps(&hand, &relabelled).unwrap();
+    let mapped: Vec<Card> = hand
+        .iter()
+        .map(|c| Card {
+            rank: c.rank,
+            suit: p[c.suit as usize],
+        })
+        .collect();
+    assert_eq!(cards2str(&mapped), "AsKsQc2h3d");
+    let mut sorted_p = p;
+    sorted_p.sort_unstable();
+    assert_eq!(sorted_p, [0, 1, 2, 3]);
+
+    // Order doesn't matter, except for which cards are the hole cards
+    let reordered = str2cards("KhAh3c2dQs").unwrap();
+    assert!(hands_isomorphic(&hand, &reordered, true));
+    assert!(suit_permutation_that_maps(&hand, &reordered).is_some());
+    let swapped = str2cards("QsKhAh2d3c").unwrap();
+    assert!(!hands_isomorphic(&hand, &swapped, true));
+    assert!(hands_isomorphic(&hand, &swapped, false));
+
+    // Suited and offsuit aren't the same hand
+    let offsuit = str2cards("AhKdQs2d3c").unwrap();
+    assert!(!hands_isomorphic(&hand, &offsuit, false));
+    assert_eq!(suit_permutation_that_maps(&hand, &offsuit), None);
+    assert!(!hands_isomorphic(&hand, &hand[..4], false));
+    assert_eq!(suit_permutation_that_maps(&hand, &hand[..4]), None);
+
+    // Agrees with canonical_hand() on random hands
+    let mut rng = StdRng::seed_from_u64(2);
+    let mut deck = deck();
+    for _ in 0..1000 {
+        deck.shuffle(&mut rng);
+        let h1 = deck[..5].to_vec();
+        let h2 = canonical_hand(&h1, false);
+        assert!(hands_isomorphic(&h1, &h2, false));
+        assert!(suit_permutation_that_maps(&h1, &h2).is_some());
+        let h3 = deck[5..10].to_vec();
+        let same = canonical_hand(&h1, false) == canonical_hand(&h3, false);
+        assert_eq!(hands_isomorphic(&h1, &h3, false), same);
+        assert_eq!(suit_permutation_that_maps(&h1, &h3).is_some(), same);
+    }
+}
+
+// canonical_hand() used to be about 3x slower than canonical_hand_id() in
+// debug builds because of all the allocations in canonical_hand_with_hole().
+// They do the same work apart from building the Vec, so they should stay
+// close. Comparing the two keeps this independent of the machine, and taking
+// each one's best of several interleaved rounds keeps a busy machine from
+// slowing down just one of them. See benches/canonical.rs for the absolute
+// numbers.
+#[test]
+fn test_canonical_hand_performance() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut deck = deck();
+    let hands: Vec<Vec<Card>> = (0..20_000)
+        .map(|i| {
+            deck.shuffle(&mut rng);
+            deck[..5 + i % 3].to_vec()
+        })
+        .collect();
+    let mut id_time = Duration::from_secs(u64::MAX);
+    let mut hand_time = Duration::from_secs(u64::MAX);
+    for _ in 0..5 {
+        // Summing the results keeps the calls from being optimized away
+        let start = Instant::now();
+        let id_sum: u64 = hands.iter().map(|h| canonical_hand_id(h, true) % 2).sum();
+        id_time = id_time.min(start.elapsed());
+        let start = Instant::now();
+        let hand_sum: u64 = hands
+            .iter()
+            .map(|h| cards2hand(&canonical_hand_with_hole(h, 2)) % 2)
+            .sum();
+        hand_time = hand_time.min(start.elapsed());
+        assert_eq!(id_sum, hand_sum);
+    }
+    assert!(
+        hand_time < id_time * 2,
+        "canonical_hand_id {:?}, canonical_hand_with_hole {:?}",
+        id_time,
+        hand_time
+    );
+}
+
+#[test]
+fn test_sample_canonical() {
+    let mut rng = StdRng::seed_from_u64(0);
+    // There are 169 canonical preflop hands, each with probability 1/169
+    let per_hand = 300;
+    let samples = sample_canonical(2, 169 * per_hand, &mut rng, false);
+    let mut counts: HashMap<Vec<Card>, usize> = HashMap::new();
+    for hand in &samples {
+        assert!(is_canonical(hand, false));
+        *counts.entry(hand.clone()).or_insert(0) += 1;
+    }
+    assert_eq!(counts.len(), 169);
+    // Each count is roughly Binomial(169 * 300, 1/169), with a standard
+    // deviation of about 17
+    for count in counts.values() {
+        assert!((*count as i32 - per_hand as i32).abs() < 100);
+    }
+
+    let flops = sample_canonical(5, 1000, &mut rng, true);
+    assert_eq!(flops.len(), 1000);
+    assert!(flops.iter().all(|h| h.len() == 5 && is_canonical(h, true)));
+}
+
+#[test]
+fn test_range_equity() {
+    let aces: HandRange = "AA".parse().unwrap();
+    let kings: HandRange = "KK".parse().unwrap();
+    let equities = range_vs_range_equity(&aces, &kings, &[]);
+    assert_eq!(equities.len(), 6);
+    assert!(equities.iter().all(|e| (e - 0.82).abs() < 0.03));
+    assert!((range_equity(&aces, &kings, &[]) - 0.82).abs() < 0.02);
+
+    // The ace of spades on the board blocks three combos of aces
+    let board = strvec2cards(&["As", "7d", "2c", "9h", "4s"]);
+    let equities = range_vs_range_equity(&aces, &kings, &board);
+    assert_eq!(equities.iter().filter(|e| e.is_nan()).count(), 3);
+    assert_eq!(range_equity(&aces, &kings, &board), 1.0);
+}
+
+#[test]
+fn test_equity_realization_factor() {
+    let factor = |hand: &str| {
+        let cards = str2cards(hand).unwrap();
+        equity_realization_factor(&[cards[0].clone(), cards[1].clone()])
+    };
+    assert_eq!(factor("AhAd"), 1.0);
+    assert!((factor("JhTh") - 1.25).abs() < 1e-9);
+    assert!((factor("Kh2d") - 0.75).abs() < 1e-9);
+    assert!((factor("7h2d") - 0.65).abs() < 1e-9);
+    assert!(factor("JhTh") > factor("JhTd"));
+    assert!(factor("JhTd") > factor("Jh5d"));
+    // The ace plays low, so A5s is closer to connected than A6s
+    assert!(factor("Ah5h") > factor("Ah6h"));
+    for hand in preflop_matrix_169().iter() {
+        let suit2 = if hand.suited { 0 } else { 1 };
+        let cards = [
+            Card {
+                rank: hand.rank1,
+                suit: 0,
+            },
+            Card {
+                rank: hand.rank2,
+                suit: suit2,
+            },
+        ];
+        let f = equity_realization_factor(&cards);
+        assert!(f >= 0.5 && f <= 1.5);
+    }
+}
+
+#[test]
+fn test_bot_action_fallback() {
+    let hands = [strvec2cards(&["Ah", "Ad"]), strvec2cards(&["7c", "2d"])];
+    let blueprint = CompressedStrategy::new();
+    // Nothing is in the blueprint, so every preflop action is a fallback
+    // instead of a panic
+    for _ in 0..20 {
+        let mut history = ActionHistory::new();
+        let before = blueprint_fallback_count();
+        let mut n_actions = 0;
+        while !history.hand_over() && history.street == PREFLOP {
+            let hand = &hands[history.player];
+            let (action, fallback) = bot_action_with_blueprint(&blueprint, hand, &[], &history);
+            assert!(fallback);
+            assert!(history.legal_actions().contains(&action));
+            history.add(&action);
+            n_actions += 1;
+        }
+        assert!(blueprint_fallback_count() >= before + n_actions);
+    }
+
+    // With the infoset in the blueprint, its most likely action is played
+    let history = ActionHistory::new();
+    let infoset = InfoSet::from_hand(&hands[DEALER], &history);
+    let actions = infoset.next_actions();
+    let call_index = actions
+        .iter()
+        .position(|a| a.action == ActionType::Call)
+        .unwrap();
+    let mut probs = vec![0; actions.len()];
+    probs[call_index] = 255;
+    let mut blueprint = CompressedStrategy::new();
+    blueprint.insert(infoset.compress(), probs);
+    let (action, fallback) = bot_action_with_blueprint(&blueprint, &hands[DEALER], &[], &history);
+    assert!(!fallback);
+    assert_eq!(action.action, ActionType::Call);
+}
+
+#[test]
+fn test_bot_action_dls() {
+    // The dealer shoves preflop, so every line of the subgame ends the hand
+    // and no postflop buckets are needed
+    let mut history = ActionHistory::new();
+    let shove = history
+        .legal_actions()
+        .into_iter()
+        .max_by_key(|a| a.amount)
+        .unwrap();
+    history.add(&shove);
+    let hand = strvec2cards(&["7c", "2d"]);
+    // The blueprint calls it off with 72o
+    let infoset = InfoSet::from_hand(&hand, &history.translate(bet_abstraction()));
+    let actions = infoset.next_actions();
+    let mut probs = vec![0; actions.len()];
+    probs[actions
+        .iter()
+        .position(|a| a.action == ActionType::Call)
+        .unwrap()] = 255;
+    let mut blueprint = CompressedStrategy::new();
+    blueprint.insert(infoset.compress(), probs);
+
+    // Without any time to solve, the blueprint action is played
+    let (blueprint_action, _) = bot_action_with_blueprint(&blueprint, &hand, &[], &history);
+    assert_eq!(blueprint_action.action, ActionType::Call);
+    let action = bot_action_dls_with(&blueprint, &hand, &[], &history, 0, 1000, 0);
+    assert_eq!(action, blueprint_action);
+
+    let legal = history.legal_actions();
+    for _ in 0..10 {
+        let action = bot_action_dls_with(&blueprint, &hand, &[], &history, 0, 100, u128::MAX);
+        assert!(legal.contains(&action), "{:?}", action);
+    }
+
+    // 72o only has about a third of the equity against a random hand, which
+    // isn't enough to call a shove, so solving should overrule the blueprint.
+    // The action is sampled from the solved strategy, so just check that
+    // folding is what it usually does.
+    let folds = (0..20)
+        .map(|_| bot_action_dls_with(&blueprint, &hand, &[], &history, 0, 200, u128::MAX))
+        .filter(|action| action.action == ActionType::Fold)
+        .count();
+    assert!(folds > 10, "{} folds", folds);
+}
+
+#[test]
+fn test_solve_river_subgame() {
+    // The opponent bets into us on the river
+    let mut history = river_history();
+    let bet = history
+        .next_actions(bet_abstraction())
+        .into_iter()
+        .find(|a| a.action == ActionType::Bet)
+        .unwrap();
+    history.add(&bet);
+    let fold = Action {
+        action: ActionType::Fold,
+        amount: 0,
+    };
+    let call = Action {
+        action: ActionType::Call,
+        amount: history.to_call(),
+    };
+    lazy_static::initialize(&crate::trainer_utils::HAND_TABLE);
+    let board = strvec2cards(&["Ah", "Kh", "Qh", "Jh", "2c"]);
+    let range = |range: &str| {
+        let range: HandRange = range.parse().unwrap();
+        WeightedRange::uniform(&range.remove_blockers(&board))
+    };
+    let bot_range = range("T9o,88,43o");
+    let opp_range = range("AQo,KJo,77,65o,T8o");
+
+    // With a royal flush we should never fold
+    let nuts = strvec2cards(&["Th", "9d"]);
+    let strategy = solve_river_subgame(&nuts, &board, &bot_range, &opp_range, &history, 200);
+    assert!((strategy.values().sum::<f64>() - 1.0).abs() < 1e-9);
+    assert!(strategy[&fold] < 0.05, "{:?}", strategy);
+
+    // With a hand that loses to almost everything, calling always loses more
+    // than folding. Raising can still be a bluff.
+    let air = strvec2cards(&["3c", "4d"]);
+    let strategy = solve_river_subgame(&air, &board, &bot_range, &opp_range, &history, 200);
+    assert!(strategy[&call] < 0.05, "{:?}", strategy);
+}
+
+#[test]
+fn test_estimate_range_from_blueprint() {
+    let history = ActionHistory::new();
+    let infoset = InfoSet::new(history.clone(), 0);
+    let actions = infoset.next_actions();
+    let raise_index = actions
+        .iter()
+        .position(|a| a.action == ActionType::Bet)
+        .unwrap();
+    // Pocket pairs (bucket 0) always raise and everything else never does
+    let mut blueprint = CompressedStrategy::new();
+    let mut probs = vec![0; actions.len()];
+    probs[raise_index] = 255;
+    blueprint.insert(infoset.compress(), probs);
+    let never = InfoSet::new(history.clone(), 1);
+    let mut probs = vec![0; actions.len()];
+    probs[(raise_index + 1) % actions.len()] = 255;
+    blueprint.insert(never.compress(), probs);
+
+    let mut raised = history.clone();
+    raised.add(&actions[raise_index]);
+    let aces = strvec2cards(&["Ah", "Ad"]);
+    let estimator = RangeEstimator::from_blueprint(&blueprint)
+        .with_bucket_fn(|cards| (cards[0].rank != cards[1].rank) as i32);
+    let range = estimator.estimate_range(&raised, DEALER, &[], &[]);
+    // Only the 78 pocket pairs raise
+    assert_eq!(range.hand_range(1e-9).len(), 78);
+    assert!((range.weight(&aces) - 1.0 / 78.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_estimate_range() {
+    let history = ActionHistory::new();
+    let raise = history
+        .next_actions(bet_abstraction())
+        .into_iter()
+        .find(|a| a.action == ActionType::Bet)
+        .unwrap();
+    let call = Action {
+        action: ActionType::Call,
+        amount: history.to_call(),
+    };
+    // The dealer always raises aces and never raises 72o. Everything else
+    // isn't in the strategy, so it raises as often as it does anything else.
+    let mut nodes = NodeMap::new();
+    let mut raise_always = HashMap::new();
+    raise_always.insert(raise.clone(), 1.0);
+    raise_always.insert(call.clone(), 0.0);
+    let mut raise_never = raise_always.clone();
+    raise_never.insert(raise.clone(), 0.0);
+    raise_never.insert(call.clone(), 1.0);
+    let aces = strvec2cards(&["Ah", "Ad"]);
+    let trash = strvec2cards(&["7c", "2d"]);
+    for (hand, strategy) in &[(&aces, &raise_always), (&trash, &raise_never)] {
+        let infoset = InfoSet::new(history.clone(), preflop_bin_index(hand) as i32);
+        nodes.insert(infoset.compress(), Node::from_strategy(strategy));
+    }
+
+    let mut raised = history.clone();
+    raised.add(&raise);
+    let estimator =
+        RangeEstimator::new(&nodes).with_bucket_fn(|cards| preflop_bin_index(cards) as i32);
+    let range = estimator.estimate_range(&raised, DEALER, &[], &[]);
+    assert_eq!(range.len(), 1326);
+    let total: f64 = range.combos().iter().map(|(_, w)| w).sum();
+    assert!((total - 1.0).abs() < 1e-9);
+    assert_eq!(range.weight(&trash), 0.0);
+    assert!(range.weight(&aces) > range.weight(&strvec2cards(&["Kh", "Kd"])));
+    // The opponent hasn't done anything yet, so anything goes for them
+    let opp_range = estimator.estimate_range(&raised, OPPONENT, &[], &aces);
+    assert_eq!(opp_range.len(), 1225);
+    assert_eq!(opp_range.weight(&trash), 1.0 / 1225.0);
+    assert!(!opp_range.hand_range(0.0).contains(&aces));
+    // All 12 combos of 72o share its bucket, so none of them raised
+    assert_eq!(range.hand_range(1e-9).len(), 1326 - 12);
+}
+
+#[test]
+fn test_icm_equity() {
+    // The usual textbook example, which works out to $38.39, $32.75, $28.86
+    let equity = icm_equity(&[5000, 3000, 2000], &[50.0, 30.0, 20.0]);
+    let expected = [38.392_857, 32.75, 28.857_143];
+    for (e, x) in equity.iter().zip(expected.iter()) {
+        assert!((e - x).abs() < 1e-4);
+    }
+    // Four left with three paid
+    let equity = icm_equity(&[4000, 3000, 2000, 1000], &[50.0, 30.0, 20.0]);
+    let expected = [33.603_175, 29.488_095, 23.587_302, 13.321_429];
+    for (e, x) in equity.iter().zip(expected.iter()) {
+        assert!((e - x).abs() < 1e-4);
+    }
+    assert!((equity.iter().sum::<f64>() - 100.0).abs() < 1e-9);
+    for stacks in &[
+        vec![4000, 3000, 2000, 1000],
+        vec![2500, 2500, 700, 4100, 1800],
+    ] {
+        let prizes = [50.0, 30.0, 20.0];
+        let brute_force = icm_equity_brute_force(stacks, &prizes);
+        for (e, x) in icm_equity(stacks, &prizes).iter().zip(&brute_force) {
+            assert!((e - x).abs() < 1e-9, "{} {}", e, x);
+        }
+    }
+    // Winner take all is just the share of the chips, and busted players get
+    // nothing
+    let equity = icm_equity(&[3000, 1000, 0], &[100.0]);
+    assert_eq!(equity, vec![75.0, 25.0, 0.0]);
+}
+
+// ICM equity summed over every finishing order. Each place goes to one of the
+// players left with probability proportional to their stack.
+fn icm_equity_brute_force(stacks: &[i32], prize_pool: &[f64]) -> Vec<f64> {
+    let n = stacks.len();
+    let mut equity = vec![0.0; n];
+    for order in (0..n).permutations(n) {
+        let mut prob = 1.0;
+        let mut chips_left: i32 = stacks.iter().sum();
+        for &player in &order {
+            prob *= stacks[player] as f64 / chips_left as f64;
+            chips_left -= stacks[player];
+        }
+        for (place, &player) in order.iter().enumerate() {
+            equity[player] += prob * prize_pool.get(place).unwrap_or(&0.0);
+        }
+    }
+    equity
+}
+
+#[test]
+fn test_icm_adjusted_ev() {
+    let stacks = [5000, 3000, 2000];
+    let prizes = [50.0, 30.0, 20.0];
+    // A coin flip for the short stack's chips is break even in chips, but
+    // loses money for the chip leader under ICM
+    let ev = icm_adjusted_ev(0.0, 2000, &stacks, &prizes, 0, 2);
+    assert!(ev < 0.0);
+    // A sure win is worth exactly the equity it gains
+    let ev = icm_adjusted_ev(2000.0, 2000, &stacks, &prizes, 0, 2);
+    let gained = icm_equity(&[7000, 3000, 0], &prizes)[0] - icm_equity(&stacks, &prizes)[0];
+    assert!((ev - gained).abs() < 1e-9);
+    // Winner take all makes chips and money the same thing
+    let ev = icm_adjusted_ev(500.0, 2000, &stacks, &[100.0], 0, 2);
+    assert!((ev - 5.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_node_display() {
+    let history = ActionHistory::new();
+    let actions = history.next_actions(bet_abstraction());
+    let mut strategy = HashMap::new();
+    for action in &actions {
+        let prob = match action.action {
+            ActionType::Call => 0.25,
+            ActionType::Bet if action == &actions[0] => 0.75,
+            _ => 0.0,
+        };
+        strategy.insert(action.clone(), prob);
+    }
+    let node = Node::from_strategy(&strategy);
+    let text = node.to_string();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), actions.len() + 1);
+    assert!(lines[0].starts_with(&actions[0].to_string()));
+    assert!(lines[0].ends_with("75.00%"));
+    assert!(lines[1].starts_with("call"));
+
+    // Aces are the top left corner of the matrix
+    let aces = strvec2cards(&["Ah", "Ad"]);
+    let mut nodes = NodeMap::new();
+    let infoset = InfoSet::new(history.clone(), preflop_bin_index(&aces) as i32);
+    nodes.insert(infoset.compress(), node);
+    let matrix = preflop_matrix_string(&nodes);
+    let aces_row = matrix.lines().nth(2).unwrap();
+    assert!(aces_row.starts_with("A "));
+    assert_eq!(aces_row.split_whitespace().nth(1), Some("75/25/0"));
+    assert_eq!(aces_row.split_whitespace().nth(2), Some("-"));
+}
+
+#[test]
+fn test_simulate_heads_up() {
+    let call = |_: &[Card], _: &[Card], history: &ActionHistory| Action {
+        action: ActionType::Call,
+        amount: history.to_call(),
+    };
+    // Folds whenever it has to put chips in
+    let fold = |_: &[Card], _: &[Card], history: &ActionHistory| {
+        if history.to_call() > 0 {
+            FOLD
+        } else {
+            Action {
+                action: ActionType::Call,
+                amount: 0,
+            }
+        }
+    };
+
+    // Calling stations check every hand down for a big blind each
+    let result = simulate_heads_up_with(200, &mut StdRng::seed_from_u64(0), [call, call]);
+    assert_eq!(result.n_hands, 200);
+    assert_eq!(result.showdown_rate, 1.0);
+    assert_eq!(result.all_in_rate, 0.0);
+    assert_eq!(result.action_frequencies[&ActionType::Call], 1.0);
+    assert_eq!(result.ev_by_street[RIVER], result.total_ev);
+    assert!(result.total_ev.abs() <= 200.0);
+    assert_eq!(
+        result,
+        simulate_heads_up_with(200, &mut StdRng::seed_from_u64(0), [call, call])
+    );
+
+    // The hero folds its small blind as the dealer. It hasn't put in the big
+    // blind yet when the dealer calls, so it folds that too.
+    let result = simulate_heads_up_with(200, &mut StdRng::seed_from_u64(0), [fold, call]);
+    assert_eq!(result.showdown_rate, 0.0);
+    assert_eq!(result.ev_by_street[PREFLOP], -150.0);
+    assert_eq!(result.total_ev, -150.0);
+    assert_eq!(result.action_frequencies[&ActionType::Fold], 200.0 / 300.0);
+}
+
+#[test]
+fn test_build_hand_table() {
+    // Building the 5-card strengths from scratch gives the same table as the
+    // one that was shipped
+    let built = build_standard_strengths();
+    let text = std::fs::read_to_string(&config().paths.light_hand_table).unwrap();
+    let shipped: HashMap<String, i32> = serde_json::from_str(&text).unwrap();
+    assert_eq!(built.len(), shipped.len());
+    for (hand, strength) in &built {
+        assert_eq!(shipped.get(&cards2str(hand)), Some(strength));
+    }
+    let distinct: HashSet<&i32> = built.values().collect();
+    assert_eq!(distinct.len(), 7462);
+
+    let path = std::env::temp_dir().join("test_hand_table.json");
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+    let table = HandTable::load_or_build(path);
+    assert!(std::path::Path::new(path).exists());
+    let loaded = HandTable::load_or_build(path);
+    let strength = |table: &HandTable, hand: &str| table.hand_strength(&str2cards(hand).unwrap());
+    for hand in &[
+        "AsKsQsJsTs2c3d",
+        "Ah2c3d4h5s9cJd",
+        "2c3d4h5s6c9dJh",
+        "KhKd2c2s3h3d9c",
+    ] {
+        assert_eq!(strength(&table, hand), strength(&loaded, hand));
+    }
+    // The wheel is the weakest straight
+    assert!(strength(&table, "Ah2c3d4h5s9cJd") < strength(&table, "2c3d4h5s6c9dJh"));
+    assert_eq!(
+        category_from_strength(strength(&table, "AsKsQsJsTs2c3d")),
+        HandCategory::RoyalFlush
+    );
+    std::fs::remove_file(path).unwrap();
+}
@@ -1,50 +1,790 @@
 use crate::card_abstraction::Abstraction;
 use crate::card_utils;
 use crate::card_utils::Card;
+use crate::config::config;
 use crate::exploiter::exploitability;
 use crate::trainer_utils::*;
+use crate::validation::print_strategy_coverage;
 use rand::prelude::SliceRandom;
+use rand::rngs::StdRng;
 use rand::thread_rng;
+use rand::SeedableRng;
 use rayon::prelude::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::fs;
 use std::fs::File;
-use std::io::{BufReader, Write};
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::ops::Range;
+use std::path::Path;
 
-// TODO: Use a parameter file
-const NODES_PATH: &str = "products/nodes.bin";
+pub const CHECKPOINT_PATH: &str = "products/checkpoint.bin";
+const CHECKPOINT_INTERVAL: u64 = 1_000_000;
+// Deals trained on each snapshot of the strategy in train_parallel()
+const PARALLEL_BATCH_SIZE: u64 = 64;
 
-pub fn train(iters: u64) {
-    let mut rng = thread_rng();
-    let mut deck = card_utils::deck();
-    let mut nodes: HashMap<CompactInfoSet, Node> = HashMap::new();
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(std::io::Error),
+    Serialization(bincode::Error),
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckpointError::Io(e) => write!(f, "checkpoint I/O error: {}", e),
+            CheckpointError::Serialization(e) => write!(f, "checkpoint serialization error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+impl From<std::io::Error> for CheckpointError {
+    fn from(e: std::io::Error) -> CheckpointError {
+        CheckpointError::Io(e)
+    }
+}
+
+impl From<bincode::Error> for CheckpointError {
+    fn from(e: bincode::Error) -> CheckpointError {
+        CheckpointError::Serialization(e)
+    }
+}
+
+// Strategies are saved as STRATEGY_MAGIC, then the format version as a
+// little-endian u32, then the bincoded nodes. Version 1 files are just the
+// nodes; migrate_strategy_v1_to_v2() converts them.
+pub const STRATEGY_FORMAT_VERSION: u32 = 2;
+const STRATEGY_MAGIC: [u8; 4] = *b"PKST";
+
+// The quantized blueprint gets the same kind of header with its own magic, so
+// a strategy file can't be loaded as a blueprint or the other way around.
+// Blueprints written before the header existed count as version 0.
+pub const BLUEPRINT_FORMAT_VERSION: u32 = 1;
+const BLUEPRINT_MAGIC: [u8; 4] = *b"PKBP";
+
+#[derive(Debug)]
+pub struct StrategyLoadError {
+    pub source: StrategyLoadErrorKind,
+    pub path: String,
+}
+
+#[derive(Debug)]
+pub enum StrategyLoadErrorKind {
+    FileNotFound,
+    // Any other problem opening or writing the file
+    Io(io::Error),
+    CorruptData(bincode::Error),
+    // The version in the file, then the version that was expected
+    VersionMismatch(u32, u32),
+}
+
+impl fmt::Display for StrategyLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "could not load strategy {}: ", self.path)?;
+        match &self.source {
+            StrategyLoadErrorKind::FileNotFound => {
+                write!(f, "file not found, has the blueprint been trained?")
+            }
+            StrategyLoadErrorKind::Io(e) => write!(f, "{}", e),
+            StrategyLoadErrorKind::CorruptData(e) => write!(f, "corrupt data: {}", e),
+            StrategyLoadErrorKind::VersionMismatch(1, STRATEGY_FORMAT_VERSION) => write!(
+                f,
+                "saved in format version 1, expected {} (run migrate_strategy_v1_to_v2 on it)",
+                STRATEGY_FORMAT_VERSION
+            ),
+            StrategyLoadErrorKind::VersionMismatch(0, expected) => write!(
+                f,
+                "saved without a format header, expected version {} (delete it to rebuild it)",
+                expected
+            ),
+            StrategyLoadErrorKind::VersionMismatch(found, expected) => write!(
+                f,
+                "saved in format version {}, expected {}",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StrategyLoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.source {
+            StrategyLoadErrorKind::Io(e) => Some(e),
+            StrategyLoadErrorKind::CorruptData(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for StrategyLoadErrorKind {
+    fn from(e: io::Error) -> StrategyLoadErrorKind {
+        match e.kind() {
+            io::ErrorKind::NotFound => StrategyLoadErrorKind::FileNotFound,
+            _ => StrategyLoadErrorKind::Io(e),
+        }
+    }
+}
+
+impl From<bincode::Error> for StrategyLoadErrorKind {
+    fn from(e: bincode::Error) -> StrategyLoadErrorKind {
+        match *e {
+            bincode::ErrorKind::Io(e) => StrategyLoadErrorKind::from(e),
+            kind => StrategyLoadErrorKind::CorruptData(Box::new(kind)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CsvError {
+    Io(io::Error),
+    // A line of the file that couldn't be read, counting from 1
+    Parse(usize, String),
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CsvError::Io(e) => write!(f, "strategy CSV I/O error: {}", e),
+            CsvError::Parse(line, message) => {
+                write!(f, "strategy CSV error on line {}: {}", line, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CsvError {}
+
+impl From<io::Error> for CsvError {
+    fn from(e: io::Error) -> CsvError {
+        CsvError::Io(e)
+    }
+}
+
+// The training algorithms that train_with_config() can run
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum CFRAlgorithm {
+    Vanilla,
+    CFRPlus,
+    DCFR(DCFRConfig),
+    Linear,
+    // Outcome sampling MCCFR, with the given exploration epsilon
+    OutcomeSampling(f64),
+    ExternalSampling,
+}
+
+// Everything needed to run a training session. Use TrainingConfig::builder()
+// to override just the settings that differ from the defaults. It's also the
+// [training] table of the config file.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct TrainingConfig {
+    pub algorithm: CFRAlgorithm,
+    pub iterations: u64,
+    // Config files checkpoint to CHECKPOINT_PATH unless they say otherwise
+    #[serde(default = "default_checkpoint_path")]
+    pub checkpoint_path: Option<String>,
+    pub checkpoint_interval: u64,
+    pub seed: u64,
+    // Size of the rayon thread pool, or 0 to let rayon decide
+    pub num_threads: usize,
+}
+
+impl Default for TrainingConfig {
+    fn default() -> TrainingConfig {
+        TrainingConfig {
+            algorithm: CFRAlgorithm::DCFR(DCFRConfig::default()),
+            iterations: 1_000_000,
+            checkpoint_path: None,
+            checkpoint_interval: CHECKPOINT_INTERVAL,
+            seed: 0,
+            num_threads: 0,
+        }
+    }
+}
+
+fn default_checkpoint_path() -> Option<String> {
+    Some(CHECKPOINT_PATH.to_string())
+}
+
+impl TrainingConfig {
+    pub fn builder() -> TrainingConfigBuilder {
+        TrainingConfigBuilder {
+            config: TrainingConfig::default(),
+        }
+    }
+
+    // Catches settings that would only blow up partway through training
+    pub fn validate(&self) -> Result<(), TrainingConfigError> {
+        if self.checkpoint_interval == 0 {
+            return Err(TrainingConfigError::ZeroCheckpointInterval);
+        }
+        if let CFRAlgorithm::OutcomeSampling(epsilon) = self.algorithm {
+            // Without exploration the traverser's sampling probabilities can
+            // be zero, and the importance weights divide by them
+            if !(epsilon > 0.0 && epsilon <= 1.0) {
+                return Err(TrainingConfigError::BadEpsilon(epsilon));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrainingConfigError {
+    ZeroCheckpointInterval,
+    // Outcome sampling's exploration has to be in (0, 1]
+    BadEpsilon(f64),
+}
+
+impl fmt::Display for TrainingConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrainingConfigError::ZeroCheckpointInterval => {
+                write!(f, "the checkpoint interval must be positive")
+            }
+            TrainingConfigError::BadEpsilon(epsilon) => write!(
+                f,
+                "the outcome sampling epsilon must be in (0, 1], not {}",
+                epsilon
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TrainingConfigError {}
+
+pub struct TrainingConfigBuilder {
+    config: TrainingConfig,
+}
+
+impl TrainingConfigBuilder {
+    pub fn algorithm(mut self, algorithm: CFRAlgorithm) -> TrainingConfigBuilder {
+        self.config.algorithm = algorithm;
+        self
+    }
+
+    pub fn iterations(mut self, iterations: u64) -> TrainingConfigBuilder {
+        self.config.iterations = iterations;
+        self
+    }
+
+    pub fn checkpoint_path(mut self, path: &str) -> TrainingConfigBuilder {
+        self.config.checkpoint_path = Some(path.to_string());
+        self
+    }
+
+    pub fn checkpoint_interval(mut self, interval: u64) -> TrainingConfigBuilder {
+        self.config.checkpoint_interval = interval;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> TrainingConfigBuilder {
+        self.config.seed = seed;
+        self
+    }
+
+    pub fn num_threads(mut self, num_threads: usize) -> TrainingConfigBuilder {
+        self.config.num_threads = num_threads;
+        self
+    }
+
+    // Panics if the settings are invalid, see TrainingConfig::validate()
+    pub fn build(self) -> TrainingConfig {
+        if let Err(e) = self.config.validate() {
+            panic!("Bad training config: {}", e);
+        }
+        self.config
+    }
+}
+
+// Hooks for watching a training run, eg for logging, early stopping or
+// plotting, without changing the training code. on_iteration() gets the
+// number of iterations finished so far.
+pub trait TrainingCallback: Send {
+    fn on_iteration(&mut self, iteration: u64, strategy: &NodeMap);
+    fn on_checkpoint_saved(&mut self, path: &str);
+}
+
+// The default callback, which shows the usual progress bar
+pub struct ProgressBarCallback {
+    bar: indicatif::ProgressBar,
+    iters: u64,
+}
+
+impl ProgressBarCallback {
+    pub fn new(iters: u64) -> ProgressBarCallback {
+        ProgressBarCallback {
+            bar: card_utils::pbar(iters),
+            iters: iters,
+        }
+    }
+}
+
+impl TrainingCallback for ProgressBarCallback {
+    fn on_iteration(&mut self, iteration: u64, _strategy: &NodeMap) {
+        self.bar.set_position(iteration);
+        if iteration == self.iters {
+            self.bar.finish();
+        }
+    }
+
+    fn on_checkpoint_saved(&mut self, _path: &str) {}
+}
+
+// Prints the exploitability of the strategy every `interval` iterations.
+// Calculating exploitability is slow, so the interval should be large.
+pub struct ExploitabilityCallback {
+    interval: u64,
+}
+
+impl ExploitabilityCallback {
+    pub fn new(interval: u64) -> ExploitabilityCallback {
+        assert!(interval > 0, "The exploitability interval must be positive");
+        ExploitabilityCallback { interval: interval }
+    }
+}
+
+impl TrainingCallback for ExploitabilityCallback {
+    fn on_iteration(&mut self, iteration: u64, strategy: &NodeMap) {
+        if iteration % self.interval == 0 {
+            let exploit = exploitability(strategy);
+            println!(
+                "[INFO] Exploitability at iteration {}: {} BB/h",
+                iteration, exploit
+            );
+        }
+    }
+
+    fn on_checkpoint_saved(&mut self, _path: &str) {}
+}
+
+// Runs the training algorithm in the config. Checkpoints and seeding are only
+// supported by the full-traversal algorithms so far, the MCCFR ones ignore
+// those settings. Progress goes to the callback, or to a progress bar if
+// there isn't one.
+pub fn train_with_config(config: TrainingConfig, callback: Option<Box<dyn TrainingCallback>>) {
+    let mut callback =
+        callback.unwrap_or_else(|| Box::new(ProgressBarCallback::new(config.iterations)));
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(config.num_threads)
+        .build()
+        .expect("Could not build the thread pool");
+    let checkpoint = config.checkpoint_path.as_ref().map(|p| p.as_str());
+    pool.install(|| {
+        let variant = match config.algorithm {
+            CFRAlgorithm::Vanilla => CFRVariant::Vanilla,
+            CFRAlgorithm::CFRPlus => CFRVariant::CFRPlus,
+            CFRAlgorithm::DCFR(dcfr) => CFRVariant::DCFR(dcfr),
+            CFRAlgorithm::Linear => CFRVariant::Linear,
+            CFRAlgorithm::OutcomeSampling(epsilon) => {
+                return train_mccfr_outcome(config.iterations, epsilon, callback.as_mut())
+            }
+            CFRAlgorithm::ExternalSampling => {
+                return train_mccfr_external(config.iterations, callback.as_mut())
+            }
+        };
+        train_variant(
+            config.iterations,
+            variant,
+            checkpoint,
+            config.checkpoint_interval,
+            config.seed,
+            callback.as_mut(),
+        );
+    });
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StrategySizeReport {
+    pub total_bytes: usize,
+    pub entries: usize,
+    pub bytes_per_entry: f64,
+    // Number of entries on each street, PREFLOP to RIVER
+    pub by_street: [usize; 4],
+}
+
+impl fmt::Display for StrategySizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Strategy uses {:.2} MB for {} infosets ({:.1} bytes each). \
+             Preflop: {}, flop: {}, turn: {}, river: {}",
+            self.total_bytes as f64 / 1e6,
+            self.entries,
+            self.bytes_per_entry,
+            self.by_street[PREFLOP],
+            self.by_street[FLOP],
+            self.by_street[TURN],
+            self.by_street[RIVER]
+        )
+    }
+}
+
+// Estimates how much memory the strategy takes up, to see how far training
+// can go before it runs out. Each entry counts the size of its key and node
+// along with what they keep on the heap, but not the NodeMap's own overhead.
+pub fn strategy_size_bytes(nodes: &NodeMap) -> StrategySizeReport {
+    let mut total_bytes = 0;
+    let mut by_street = [0; 4];
+    for (infoset, node) in nodes {
+        total_bytes += infoset.size_bytes() + node.size_bytes();
+        by_street[infoset.uncompress().street() as usize] += 1;
+    }
+    let bytes_per_entry = if nodes.is_empty() {
+        0.0
+    } else {
+        total_bytes as f64 / nodes.len() as f64
+    };
+    StrategySizeReport {
+        total_bytes: total_bytes,
+        entries: nodes.len(),
+        bytes_per_entry: bytes_per_entry,
+        by_street: by_street,
+    }
+}
+
+// Reach probability of each infoset, from compute_reach_probabilities()
+pub type ReachProbMap = HashMap<CompactInfoSet, f64>;
+
+// The infosets at each compressed history and their average strategies
+type StrategiesByHistory<'a> = HashMap<&'a [u8], Vec<(&'a CompactInfoSet, HashMap<Action, f64>)>>;
+
+// For each infoset, how likely its player's own strategy is to lead there.
+// The opponent's actions aren't counted, since a best response can play into
+// anything they allow. The cards aren't known along the way, so each action is
+// taken with the highest probability of any hand that has a node there, which
+// makes these upper bounds.
+pub fn compute_reach_probabilities(nodes: &NodeMap) -> ReachProbMap {
+    let mut by_history = StrategiesByHistory::new();
+    // Every history that leads to a node, so the traversal knows where to stop
+    let mut prefixes: HashSet<&[u8]> = HashSet::new();
+    for (infoset, node) in nodes {
+        let history = infoset.history();
+        by_history
+            .entry(history)
+            .or_insert_with(Vec::new)
+            .push((infoset, node.cumulative_strategy()));
+        for i in 0..=history.len() {
+            prefixes.insert(&history[..i]);
+        }
+    }
+    let mut reach = ReachProbMap::new();
+    add_reach_probabilities(
+        &by_history,
+        &prefixes,
+        ActionHistory::new(),
+        [1.0, 1.0],
+        &mut reach,
+    );
+    reach
+}
+
+fn add_reach_probabilities(
+    by_history: &StrategiesByHistory,
+    prefixes: &HashSet<&[u8]>,
+    history: ActionHistory,
+    own_reach: [f64; 2],
+    reach: &mut ReachProbMap,
+) {
+    let compressed = history.compress(bet_abstraction());
+    if history.hand_over() || !prefixes.contains(&compressed[..]) {
+        return;
+    }
+    let player = history.player;
+    let infosets = by_history.get(&compressed[..]);
+    if let Some(infosets) = infosets {
+        for (infoset, _) in infosets {
+            reach.insert((*infoset).clone(), own_reach[player]);
+        }
+    }
+    for action in history.next_actions(bet_abstraction()) {
+        // Nobody's strategy is known here if there are no nodes, so assume
+        // the action could always be taken
+        let prob = match infosets {
+            Some(infosets) => infosets
+                .iter()
+                .map(|(_, strategy)| strategy.get(&action).cloned().unwrap_or(0.0))
+                .fold(0.0, f64::max),
+            None => 1.0,
+        };
+        let mut next_history = history.clone();
+        next_history.add(&action);
+        let mut next_reach = own_reach;
+        next_reach[player] *= prob;
+        add_reach_probabilities(by_history, prefixes, next_history, next_reach, reach);
+    }
+}
+
+// Drops the infosets whose player reaches them with less than min_reach_prob,
+// for a smaller blueprint. Infosets without a reach probability are kept.
+pub fn prune_strategy(
+    nodes: &NodeMap,
+    min_reach_prob: f64,
+    history_probs: &ReachProbMap,
+) -> NodeMap {
+    nodes
+        .iter()
+        .filter(|(infoset, _)| history_probs.get(infoset).cloned().unwrap_or(1.0) >= min_reach_prob)
+        .map(|(infoset, node)| (infoset.clone(), node.clone()))
+        .collect()
+}
+
+// Trains the blueprint with Discounted CFR using the default parameters.
+// If a checkpoint path is given, training resumes from the checkpoint there
+// (if there is one) and saves a new one every million iterations.
+pub fn train(iters: u64, checkpoint: Option<&str>) {
+    let variant = CFRVariant::DCFR(DCFRConfig::default());
+    train_variant(
+        iters,
+        variant,
+        checkpoint,
+        CHECKPOINT_INTERVAL,
+        0,
+        &mut ProgressBarCallback::new(iters),
+    );
+}
+
+pub fn train_dcfr(iters: u64, config: DCFRConfig) {
+    train_variant(
+        iters,
+        CFRVariant::DCFR(config),
+        None,
+        CHECKPOINT_INTERVAL,
+        0,
+        &mut ProgressBarCallback::new(iters),
+    );
+}
+
+// Trains the blueprint with CFR+. The traversal is the same as train(), only
+// the regret and cumulative strategy updates differ.
+pub fn train_cfr_plus(iters: u64) {
+    train_variant(
+        iters,
+        CFRVariant::CFRPlus,
+        None,
+        CHECKPOINT_INTERVAL,
+        0,
+        &mut ProgressBarCallback::new(iters),
+    );
+}
+
+// Trains the blueprint with Linear CFR, which weights iteration t's regrets
+// and strategy by t so that the early, mostly random iterations fade out.
+// Same traversal as train() again.
+pub fn train_linear_cfr(iters: u64) {
+    train_variant(
+        iters,
+        CFRVariant::Linear,
+        None,
+        CHECKPOINT_INTERVAL,
+        0,
+        &mut ProgressBarCallback::new(iters),
+    );
+}
+
+fn train_variant(
+    iters: u64,
+    variant: CFRVariant,
+    checkpoint: Option<&str>,
+    checkpoint_interval: u64,
+    seed: u64,
+    callback: &mut dyn TrainingCallback,
+) {
+    let (mut nodes, start) = match checkpoint {
+        Some(path) if Path::new(path).exists() => {
+            let (nodes, start) = load_checkpoint(path).expect("Could not load checkpoint");
+            println!("[INFO] Resuming training from iteration {}.", start);
+            (nodes, start)
+        }
+        _ => (HashMap::new(), 0),
+    };
     lazy_static::initialize(&HAND_TABLE);
     lazy_static::initialize(&ABSTRACTION);
     println!("[INFO] Beginning training.");
+    let [p0_util, p1_util] = train_iterations(
+        &mut nodes,
+        start..iters,
+        variant,
+        seed,
+        checkpoint,
+        checkpoint_interval,
+        callback,
+    );
+    // exploitability(&nodes);
+
+    // view_preflop(&nodes);
+
+    println!("{} nodes reached.", nodes.len());
+    println!("{}", strategy_size_bytes(&nodes));
+    // Training ran on this thread, so its cache saw all the lookups
+    let (hits, misses) = card_utils::canonical_cache_stats();
+    println!(
+        "[INFO] canonical_hand_cached() cache: {} hits, {} misses ({:.1}% hit rate)",
+        hits,
+        misses,
+        100.0 * hits as f64 / (hits + misses).max(1) as f64
+    );
+    // Resuming from a checkpoint at or past iters runs no iterations
+    let n_iters = iters.saturating_sub(start);
+    if n_iters > 0 {
+        println!(
+            "Utilities:
+            Dealer:   {} BB/h,
+            Opponent: {} BB/h",
+            p0_util / (n_iters as f64) / (BIG_BLIND as f64),
+            p1_util / (n_iters as f64) / (BIG_BLIND as f64),
+        );
+    }
+
+    serialize_nodes(&nodes);
+    write_compact_blueprint(&nodes);
+    print_strategy_coverage(&nodes);
+    // println!("Exploitability: {}", exploitability(&nodes));
+}
+
+// The training loop behind train_variant(). Runs the given iterations on the
+// nodes, saving checkpoints and reporting to the callback along the way, and
+// returns the total utility of each player.
+pub fn train_iterations(
+    nodes: &mut NodeMap,
+    iterations: Range<u64>,
+    variant: CFRVariant,
+    seed: u64,
+    checkpoint: Option<&str>,
+    checkpoint_interval: u64,
+    callback: &mut dyn TrainingCallback,
+) -> [f64; 2] {
+    train_iterations_with(
+        nodes,
+        iterations,
+        variant,
+        seed,
+        checkpoint,
+        checkpoint_interval,
+        callback,
+        InfoSet::from_deck,
+    )
+}
+
+// train_iterations() with the infosets built by the given function, like
+// train_iteration_with()
+pub fn train_iterations_with(
+    nodes: &mut NodeMap,
+    iterations: Range<u64>,
+    variant: CFRVariant,
+    seed: u64,
+    checkpoint: Option<&str>,
+    checkpoint_interval: u64,
+    callback: &mut dyn TrainingCallback,
+    infoset_fn: fn(&[Card], &ActionHistory) -> InfoSet,
+) -> [f64; 2] {
     let mut p0_util = 0.0;
     let mut p1_util = 0.0;
-    let bar = card_utils::pbar(iters);
-    for i in 0..iters {
-        deck.shuffle(&mut rng);
-        p0_util += iterate(DEALER, &deck, ActionHistory::new(), [1.0, 1.0], &mut nodes);
-        deck.shuffle(&mut rng);
-        p1_util += iterate(
-            OPPONENT,
-            &deck,
-            ActionHistory::new(),
-            [1.0, 1.0],
-            &mut nodes,
-        );
-        if i % 1_000_000 == 0 {
-            serialize_nodes(&nodes);
+    for i in iterations {
+        let [u0, u1] = train_iteration_with(seed, i, nodes, variant, infoset_fn);
+        p0_util += u0;
+        p1_util += u1;
+        if (i + 1) % 1_000_000 == 0 {
+            serialize_nodes(nodes);
+        }
+        if (i + 1) % checkpoint_interval == 0 {
+            if let Some(path) = checkpoint {
+                save_checkpoint(nodes, i + 1, path).expect("Could not save checkpoint");
+                callback.on_checkpoint_saved(path);
+            }
         }
-        bar.inc(1);
+        callback.on_iteration(i + 1, nodes);
     }
-    bar.finish();
-    // exploitability(&nodes);
+    [p0_util, p1_util]
+}
 
-    // view_preflop(&nodes);
+// Runs one CFR iteration for each player. The deal and the opponent's sampled
+// actions only depend on the seed and the iteration number, so a run that was
+// resumed from a checkpoint ends up the same as one that was never interrupted.
+pub fn train_iteration(seed: u64, i: u64, nodes: &mut NodeMap, variant: CFRVariant) -> [f64; 2] {
+    train_iteration_with(seed, i, nodes, variant, InfoSet::from_deck)
+}
+
+// train_iteration() with the infosets built by the given function instead of
+// InfoSet::from_deck(), which needs the abstraction tables
+pub fn train_iteration_with(
+    seed: u64,
+    i: u64,
+    nodes: &mut NodeMap,
+    variant: CFRVariant,
+    infoset_fn: fn(&[Card], &ActionHistory) -> InfoSet,
+) -> [f64; 2] {
+    let mut rng = StdRng::seed_from_u64(seed.rotate_left(32) ^ i);
+    let mut deck = card_utils::deck();
+    deck.shuffle(&mut rng);
+    let p0_util = iterate(
+        DEALER,
+        &deck,
+        ActionHistory::new(),
+        [1.0, 1.0],
+        nodes,
+        None,
+        variant,
+        infoset_fn,
+        &mut rng,
+    );
+    deck.shuffle(&mut rng);
+    let p1_util = iterate(
+        OPPONENT,
+        &deck,
+        ActionHistory::new(),
+        [1.0, 1.0],
+        nodes,
+        None,
+        variant,
+        infoset_fn,
+        &mut rng,
+    );
+    [p0_util, p1_util]
+}
 
+// Trains n_seeds blueprints with different seeds in parallel and averages
+// them, which smooths out the noise from any one run's card sampling.
+pub fn train_ensemble(iters: u64, n_seeds: u64) -> CompressedStrategy {
+    lazy_static::initialize(&HAND_TABLE);
+    lazy_static::initialize(&ABSTRACTION);
+    println!("[INFO] Training an ensemble of {} blueprints.", n_seeds);
+    let variant = CFRVariant::DCFR(DCFRConfig::default());
+    let seeds: Vec<u64> = (0..n_seeds).collect();
+    let bar = card_utils::pbar(iters * n_seeds);
+    let runs: Vec<NodeMap> = seeds
+        .par_iter()
+        .map(|seed| {
+            let mut nodes: NodeMap = HashMap::new();
+            for i in 0..iters {
+                train_iteration(*seed, i, &mut nodes, variant);
+                bar.inc(1);
+            }
+            nodes
+        })
+        .collect();
+    bar.finish();
+    compress_strategy(&average_strategies(&runs))
+}
+
+// Trains the blueprint with vanilla CFR on n_threads threads. See
+// train_parallel_iterations() for how the work is split up.
+pub fn train_parallel(iters: u64, n_threads: usize) {
+    lazy_static::initialize(&HAND_TABLE);
+    lazy_static::initialize(&ABSTRACTION);
+    println!("[INFO] Beginning training on {} threads.", n_threads);
+    let mut nodes: NodeMap = HashMap::new();
+    let [p0_util, p1_util] = train_parallel_iterations(
+        &mut nodes,
+        0..iters,
+        0,
+        n_threads,
+        PARALLEL_BATCH_SIZE,
+        &mut ProgressBarCallback::new(iters),
+    );
     println!("{} nodes reached.", nodes.len());
     println!(
         "Utilities:
@@ -53,10 +793,371 @@ pub fn train(iters: u64) {
         p0_util / (iters as f64) / (BIG_BLIND as f64),
         p1_util / (iters as f64) / (BIG_BLIND as f64),
     );
+    serialize_nodes(&nodes);
+    write_compact_blueprint(&nodes);
+}
+
+// Parallel vanilla CFR. The iterations are split into batches of batch_size
+// deals, and every deal in a batch is traversed against the same snapshot of
+// the nodes, with its regret and strategy updates kept in a map of its own.
+// Once the batch is done the updates are added to the nodes in deal order, so
+// nothing is shared between threads while they work and the result doesn't
+// depend on how many threads there were or which deals each one got. Deals
+// are the same as train_iteration()'s, and each deal sees its own updates, so
+// with a batch size of 1 this is the same as training sequentially. Bigger
+// batches can't see the rest of the batch's updates.
+pub fn train_parallel_iterations(
+    nodes: &mut NodeMap,
+    iterations: Range<u64>,
+    seed: u64,
+    n_threads: usize,
+    batch_size: u64,
+    callback: &mut dyn TrainingCallback,
+) -> [f64; 2] {
+    train_parallel_iterations_with(
+        nodes,
+        iterations,
+        seed,
+        n_threads,
+        batch_size,
+        callback,
+        InfoSet::from_deck,
+    )
+}
+
+// train_parallel_iterations() with the infosets built by the given function,
+// like train_iteration_with()
+pub fn train_parallel_iterations_with(
+    nodes: &mut NodeMap,
+    iterations: Range<u64>,
+    seed: u64,
+    n_threads: usize,
+    batch_size: u64,
+    callback: &mut dyn TrainingCallback,
+    infoset_fn: fn(&[Card], &ActionHistory) -> InfoSet,
+) -> [f64; 2] {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(n_threads)
+        .build()
+        .expect("Could not build the thread pool");
+    assert!(batch_size > 0, "The batch size must be positive");
+    let mut utils = [0.0, 0.0];
+    let mut start = iterations.start;
+    while start < iterations.end {
+        let end = (start + batch_size).min(iterations.end);
+        let snapshot: &NodeMap = nodes;
+        let batch: Vec<([f64; 2], NodeMap)> = pool.install(|| {
+            (start..end)
+                .into_par_iter()
+                .map(|i| train_iteration_on_snapshot(seed, i, snapshot, infoset_fn))
+                .collect()
+        });
+        for ([u0, u1], updates) in batch {
+            utils[0] += u0;
+            utils[1] += u1;
+            for (infoset, update) in updates {
+                // The updates started from an empty node, so a new infoset
+                // can take them as they are
+                match nodes.get_mut(&infoset) {
+                    Some(node) => node.add_node(&update),
+                    None => {
+                        nodes.insert(infoset, update);
+                    }
+                }
+            }
+        }
+        callback.on_iteration(end, nodes);
+        start = end;
+    }
+    utils
+}
+
+// train_iteration() for train_parallel_iterations(), which leaves the nodes
+// alone and returns the updates it would have made
+fn train_iteration_on_snapshot(
+    seed: u64,
+    i: u64,
+    snapshot: &NodeMap,
+    infoset_fn: fn(&[Card], &ActionHistory) -> InfoSet,
+) -> ([f64; 2], NodeMap) {
+    let mut rng = StdRng::seed_from_u64(seed.rotate_left(32) ^ i);
+    let mut updates: NodeMap = HashMap::new();
+    let mut deck = card_utils::deck();
+    deck.shuffle(&mut rng);
+    let p0_util = iterate(
+        DEALER,
+        &deck,
+        ActionHistory::new(),
+        [1.0, 1.0],
+        &mut updates,
+        Some(snapshot),
+        CFRVariant::Vanilla,
+        infoset_fn,
+        &mut rng,
+    );
+    deck.shuffle(&mut rng);
+    let p1_util = iterate(
+        OPPONENT,
+        &deck,
+        ActionHistory::new(),
+        [1.0, 1.0],
+        &mut updates,
+        Some(snapshot),
+        CFRVariant::Vanilla,
+        infoset_fn,
+        &mut rng,
+    );
+    ([p0_util, p1_util], updates)
+}
+
+// L2 distance between the average strategies of two sets of nodes, over all
+// the infosets that either one has. An infoset that only one of them reached
+// is compared to the uniform strategy, since that's what an untrained node
+// plays.
+pub fn strategy_diff(s1: &NodeMap, s2: &NodeMap) -> f64 {
+    squared_strategy_diffs(s1, s2)
+        .iter()
+        .map(|(_, diff)| diff)
+        .sum::<f64>()
+        .sqrt()
+}
+
+// Same as strategy_diff(), but split up by the street of each infoset
+pub fn strategy_diff_by_street(s1: &NodeMap, s2: &NodeMap) -> [f64; 4] {
+    let mut by_street = [0.0; 4];
+    for (infoset, diff) in squared_strategy_diffs(s1, s2) {
+        by_street[infoset.uncompress().street() as usize] += diff;
+    }
+    for diff in by_street.iter_mut() {
+        *diff = diff.sqrt();
+    }
+    by_street
+}
+
+fn squared_strategy_diffs<'a>(s1: &'a NodeMap, s2: &'a NodeMap) -> Vec<(&'a CompactInfoSet, f64)> {
+    let squared_diff = |node1: Option<&Node>, node2: Option<&Node>| {
+        let uniform = |node: &Node| {
+            let n_actions = node.cumulative_strategy().len() as f64;
+            node.cumulative_strategy()
+                .keys()
+                .map(|a| (a.clone(), 1.0 / n_actions))
+                .collect::<HashMap<Action, f64>>()
+        };
+        let (strategy1, strategy2) = match (node1, node2) {
+            (Some(n1), Some(n2)) => (n1.cumulative_strategy(), n2.cumulative_strategy()),
+            (Some(n1), None) => (n1.cumulative_strategy(), uniform(n1)),
+            (None, Some(n2)) => (uniform(n2), n2.cumulative_strategy()),
+            (None, None) => unreachable!(),
+        };
+        strategy1
+            .iter()
+            .map(|(action, p)| (p - strategy2[action]).powi(2))
+            .sum::<f64>()
+    };
+    let mut diffs = Vec::new();
+    for (infoset, node) in s1 {
+        diffs.push((infoset, squared_diff(Some(node), s2.get(infoset))));
+    }
+    for (infoset, node) in s2 {
+        if !s1.contains_key(infoset) {
+            diffs.push((infoset, squared_diff(None, Some(node))));
+        }
+    }
+    diffs
+}
+
+// Writes the average strategy to a CSV file with one row per infoset, sorted by
+// street and then by card bucket. The infosets don't know the actual cards,
+// so the card bucket stands in for the hand and board. Each row is
+//
+//      street,bucket,"history",action1,action1_prob,action2,action2_prob,...
+//
+// where the history and actions are written the same way they're displayed.
+pub fn export_strategy_csv(nodes: &NodeMap, path: &str) -> Result<(), io::Error> {
+    let mut rows: Vec<(u8, &CompactInfoSet, InfoSet)> = nodes
+        .keys()
+        .map(|infoset| {
+            let full = infoset.uncompress();
+            (full.street(), infoset, full)
+        })
+        .collect();
+    rows.sort_by_key(|(street, infoset, full)| {
+        (*street, infoset.card_bucket(), full.history.to_string())
+    });
+    let max_actions = nodes
+        .values()
+        .map(|n| n.cumulative_strategy().len())
+        .max()
+        .unwrap_or(0);
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    let mut header = String::from("street,bucket,history");
+    for i in 1..=max_actions {
+        header.push_str(&format!(",action{},action{}_prob", i, i));
+    }
+    writeln!(writer, "{}", header)?;
+    for (street, infoset, full) in rows {
+        let strategy = nodes[infoset].cumulative_strategy();
+        write!(
+            writer,
+            "{},{},\"{}\"",
+            street,
+            infoset.card_bucket(),
+            full.history
+        )?;
+        for action in full.next_actions() {
+            write!(writer, ",{},{}", action, strategy[&action])?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+// Reads back a strategy written by export_strategy_csv(). Only the average
+// strategy is saved, so the nodes come back without their regrets.
+pub fn import_strategy_csv(path: &str) -> Result<NodeMap, CsvError> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut nodes: NodeMap = HashMap::new();
+    for (i, line) in reader.lines().enumerate().skip(1) {
+        let line = line?;
+        let parse_error = |message: &str| CsvError::Parse(i + 1, message.to_string());
+        // The history is the only quoted field, and the only one with commas
+        let mut quoted = line.split('"');
+        let (before, history, after) = match (quoted.next(), quoted.next(), quoted.next()) {
+            (Some(before), Some(history), Some(after)) => (before, history, after),
+            _ => return Err(parse_error("missing quoted history")),
+        };
+        let bucket: i32 = before
+            .split(',')
+            .nth(1)
+            .and_then(|b| b.parse().ok())
+            .ok_or_else(|| parse_error("bad card bucket"))?;
+
+        let mut full_history = ActionHistory::new();
+        for action in history.split(|c| c == ',' || c == ';') {
+            if action.is_empty() {
+                continue;
+            }
+            let action = parse_action(action).ok_or_else(|| parse_error("bad action"))?;
+            full_history.add(&action);
+        }
+
+        let fields: Vec<&str> = after.split(',').skip(1).collect();
+        let mut strategy = HashMap::new();
+        for pair in fields.chunks(2) {
+            match pair {
+                [action, prob] => {
+                    let action = parse_action(action).ok_or_else(|| parse_error("bad action"))?;
+                    let prob: f64 = prob.parse().map_err(|_| parse_error("bad probability"))?;
+                    strategy.insert(action, prob);
+                }
+                _ => return Err(parse_error("action without a probability")),
+            }
+        }
+        let infoset = CompactInfoSet::new(full_history.compress(bet_abstraction()), bucket);
+        nodes.insert(infoset, Node::from_strategy(&strategy));
+    }
+    Ok(nodes)
+}
+
+// Parses an action the way it's displayed, eg "bet 300"
+fn parse_action(action: &str) -> Option<Action> {
+    let mut parts = action.split(' ');
+    let action_type = match parts.next()? {
+        "fold" => ActionType::Fold,
+        "call" => ActionType::Call,
+        "bet" => ActionType::Bet,
+        _ => return None,
+    };
+    let amount = parts.next()?.parse().ok()?;
+    Some(Action {
+        action: action_type,
+        amount: amount,
+    })
+}
+
+// Saves the nodes and the number of iterations done so far. The checkpoint is
+// written to a temporary file first so that an interrupted save can't clobber
+// the previous checkpoint.
+pub fn save_checkpoint(nodes: &NodeMap, iteration: u64, path: &str) -> Result<(), CheckpointError> {
+    let tmp_path = format!("{}.tmp", path);
+    let bincode: Vec<u8> = bincode::serialize(&(nodes, iteration))?;
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(&bincode)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    println!("[INFO] Saved checkpoint at iteration {}.", iteration);
+    Ok(())
+}
+
+pub fn load_checkpoint(path: &str) -> Result<(NodeMap, u64), CheckpointError> {
+    let file = File::open(path)?;
+    let reader = BufReader::new(file);
+    let (nodes, iteration) = bincode::deserialize_from(reader)?;
+    Ok((nodes, iteration))
+}
+
+// Trains the blueprint with outcome-sampling Monte Carlo CFR. Each iteration
+// samples a single trajectory through the game tree, with the traversing
+// player picking a uniformly random action with probability epsilon so that
+// every action keeps getting explored.
+pub fn train_mccfr_outcome(iters: u64, epsilon: f64, callback: &mut dyn TrainingCallback) {
+    let mut rng = thread_rng();
+    let mut deck = card_utils::deck();
+    let mut nodes: HashMap<CompactInfoSet, Node> = HashMap::new();
+    lazy_static::initialize(&HAND_TABLE);
+    lazy_static::initialize(&ABSTRACTION);
+    println!("[INFO] Beginning outcome sampling training.");
+    for i in 0..iters {
+        for player in &[DEALER, OPPONENT] {
+            deck.shuffle(&mut rng);
+            outcome_sample(
+                *player,
+                &deck,
+                ActionHistory::new(),
+                [1.0, 1.0],
+                1.0,
+                epsilon,
+                &mut nodes,
+            );
+        }
+        if i % 1_000_000 == 0 {
+            serialize_nodes(&nodes);
+        }
+        callback.on_iteration(i + 1, &nodes);
+    }
+    println!("{} nodes reached.", nodes.len());
 
     serialize_nodes(&nodes);
     write_compact_blueprint(&nodes);
-    // println!("Exploitability: {}", exploitability(&nodes));
+    print_strategy_coverage(&nodes);
+}
+
+// Trains the blueprint with external-sampling Monte Carlo CFR. Chance and the
+// opponent's actions are sampled, but every action of the traversing player is
+// explored, which gives lower variance than outcome sampling.
+pub fn train_mccfr_external(iters: u64, callback: &mut dyn TrainingCallback) {
+    let mut rng = thread_rng();
+    let mut deck = card_utils::deck();
+    let mut nodes: HashMap<CompactInfoSet, Node> = HashMap::new();
+    lazy_static::initialize(&HAND_TABLE);
+    lazy_static::initialize(&ABSTRACTION);
+    println!("[INFO] Beginning external sampling training.");
+    for i in 0..iters {
+        for player in &[DEALER, OPPONENT] {
+            deck.shuffle(&mut rng);
+            external_sample(*player, &deck, ActionHistory::new(), &mut nodes);
+        }
+        if i % 1_000_000 == 0 {
+            serialize_nodes(&nodes);
+        }
+        callback.on_iteration(i + 1, &nodes);
+    }
+    println!("{} nodes reached.", nodes.len());
+
+    serialize_nodes(&nodes);
+    write_compact_blueprint(&nodes);
+    print_strategy_coverage(&nodes);
 }
 
 pub fn view_preflop(nodes: &HashMap<InfoSet, Node>) {
@@ -75,98 +1176,454 @@ pub fn view_preflop(nodes: &HashMap<InfoSet, Node>) {
     }
 }
 
+// Prints the strategy for the given cards and history, translating the
+// history into the bet abstraction first like the bot does
+pub fn print_strategy_for_hand(
+    nodes: &NodeMap,
+    hand: &[Card],
+    board: &[Card],
+    history: &ActionHistory,
+) {
+    let translated = history.translate(bet_abstraction());
+    let infoset = InfoSet::from_hand(&[hand, board].concat(), &translated);
+    println!("{}", infoset);
+    match nodes.get(&infoset.compress()) {
+        Some(node) => println!("{}", node),
+        None => println!("Not in the strategy"),
+    }
+}
+
+// Prints how often the dealer raises, calls and folds each preflop hand when
+// opening, as percentages. Suited hands are above the diagonal, like
+// preflop_matrix_169().
+pub fn print_preflop_matrix(nodes: &NodeMap) {
+    print!("{}", preflop_matrix_string(nodes));
+}
+
+pub fn preflop_matrix_string(nodes: &NodeMap) -> String {
+    const RANKS: [&str; 13] = [
+        "A", "K", "Q", "J", "T", "9", "8", "7", "6", "5", "4", "3", "2",
+    ];
+    let history = ActionHistory::new();
+    let mut out = String::from("Raise/call/fold % for the dealer's first action\n  ");
+    for rank in RANKS.iter() {
+        out.push_str(&format!("{:^12}", rank));
+    }
+    out.push('\n');
+    for (row, rank) in RANKS.iter().enumerate() {
+        out.push_str(&format!("{} ", rank));
+        for col in 0..13 {
+            let infoset = InfoSet::new(history.clone(), (row * 13 + col) as i32);
+            let cell = match nodes.get(&infoset.compress()) {
+                Some(node) => {
+                    let mut freqs = [0.0; 3];
+                    for (action, prob) in node.cumulative_strategy() {
+                        let i = match action.action {
+                            ActionType::Bet => 0,
+                            ActionType::Call => 1,
+                            ActionType::Fold => 2,
+                        };
+                        freqs[i] += 100.0 * prob;
+                    }
+                    format!("{:.0}/{:.0}/{:.0}", freqs[0], freqs[1], freqs[2])
+                }
+                None => String::from("-"),
+            };
+            out.push_str(&format!("{:^12}", cell));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+// Loads the strategy, panicking if it can't. Use load_strategy() to handle
+// the error instead.
 pub fn load_nodes() -> HashMap<CompactInfoSet, Node> {
+    load_strategy().unwrap_or_else(|e| panic!("{}", e))
+}
+
+pub fn load_strategy() -> Result<NodeMap, StrategyLoadError> {
+    load_strategy_from(&config().paths.nodes)
+}
+
+pub fn load_strategy_from(path: &str) -> Result<NodeMap, StrategyLoadError> {
     println!("[INFO] Loading strategy...");
-    let file = File::open(NODES_PATH).expect("Nodes file not found");
-    let reader = BufReader::new(file);
-    let nodes = bincode::deserialize_from(reader).expect("Failed to deserialize nodes");
+    let error = |source| StrategyLoadError {
+        source: source,
+        path: String::from(path),
+    };
+    let file = File::open(path).map_err(|e| error(StrategyLoadErrorKind::from(e)))?;
+    let mut reader = BufReader::new(file);
+    let version = read_format_version(&mut reader, &STRATEGY_MAGIC)
+        .map_err(error)?
+        .unwrap_or(1);
+    if version != STRATEGY_FORMAT_VERSION {
+        return Err(error(StrategyLoadErrorKind::VersionMismatch(
+            version,
+            STRATEGY_FORMAT_VERSION,
+        )));
+    }
+    let nodes = bincode::deserialize_from(reader)
+        .map_err(|e| error(StrategyLoadErrorKind::CorruptData(e)))?;
     println!("[INFO] Done loading strategy");
-    nodes
+    Ok(nodes)
+}
+
+// The version in the header, or None if the file doesn't start with magic
+fn read_format_version(
+    reader: &mut impl Read,
+    magic: &[u8; 4],
+) -> Result<Option<u32>, StrategyLoadErrorKind> {
+    let mut header = [0; 8];
+    reader.read_exact(&mut header).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => StrategyLoadErrorKind::CorruptData(e.into()),
+        _ => StrategyLoadErrorKind::from(e),
+    })?;
+    if header[..4] != magic[..] {
+        return Ok(None);
+    }
+    let mut version = [0; 4];
+    version.copy_from_slice(&header[4..]);
+    Ok(Some(u32::from_le_bytes(version)))
+}
+
+fn write_format_header(writer: &mut impl Write, magic: &[u8; 4], version: u32) -> io::Result<()> {
+    writer.write_all(magic)?;
+    writer.write_all(&version.to_le_bytes())
+}
+
+pub fn save_strategy(nodes: &NodeMap, path: &str) -> Result<(), bincode::Error> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_format_header(&mut writer, &STRATEGY_MAGIC, STRATEGY_FORMAT_VERSION)?;
+    bincode::serialize_into(&mut writer, nodes)?;
+    writer.flush()?;
+    Ok(())
 }
 
 fn serialize_nodes(nodes: &HashMap<CompactInfoSet, Node>) {
-    let bincode: Vec<u8> = bincode::serialize(nodes).unwrap();
-    let mut file = File::create(NODES_PATH).unwrap();
-    file.write_all(&bincode).unwrap();
+    save_strategy(nodes, &config().paths.nodes).expect("Could not save the strategy");
     println!("[INFO] Saved strategy to disk.");
 }
 
-pub fn load_blueprint() -> HashMap<CompactInfoSet, Action> {
-    let file = match File::open(BLUEPRINT_STRATEGY_PATH) {
-        Err(_e) => {
+// Rewrites a strategy saved before the format had a version (just the
+// bincoded nodes) in the current format
+pub fn migrate_strategy_v1_to_v2(old_path: &str, new_path: &str) -> Result<(), StrategyLoadError> {
+    let error = |source| StrategyLoadError {
+        source: source,
+        path: String::from(old_path),
+    };
+    let file = File::open(old_path).map_err(|e| error(StrategyLoadErrorKind::from(e)))?;
+    let nodes: NodeMap = bincode::deserialize_from(BufReader::new(file))
+        .map_err(|e| error(StrategyLoadErrorKind::CorruptData(e)))?;
+    save_strategy(&nodes, new_path).map_err(|e| StrategyLoadError {
+        source: StrategyLoadErrorKind::from(e),
+        path: String::from(new_path),
+    })
+}
+
+// Loads the blueprint, compressing the saved strategy first if it hasn't
+// been written yet. Panics if it can't be loaded.
+pub fn load_blueprint() -> CompressedStrategy {
+    let path = &config().paths.blueprint;
+    match load_blueprint_from(path) {
+        Ok(blueprint) => blueprint,
+        Err(StrategyLoadError {
+            source: StrategyLoadErrorKind::FileNotFound,
+            ..
+        }) => {
             write_compact_blueprint(&load_nodes());
-            File::open(BLUEPRINT_STRATEGY_PATH).unwrap()
+            load_blueprint_from(path).unwrap_or_else(|e| panic!("{}", e))
         }
-        Ok(f) => f,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+pub fn load_blueprint_from(path: &str) -> Result<CompressedStrategy, StrategyLoadError> {
+    let error = |source| StrategyLoadError {
+        source: source,
+        path: String::from(path),
     };
-    let reader = BufReader::new(file);
-    let blueprint = bincode::deserialize_from(reader).expect("Failed to deserialize blueprint");
-    blueprint
+    let file = File::open(path).map_err(|e| error(StrategyLoadErrorKind::from(e)))?;
+    let mut reader = BufReader::new(file);
+    let version = read_format_version(&mut reader, &BLUEPRINT_MAGIC)
+        .map_err(error)?
+        .unwrap_or(0);
+    if version != BLUEPRINT_FORMAT_VERSION {
+        return Err(error(StrategyLoadErrorKind::VersionMismatch(
+            version,
+            BLUEPRINT_FORMAT_VERSION,
+        )));
+    }
+    bincode::deserialize_from(reader).map_err(|e| error(StrategyLoadErrorKind::CorruptData(e)))
+}
+
+pub fn save_blueprint(blueprint: &CompressedStrategy, path: &str) -> Result<(), bincode::Error> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_format_header(&mut writer, &BLUEPRINT_MAGIC, BLUEPRINT_FORMAT_VERSION)?;
+    bincode::serialize_into(&mut writer, blueprint)?;
+    writer.flush()?;
+    Ok(())
 }
 
+// One CFR traversal for player. Normally the strategies come from the nodes
+// and the updates go straight into them. With a snapshot, which only vanilla
+// CFR supports, each node's strategy comes from the snapshot plus whatever is
+// in nodes, and nodes only collects the updates, starting from zero. That's
+// how train_parallel_iterations() keeps its threads apart.
 fn iterate(
     player: usize,
     deck: &[Card],
     history: ActionHistory,
     weights: [f64; 2],
-    nodes: &mut HashMap<CompactInfoSet, Node>,
+    nodes: &mut NodeMap,
+    snapshot: Option<&NodeMap>,
+    variant: CFRVariant,
+    infoset_fn: fn(&[Card], &ActionHistory) -> InfoSet,
+    rng: &mut StdRng,
 ) -> f64 {
     if history.hand_over() {
         return terminal_utility(&deck, history, player);
     }
 
-    // Look up the DCFR node for this information set, or make a new one if it
-    // doesn't exist
-    let mut history = history.clone();
-    let mut infoset = InfoSet::from_deck(&deck, &history);
-    let mut node: Node = match nodes.get(&infoset.compress()) {
-        Some(n) => n.clone(),
-        None => Node::new(&infoset),
-    };
-
     // If it's not our turn, we sample the other player's action from their
-    // current policy, and load our node.
+    // current policy.
+    let mut history = history.clone();
     let opponent = 1 - player;
     if history.player == opponent {
-        history.add(&sample_action_from_node(&node));
+        let infoset = infoset_fn(&deck, &history);
+        let strategy = current_node(&infoset, nodes, snapshot).strategy();
+        history.add(&sample_action_with_rng(&strategy, rng));
         if history.hand_over() {
             return terminal_utility(&deck, history, player);
         }
-        infoset = InfoSet::from_deck(&deck, &history);
-        node = match nodes.get(&infoset.compress()) {
-            Some(n) => n.clone(),
-            None => Node::new(&infoset),
-        };
     }
 
-    // Grab the current strategy at this node
+    // Grab the current strategy at this node, and the node the updates go in
+    let infoset = infoset_fn(&deck, &history);
+    let (strategy, mut node) = match snapshot {
+        None => {
+            let mut node = current_node(&infoset, nodes, None);
+            (node.current_strategy(weights[player], variant), node)
+        }
+        Some(_) => {
+            let strategy = current_node(&infoset, nodes, snapshot).strategy();
+            let mut update = current_node(&infoset, nodes, None);
+            update.add_strategy(&strategy, weights[player]);
+            (strategy, update)
+        }
+    };
     let [p0, p1] = weights;
-    let strategy = node.current_strategy(weights[player]);
     let mut utilities: HashMap<Action, f64> = HashMap::new();
     let mut node_utility = 0.0;
 
-    // Recurse to further nodes in the game tree. Find the utilities for each action.
-    for (action, prob) in strategy {
+    // Recurse to further nodes in the game tree. Find the utilities for each
+    // action, in a fixed order so the opponent's sampled actions don't depend
+    // on how the HashMap was laid out.
+    for action in sorted_actions(&strategy) {
+        let prob = strategy[action];
         let mut next_history = history.clone();
-        next_history.add(&action);
+        next_history.add(action);
         let new_weights = match player {
             0 => [p0 * prob, p1],
             1 => [p0, p1 * prob],
             _ => panic!("Bad player value"),
         };
-        let utility = iterate(player, &deck, next_history, new_weights, nodes);
-        utilities.insert(action, utility);
+        let utility = iterate(
+            player,
+            &deck,
+            next_history,
+            new_weights,
+            nodes,
+            snapshot,
+            variant,
+            infoset_fn,
+            rng,
+        );
+        utilities.insert(action.clone(), utility);
         node_utility += prob * utility;
     }
     // TODO: multithread here -- maybe just on the flop. Return a Vec<Node> of updated nodes
 
     // Update regrets
     for (action, utility) in &utilities {
-        let regret = utilities.get(&action).unwrap() - node_utility;
-        node.add_regret(&action, weights[opponent] * regret);
+        node.add_regret(
+            &action,
+            weights[opponent] * (utility - node_utility),
+            variant,
+        );
     }
 
-    let updated = node.clone();
-    nodes.insert(infoset.compress(), updated);
+    nodes.insert(infoset.compress(), node);
     node_utility
 }
+
+// The infoset's node as iterate() sees it: the one in nodes, or with a
+// snapshot, the snapshot's with the updates in nodes added on
+fn current_node(infoset: &InfoSet, nodes: &NodeMap, snapshot: Option<&NodeMap>) -> Node {
+    let key = infoset.compress();
+    let mut node = match snapshot.unwrap_or(nodes).get(&key) {
+        Some(n) => n.clone(),
+        None => Node::new(infoset),
+    };
+    if let (Some(_), Some(update)) = (snapshot, nodes.get(&key)) {
+        node.add_node(update);
+    }
+    node
+}
+
+// One outcome sampling traversal. reach holds both players' probabilities of
+// reaching this history under the current strategies, and sample_prob is the
+// probability that the sampling scheme reached it. Returns the sampled utility
+// for player divided by the probability of sampling it, along with the
+// probability of playing the rest of the sampled trajectory from here under
+// the current strategies.
+fn outcome_sample(
+    player: usize,
+    deck: &[Card],
+    history: ActionHistory,
+    reach: [f64; 2],
+    sample_prob: f64,
+    epsilon: f64,
+    nodes: &mut HashMap<CompactInfoSet, Node>,
+) -> (f64, f64) {
+    if history.hand_over() {
+        return (terminal_utility(&deck, history, player) / sample_prob, 1.0);
+    }
+
+    let infoset = InfoSet::from_deck(&deck, &history);
+    let mut node: Node = match nodes.get(&infoset.compress()) {
+        Some(n) => n.clone(),
+        None => Node::new(&infoset),
+    };
+    let strategy = node.strategy();
+    let acting = history.player;
+    let opponent = 1 - player;
+
+    // The traversing player mixes in uniform exploration, the opponent just
+    // plays their current strategy.
+    let sampling: HashMap<Action, f64> = if acting == player {
+        let n_actions = strategy.len() as f64;
+        strategy
+            .iter()
+            .map(|(a, p)| (a.clone(), epsilon / n_actions + (1.0 - epsilon) * p))
+            .collect()
+    } else {
+        strategy.clone()
+    };
+    let action = sample_action_from_strategy(&sampling);
+    let action_prob = strategy[&action];
+
+    let mut next_history = history.clone();
+    next_history.add(&action);
+    let mut next_reach = reach;
+    next_reach[acting] *= action_prob;
+    let (utility, tail_prob) = outcome_sample(
+        player,
+        &deck,
+        next_history,
+        next_reach,
+        sample_prob * sampling[&action],
+        epsilon,
+        nodes,
+    );
+
+    if acting == player {
+        // The importance weight 1 / sample_prob is already part of utility
+        let weighted_utility = utility * reach[opponent];
+        for (candidate, regret) in
+            outcome_sample_regrets(&strategy, &action, weighted_utility, tail_prob)
+        {
+            node.add_regret(&candidate, regret, CFRVariant::Vanilla);
+        }
+    } else {
+        // Stochastically weighted averaging of the opponent's strategy
+        node.current_strategy(reach[opponent] / sample_prob, CFRVariant::Vanilla);
+    }
+    nodes.insert(infoset.compress(), node);
+    (utility, tail_prob * action_prob)
+}
+
+// Sampled regrets at one of the traversing player's nodes for outcome sampling.
+// weighted_utility is the sampled utility times the opponent's reach, and
+// tail_prob is the probability of playing the rest of the sampled trajectory
+// after the sampled action. Every action's counterfactual value is estimated
+// through the sampled action, so the unsampled ones all use its probability.
+pub fn outcome_sample_regrets(
+    strategy: &HashMap<Action, f64>,
+    sampled: &Action,
+    weighted_utility: f64,
+    tail_prob: f64,
+) -> HashMap<Action, f64> {
+    let sampled_prob = strategy[sampled];
+    strategy
+        .keys()
+        .map(|candidate| {
+            let regret = if candidate == sampled {
+                weighted_utility * tail_prob * (1.0 - sampled_prob)
+            } else {
+                -weighted_utility * tail_prob * sampled_prob
+            };
+            (candidate.clone(), regret)
+        })
+        .collect()
+}
+
+// One external sampling traversal, returning the sampled counterfactual value
+// for player. Since the opponent's actions and the cards are sampled according
+// to their true probabilities, the opponent's reach probability cancels out of
+// the regret updates.
+fn external_sample(
+    player: usize,
+    deck: &[Card],
+    history: ActionHistory,
+    nodes: &mut HashMap<CompactInfoSet, Node>,
+) -> f64 {
+    if history.hand_over() {
+        return terminal_utility(&deck, history, player);
+    }
+
+    let infoset = InfoSet::from_deck(&deck, &history);
+    let mut node: Node = match nodes.get(&infoset.compress()) {
+        Some(n) => n.clone(),
+        None => Node::new(&infoset),
+    };
+
+    if history.player != player {
+        // Update the opponent's average strategy, then sample their action
+        let strategy = node.current_strategy(1.0, CFRVariant::Vanilla);
+        nodes.insert(infoset.compress(), node);
+        let mut next_history = history.clone();
+        next_history.add(&sample_action_from_strategy(&strategy));
+        return external_sample(player, &deck, next_history, nodes);
+    }
+
+    let strategy = node.strategy();
+    let mut utilities: HashMap<Action, f64> = HashMap::new();
+    for action in strategy.keys() {
+        let mut next_history = history.clone();
+        next_history.add(&action);
+        let utility = external_sample(player, &deck, next_history, nodes);
+        utilities.insert(action.clone(), utility);
+    }
+    let (regrets, node_utility) = external_sample_regrets(&strategy, &utilities);
+    for (action, regret) in &regrets {
+        node.add_regret(&action, *regret, CFRVariant::Vanilla);
+    }
+    nodes.insert(infoset.compress(), node);
+    node_utility
+}
+
+// Sampled regrets at one of the traversing player's nodes for external
+// sampling, given the sampled value of each action. Also returns the node's
+// value under the current strategy.
+pub fn external_sample_regrets(
+    strategy: &HashMap<Action, f64>,
+    utilities: &HashMap<Action, f64>,
+) -> (HashMap<Action, f64>, f64) {
+    let node_utility: f64 = strategy.iter().map(|(a, p)| p * utilities[a]).sum();
+    let regrets = utilities
+        .iter()
+        .map(|(a, u)| (a.clone(), u - node_utility))
+        .collect();
+    (regrets, node_utility)
+}
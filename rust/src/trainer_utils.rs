@@ -1,14 +1,17 @@
 use crate::card_abstraction;
 use crate::card_utils;
 use crate::card_utils::Card;
+use crate::config::config;
 use rand::prelude::SliceRandom;
 use rand::thread_rng;
+use rand::Rng;
 use std::cmp::Eq;
 use std::collections::HashMap;
 use std::fmt;
-use std::fs::File;
 use std::hash::Hash;
-use std::io::Write;
+use std::io;
+use std::mem::{size_of, size_of_val};
+use std::sync::OnceLock;
 
 pub const SMALL_BLIND: i32 = 50;
 pub const BIG_BLIND: i32 = 100;
@@ -32,12 +35,41 @@ pub const ALL_IN: f64 = -1.0;
 // pub const BET_ABSTRACTION: [f64; 4] = [0.5, 1.0, 2.0, ALL_IN];
 // pub const BET_ABSTRACTION: [f64; 2] = [1.0, ALL_IN];
 
-// Discounted Regret Minimization parameters
-const ALPHA: f64 = 1.5;
-const BETA: f64 = 0.0;
-const GAMMA: f64 = 2.0;
+// The update rules used for regrets and the cumulative strategy during CFR.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum CFRVariant {
+    // Plain CFR: regrets and the cumulative strategy are simple sums
+    Vanilla,
+    // Discounted CFR (https://arxiv.org/abs/1809.04040)
+    DCFR(DCFRConfig),
+    // CFR+, which floors regrets at zero and weights the cumulative strategy
+    // linearly by iteration
+    CFRPlus,
+    // Linear CFR (https://arxiv.org/abs/1809.04040), which weights both the
+    // regrets and the cumulative strategy of iteration t by t
+    Linear,
+}
 
-pub const BLUEPRINT_STRATEGY_PATH: &str = "products/compact_blueprint.bin";
+// Discounted Regret Minimization parameters. Positive regrets are discounted
+// by t^alpha / (t^alpha + 1), negative regrets by t^beta / (t^beta + 1), and
+// the cumulative strategy by (t / (t + 1))^gamma.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DCFRConfig {
+    pub alpha: f64,
+    pub beta: f64,
+    pub gamma: f64,
+}
+
+impl Default for DCFRConfig {
+    // The parameters recommended in the DCFR paper
+    fn default() -> DCFRConfig {
+        DCFRConfig {
+            alpha: 1.5,
+            beta: 0.0,
+            gamma: 2.0,
+        }
+    }
+}
 
 lazy_static! {
     pub static ref ABSTRACTION: card_abstraction::Abstraction = card_abstraction::Abstraction::new();
@@ -45,12 +77,127 @@ lazy_static! {
 
     // pub static ref ABSTRACTION: card_abstraction::LightAbstraction = card_abstraction::LightAbstraction::new();
     // pub static ref HAND_TABLE: card_utils::LightHandTable = card_utils::LightHandTable::new();
+}
+
+// The bet sizes the game tree allows on each street, as fractions of the pot
+// (ALL_IN for all-in).
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct BetAbstraction {
+    pub fractions: Vec<Vec<f64>>,
+}
+
+// How the bet abstraction is laid out in its TOML file, eg
+//
+//     preflop = [1.0, 2.0, 2.5, 3.0, 5.0, -1.0]
+//     flop = [0.33, 0.67, 1.0, 2.0, -1.0]
+//     turn = [0.25, 0.5, 1.0, -1.0]
+//     river = [0.25, 0.5, 1.0, -1.0]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct BetAbstractionFile {
+    pub preflop: Vec<f64>,
+    pub flop: Vec<f64>,
+    pub turn: Vec<f64>,
+    pub river: Vec<f64>,
+}
 
-    pub static ref BET_ABSTRACTION: Vec<Vec<f64>> = vec![vec![1.0, 2.0, 2.5, 3.0, 5.0, ALL_IN],   // preflop
-                                                         vec![0.33, 0.67, 1.0, 2.0, ALL_IN],  // flop
-                                                         vec![0.25, 0.5, 1.0, ALL_IN], // turn
-                                                         vec![0.25, 0.5, 1.0, ALL_IN]]; // river
+#[derive(Debug)]
+pub enum BetAbstractionError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    // A fraction that isn't positive or ALL_IN
+    BadFraction(f64),
+}
 
+impl fmt::Display for BetAbstractionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BetAbstractionError::Io(e) => write!(f, "bet abstraction I/O error: {}", e),
+            BetAbstractionError::Parse(e) => write!(f, "bad bet abstraction file: {}", e),
+            BetAbstractionError::BadFraction(x) => {
+                write!(f, "bad bet size in the bet abstraction: {}", x)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BetAbstractionError {}
+
+impl From<io::Error> for BetAbstractionError {
+    fn from(e: io::Error) -> BetAbstractionError {
+        BetAbstractionError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for BetAbstractionError {
+    fn from(e: toml::de::Error) -> BetAbstractionError {
+        BetAbstractionError::Parse(e)
+    }
+}
+
+impl Default for BetAbstraction {
+    fn default() -> BetAbstraction {
+        BetAbstraction {
+            fractions: vec![
+                vec![1.0, 2.0, 2.5, 3.0, 5.0, ALL_IN], // preflop
+                vec![0.33, 0.67, 1.0, 2.0, ALL_IN],    // flop
+                vec![0.25, 0.5, 1.0, ALL_IN],          // turn
+                vec![0.25, 0.5, 1.0, ALL_IN],          // river
+            ],
+        }
+    }
+}
+
+impl BetAbstraction {
+    pub fn load_from_file(path: &str) -> Result<BetAbstraction, BetAbstractionError> {
+        let text = std::fs::read_to_string(path)?;
+        BetAbstraction::from_streets(toml::from_str(&text)?)
+    }
+
+    // Checks that every bet size makes sense
+    pub fn from_streets(file: BetAbstractionFile) -> Result<BetAbstraction, BetAbstractionError> {
+        let fractions = vec![file.preflop, file.flop, file.turn, file.river];
+        for &fraction in fractions.iter().flatten() {
+            if !(fraction > 0.0 || fraction == ALL_IN) {
+                return Err(BetAbstractionError::BadFraction(fraction));
+            }
+        }
+        Ok(BetAbstraction {
+            fractions: fractions,
+        })
+    }
+
+    pub fn street(&self, street: usize) -> &[f64] {
+        &self.fractions[street]
+    }
+
+    // Most actions next_actions() can give: every bet size of the street
+    // with the most of them, plus a call and a fold
+    pub fn max_actions(&self) -> usize {
+        self.fractions.iter().map(|f| f.len()).max().unwrap_or(0) + 2
+    }
+}
+
+static BET_ABSTRACTION: OnceLock<BetAbstraction> = OnceLock::new();
+
+// The bet abstraction everything uses. The first time it's needed it's loaded
+// from the file at paths.bet_abstraction in the config. If there's no file
+// there, it's the default one. Strategies only make sense with the
+// abstraction they were trained with.
+pub fn bet_abstraction() -> &'static BetAbstraction {
+    BET_ABSTRACTION.get_or_init(|| {
+        match BetAbstraction::load_from_file(&config().paths.bet_abstraction) {
+            Ok(abstraction) => abstraction,
+            Err(BetAbstractionError::Io(_)) => BetAbstraction::default(),
+            Err(e) => panic!("{}", e),
+        }
+    })
+}
+
+// Uses the given bet abstraction instead of the configured one.
+// This only works before anything has used the bet abstraction, otherwise
+// the abstraction is handed back.
+pub fn set_bet_abstraction(abstraction: BetAbstraction) -> Result<(), BetAbstraction> {
+    BET_ABSTRACTION.set(abstraction)
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, serde::Serialize, serde::Deserialize)]
@@ -84,6 +231,10 @@ pub struct ActionHistory {
     pub street: usize,
     pub player: usize,
     stacks: [i32; 2],
+    // Blinds that were posted before the first action. They're [0, 0] for
+    // new(), where the first preflop actions include the blinds instead.
+    #[serde(default)]
+    posted_blinds: [i32; 2],
 }
 
 impl ActionHistory {
@@ -94,9 +245,32 @@ impl ActionHistory {
             last_action: None,
             player: DEALER,
             stacks: [STACK_SIZE, STACK_SIZE],
+            posted_blinds: [0, 0],
+        }
+    }
+
+    // A history where the dealer has posted the small blind and the opponent
+    // the big blind, so the dealer only has to put in the difference to call
+    // and the big blind gets to check or raise after that. Strategies are
+    // trained on new() histories, so this is for playing out real hands.
+    pub fn with_blinds(small: i32, big: i32) -> ActionHistory {
+        ActionHistory {
+            stacks: [STACK_SIZE - small, STACK_SIZE - big],
+            posted_blinds: [small, big],
+            ..ActionHistory::new()
         }
     }
 
+    // An empty history with the same blinds as this one
+    fn restart(&self) -> ActionHistory {
+        let [small, big] = self.posted_blinds;
+        ActionHistory::with_blinds(small, big)
+    }
+
+    fn blinds_posted(&self) -> bool {
+        self.posted_blinds != [0, 0]
+    }
+
     // Returns true if the hand is over (either someone has folded or it's time for
     // a showdown).
     pub fn hand_over(&self) -> bool {
@@ -134,6 +308,11 @@ impl ActionHistory {
         }
     }
 
+    // The actions taken on the given street, in order
+    pub fn street_actions(&self, street: usize) -> &[Action] {
+        &self.history[street]
+    }
+
     pub fn last_action(&self) -> Option<Action> {
         self.last_action.clone()
     }
@@ -151,9 +330,46 @@ impl ActionHistory {
         }
     }
 
-    // Returns the amount needed to call, so 0 for checking
+    // Chips each player has put in so far, counting the blinds as put in
+    // even before the players have acted on them.
+    fn contributions(&self) -> [i32; 2] {
+        let blinds = if self.blinds_posted() {
+            self.posted_blinds
+        } else {
+            [SMALL_BLIND, BIG_BLIND]
+        };
+        let mut contributions = [
+            (STACK_SIZE - self.stacks[DEALER]).max(blinds[DEALER]),
+            (STACK_SIZE - self.stacks[OPPONENT]).max(blinds[OPPONENT]),
+        ];
+        // If a player is all-in for less than the other's bet, the part of
+        // the bet they can't match isn't in the pot
+        for player in 0..2 {
+            if self.stacks[player] == 0 {
+                let other = 1 - player;
+                contributions[other] = contributions[other].min(contributions[player]);
+            }
+        }
+        contributions
+    }
+
+    // Returns the real pot in chips, including the blinds. Unlike pot(), this
+    // counts the small blind as dead money if the dealer folds preflop.
+    pub fn pot_size(&self) -> i32 {
+        let contributions = self.contributions();
+        contributions[0] + contributions[1]
+    }
+
+    // The most either player can still win or lose in this hand
+    pub fn effective_stack(&self) -> i32 {
+        let contributions = self.contributions();
+        (STACK_SIZE - contributions[0]).min(STACK_SIZE - contributions[1])
+    }
+
+    // Returns the amount needed to call, so 0 for checking. Without posted
+    // blinds the dealer's first call puts in the whole big blind.
     pub fn to_call(&self) -> i32 {
-        if self.street == PREFLOP && self.history[PREFLOP].len() == 0 {
+        if self.street == PREFLOP && self.history[PREFLOP].len() == 0 && !self.blinds_posted() {
             BIG_BLIND
         } else {
             self.stacks[self.player] - self.stacks[1 - self.player]
@@ -163,6 +379,9 @@ impl ActionHistory {
     pub fn min_bet(&self) -> i32 {
         match &self.last_action {
             Some(action) => 2 * action.amount,
+            // With posted blinds, the smallest opening raise is to twice the
+            // big blind
+            None if self.blinds_posted() => self.to_call() + self.posted_blinds[OPPONENT],
             None => BIG_BLIND,
         }
     }
@@ -174,15 +393,12 @@ impl ActionHistory {
 
     // Returns a vector of the possible next actions after this state, that are
     // allowed in our action abstraction.
-    pub fn next_actions(&self, bet_abstraction: &Vec<Vec<f64>>) -> Vec<Action> {
+    pub fn next_actions(&self, bet_abstraction: &BetAbstraction) -> Vec<Action> {
         let mut actions = Vec::new();
-        let min_bet = match &self.last_action {
-            Some(action) => 2 * action.amount,
-            None => BIG_BLIND,
-        };
+        let min_bet = self.min_bet();
         let max_bet = self.stacks[self.player];
         let pot = self.pot();
-        for fraction in bet_abstraction[self.street].iter() {
+        for fraction in bet_abstraction.street(self.street).iter() {
             let bet = if fraction == &ALL_IN {
                 self.stacks[self.player]
             } else {
@@ -212,12 +428,51 @@ impl ActionHistory {
         actions
     }
 
+    // Returns every action that's legal right now, as opposed to just the ones
+    // in the bet abstraction's tree. Bets are sized off the real pot, and any
+    // that are below the minimum raise or more than the opponent could call
+    // are left out. Going all-in is always allowed.
+    pub fn legal_actions(&self) -> Vec<Action> {
+        let mut actions = Vec::new();
+        let to_call = self.to_call();
+        let all_in = self.stacks[self.player];
+        // Betting more than this just puts in chips the opponent can't match
+        let max_bet = all_in.min(self.stacks[1 - self.player] + to_call);
+        let pot = self.pot_size();
+        for fraction in bet_abstraction().street(self.street).iter() {
+            if fraction == &ALL_IN {
+                continue;
+            }
+            let bet = (fraction * (pot as f64)) as i32;
+            if self.min_bet() <= bet && bet <= max_bet && bet != to_call && bet != all_in {
+                actions.push(Action {
+                    action: ActionType::Bet,
+                    amount: bet,
+                });
+            }
+        }
+        if all_in > to_call {
+            actions.push(Action {
+                action: ActionType::Bet,
+                amount: all_in,
+            });
+        }
+        actions.push(Action {
+            action: ActionType::Call,
+            amount: to_call.min(all_in),
+        });
+        if to_call > 0 {
+            actions.push(FOLD);
+        }
+        actions
+    }
+
     // Performs action translation and returns a translated version of the
     // current history, with actions mapped to those of the given bet abstraction.
     // This assumes that folding and calling are always going to be implicitly
     // allowed in the abstraction.
-    pub fn translate(&self, bet_abstraction: &Vec<Vec<f64>>) -> ActionHistory {
-        let mut translated = ActionHistory::new();
+    pub fn translate(&self, bet_abstraction: &BetAbstraction) -> ActionHistory {
+        let mut translated = self.restart();
         for street in self.history.clone() {
             for action in street {
                 let next = translated.next_actions(bet_abstraction);
@@ -241,9 +496,9 @@ impl ActionHistory {
         translated
     }
 
-    pub fn compress(&self, bet_abstraction: &Vec<Vec<f64>>) -> Vec<u8> {
+    pub fn compress(&self, bet_abstraction: &BetAbstraction) -> Vec<u8> {
         let mut compressed = Vec::new();
-        let mut builder = ActionHistory::new();
+        let mut builder = self.restart();
         for street in self.history.clone() {
             for action in street {
                 for (i, candidate) in builder.next_actions(bet_abstraction).iter().enumerate() {
@@ -309,9 +564,12 @@ impl InfoSet {
     // are the second two cards. They are followed by the 5 board cards.
     pub fn from_deck(deck: &[Card], history: &ActionHistory) -> InfoSet {
         let cards = get_hand(&deck, history.player, history.street);
-        let card_bucket = ABSTRACTION.bin(&cards);
+        InfoSet::from_hand(&cards, history)
+    }
+
+    pub fn new(history: ActionHistory, card_bucket: i32) -> InfoSet {
         InfoSet {
-            history: history.clone(),
+            history: history,
             card_bucket: card_bucket,
         }
     }
@@ -319,22 +577,47 @@ impl InfoSet {
     pub fn from_hand(hand: &[Card], history: &ActionHistory) -> InfoSet {
         // hand should contain the exact right number of cards for the current
         // street of the history. Maybe do error checking for this in the future.
+        // Preflop buckets don't need the postflop tables, so don't make
+        // ABSTRACTION load them
+        let card_bucket = if hand.len() == 2 {
+            card_abstraction::preflop_bin_index(hand) as i32
+        } else {
+            ABSTRACTION.bin(hand)
+        };
         InfoSet {
             history: history.clone(),
-            card_bucket: ABSTRACTION.bin(hand),
+            card_bucket: card_bucket,
         }
     }
 
     pub fn next_actions(&self) -> Vec<Action> {
-        self.history.next_actions(&BET_ABSTRACTION.to_vec())
+        self.history.next_actions(bet_abstraction())
     }
 
     pub fn compress(&self) -> CompactInfoSet {
         CompactInfoSet {
-            history: self.history.compress(&BET_ABSTRACTION.to_vec()),
+            history: self.history.compress(bet_abstraction()),
             card_bucket: self.card_bucket,
         }
     }
+
+    pub fn card_bucket(&self) -> i32 {
+        self.card_bucket
+    }
+
+    // 0 for the preflop up to 3 for the river
+    pub fn street(&self) -> u8 {
+        self.history.street as u8
+    }
+
+    // The player to act, either DEALER or OPPONENT
+    pub fn acting_player(&self) -> u8 {
+        self.history.player as u8
+    }
+
+    pub fn num_actions_this_street(&self) -> u8 {
+        self.history.history[self.history.street].len() as u8
+    }
 }
 
 impl fmt::Display for InfoSet {
@@ -345,14 +628,37 @@ impl fmt::Display for InfoSet {
 }
 
 impl CompactInfoSet {
+    // Builds the infoset directly from a compressed history and card bucket,
+    // for traversals that track the compressed history themselves.
+    pub fn new(history: Vec<u8>, card_bucket: i32) -> CompactInfoSet {
+        CompactInfoSet {
+            history: history,
+            card_bucket: card_bucket,
+        }
+    }
+
+    pub fn card_bucket(&self) -> i32 {
+        self.card_bucket
+    }
+
+    // The index of each action taken in its history's next_actions()
+    pub fn history(&self) -> &[u8] {
+        &self.history
+    }
+
+    // Memory used by the key, counting its history on the heap
+    pub fn size_bytes(&self) -> usize {
+        size_of_val(self) + self.history.capacity()
+    }
+
     pub fn uncompress(&self) -> InfoSet {
         let mut full_history = ActionHistory::new();
         for action in &self.history {
-            let next_actions = full_history.next_actions(&BET_ABSTRACTION);
+            let next_actions = full_history.next_actions(bet_abstraction());
             let next_action = &next_actions[action.clone() as usize];
             full_history.add(next_action);
         }
-        InfoSet { history: full_history, card_bucket: self.card_bucket}
+        InfoSet::new(full_history, self.card_bucket)
     }
 }
 
@@ -369,6 +675,12 @@ fn hand_with_bucket(bucket: i32, street: usize) -> String {
     }
 }
 
+pub type NodeMap = HashMap<CompactInfoSet, Node>;
+// The blueprint format: action probabilities for each infoset, in the order
+// of its next_actions(). Each probability is rounded to a byte, where 0 is
+// never and 255 is always, which takes an eighth of the space of an f64.
+pub type CompressedStrategy = HashMap<CompactInfoSet, Vec<u8>>;
+
 #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Node {
     regrets: HashMap<Action, f64>,
@@ -378,11 +690,15 @@ pub struct Node {
 
 impl Node {
     pub fn new(infoset: &InfoSet) -> Node {
+        Node::from_actions(&infoset.next_actions())
+    }
+
+    pub fn from_actions(actions: &[Action]) -> Node {
         // Create a HashMap of action -> 0.0 to initialize the regrets and
         // cumulative strategy sum
         let mut zeros = HashMap::new();
-        for action in infoset.next_actions() {
-            zeros.insert(action, 0.0);
+        for action in actions {
+            zeros.insert(action.clone(), 0.0);
         }
         Node {
             regrets: zeros.clone(),
@@ -391,7 +707,9 @@ impl Node {
         }
     }
 
-    pub fn current_strategy(&mut self, prob: f64) -> HashMap<Action, f64> {
+    // Returns the regret matching strategy, without updating the cumulative
+    // strategy.
+    pub fn strategy(&self) -> HashMap<Action, f64> {
         let mut strat: HashMap<Action, f64> = HashMap::new();
         for (action, regret) in self.regrets.clone() {
             if regret > 0.0 {
@@ -400,15 +718,26 @@ impl Node {
                 strat.insert(action, 0.0);
             }
         }
-        strat = normalize(&strat);
+        normalize(&strat)
+    }
+
+    pub fn current_strategy(&mut self, prob: f64, variant: CFRVariant) -> HashMap<Action, f64> {
+        let strat = self.strategy();
         for action in strat.keys() {
             // Add this action's probability to the cumulative strategy sum
             let sum_prob = self.strategy_sum.get(action).unwrap().clone();
             let new_prob = strat.get(action).unwrap() * prob;
-            let mut cumulative_strategy = sum_prob + new_prob;
-            // Multiply the cumulative strategy sum according to Discounted
-            // Counterfactual Regret Minimization
-            cumulative_strategy *= (self.t / (self.t + 1.0)).powf(GAMMA);
+            let cumulative_strategy = match variant {
+                // Multiply the cumulative strategy sum according to Discounted
+                // Counterfactual Regret Minimization
+                CFRVariant::Vanilla => sum_prob + new_prob,
+                CFRVariant::DCFR(config) => {
+                    (sum_prob + new_prob) * (self.t / (self.t + 1.0)).powf(config.gamma)
+                }
+                // CFR+ and Linear CFR weight each iteration's contribution by
+                // the iteration number
+                CFRVariant::CFRPlus | CFRVariant::Linear => sum_prob + (self.t + 1.0) * new_prob,
+            };
             self.strategy_sum
                 .insert(action.clone(), cumulative_strategy);
         }
@@ -418,61 +747,267 @@ impl Node {
         strat
     }
 
+    // A node whose average strategy is the given one, for strategies that were
+    // saved without their regrets
+    pub fn from_strategy(strategy: &HashMap<Action, f64>) -> Node {
+        let actions: Vec<Action> = strategy.keys().cloned().collect();
+        let mut node = Node::from_actions(&actions);
+        node.strategy_sum = strategy.clone();
+        node
+    }
+
     pub fn cumulative_strategy(&self) -> HashMap<Action, f64> {
         normalize(&self.strategy_sum)
     }
 
-    pub fn add_regret(&mut self, action: &Action, regret: f64) {
+    // Averages the same infoset's node across several training runs, where
+    // None means the run never reached it and counts as a uniform strategy.
+    // The averaged cumulative strategy is stored already normalized, and the
+    // regrets and iteration counts are averaged over the runs that have them.
+    pub fn average(nodes: &[Option<&Node>]) -> Node {
+        let present: Vec<&Node> = nodes.iter().filter_map(|n| *n).collect();
+        let actions: Vec<Action> = present[0].strategy_sum.keys().cloned().collect();
+        let mut averaged = Node::from_actions(&actions);
+        let uniform = 1.0 / averaged.strategy_sum.len() as f64;
+        let weight = 1.0 / nodes.len() as f64;
+        for node in nodes {
+            let strategy = node.map(|n| n.cumulative_strategy());
+            for (action, prob) in averaged.strategy_sum.iter_mut() {
+                let p = match &strategy {
+                    Some(s) => s[action],
+                    None => uniform,
+                };
+                *prob += weight * p;
+            }
+        }
+        for node in &present {
+            for (action, regret) in averaged.regrets.iter_mut() {
+                *regret += node.regrets[action] / present.len() as f64;
+            }
+            averaged.t += node.t / present.len() as f64;
+        }
+        averaged
+    }
+
+    // Memory used by the node, counting the heap storage of its two maps
+    pub fn size_bytes(&self) -> usize {
+        size_of_val(self) + map_heap_bytes(&self.regrets) + map_heap_bytes(&self.strategy_sum)
+    }
+
+    pub fn add_regret(&mut self, action: &Action, regret: f64, variant: CFRVariant) {
+        let regret = match variant {
+            // current_strategy() has already counted this iteration, so t is
+            // the iteration number
+            CFRVariant::Linear => self.t * regret,
+            _ => regret,
+        };
         let mut accumulated_regret = self.regrets[action] + regret;
-        // Update the accumulated regret according to Discounted Counterfactual
-        // Regret Minimization rules
-        if accumulated_regret >= 0.0 {
-            accumulated_regret *= self.t.powf(ALPHA) / (self.t.powf(ALPHA) + 1.0);
-        } else {
-            accumulated_regret *= self.t.powf(BETA) / (self.t.powf(BETA) + 1.0);
+        match variant {
+            CFRVariant::Vanilla | CFRVariant::Linear => {}
+            CFRVariant::DCFR(config) => {
+                // Update the accumulated regret according to Discounted
+                // Counterfactual Regret Minimization rules
+                let exponent = if accumulated_regret >= 0.0 {
+                    config.alpha
+                } else {
+                    config.beta
+                };
+                accumulated_regret *= self.t.powf(exponent) / (self.t.powf(exponent) + 1.0);
+            }
+            CFRVariant::CFRPlus => {
+                // CFR+ never lets regrets go negative
+                accumulated_regret = accumulated_regret.max(0.0);
+            }
         }
         self.regrets.insert(action.clone(), accumulated_regret);
     }
+
+    // Vanilla CFR's cumulative strategy update, for a strategy that came from
+    // somewhere else. train_parallel() takes the strategy from a snapshot and
+    // records the update in a separate node.
+    pub fn add_strategy(&mut self, strategy: &HashMap<Action, f64>, prob: f64) {
+        for (action, p) in strategy {
+            *self.strategy_sum.get_mut(action).unwrap() += p * prob;
+        }
+        if prob > 0.0 {
+            self.t += 1.0;
+        }
+    }
+
+    // Adds the regrets, cumulative strategy and iteration count of a node for
+    // the same infoset to this one
+    pub fn add_node(&mut self, other: &Node) {
+        for (action, regret) in &other.regrets {
+            *self.regrets.get_mut(action).unwrap() += regret;
+        }
+        for (action, prob) in &other.strategy_sum {
+            *self.strategy_sum.get_mut(action).unwrap() += prob;
+        }
+        self.t += other.t;
+    }
+}
+
+// The average strategy, most likely action first, one per line
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let strategy = self.cumulative_strategy();
+        let mut actions = sorted_actions(&strategy);
+        // Stable, so ties stay in sorted_actions() order
+        actions.sort_by(|a, b| {
+            strategy[*b]
+                .partial_cmp(&strategy[*a])
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        for action in actions {
+            writeln!(
+                f,
+                "{:<12} {:>6.2}%",
+                action.to_string(),
+                100.0 * strategy[action]
+            )?;
+        }
+        write!(f, "(t = {})", self.t)
+    }
+}
+
+// Roughly what a HashMap keeps on the heap: a slot and a control byte for
+// each entry it has room for
+fn map_heap_bytes<K, V>(map: &HashMap<K, V>) -> usize {
+    map.capacity() * (size_of::<(K, V)>() + 1)
 }
 
 // Normalizes the values of a HashMap so that its elements sum to 1.
 pub fn normalize<T: Eq + Hash + Clone>(map: &HashMap<T, f64>) -> HashMap<T, f64> {
-    let mut map = map.clone();
-    let mut sum = 0.0;
-    for elem in map.values() {
-        sum += elem;
-    }
-    for (action, val) in map.clone() {
-        let newval = if sum == 0.0 {
-            // If all values are 0, then just return a uniform distribution
-            1.0 / map.len() as f64
-        } else {
-            // Otherwise normalize based on the sum.
-            val / sum
-        };
-        map.insert(action.clone(), newval);
+    match normalize_checked(map) {
+        Ok(normalized) => normalized,
+        // If the values don't add up to anything positive (all 0, or NaN from
+        // some bad update), then just return a uniform distribution
+        Err(_) => {
+            let uniform = 1.0 / map.len() as f64;
+            map.keys().map(|k| (k.clone(), uniform)).collect()
+        }
     }
-    map
+}
+
+// Why normalize_checked() couldn't turn the values into a distribution
+#[derive(Debug, Clone, PartialEq)]
+pub enum NormalizeError {
+    Empty,
+    // The sum of the values, which is 0, negative or NaN
+    BadSum(f64),
+}
+
+impl fmt::Display for NormalizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NormalizeError::Empty => write!(f, "can't normalize an empty distribution"),
+            NormalizeError::BadSum(sum) => {
+                write!(f, "can't normalize values that add up to {}", sum)
+            }
+        }
+    }
+}
+
+impl std::error::Error for NormalizeError {}
+
+// Same as normalize(), but for callers where values that don't add up to
+// anything positive are a bug rather than something to paper over. Tiny
+// positive sums are fine, since strategy sums weighted by reach can be tiny.
+pub fn normalize_checked<T: Eq + Hash + Clone>(
+    map: &HashMap<T, f64>,
+) -> Result<HashMap<T, f64>, NormalizeError> {
+    if map.is_empty() {
+        return Err(NormalizeError::Empty);
+    }
+    let sum: f64 = map.values().sum();
+    if !sum.is_finite() || sum <= 0.0 {
+        return Err(NormalizeError::BadSum(sum));
+    }
+    Ok(map.iter().map(|(k, v)| (k.clone(), v / sum)).collect())
 }
 
 // Randomly sample an action given the strategy at this node.
 pub fn sample_action_from_node(node: &Node) -> Action {
-    let node = &mut node.clone();
-    let strategy = node.current_strategy(0.0);
+    let strategy = node.strategy();
     sample_action_from_strategy(&strategy)
 }
 
 pub fn sample_action_from_strategy(strategy: &HashMap<Action, f64>) -> Action {
-    let actions: Vec<&Action> = strategy.keys().collect();
-    let mut rng = thread_rng();
+    sample_action_with_rng(strategy, &mut thread_rng())
+}
+
+// Same as sample_action_from_strategy(), but the result only depends on the
+// given RNG, so seeded training runs can be reproduced.
+pub fn sample_action_with_rng(strategy: &HashMap<Action, f64>, rng: &mut impl Rng) -> Action {
+    let actions = sorted_actions(strategy);
     let action = actions
-        .choose_weighted(&mut rng, |a| strategy.get(&a).unwrap())
+        .choose_weighted(rng, |a| strategy.get(&a).unwrap())
         .unwrap()
         .clone()
         .clone();
     action
 }
 
+// HashMap iteration order is random, so this puts a strategy's actions in a
+// fixed order.
+pub fn sorted_actions(strategy: &HashMap<Action, f64>) -> Vec<&Action> {
+    let mut actions: Vec<&Action> = strategy.keys().collect();
+    actions.sort_by_key(|a| (a.action.clone() as u8, a.amount));
+    actions
+}
+
+pub fn most_likely_action(strategy: &HashMap<Action, f64>) -> Action {
+    let mut best = None;
+    for action in sorted_actions(strategy) {
+        match best {
+            Some(b) if strategy[action] <= strategy[b] => {}
+            _ => best = Some(action),
+        }
+    }
+    best.unwrap().clone()
+}
+
+// Picks the single most likely action of the cumulative strategy at every
+// infoset. Ties go to the first action in sorted order.
+pub fn to_pure_strategy(nodes: &NodeMap) -> HashMap<CompactInfoSet, Action> {
+    nodes
+        .iter()
+        .map(|(infoset, node)| {
+            (
+                infoset.clone(),
+                most_likely_action(&node.cumulative_strategy()),
+            )
+        })
+        .collect()
+}
+
+// Same as to_pure_strategy(), except that each infoset gets a uniformly random
+// action instead with probability epsilon.
+pub fn to_pure_strategy_eps_greedy(
+    nodes: &NodeMap,
+    epsilon: f64,
+    rng: &mut impl Rng,
+) -> HashMap<CompactInfoSet, Action> {
+    let mut pure = HashMap::new();
+    // Go through the infosets in a fixed order so that seeded runs match
+    let mut infosets: Vec<&CompactInfoSet> = nodes.keys().collect();
+    infosets.sort_by_key(|i| (i.card_bucket, i.history.clone()));
+    for infoset in infosets {
+        let strategy = nodes[infoset].cumulative_strategy();
+        let action = if rng.gen::<f64>() < epsilon {
+            sorted_actions(&strategy)
+                .choose(rng)
+                .unwrap()
+                .clone()
+                .clone()
+        } else {
+            most_likely_action(&strategy)
+        };
+        pure.insert(infoset.clone(), action);
+    }
+    pure
+}
+
 // Assuming history represents a terminal state (someone folded, or it's a showdown),
 // return the utility, in chips, that the given player gets.
 pub fn terminal_utility(deck: &[Card], history: ActionHistory, player: usize) -> f64 {
@@ -521,6 +1056,15 @@ pub fn terminal_utility(deck: &[Card], history: ActionHistory, player: usize) ->
 // Presamples actions and represents the blueprint strategy in a much more
 // compact format.
 pub fn write_compact_blueprint(nodes: &HashMap<CompactInfoSet, Node>) {
+    let compressed = compress_strategy(nodes);
+    crate::trainer::save_blueprint(&compressed, &config().paths.blueprint)
+        .expect("Could not save the blueprint");
+    println!("[INFO] Wrote compressed blueprint strategy to disk");
+}
+
+// Strips each node down to the probabilities of its cumulative strategy, in
+// the order of the infoset's next_actions().
+pub fn compress_strategy(nodes: &HashMap<CompactInfoSet, Node>) -> CompressedStrategy {
     let mut compressed = HashMap::new();
     println!("[INFO] Compressing the blueprint strategy");
     let bar = card_utils::pbar(nodes.len() as u64);
@@ -531,15 +1075,60 @@ pub fn write_compact_blueprint(nodes: &HashMap<CompactInfoSet, Node>) {
         let strategy = node.cumulative_strategy();
         let mut probs = Vec::new();
         for action in infoset.uncompress().next_actions() {
-            probs.push(strategy.get(&action).unwrap().clone() as f32);
+            probs.push(strategy.get(&action).unwrap().clone());
         }
-        compressed.insert(infoset, probs);
+        compressed.insert(infoset.clone(), quantize_probs(&probs));
 
         bar.inc(1);
     }
     bar.finish();
-    let bincode: Vec<u8> = bincode::serialize(&compressed).unwrap();
-    let mut file = File::create(BLUEPRINT_STRATEGY_PATH).unwrap();
-    file.write_all(&bincode).unwrap();
-    println!("[INFO] Wrote compressed blueprint strategy to disk");
+    compressed
+}
+
+// Turns the blueprint back into nodes, with the regrets left at 0
+pub fn decompress_strategy(compressed: &CompressedStrategy) -> NodeMap {
+    let mut nodes = HashMap::new();
+    for (infoset, probs) in compressed {
+        let strategy: HashMap<Action, f64> = infoset
+            .uncompress()
+            .next_actions()
+            .into_iter()
+            .zip(dequantize_probs(probs))
+            .collect();
+        nodes.insert(infoset.clone(), Node::from_strategy(&strategy));
+    }
+    nodes
+}
+
+pub fn quantize_probs(probs: &[f64]) -> Vec<u8> {
+    probs.iter().map(|p| (p * 255.0).round() as u8).collect()
+}
+
+// The rounded probabilities don't always add up to 255, so they're normalized
+// again
+pub fn dequantize_probs(probs: &[u8]) -> Vec<f64> {
+    let total: f64 = probs.iter().map(|&p| p as f64).sum();
+    if total == 0.0 {
+        return vec![1.0 / probs.len() as f64; probs.len()];
+    }
+    probs.iter().map(|&p| p as f64 / total).collect()
+}
+
+// Merges the nodes of several independent training runs. Each run gets an
+// equal say in the averaged strategy, and runs that never reached an infoset
+// count as playing uniformly at random there.
+pub fn average_strategies(
+    strategies: &[HashMap<CompactInfoSet, Node>],
+) -> HashMap<CompactInfoSet, Node> {
+    let mut averaged = HashMap::new();
+    for run in strategies {
+        for infoset in run.keys() {
+            if averaged.contains_key(infoset) {
+                continue;
+            }
+            let nodes: Vec<Option<&Node>> = strategies.iter().map(|s| s.get(infoset)).collect();
+            averaged.insert(infoset.clone(), Node::average(&nodes));
+        }
+    }
+    averaged
 }
@@ -1,8 +1,228 @@
 // Sanity checks for the blueprint strategy.
 
 use crate::bot;
-use crate::card_utils::{cards2str, pbar, Card};
+use crate::card_abstraction::{FLOP_BUCKETS, PREFLOP_BUCKETS, RIVER_BUCKETS, TURN_BUCKETS};
+use crate::card_utils::{
+    cards2str, category_from_strength, pbar, str2cards, Card, HandCategory, HandTable,
+};
 use crate::trainer_utils::*;
+use std::fmt;
+
+// check_strategy_coverage() only follows lines with up to this many actions
+// on each street. The number of histories grows exponentially with it.
+const COVERAGE_ACTIONS_PER_STREET: usize = 2;
+// Most missing infosets to list in a CoverageReport
+const MAX_MISSING_EXAMPLES: usize = 100;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverageReport {
+    pub total_infosets: u64,
+    pub covered: u64,
+    // Up to MAX_MISSING_EXAMPLES of the infosets that aren't in the strategy
+    pub missing: Vec<CompactInfoSet>,
+}
+
+impl CoverageReport {
+    pub fn coverage(&self) -> f64 {
+        if self.total_infosets == 0 {
+            1.0
+        } else {
+            self.covered as f64 / self.total_infosets as f64
+        }
+    }
+}
+
+impl fmt::Display for CoverageReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Strategy covers {}/{} infosets ({:.2}%)",
+            self.covered,
+            self.total_infosets,
+            100.0 * self.coverage()
+        )
+    }
+}
+
+// Checks which infosets on the given streets are missing from the strategy,
+// eg because training didn't run long enough to reach them. The histories
+// come from the bet abstraction, following at most COVERAGE_ACTIONS_PER_STREET
+// actions per street, and each one is checked with every card bucket.
+// Buckets stand in for the canonical hands here, since every hand in a bucket
+// shares the same infoset.
+pub fn check_strategy_coverage(nodes: &NodeMap, streets: &[u8]) -> CoverageReport {
+    let mut report = CoverageReport {
+        total_infosets: 0,
+        covered: 0,
+        missing: Vec::new(),
+    };
+    let last_street = match streets.iter().max() {
+        Some(&street) => street as usize,
+        None => return report,
+    };
+    let mut histories = vec![ActionHistory::new()];
+    while let Some(history) = histories.pop() {
+        if history.hand_over() || history.street > last_street {
+            continue;
+        }
+        if streets.contains(&(history.street as u8)) {
+            let compressed = history.compress(bet_abstraction());
+            for bucket in 0..n_buckets(history.street) {
+                let infoset = CompactInfoSet::new(compressed.clone(), bucket);
+                report.total_infosets += 1;
+                if nodes.contains_key(&infoset) {
+                    report.covered += 1;
+                } else if report.missing.len() < MAX_MISSING_EXAMPLES {
+                    report.missing.push(infoset);
+                }
+            }
+        }
+        if history.street_actions(history.street).len() >= COVERAGE_ACTIONS_PER_STREET {
+            continue;
+        }
+        for action in history.next_actions(bet_abstraction()) {
+            let mut next_history = history.clone();
+            next_history.add(&action);
+            histories.push(next_history);
+        }
+    }
+    report
+}
+
+// Runs check_strategy_coverage() on every street and prints the results
+pub fn print_strategy_coverage(nodes: &NodeMap) {
+    let streets = [PREFLOP as u8, FLOP as u8, TURN as u8, RIVER as u8];
+    let report = check_strategy_coverage(nodes, &streets);
+    println!("[INFO] {}", report);
+    for infoset in report.missing.iter().take(10) {
+        println!("[INFO] Missing {:?}", infoset);
+    }
+}
+
+// A 7-card hand for each category, weakest category first
+const CATEGORY_REPRESENTATIVES: [(HandCategory, &str); 10] = [
+    (HandCategory::HighCard, "AhJd9c7s5h3d2c"),
+    (HandCategory::Pair, "2h2dAc9s7h5d3c"),
+    (HandCategory::TwoPair, "3h3d2c2sAh9d7c"),
+    (HandCategory::ThreeOfAKind, "4h4d4cAs9h7d2c"),
+    (HandCategory::Straight, "5h6d7c8s9hKdAc"),
+    (HandCategory::Flush, "2h5h7h9hJhKdAc"),
+    (HandCategory::FullHouse, "3h3d3c2s2hAd9c"),
+    (HandCategory::FourOfAKind, "2h2d2c2sAh9d7c"),
+    (HandCategory::StraightFlush, "5h6h7h8h9hKdAc"),
+    (HandCategory::RoyalFlush, "ThJhQhKhAh2c3d"),
+];
+
+// Pairs of 7-card hands from the same category where the first should win
+const WITHIN_CATEGORY_CHECKS: [(&str, &str); 12] = [
+    ("AhKd9c7s5h3d2c", "AhQd9c7s5h3d2c"),
+    ("AhAd9c7s5h3d2c", "KhKdQc7s5h3d2c"),
+    ("AhAdKc7s5h3d2c", "AhAdQc7s5h3d2c"),
+    ("AhAd2c2s9h5d3c", "KhKdQcQs9h5d3c"),
+    ("KhKdKc2s5h7d9c", "QhQdQcAsJh7d9c"),
+    ("Th9d8c7s6h2d2c", "9h8d7c6s5h2d2c"),
+    ("AhJh9h7h5h2c3d", "KhQhJh9h7h2c3d"),
+    ("3h3d3c2s2hAd9c", "2h2d2c3s3hAd9c"),
+    ("3h3d3c3sAh9d7c", "2h2d2c2sAh9d7c"),
+    ("2h2d2c2sAh9d7c", "2h2d2c2sKh9d7c"),
+    ("6h7h8h9hThKdAc", "5h6h7h8h9hKdAc"),
+    ("ThJhQhKhAh2c3d", "9h8h7h6h5hAcAd"),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MonotonicityError {
+    // The hand's strength falls in the wrong category's range
+    WrongCategory {
+        hand: String,
+        expected: HandCategory,
+        actual: HandCategory,
+    },
+    // The stronger hand didn't beat the weaker one
+    OutOfOrder {
+        stronger: String,
+        stronger_strength: i32,
+        weaker: String,
+        weaker_strength: i32,
+    },
+}
+
+impl fmt::Display for MonotonicityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MonotonicityError::WrongCategory {
+                hand,
+                expected,
+                actual,
+            } => write!(f, "{} should be {:?} but is {:?}", hand, expected, actual),
+            MonotonicityError::OutOfOrder {
+                stronger,
+                stronger_strength,
+                weaker,
+                weaker_strength,
+            } => write!(
+                f,
+                "{} (strength {}) should beat {} (strength {})",
+                stronger, stronger_strength, weaker, weaker_strength
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MonotonicityError {}
+
+// Sanity checks the hand strength table: one hand from each category has to
+// land in that category and beat the hand from the category below it, and
+// hands within a category have to be ordered by their ranks and kickers.
+pub fn hand_strength_monotonicity_check(table: &HandTable) -> Result<(), MonotonicityError> {
+    let strength = |hand: &str| table.hand_strength(&str2cards(hand).unwrap());
+    let mut previous: Option<(&str, i32)> = None;
+    for (category, hand) in CATEGORY_REPRESENTATIVES.iter() {
+        let hand_strength = strength(hand);
+        let actual = category_from_strength(hand_strength);
+        if actual != *category {
+            return Err(MonotonicityError::WrongCategory {
+                hand: hand.to_string(),
+                expected: *category,
+                actual: actual,
+            });
+        }
+        if let Some((weaker, weaker_strength)) = previous {
+            check_order(hand, hand_strength, weaker, weaker_strength)?;
+        }
+        previous = Some((hand, hand_strength));
+    }
+    for (stronger, weaker) in WITHIN_CATEGORY_CHECKS.iter() {
+        check_order(stronger, strength(stronger), weaker, strength(weaker))?;
+    }
+    Ok(())
+}
+
+fn check_order(
+    stronger: &str,
+    stronger_strength: i32,
+    weaker: &str,
+    weaker_strength: i32,
+) -> Result<(), MonotonicityError> {
+    if stronger_strength > weaker_strength {
+        Ok(())
+    } else {
+        Err(MonotonicityError::OutOfOrder {
+            stronger: stronger.to_string(),
+            stronger_strength: stronger_strength,
+            weaker: weaker.to_string(),
+            weaker_strength: weaker_strength,
+        })
+    }
+}
+
+fn n_buckets(street: usize) -> i32 {
+    match street {
+        PREFLOP => PREFLOP_BUCKETS,
+        FLOP => FLOP_BUCKETS,
+        TURN => TURN_BUCKETS,
+        _ => RIVER_BUCKETS,
+    }
+}
 
 // Displays the preflop strategy matrix for opening / raising.
 pub fn preflop_matrix() {
@@ -136,7 +356,7 @@ pub fn preflop_matrix() {
 //         action: ActionType::Call,
 //         amount: 250,
 //     });
-//     let donk_history = donk_history.compress(&BET_ABSTRACTION);
+//     let donk_history = donk_history.compress(bet_abstraction());
 //     let nodes = crate::trainer::load_nodes();
 
 //     for (infoset, node) in &nodes {